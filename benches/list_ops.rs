@@ -0,0 +1,94 @@
+//! Head-of-list cost: `Database::lpush` and `Database::lpop` on lists of
+//! growing length, next to the same pushes and pops on a plain `Vec`, which
+//! is how lists were stored before they became a `VecDeque`.
+//!
+//! Both ends of a `VecDeque` are O(1), so its time per operation should stay
+//! flat however long the list gets, while the `Vec` shifts every element.
+//!
+//! Run with `cargo bench --bench list_ops`.
+
+// The crate is a binary only, so its modules are compiled in here. Most of
+// what they hold isn't used by the benchmark.
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src/client.rs"]
+mod client;
+#[path = "../src/config.rs"]
+mod config;
+#[path = "../src/persistence/mod.rs"]
+mod persistence;
+#[path = "../src/pubsub/mod.rs"]
+mod pubsub;
+#[path = "../src/resp/mod.rs"]
+mod resp;
+#[path = "../src/scripting/mod.rs"]
+mod scripting;
+#[path = "../src/server.rs"]
+mod server;
+#[path = "../src/store/mod.rs"]
+mod store;
+#[path = "../src/types.rs"]
+mod types;
+#[path = "../src/utils.rs"]
+mod utils;
+
+use std::{hint::black_box, time::Instant};
+
+use store::Database;
+
+const SIZES: [usize; 3] = [10_000, 100_000, 1_000_000];
+const ROUNDS: usize = 20_000;
+const KEY: &[u8] = b"bench:queue";
+
+fn main() {
+    println!(
+        "{:>10}  {:>12}  {:>12}  {:>12}  {:>12}",
+        "length", "LPUSH ns/op", "LPOP ns/op", "Vec push", "Vec pop"
+    );
+    for size in SIZES {
+        let (push_ns, pop_ns) = database(size);
+        let (vec_push_ns, vec_pop_ns) = vec(size);
+        println!(
+            "{:>10}  {:>12}  {:>12}  {:>12}  {:>12}",
+            size, push_ns, pop_ns, vec_push_ns, vec_pop_ns
+        );
+    }
+}
+
+fn database(size: usize) -> (u128, u128) {
+    let mut db = Database::new();
+    let fill: Vec<Vec<u8>> = (0..size).map(|i| i.to_string().into_bytes()).collect();
+    db.rpush(KEY, &fill).unwrap();
+
+    let value = [b"x".to_vec()];
+    let push_ns = time_per_op(|| {
+        black_box(db.lpush(KEY, &value).unwrap());
+    });
+    let pop_ns = time_per_op(|| {
+        black_box(db.lpop(KEY).unwrap());
+    });
+    (push_ns, pop_ns)
+}
+
+/// The old representation: pushing and popping at the head means
+/// `insert(0, ..)` and `remove(0)`.
+fn vec(size: usize) -> (u128, u128) {
+    let mut list: Vec<Vec<u8>> = (0..size).map(|i| i.to_string().into_bytes()).collect();
+    let push_ns = time_per_op(|| {
+        list.insert(0, b"x".to_vec());
+        black_box(list.len());
+    });
+    let pop_ns = time_per_op(|| {
+        black_box(list.remove(0));
+    });
+    (push_ns, pop_ns)
+}
+
+/// Runs `op` `ROUNDS` times and returns the mean time per call.
+fn time_per_op(mut op: impl FnMut()) -> u128 {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        op();
+    }
+    start.elapsed().as_nanos() / ROUNDS as u128
+}
@@ -1,30 +1,47 @@
-use crate::pubsub::PubSub;
+use crate::pubsub::{allowed_when_subscribed, PubSub, PubSubSession};
 use crate::{
-    persistence::Persister, resp::handler::handle_command, store::db::Database,
-    utils::is_mutating_command,
+    persistence::{Persister, Saver},
+    resp::{
+        blocking::{is_blocking_command, serve_blocked, try_blocking_command},
+        handler::handle_command,
+        parser::Decoder,
+        transaction::{is_transaction_command, Transaction},
+        utils::{error, simple_string},
+    },
+    scripting::{busy_error, ScriptMonitor},
+    store::db::Database,
+    utils::persist_if_mutating,
 };
 
 use std::sync::Arc;
-use tokio::io::BufReader;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt},
-    net::TcpStream,
+    io::AsyncReadExt,
+    net::{tcp::OwnedReadHalf, TcpStream},
     sync::Mutex,
+    time::Instant,
 };
 
 pub async fn handle_connection(
     stream: TcpStream,
     db: Arc<Mutex<Database>>,
     persister: Arc<dyn Persister + Send + Sync>,
+    saver: Arc<Saver>,
     pubsub: Arc<PubSub>,
+    scripts: Arc<ScriptMonitor>,
 ) {
-    let (reader, writer) = stream.into_split();
-    let writer = Arc::new(Mutex::new(writer));
-    let mut reader = BufReader::new(reader);
+    let (mut reader, writer) = stream.into_split();
+    let mut buf: Vec<u8> = Vec::with_capacity(4096);
+    let mut decoder = Decoder::default();
+    let mut tx = Transaction::default();
+    let mut session = PubSubSession::new(pubsub, Arc::new(Mutex::new(writer)));
 
-    loop {
-        let mut first_line = String::new();
-        match reader.read_line(&mut first_line).await {
+    'conn: loop {
+        // A subscriber that can't keep up with its messages is dropped.
+        let read = tokio::select! {
+            read = reader.read_buf(&mut buf) => read,
+            _ = session.evicted() => break,
+        };
+        match read {
             Ok(0) => {
                 println!("⚠️  Client disconnected");
                 break;
@@ -36,100 +53,220 @@ pub async fn handle_connection(
             _ => {}
         }
 
-        let first_line = first_line.trim().to_string();
-        if first_line.is_empty() {
-            continue;
-        }
-
-        let parts = if first_line.starts_with('*') {
-            // RESP protocol: *N\r\n followed by N bulk strings
-            let count: usize = match first_line[1..].parse() {
-                Ok(n) => n,
-                Err(_) => {
-                    let mut w = writer.lock().await;
-                    let _ = w.write_all(b"-ERR invalid RESP array\r\n").await;
-                    let _ = w.flush().await;
-                    continue;
+        // Several pipelined requests may have arrived in one read; the tail
+        // of the buffer is kept for the next read if a frame is incomplete.
+        let mut consumed = 0;
+        loop {
+            let (parts, used) = match decoder.decode(&buf[consumed..]) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("❌ {e}");
+                    let _ = session.write(e.to_resp().as_bytes()).await;
+                    break 'conn;
                 }
             };
+            consumed += used;
+
+            if parts.is_empty() {
+                continue;
+            }
+
+            let command_name = String::from_utf8_lossy(&parts[0]).to_uppercase();
+            println!("📥 Command: {}", command_name);
+
+            // Blocking commands wait without holding the db lock; while they
+            // wait the socket is still read so a disconnect is noticed.
+            if command_name == "QUIT" {
+                let _ = session.write(&simple_string("OK")).await;
+                break 'conn;
+            }
 
-            let mut parts = Vec::with_capacity(count);
-            let mut valid = true;
-            for _ in 0..count {
-                // Read $N line
-                let mut len_line = String::new();
-                if reader.read_line(&mut len_line).await.unwrap_or(0) == 0 {
-                    valid = false;
-                    break;
+            // A script holds the database lock for as long as it runs; past
+            // the busy threshold clients are told so instead of waiting, and
+            // may try to stop it.
+            let response = if command_name == "SCRIPT"
+                && parts.len() == 2
+                && parts[1].eq_ignore_ascii_case(b"KILL")
+            {
+                scripts.kill()
+            } else if scripts.is_busy() {
+                busy_error()
+            } else if command_name == "RESET" {
+                // Back to a fresh connection's state, even mid-MULTI.
+                tx.reset(&mut *db.lock().await);
+                session.unsubscribe_all();
+                simple_string("RESET")
+            } else if session.is_subscribed() && !allowed_when_subscribed(&command_name) {
+                error(format!(
+                    "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                    command_name.to_lowercase()
+                ))
+            } else if is_transaction_command(&command_name) {
+                let mut db = db.lock().await;
+                let response = run_transaction_command(
+                    &command_name,
+                    &parts,
+                    &mut tx,
+                    &mut db,
+                    &mut session,
+                    &*persister,
+                    &saver,
+                )
+                .await;
+                // Parked clients are served once EXEC is done, not midway.
+                serve_blocked(&mut db, &*persister);
+                response
+            } else if tx.in_multi() {
+                match tx.queue(&command_name, parts) {
+                    Ok(()) => simple_string("QUEUED"),
+                    Err(e) => error(e),
                 }
-                // Read the value line
-                let mut value_line = String::new();
-                if reader.read_line(&mut value_line).await.unwrap_or(0) == 0 {
-                    valid = false;
-                    break;
+            } else if is_blocking_command(&command_name) {
+                match run_blocking(&command_name, parts, &db, &*persister, &mut reader, &mut buf)
+                    .await
+                {
+                    Some(response) => response,
+                    None => {
+                        println!("⚠️  Client disconnected while blocked");
+                        break 'conn;
+                    }
                 }
-                parts.push(
-                    value_line
-                        .trim_end_matches('\n')
-                        .trim_end_matches('\r')
-                        .to_string(),
-                );
-            }
+            } else {
+                // Lock db, process command, persist, then release lock before writing
+                let mut db = db.lock().await;
 
-            if !valid {
-                println!("⚠️  Client disconnected mid-command");
-                break;
+                let response = handle_command(
+                    &parts,
+                    &mut db,
+                    &mut session,
+                    &*persister,
+                    &saver,
+                )
+                .await;
+
+                persist_if_mutating(&command_name, &parts, &mut db, &*persister);
+                serve_blocked(&mut db, &*persister);
+                response
+            }; // db lock dropped here
+
+            if !response.is_empty() {
+                if let Err(e) = session.write(&response).await {
+                    eprintln!("❌ Write error: {e}");
+                    break 'conn;
+                }
             }
-            parts
-        } else {
-            // Inline command
-            first_line
-                .split_whitespace()
-                .map(|s| s.to_string())
-                .collect()
-        };
+        }
+        buf.drain(..consumed);
+    }
 
-        if parts.is_empty() {
-            continue;
+    tx.unwatch(&mut *db.lock().await);
+    session.close();
+}
+
+/// MULTI, EXEC, DISCARD, WATCH and UNWATCH.
+async fn run_transaction_command(
+    command_name: &str,
+    parts: &[Vec<u8>],
+    tx: &mut Transaction,
+    db: &mut Database,
+    session: &mut PubSubSession,
+    persister: &(dyn Persister + Send + Sync),
+    saver: &Arc<Saver>,
+) -> Vec<u8> {
+    let result = match command_name {
+        "MULTI" => tx.multi(),
+        "DISCARD" => tx.discard(db),
+        "WATCH" if parts.len() < 2 => Err("ERR wrong number of arguments for 'watch' command"),
+        "WATCH" => tx.watch(&parts[1..], db),
+        "UNWATCH" => {
+            tx.unwatch(db);
+            Ok(())
         }
+        _ => {
+            let queued = match tx.exec(db) {
+                Ok(Some(queued)) => queued,
+                Ok(None) => return b"*-1\r\n".to_vec(),
+                Err(e) => return error(e),
+            };
 
-        let command_name = parts[0].to_uppercase();
-        println!("📥 Command: {}", command_name);
-
-        // Lock db, process command, persist, then release lock before writing
-        let response = {
-            let mut db = db.lock().await;
-
-            let response = handle_command(
-                &parts,
-                &mut db,
-                Arc::clone(&pubsub),
-                Arc::clone(&writer),
-            )
-            .await;
-
-            // Save to disk if mutating
-            if is_mutating_command(&command_name) {
-                if let Err(e) = persister.save(&db) {
-                    eprintln!("❌ Failed to save database: {e}");
+            // Everything runs under the one lock the caller holds, so no
+            // other client sees the transaction half applied.
+            let mut response = format!("*{}\r\n", queued.len()).into_bytes();
+            for parts in &queued {
+                let command_name = String::from_utf8_lossy(&parts[0]).to_uppercase();
+                // A blocking command inside a transaction never waits.
+                let reply = if is_blocking_command(&command_name) {
+                    try_blocking_command(&command_name, parts, db)
+                        .unwrap_or_else(|_| b"*-1\r\n".to_vec())
                 } else {
-                    println!("💾 Database saved to disk");
-                }
+                    handle_command(parts, db, session, persister, saver).await
+                };
+                persist_if_mutating(&command_name, parts, db, persister);
+                response.extend_from_slice(&reply);
             }
+            return response;
+        }
+    };
 
-            response
-        }; // db lock dropped here
+    match result {
+        Ok(()) => simple_string("OK"),
+        Err(e) => error(e),
+    }
+}
 
-        if command_name != "SUBSCRIBE" {
-            let mut s = writer.lock().await;
-            if let Err(e) = s.write_all(response.as_bytes()).await {
-                eprintln!("❌ Write error: {e}");
-                break;
+/// Runs a blocking command until it can reply or its timeout elapses.
+///
+/// Returns `None` if the client disconnects while parked. Anything the client
+/// pipelines meanwhile is appended to `buf` and handled after the reply.
+async fn run_blocking(
+    command_name: &str,
+    parts: Vec<Vec<u8>>,
+    db: &Mutex<Database>,
+    persister: &(dyn Persister + Send + Sync),
+    reader: &mut OwnedReadHalf,
+    buf: &mut Vec<u8>,
+) -> Option<Vec<u8>> {
+    // Once parked, the command is run for this client by whichever write
+    // lets it through, and the reply handed over.
+    let (id, mut served, until) = {
+        let mut db = db.lock().await;
+        let req = match try_blocking_command(command_name, &parts, &mut db) {
+            Ok(response) => {
+                persist_if_mutating(command_name, &parts, &mut db, persister);
+                serve_blocked(&mut db, persister);
+                return Some(response);
             }
-            if let Err(e) = s.flush().await {
-                eprintln!("❌ Flush error: {e}");
-                break;
+            Err(req) => req,
+        };
+        let until = req.timeout.map(|t| Instant::now() + t);
+        let (id, served) = db.blocked_mut().register(req.keys, req.retry);
+        (id, served, until)
+    };
+
+    loop {
+        let timeout = async {
+            match until {
+                Some(until) => tokio::time::sleep_until(until).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            reply = &mut served => return Some(reply.unwrap_or_else(|_| b"*-1\r\n".to_vec())),
+            _ = timeout => break,
+            read = reader.read_buf(buf) => {
+                if matches!(read, Ok(0) | Err(_)) {
+                    db.lock().await.blocked_mut().leave(id);
+                    return None;
+                }
             }
         }
     }
+
+    // It may have been served while the lock was being taken.
+    if db.lock().await.blocked_mut().leave(id) {
+        return Some(b"*-1\r\n".to_vec());
+    }
+    Some(served.try_recv().unwrap_or_else(|_| b"*-1\r\n".to_vec()))
 }
@@ -0,0 +1,334 @@
+use std::{fmt, str::FromStr};
+
+/// How often the append-only file is fsynced (`appendfsync`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// After every write; slowest, loses nothing.
+    Always,
+    /// At most once per second from a background thread.
+    EverySec,
+    /// Left to the operating system.
+    No,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Ok(FsyncPolicy::Always),
+            "everysec" => Ok(FsyncPolicy::EverySec),
+            "no" => Ok(FsyncPolicy::No),
+            _ => Err(format!("invalid appendfsync value '{s}' (expected always, everysec or no)")),
+        }
+    }
+}
+
+impl fmt::Display for FsyncPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FsyncPolicy::Always => "always",
+            FsyncPolicy::EverySec => "everysec",
+            FsyncPolicy::No => "no",
+        })
+    }
+}
+
+/// On-disk format of the `dbfilename` snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbFormat {
+    /// Pretty-printed JSON.
+    Json,
+    /// Compact, checksummed binary snapshot.
+    Rdb,
+}
+
+impl FromStr for DbFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(DbFormat::Json),
+            "rdb" => Ok(DbFormat::Rdb),
+            _ => Err(format!("invalid dbformat value '{s}' (expected json or rdb)")),
+        }
+    }
+}
+
+/// Take a snapshot once `changes` writes have happened and at least
+/// `seconds` have passed since the last one (`save <seconds> <changes>`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// When a pub/sub client that isn't reading its messages gets disconnected
+/// (`client-output-buffer-limit pubsub <hard> <soft> <soft seconds>`).
+///
+/// A soft limit of 0 disables it; the hard limit is always enforced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputBufferLimit {
+    /// Disconnect as soon as this many bytes are waiting to be written.
+    /// Never 0: it is the only bound on a subscriber's queue.
+    pub hard: u64,
+    /// Disconnect once at least this many bytes have been waiting for
+    /// `soft_seconds`; 0 turns the soft limit off.
+    pub soft: u64,
+    pub soft_seconds: u64,
+}
+
+impl Default for OutputBufferLimit {
+    fn default() -> Self {
+        OutputBufferLimit {
+            hard: 32 * 1024 * 1024,
+            soft: 8 * 1024 * 1024,
+            soft_seconds: 60,
+        }
+    }
+}
+
+/// Which keyspace events are published (`notify-keyspace-events`), as a set
+/// of class flags.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyspaceEvents(u16);
+
+impl KeyspaceEvents {
+    /// `K`: publish to `__keyspace@0__:<key>` with the event as message.
+    pub const KEYSPACE: Self = Self(1 << 0);
+    /// `E`: publish to `__keyevent@0__:<event>` with the key as message.
+    pub const KEYEVENT: Self = Self(1 << 1);
+    /// `g`: generic commands such as DEL, EXPIRE and PERSIST.
+    pub const GENERIC: Self = Self(1 << 2);
+    /// `$`: string commands.
+    pub const STRING: Self = Self(1 << 3);
+    /// `l`: list commands.
+    pub const LIST: Self = Self(1 << 4);
+    /// `s`: set commands.
+    pub const SET: Self = Self(1 << 5);
+    /// `h`: hash commands.
+    pub const HASH: Self = Self(1 << 6);
+    /// `x`: keys removed because their TTL ran out.
+    pub const EXPIRED: Self = Self(1 << 7);
+    /// `e`: keys evicted to free memory.
+    pub const EVICTED: Self = Self(1 << 8);
+    /// `A`: every event class above.
+    pub const ALL: Self = Self(
+        Self::GENERIC.0
+            | Self::STRING.0
+            | Self::LIST.0
+            | Self::SET.0
+            | Self::HASH.0
+            | Self::EXPIRED.0
+            | Self::EVICTED.0,
+    );
+
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Nothing is published unless a class and at least one of `K` and `E`
+    /// are enabled.
+    pub fn is_enabled(self) -> bool {
+        self.intersects(Self::ALL) && self.intersects(Self(Self::KEYSPACE.0 | Self::KEYEVENT.0))
+    }
+}
+
+impl FromStr for KeyspaceEvents {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.chars().try_fold(KeyspaceEvents::default(), |events, c| {
+            let class = match c {
+                'K' => Self::KEYSPACE,
+                'E' => Self::KEYEVENT,
+                'g' => Self::GENERIC,
+                '$' => Self::STRING,
+                'l' => Self::LIST,
+                's' => Self::SET,
+                'h' => Self::HASH,
+                'x' => Self::EXPIRED,
+                'e' => Self::EVICTED,
+                'A' => Self::ALL,
+                _ => {
+                    return Err(format!(
+                        "invalid notify-keyspace-events class '{c}' (expected K, E, g, $, l, s, h, x, e or A)"
+                    ))
+                }
+            };
+            Ok(KeyspaceEvents(events.0 | class.0))
+        })
+    }
+}
+
+/// Server settings, given on the command line as `--name value` pairs using
+/// the same names as redis.conf (e.g. `--appendonly yes`).
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind: String,
+    pub dbfilename: String,
+    pub dbformat: DbFormat,
+    pub save: Vec<SaveRule>,
+    /// Start with an empty database when the snapshot can't be read,
+    /// instead of refusing to start.
+    pub ignore_corrupt_snapshot: bool,
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: FsyncPolicy,
+    /// Rewrite the log once it has grown by this percentage since the last
+    /// rewrite; 0 disables automatic rewrites.
+    pub auto_aof_rewrite_percentage: u64,
+    /// ...but never while it is smaller than this many bytes.
+    pub auto_aof_rewrite_min_size: u64,
+    pub client_output_buffer_limit_pubsub: OutputBufferLimit,
+    pub notify_keyspace_events: KeyspaceEvents,
+    /// Scripts still running after this many milliseconds are aborted;
+    /// 0 lets them run for as long as they like.
+    pub busy_reply_threshold: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: "127.0.0.1:6379".to_string(),
+            dbfilename: "db.json".to_string(),
+            dbformat: DbFormat::Json,
+            save: vec![
+                SaveRule { seconds: 3600, changes: 1 },
+                SaveRule { seconds: 300, changes: 100 },
+                SaveRule { seconds: 60, changes: 10000 },
+            ],
+            ignore_corrupt_snapshot: false,
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appendfsync: FsyncPolicy::EverySec,
+            auto_aof_rewrite_percentage: 100,
+            auto_aof_rewrite_min_size: 64 * 1024 * 1024,
+            client_output_buffer_limit_pubsub: OutputBufferLimit::default(),
+            notify_keyspace_events: KeyspaceEvents::default(),
+            busy_reply_threshold: 5000,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut config = Config::default();
+        let mut args = args.into_iter();
+        let mut dbfilename = None;
+
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                return Err(format!("unexpected argument '{arg}'"));
+            };
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for '--{name}'"))?;
+
+            match name.to_ascii_lowercase().as_str() {
+                "bind" => config.bind = value,
+                "dbfilename" => dbfilename = Some(value),
+                "dbformat" => config.dbformat = value.parse()?,
+                "save" => config.save = parse_save_rules(name, &value)?,
+                "ignore-corrupt-snapshot" => {
+                    config.ignore_corrupt_snapshot = parse_yes_no(name, &value)?
+                }
+                "appendonly" => config.appendonly = parse_yes_no(name, &value)?,
+                "appendfilename" => config.appendfilename = value,
+                "appendfsync" => config.appendfsync = value.parse()?,
+                "auto-aof-rewrite-percentage" => {
+                    config.auto_aof_rewrite_percentage = value
+                        .parse()
+                        .map_err(|_| format!("'--{name}' must be a non-negative integer, got '{value}'"))?
+                }
+                "auto-aof-rewrite-min-size" => {
+                    config.auto_aof_rewrite_min_size = parse_memory(name, &value)?
+                }
+                "client-output-buffer-limit-pubsub" => {
+                    config.client_output_buffer_limit_pubsub = parse_output_buffer_limit(name, &value)?
+                }
+                "notify-keyspace-events" => config.notify_keyspace_events = value.parse()?,
+                "busy-reply-threshold" | "lua-time-limit" => {
+                    config.busy_reply_threshold = value
+                        .parse()
+                        .map_err(|_| format!("'--{name}' must be a non-negative integer, got '{value}'"))?
+                }
+                _ => return Err(format!("unknown option '--{name}'")),
+            }
+        }
+
+        config.dbfilename = dbfilename.unwrap_or_else(|| match config.dbformat {
+            DbFormat::Json => "db.json".to_string(),
+            DbFormat::Rdb => "dump.rdb".to_string(),
+        });
+        Ok(config)
+    }
+}
+
+fn parse_yes_no(name: &str, value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("'--{name}' must be yes or no, got '{value}'")),
+    }
+}
+
+/// Parses `"<seconds> <changes> [<seconds> <changes> ...]"`; an empty
+/// string disables automatic snapshots.
+fn parse_save_rules(name: &str, value: &str) -> Result<Vec<SaveRule>, String> {
+    let numbers = value
+        .split_whitespace()
+        .map(|n| n.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("'--{name}' must be pairs of non-negative integers, got '{value}'"))?;
+    if numbers.len() % 2 != 0 {
+        return Err(format!("'--{name}' must be pairs of <seconds> <changes>, got '{value}'"));
+    }
+    Ok(numbers
+        .chunks(2)
+        .map(|pair| SaveRule { seconds: pair[0], changes: pair[1] })
+        .collect())
+}
+
+/// Parses `"<hard> <soft> <soft seconds>"`, e.g. `"32mb 8mb 60"`.
+fn parse_output_buffer_limit(name: &str, value: &str) -> Result<OutputBufferLimit, String> {
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    let [hard, soft, soft_seconds] = fields[..] else {
+        return Err(format!("'--{name}' must be <hard> <soft> <soft seconds>, got '{value}'"));
+    };
+    let hard = parse_memory(name, hard)?;
+    if hard == 0 {
+        return Err(format!("'--{name}' hard limit must be above 0"));
+    }
+    Ok(OutputBufferLimit {
+        hard,
+        soft: parse_memory(name, soft)?,
+        soft_seconds: soft_seconds
+            .parse()
+            .map_err(|_| format!("'--{name}' soft seconds must be a non-negative integer, got '{soft_seconds}'"))?,
+    })
+}
+
+/// Parses a size such as `64mb` or `1gb`; a bare number is bytes.
+fn parse_memory(name: &str, value: &str) -> Result<u64, String> {
+    let lower = value.to_ascii_lowercase();
+    let (digits, unit) = match lower.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => lower.split_at(i),
+        None => (lower.as_str(), ""),
+    };
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("'--{name}' has an invalid size '{value}'")),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("'--{name}' has an invalid size '{value}'"))
+}
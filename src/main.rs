@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+mod config;
 mod server;
 mod store;
 mod resp;
@@ -8,20 +9,61 @@ mod client;
 mod persistence;
 mod utils;
 mod pubsub;
+mod scripting;
 
 use pubsub::PubSub;
 
-use crate::persistence::JsonPersister;
+use crate::{
+    config::{Config, DbFormat},
+    persistence::{AofPersister, AutoRewrite, JsonPersister, Persister, RdbPersister, Saver},
+};
 
 #[tokio::main]
 async fn main(){
-    const REDIS_PORT: &str = "127.0.0.1:6379";
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("❌ {e}");
+            std::process::exit(1);
+        }
+    };
 
+    println!("🚀 Redis (Rust Edition) listening on {}", config.bind);
 
-    println!("🚀 Redis (Rust Edition) listening on {REDIS_PORT}");
+    let persister: Arc<dyn Persister + Send + Sync> = if config.appendonly {
+        println!("📝 Append-only file {} (appendfsync {})", config.appendfilename, config.appendfsync);
+        let auto_rewrite = AutoRewrite {
+            percentage: config.auto_aof_rewrite_percentage,
+            min_size: config.auto_aof_rewrite_min_size,
+        };
+        match AofPersister::new(&config.appendfilename, config.appendfsync, auto_rewrite) {
+            Ok(persister) => Arc::new(persister),
+            Err(e) => {
+                eprintln!("❌ Failed to open {}: {e}", config.appendfilename);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        match config.dbformat {
+            DbFormat::Json => Arc::new(JsonPersister::new(&config.dbfilename)),
+            DbFormat::Rdb => Arc::new(RdbPersister::new(&config.dbfilename)),
+        }
+    };
 
-    let persister = Arc::new(JsonPersister::new("db.json"));
-    let pubsub = PubSub::new();
+    // Snapshots are only taken by the saver; the append-only file already
+    // has every write.
+    let rules = if config.appendonly { Vec::new() } else { config.save.clone() };
+    let saver = Saver::new(Arc::clone(&persister), rules);
+    let pubsub = PubSub::new(config.client_output_buffer_limit_pubsub);
    
-    server::run(REDIS_PORT, persister, pubsub).await;
+    server::run(
+        &config.bind,
+        persister,
+        saver,
+        pubsub,
+        config.ignore_corrupt_snapshot,
+        config.notify_keyspace_events,
+        config.busy_reply_threshold,
+    )
+    .await;
 }
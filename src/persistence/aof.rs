@@ -0,0 +1,878 @@
+use std::{
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    config::FsyncPolicy,
+    resp::{
+        parser::parse_command,
+        utils::{format_array, format_float},
+    },
+    store::Database,
+    types::RedisValue,
+    utils::current_unix_millis,
+};
+
+use super::persister::{Command, Persister};
+
+// Large collections are rewritten in batches rather than one huge command.
+const REWRITE_ITEMS_PER_CMD: usize = 64;
+
+/// When to compact the log on its own: once it has grown by `percentage`
+/// since the last rewrite and is at least `min_size` bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct AutoRewrite {
+    pub percentage: u64,
+    pub min_size: u64,
+}
+
+impl AutoRewrite {
+    fn is_due(&self, size: u64, base_size: u64) -> bool {
+        self.percentage > 0
+            && size >= self.min_size
+            && size.saturating_sub(base_size) * 100 >= base_size.max(1) * self.percentage
+    }
+}
+
+/// Persists by appending every mutating command to a log in RESP form, the
+/// same encoding clients send. Startup replays the log from the beginning.
+pub struct AofPersister {
+    shared: Arc<AofShared>,
+    /// Writes held back by `begin_atomic`, already in their logged form.
+    block: Mutex<Option<Block>>,
+}
+
+/// An open atomic block. Blocks nest (a script's write may open its own),
+/// and only the outermost one is logged, once it ends.
+#[derive(Default)]
+struct Block {
+    depth: usize,
+    commands: Vec<Command>,
+}
+
+struct AofShared {
+    path: PathBuf,
+    fsync: FsyncPolicy,
+    auto_rewrite: AutoRewrite,
+    state: Mutex<AofState>,
+    /// Set by writes, cleared by the `everysec` fsync thread.
+    dirty: AtomicBool,
+    rewriting: AtomicBool,
+}
+
+struct AofState {
+    file: File,
+    size: u64,
+    /// Size right after the last rewrite (or startup), for `AutoRewrite`.
+    base_size: u64,
+    /// While a rewrite runs, a copy of everything appended since its
+    /// snapshot, to be added to the new file before it replaces the old one.
+    rewrite_buf: Option<Vec<u8>>,
+}
+
+impl AofShared {
+    fn sync(&self) -> io::Result<()> {
+        self.dirty.store(false, Ordering::Relaxed);
+        self.state.lock().unwrap().file.sync_data()
+    }
+
+    /// Writes `snapshot` as a fresh log next to the current one, then, with
+    /// writers held off, appends what they logged meanwhile and renames the
+    /// new file over the old.
+    fn rewrite(&self, snapshot: &Database) -> io::Result<u64> {
+        let file_name = format!("temp-rewriteaof-{}.aof", process::id());
+        let temp = self.path.with_file_name(file_name);
+
+        let result = (|| {
+            let mut writer = BufWriter::new(File::create(&temp)?);
+            write_snapshot(snapshot, &mut writer)?;
+            let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+            file.sync_data()?;
+
+            let mut state = self.state.lock().unwrap();
+            let buf = state.rewrite_buf.take().unwrap_or_default();
+            file.write_all(&buf)?;
+            file.sync_data()?;
+            fs::rename(&temp, &self.path)?;
+
+            let size = file.metadata()?.len();
+            state.file = file;
+            state.size = size;
+            state.base_size = size;
+            Ok(size)
+        })();
+
+        if result.is_err() {
+            self.state.lock().unwrap().rewrite_buf = None;
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+}
+
+impl AofPersister {
+    pub fn new(path: &str, fsync: FsyncPolicy, auto_rewrite: AutoRewrite) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        let shared = Arc::new(AofShared {
+            path: PathBuf::from(path),
+            fsync,
+            auto_rewrite,
+            state: Mutex::new(AofState {
+                file,
+                size,
+                base_size: size,
+                rewrite_buf: None,
+            }),
+            dirty: AtomicBool::new(false),
+            rewriting: AtomicBool::new(false),
+        });
+
+        if fsync == FsyncPolicy::EverySec {
+            // Holds only a weak reference so the thread ends with the persister.
+            let weak = Arc::downgrade(&shared);
+            thread::spawn(move || loop {
+                thread::sleep(Duration::from_secs(1));
+                let Some(shared) = weak.upgrade() else { break };
+                if shared.dirty.load(Ordering::Relaxed) {
+                    if let Err(e) = shared.sync() {
+                        eprintln!("❌ Failed to fsync append-only file: {e}");
+                    }
+                }
+            });
+        }
+
+        Ok(Self {
+            shared,
+            block: Mutex::new(None),
+        })
+    }
+
+    /// Appends `commands`; returns whether an automatic rewrite is due.
+    fn append(&self, commands: &[Command]) -> io::Result<bool> {
+        let mut buf = Vec::new();
+        for command in commands {
+            buf.extend(format_array(command.clone()));
+        }
+
+        let due = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.file.write_all(&buf)?;
+            state.size += buf.len() as u64;
+            if let Some(rewrite_buf) = state.rewrite_buf.as_mut() {
+                rewrite_buf.extend_from_slice(&buf);
+            }
+            self.shared.auto_rewrite.is_due(state.size, state.base_size)
+        };
+
+        match self.shared.fsync {
+            FsyncPolicy::Always => self.shared.sync()?,
+            FsyncPolicy::EverySec => self.shared.dirty.store(true, Ordering::Relaxed),
+            FsyncPolicy::No => {}
+        }
+        Ok(due)
+    }
+}
+
+impl Persister for AofPersister {
+    // Everything lives in the log; see `replay`.
+    fn load(&self) -> Result<Option<Database>, Box<dyn Error>> {
+        Ok(None)
+    }
+
+    // Every write is already in the log, so saving only forces it to disk.
+    fn save(&self, _db: &Database) -> Result<(), Box<dyn Error>> {
+        self.shared.sync()?;
+        Ok(())
+    }
+
+    // Commands are put in their logged form straight away, even when held
+    // back, since that depends on the state they left behind.
+    fn record(&self, parts: &[Vec<u8>], db: &Database) -> Result<(), Box<dyn Error>> {
+        let commands = propagate(parts, db);
+        if let Some(block) = self.block.lock().unwrap().as_mut() {
+            block.commands.extend(commands);
+            return Ok(());
+        }
+        self.log(&commands, db)
+    }
+
+    fn begin_atomic(&self) {
+        self.block.lock().unwrap().get_or_insert_with(Block::default).depth += 1;
+    }
+
+    // The block goes out in a single append, so a rewrite never starts in
+    // the middle of it; one cut short by a crash is dropped on replay.
+    fn end_atomic(&self, db: &Database) -> Result<(), Box<dyn Error>> {
+        let mut commands = {
+            let mut block = self.block.lock().unwrap();
+            match block.as_mut() {
+                Some(open) if open.depth > 1 => {
+                    open.depth -= 1;
+                    return Ok(());
+                }
+                Some(_) => block.take().unwrap().commands,
+                None => return Ok(()),
+            }
+        };
+        if commands.len() > 1 {
+            commands.insert(0, vec![b"MULTI".to_vec()]);
+            commands.push(vec![b"EXEC".to_vec()]);
+        }
+        self.log(&commands, db)
+    }
+
+    fn replay(&self) -> Result<Vec<Command>, Box<dyn Error>> {
+        self.read_log()
+    }
+
+    // The snapshot is taken here, under the caller's database lock, and the
+    // rewrite buffer is switched on before any later write can be logged.
+    fn rewrite(&self, db: &Database) -> Result<(), Box<dyn Error>> {
+        if self.shared.rewriting.swap(true, Ordering::AcqRel) {
+            return Err("Background append only file rewriting already in progress".into());
+        }
+        let snapshot = db.snapshot();
+        self.shared.state.lock().unwrap().rewrite_buf = Some(Vec::new());
+
+        let shared = Arc::clone(&self.shared);
+        thread::spawn(move || {
+            match shared.rewrite(&snapshot) {
+                Ok(size) => println!("📝 Append-only file rewritten ({size} bytes)"),
+                Err(e) => eprintln!("❌ Background append-only file rewrite failed: {e}"),
+            }
+            shared.rewriting.store(false, Ordering::Release);
+        });
+        Ok(())
+    }
+}
+
+impl AofPersister {
+    fn log(&self, commands: &[Command], db: &Database) -> Result<(), Box<dyn Error>> {
+        if commands.is_empty() {
+            return Ok(());
+        }
+        let due = self.append(commands)?;
+        if due && !self.shared.rewriting.load(Ordering::Relaxed) {
+            println!("📝 Append-only file grew past its rewrite threshold, rewriting");
+            self.rewrite(db)?;
+        }
+        Ok(())
+    }
+
+    /// Parses the whole log. A record cut short by a crash is dropped and the
+    /// file truncated to the last complete one, so new writes don't land after
+    /// garbage; anything else that fails to parse is a hard error.
+    ///
+    /// A MULTI/EXEC block comes back as the commands inside it, and one the
+    /// crash left without its EXEC is dropped whole.
+    fn read_log(&self) -> Result<Vec<Command>, Box<dyn Error>> {
+        let path = &self.shared.path;
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut commands = Vec::new();
+        // Where the open MULTI starts in the file, and in `commands`.
+        let mut block: Option<(usize, usize)> = None;
+        let mut consumed = 0;
+        while consumed < data.len() {
+            match parse_command(&data[consumed..]) {
+                Ok(Some((parts, used))) => {
+                    if parts.first().is_some_and(|cmd| cmd.eq_ignore_ascii_case(b"MULTI")) {
+                        block = Some((consumed, commands.len()));
+                    } else if parts.first().is_some_and(|cmd| cmd.eq_ignore_ascii_case(b"EXEC")) {
+                        block = None;
+                    } else if !parts.is_empty() {
+                        commands.push(parts);
+                    }
+                    consumed += used;
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    return Err(format!(
+                        "bad file format reading the append-only file {} at offset {consumed}: {e}",
+                        path.display()
+                    )
+                    .into())
+                }
+            }
+        }
+
+        if let Some((start, len)) = block {
+            commands.truncate(len);
+            consumed = start;
+        }
+        if consumed < data.len() {
+            eprintln!(
+                "⚠️  Append-only file ends with an incomplete command; truncating {} trailing bytes",
+                data.len() - consumed
+            );
+            let mut state = self.shared.state.lock().unwrap();
+            state.file.set_len(consumed as u64)?;
+            state.file.sync_data()?;
+            state.size = consumed as u64;
+            state.base_size = consumed as u64;
+        }
+
+        Ok(commands)
+    }
+}
+
+/// Writes the shortest command sequence that rebuilds `db`.
+fn write_snapshot(db: &Database, w: &mut impl Write) -> io::Result<()> {
+    let now = current_unix_millis();
+    let mut emit = |command: Command| w.write_all(&format_array(command));
+
+    for (key, value) in db.store_ref() {
+        let expire_at = db.expiry_ref().get(key).copied();
+        if expire_at.is_some_and(|at| at <= now) {
+            continue;
+        }
+
+        match value {
+            RedisValue::String(s) => emit(vec![b"SET".to_vec(), key.clone(), s.clone()])?,
+
+            RedisValue::List(items) => {
+                let items: Vec<_> = items.iter().collect();
+                for chunk in items.chunks(REWRITE_ITEMS_PER_CMD) {
+                    let mut command = vec![b"RPUSH".to_vec(), key.clone()];
+                    command.extend(chunk.iter().map(|item| item.to_vec()));
+                    emit(command)?;
+                }
+            }
+
+            RedisValue::Set(members) => {
+                let members: Vec<_> = members.iter().collect();
+                for chunk in members.chunks(REWRITE_ITEMS_PER_CMD) {
+                    let mut command = vec![b"SADD".to_vec(), key.clone()];
+                    command.extend(chunk.iter().map(|m| m.to_vec()));
+                    emit(command)?;
+                }
+            }
+
+            RedisValue::Hash(hash) => {
+                let fields: Vec<_> = hash.iter().collect();
+                for chunk in fields.chunks(REWRITE_ITEMS_PER_CMD) {
+                    let mut command = vec![b"HSET".to_vec(), key.clone()];
+                    for (field, value) in chunk {
+                        command.push(field.to_vec());
+                        command.push(value.to_vec());
+                    }
+                    emit(command)?;
+                }
+                for (field, at) in hash.expiries() {
+                    emit(vec![
+                        b"HPEXPIREAT".to_vec(), key.clone(), at.to_string().into_bytes(),
+                        b"FIELDS".to_vec(), b"1".to_vec(), field.clone(),
+                    ])?;
+                }
+            }
+
+            RedisValue::ZSet(zset) => {
+                let members: Vec<_> = zset.iter().collect();
+                for chunk in members.chunks(REWRITE_ITEMS_PER_CMD) {
+                    let mut command = vec![b"ZADD".to_vec(), key.clone()];
+                    for (member, score) in chunk {
+                        command.push(format_float(*score).into_bytes());
+                        command.push(member.to_vec());
+                    }
+                    emit(command)?;
+                }
+            }
+
+            RedisValue::Stream(stream) => {
+                let last_id = stream.last_id().to_string().into_bytes();
+                if stream.len() == 0 {
+                    // XADD can't create an empty stream, so add a placeholder
+                    // at the last ID and trim it straight away.
+                    emit(vec![
+                        b"XADD".to_vec(), key.clone(), b"MAXLEN".to_vec(), b"0".to_vec(),
+                        last_id.clone(), b"x".to_vec(), b"y".to_vec(),
+                    ])?;
+                } else {
+                    for (id, entry) in stream.entries() {
+                        let mut command =
+                            vec![b"XADD".to_vec(), key.clone(), id.to_string().into_bytes()];
+                        command.extend(entry.0.iter().cloned());
+                        emit(command)?;
+                    }
+                    emit(vec![b"XSETID".to_vec(), key.clone(), last_id])?;
+                }
+
+                for (name, group) in stream.groups() {
+                    emit(vec![
+                        b"XGROUP".to_vec(), b"CREATE".to_vec(), key.clone(), name.clone(),
+                        group.last_delivered.to_string().into_bytes(),
+                    ])?;
+                    for consumer in group.consumers().keys() {
+                        emit(vec![
+                            b"XGROUP".to_vec(), b"CREATECONSUMER".to_vec(), key.clone(),
+                            name.clone(), consumer.clone(),
+                        ])?;
+                    }
+                    for (id, pending) in group.pending() {
+                        emit(vec![
+                            b"XCLAIM".to_vec(), key.clone(), name.clone(), pending.consumer.clone(),
+                            b"0".to_vec(), id.to_string().into_bytes(),
+                            b"TIME".to_vec(), pending.delivered_at.to_string().into_bytes(),
+                            b"RETRYCOUNT".to_vec(), pending.delivery_count.to_string().into_bytes(),
+                            b"FORCE".to_vec(), b"JUSTID".to_vec(),
+                        ])?;
+                    }
+                }
+            }
+        }
+
+        if let Some(at) = expire_at {
+            emit(vec![b"PEXPIREAT".to_vec(), key.clone(), at.to_string().into_bytes()])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Rewrites a command into what has to be logged for replay to reach the
+/// same state at any later time: relative expiries become absolute (for hash
+/// fields too) and generated stream IDs are pinned.
+fn propagate(parts: &[Vec<u8>], db: &Database) -> Vec<Command> {
+    let cmd = String::from_utf8_lossy(&parts[0]).to_uppercase();
+    let expire_at = |key: &Vec<u8>| {
+        db.expiry_ref().get(key).map(|at| {
+            vec![b"PEXPIREAT".to_vec(), key.clone(), at.to_string().into_bytes()]
+        })
+    };
+
+    match (cmd.as_str(), parts.get(1)) {
+        ("EXPIRE" | "PEXPIRE", Some(key)) => expire_at(key).into_iter().collect(),
+
+        // Logged as the string they left behind, with its absolute expiry,
+        // which also covers SET's NX/XX/GET/KEEPTTL.
+        ("SET", Some(key)) if parts.len() > 3 => string_state(key, db),
+        ("SETEX" | "PSETEX", Some(key)) => string_state(key, db),
+
+        ("GETEX", Some(key)) if db.store_ref().contains_key(key) => {
+            vec![expire_at(key).unwrap_or_else(|| vec![b"PERSIST".to_vec(), key.clone()])]
+        }
+        ("GETEX", _) => vec![],
+
+        ("HEXPIRE" | "HPEXPIRE" | "HEXPIREAT" | "HPEXPIREAT", Some(key)) => {
+            field_expiries(key, parts, db)
+        }
+
+        ("XADD", Some(key)) => {
+            let mut parts = parts.to_vec();
+            let last_id = match db.store_ref().get(key) {
+                Some(RedisValue::Stream(stream)) => Some(stream.last_id()),
+                _ => None,
+            };
+            if let (Some(i), Some(last_id)) = (xadd_id_index(&parts), last_id) {
+                if parts[i] == b"*" || parts[i].ends_with(b"-*") {
+                    parts[i] = last_id.to_string().into_bytes();
+                }
+            }
+            vec![parts]
+        }
+
+        _ => vec![parts.to_vec()],
+    }
+}
+
+// A string key as it is now: SET with its PEXPIREAT, DEL if it's gone, or
+// nothing if it holds another type (the command failed).
+fn string_state(key: &Vec<u8>, db: &Database) -> Vec<Command> {
+    match db.store_ref().get(key) {
+        Some(RedisValue::String(value)) => {
+            let mut commands = vec![vec![b"SET".to_vec(), key.clone(), value.clone()]];
+            if let Some(at) = db.expiry_ref().get(key) {
+                let at = at.to_string().into_bytes();
+                commands.push(vec![b"PEXPIREAT".to_vec(), key.clone(), at]);
+            }
+            commands
+        }
+        Some(_) => vec![],
+        None => vec![vec![b"DEL".to_vec(), key.clone()]],
+    }
+}
+
+// The outcome of `HEXPIRE key time [NX|XX|GT|LT] FIELDS numfields field ...`
+// and its variants: an HPEXPIREAT for each field that has a TTL now and an
+// HDEL for those that are gone.
+fn field_expiries(key: &Vec<u8>, parts: &[Vec<u8>], db: &Database) -> Vec<Command> {
+    let start = if parts.get(3).is_some_and(|a| a.eq_ignore_ascii_case(b"FIELDS")) { 5 } else { 6 };
+    let hash = match db.store_ref().get(key) {
+        Some(RedisValue::Hash(hash)) => Some(hash),
+        _ => None,
+    };
+
+    let mut commands = Vec::new();
+    let mut gone = vec![b"HDEL".to_vec(), key.clone()];
+    for field in parts.get(start..).unwrap_or_default() {
+        match hash.map(|hash| (hash.contains(field), hash.expire_at(field))) {
+            Some((_, Some(at))) => commands.push(vec![
+                b"HPEXPIREAT".to_vec(), key.clone(), at.to_string().into_bytes(),
+                b"FIELDS".to_vec(), b"1".to_vec(), field.clone(),
+            ]),
+            Some((true, None)) => {}
+            _ => gone.push(field.clone()),
+        }
+    }
+    if gone.len() > 2 {
+        commands.push(gone);
+    }
+    commands
+}
+
+// Position of the ID argument in `XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] n [LIMIT n]] id ...`.
+fn xadd_id_index(parts: &[Vec<u8>]) -> Option<usize> {
+    let mut i = 2;
+    loop {
+        let arg = parts.get(i)?;
+        if arg.eq_ignore_ascii_case(b"NOMKSTREAM") {
+            i += 1;
+        } else if arg.eq_ignore_ascii_case(b"MAXLEN") || arg.eq_ignore_ascii_case(b"MINID") {
+            i += 1;
+            if matches!(parts.get(i).map(Vec::as_slice), Some(b"=" | b"~")) {
+                i += 1;
+            }
+            i += 1;
+            if parts.get(i).is_some_and(|a| a.eq_ignore_ascii_case(b"LIMIT")) {
+                i += 2;
+            }
+        } else {
+            return Some(i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use super::*;
+    use crate::{resp::handler::execute, utils::persist_if_mutating};
+
+    const NO_REWRITE: AutoRewrite = AutoRewrite { percentage: 0, min_size: 0 };
+
+    fn log_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("aof-test-{}-{name}.aof", process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    fn command(args: &[&str]) -> Command {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    fn replay(commands: &[Command]) -> Database {
+        let mut db = Database::new();
+        for parts in commands {
+            execute(&String::from_utf8_lossy(&parts[0]).to_uppercase(), parts, &mut db);
+        }
+        db
+    }
+
+    // Every key and expiry in a form that doesn't depend on hash map order.
+    fn dump(db: &Database) -> BTreeMap<Vec<u8>, String> {
+        let mut keys = BTreeMap::new();
+        for (key, value) in db.store_ref() {
+            let value = match value {
+                RedisValue::String(s) => format!("{s:?}"),
+                RedisValue::List(items) => format!("{items:?}"),
+                RedisValue::Set(members) => {
+                    format!("{:?}", members.iter().collect::<BTreeSet<_>>())
+                }
+                RedisValue::Hash(hash) => format!(
+                    "{:?}",
+                    hash.iter()
+                        .map(|(f, v)| (f, v, hash.expire_at(f)))
+                        .collect::<BTreeSet<_>>()
+                ),
+                RedisValue::ZSet(zset) => format!("{:?}", zset.iter().collect::<Vec<_>>()),
+                RedisValue::Stream(stream) => {
+                    let groups: BTreeMap<_, _> = stream
+                        .groups()
+                        .iter()
+                        .map(|(name, group)| {
+                            let consumers: BTreeSet<_> = group.consumers().keys().collect();
+                            (name, (group.last_delivered, consumers, group.pending()))
+                        })
+                        .collect();
+                    let entries: Vec<_> = stream.entries().collect();
+                    format!("{entries:?} {:?} {groups:?}", stream.last_id())
+                }
+            };
+            let expiry = db.expiry_ref().get(key);
+            keys.insert(key.clone(), format!("{value} expires {expiry:?}"));
+        }
+        keys
+    }
+
+    #[test]
+    fn replaying_the_log_rebuilds_the_database() {
+        let path = log_path("replay");
+        let aof = AofPersister::new(&path, FsyncPolicy::No, NO_REWRITE).unwrap();
+        let mut db = Database::new();
+        for args in [
+            &["SET", "s", "v", "EX", "100"][..],
+            &["SET", "n", "1"],
+            &["INCR", "n"],
+            &["EXPIRE", "n", "50"],
+            &["PSETEX", "p", "1500", "v"],
+            &["RPUSH", "l", "a", "b", "c"],
+            &["LPOP", "l"],
+            &["SADD", "st", "x", "y", "z"],
+            &["SPOP", "st"],
+            &["HSET", "h", "f", "v", "g", "w"],
+            &["HPEXPIRE", "h", "100000", "FIELDS", "1", "f"],
+            &["ZADD", "z", "1", "a", "2", "b"],
+            &["XADD", "x", "*", "a", "1"],
+            &["GETEX", "s", "PX", "20000"],
+            &["SET", "gone", "v"],
+            &["DEL", "gone"],
+        ] {
+            let parts = command(args);
+            execute(&args[0].to_uppercase(), &parts, &mut db);
+            persist_if_mutating(args[0], &parts, &mut db, &aof);
+        }
+        drop(aof);
+
+        let logged = AofPersister::new(&path, FsyncPolicy::No, NO_REWRITE)
+            .unwrap()
+            .replay()
+            .unwrap();
+        // Nothing in the log depends on when it is replayed.
+        for parts in &logged {
+            let name = String::from_utf8_lossy(&parts[0]).to_uppercase();
+            assert!(!matches!(name.as_str(), "EXPIRE" | "PEXPIRE" | "PSETEX" | "SPOP"), "{name}");
+            assert!(!(name == "XADD" && parts.contains(&b"*".to_vec())));
+        }
+        assert_eq!(dump(&replay(&logged)), dump(&db));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn relative_expiries_are_logged_as_absolute() {
+        let mut db = Database::new();
+        execute("SET", &command(&["SET", "k", "v", "PX", "1500"]), &mut db);
+        let at = db.expiry_ref()[&b"k"[..]].to_string();
+        assert_eq!(
+            propagate(&command(&["SET", "k", "v", "PX", "1500"]), &db),
+            vec![command(&["SET", "k", "v"]), command(&["PEXPIREAT", "k", &at])]
+        );
+        assert_eq!(
+            propagate(&command(&["PEXPIRE", "k", "1500"]), &db),
+            vec![command(&["PEXPIREAT", "k", &at])]
+        );
+        assert_eq!(propagate(&command(&["PEXPIRE", "nope", "1"]), &db), Vec::<Command>::new());
+    }
+
+    #[test]
+    fn a_torn_last_record_is_truncated() {
+        let path = log_path("torn");
+        let complete = format_array(command(&["SET", "a", "1"]));
+        let mut data = complete.clone();
+        data.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$1\r\nb");
+        fs::write(&path, &data).unwrap();
+
+        let aof = AofPersister::new(&path, FsyncPolicy::No, NO_REWRITE).unwrap();
+        assert_eq!(aof.replay().unwrap(), vec![command(&["SET", "a", "1"])]);
+        assert_eq!(fs::read(&path).unwrap(), complete);
+
+        fs::write(&path, b"*1\r\n$x\r\nSET\r\n").unwrap();
+        let aof = AofPersister::new(&path, FsyncPolicy::No, NO_REWRITE).unwrap();
+        assert!(aof.replay().is_err());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn atomic_writes_are_logged_as_one_block() {
+        let path = log_path("atomic");
+        let aof = AofPersister::new(&path, FsyncPolicy::No, NO_REWRITE).unwrap();
+        let mut db = Database::new();
+        let run = |db: &mut Database, args: &[&str]| {
+            let parts = command(args);
+            execute(args[0], &parts, db);
+            persist_if_mutating(args[0], &parts, db, &aof);
+        };
+
+        aof.begin_atomic();
+        run(&mut db, &["SET", "a", "1"]);
+        run(&mut db, &["EXPIRE", "a", "100"]);
+        // Held back until the block ends, already in its logged form.
+        assert!(fs::read(&path).unwrap().is_empty());
+        run(&mut db, &["DEL", "a"]);
+        aof.end_atomic(&db).unwrap();
+
+        // A block of one write needs no MULTI.
+        aof.begin_atomic();
+        run(&mut db, &["SET", "b", "2"]);
+        aof.end_atomic(&db).unwrap();
+
+        let logged = read_commands(&fs::read(&path).unwrap());
+        assert_eq!(logged[0], command(&["MULTI"]));
+        assert_eq!(logged[2][0], b"PEXPIREAT");
+        assert_eq!(logged[4], command(&["EXEC"]));
+        assert_eq!(logged[5], command(&["SET", "b", "2"]));
+
+        let replayed = aof.replay().unwrap();
+        assert_eq!(replayed.len(), 4);
+        assert_eq!(dump(&replay(&replayed)), dump(&db));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn a_block_without_its_exec_is_dropped_whole() {
+        let path = log_path("torn-block");
+        let complete = format_array(command(&["SET", "a", "1"]));
+        let mut data = complete.clone();
+        data.extend(format_array(command(&["MULTI"])));
+        data.extend(format_array(command(&["SET", "b", "1"])));
+        data.extend(format_array(command(&["SET", "c", "1"])));
+        fs::write(&path, &data).unwrap();
+
+        let aof = AofPersister::new(&path, FsyncPolicy::No, NO_REWRITE).unwrap();
+        assert_eq!(aof.replay().unwrap(), vec![command(&["SET", "a", "1"])]);
+        assert_eq!(fs::read(&path).unwrap(), complete);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn xautoclaim_is_logged_as_the_claims_it_made() {
+        let path = log_path("xautoclaim");
+        let aof = AofPersister::new(&path, FsyncPolicy::No, NO_REWRITE).unwrap();
+        let now = current_unix_millis().to_string();
+        // Delivery times are pinned so replay can reproduce them: 1-0, 2-0
+        // and 4-0 have been idle for ages, 3-0 was delivered just now.
+        let before = [
+            &["XADD", "x", "1-0", "f", "v"][..],
+            &["XADD", "x", "2-0", "f", "v"],
+            &["XADD", "x", "3-0", "f", "v"],
+            &["XADD", "x", "4-0", "f", "v"],
+            &["XGROUP", "CREATE", "x", "g", "0"],
+            &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "x", ">"],
+            &["XCLAIM", "x", "g", "alice", "0", "1-0", "TIME", "1000", "RETRYCOUNT", "5"],
+            &["XCLAIM", "x", "g", "alice", "0", "2-0", "4-0", "TIME", "1000", "JUSTID"],
+            &["XCLAIM", "x", "g", "alice", "0", "3-0", "TIME", &now, "JUSTID"],
+            &["XDEL", "x", "2-0"],
+        ];
+        let before: Vec<Command> = before.iter().map(|args| command(args)).collect();
+        let mut db = replay(&before);
+
+        // Inside a script's block, the claims join it instead of ending it.
+        aof.begin_atomic();
+        for args in [
+            ["XAUTOCLAIM", "x", "g", "bob", "60000", "0"],
+            ["XAUTOCLAIM", "x", "g", "carol", "60000", "0"],
+        ] {
+            let parts = command(&args);
+            execute(args[0], &parts, &mut db);
+            persist_if_mutating(args[0], &parts, &mut db, &aof);
+        }
+        aof.end_atomic(&db).unwrap();
+
+        let logged = read_commands(&fs::read(&path).unwrap());
+        let names: Vec<_> = logged.iter().map(|parts| parts[0].as_slice()).collect();
+        assert_eq!(names, [&b"MULTI"[..], b"XCLAIM", b"XCLAIM", b"XCLAIM", b"XGROUP", b"EXEC"]);
+        assert_eq!(logged[1][4..6], command(&["0", "4-0"]));
+        assert_eq!(logged[1][8..], command(&["RETRYCOUNT", "2", "JUSTID"]));
+        assert_eq!(logged[2][4..6], command(&["0", "1-0"]));
+        assert_eq!(logged[2][8..], command(&["RETRYCOUNT", "6", "JUSTID"]));
+        assert_eq!(logged[3][4..], command(&["0", "2-0", "JUSTID"]));
+        assert_eq!(logged[4], command(&["XGROUP", "CREATECONSUMER", "x", "g", "carol"]));
+
+        // On top of the state before, replay claims the same entries with
+        // the same times and counts, however much later it runs.
+        let mut replayed = before;
+        replayed.extend(aof.replay().unwrap());
+        assert_eq!(dump(&replay(&replayed)), dump(&db));
+        let _ = fs::remove_file(path);
+    }
+
+    fn read_commands(mut data: &[u8]) -> Vec<Command> {
+        let mut commands = Vec::new();
+        while let Some((parts, used)) = parse_command(data).unwrap() {
+            commands.push(parts);
+            data = &data[used..];
+        }
+        assert!(data.is_empty());
+        commands
+    }
+
+    #[test]
+    fn a_rewritten_log_rebuilds_the_database() {
+        let mut db = Database::new();
+        let items: Vec<String> = (0..150).map(|i| i.to_string()).collect();
+        let mut rpush = vec!["RPUSH", "l"];
+        rpush.extend(items.iter().map(String::as_str));
+        for args in [
+            &["SET", "s", "v", "PX", "100500"][..],
+            &rpush,
+            &["SADD", "st", "x", "y"],
+            &["HSET", "h", "f", "v", "g", "w"],
+            &["HPEXPIRE", "h", "100000", "FIELDS", "1", "f"],
+            &["ZADD", "z", "1.5", "a", "-2", "b"],
+            &["XADD", "x", "1-1", "a", "1"],
+            &["XADD", "x", "2-1", "b", "2"],
+            &["XGROUP", "CREATE", "x", "g", "0"],
+            &["XREADGROUP", "GROUP", "g", "alice", "COUNT", "1", "STREAMS", "x", ">"],
+            &["XGROUP", "CREATECONSUMER", "x", "g", "bob"],
+            &["XADD", "empty", "5-5", "a", "1"],
+            &["XDEL", "empty", "5-5"],
+        ] {
+            execute(&args[0].to_uppercase(), &command(args), &mut db);
+        }
+        db.store_mut().insert(b"stale".to_vec(), RedisValue::String(b"v".to_vec()));
+        db.expiry_mut().insert(b"stale".to_vec(), 1);
+
+        let mut log = Vec::new();
+        write_snapshot(&db, &mut log).unwrap();
+        let commands = read_commands(&log);
+        let rpushes = commands.iter().filter(|parts| parts[0] == b"RPUSH").count();
+        assert_eq!(rpushes, 150usize.div_ceil(REWRITE_ITEMS_PER_CMD));
+
+        let mut expected = dump(&db);
+        expected.remove(&b"stale"[..]);
+        assert_eq!(dump(&replay(&commands)), expected);
+    }
+
+    #[test]
+    fn rewriting_keeps_writes_logged_since() {
+        let path = log_path("rewrite");
+        let aof = AofPersister::new(&path, FsyncPolicy::No, NO_REWRITE).unwrap();
+        let mut db = Database::new();
+        let run = |args: &[&str], db: &mut Database| {
+            let parts = command(args);
+            execute(&args[0].to_uppercase(), &parts, db);
+            persist_if_mutating(args[0], &parts, db, &aof);
+        };
+        for _ in 0..100 {
+            run(&["INCR", "n"], &mut db);
+        }
+        run(&["RPUSH", "l", "a"], &mut db);
+
+        aof.rewrite(&db).unwrap();
+        run(&["RPUSH", "l", "b"], &mut db);
+        while aof.shared.rewriting.load(Ordering::Acquire) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        run(&["RPUSH", "l", "c"], &mut db);
+
+        let logged = aof.replay().unwrap();
+        assert!(logged.len() < 10, "{} commands after the rewrite", logged.len());
+        assert_eq!(dump(&replay(&logged)), dump(&db));
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,270 @@
+//! Serde helpers for binary keys and values.
+//!
+//! Byte strings that are valid UTF-8 are written as plain JSON strings so the
+//! snapshot stays readable; anything else is written as an array of bytes.
+//! Maps are written as sequences of `[key, value]` pairs because JSON object
+//! keys cannot hold arbitrary bytes; JSON objects, as snapshots were written
+//! before keys became binary, are still read.
+
+use std::{fmt, marker::PhantomData};
+
+use serde::{
+    de::{MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+pub struct BytesRef<'a>(pub &'a [u8]);
+
+impl Serialize for BytesRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(self.0) {
+            Ok(text) => serializer.serialize_str(text),
+            Err(_) => serializer.collect_seq(self.0),
+        }
+    }
+}
+
+struct ByteBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ByteBufVisitor;
+
+        impl<'de> Visitor<'de> for ByteBufVisitor {
+            type Value = ByteBuf;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string or an array of bytes")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v.as_bytes().to_vec()))
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v.to_vec()))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ByteBuf, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(b) = seq.next_element::<u8>()? {
+                    bytes.push(b);
+                }
+                Ok(ByteBuf(bytes))
+            }
+        }
+
+        deserializer.deserialize_any(ByteBufVisitor)
+    }
+}
+
+/// Reads a map as `[key, value]` pairs or as an object.
+struct PairsVisitor<V>(PhantomData<V>);
+
+impl<'de, V: Deserialize<'de>> Visitor<'de> for PairsVisitor<V> {
+    type Value = Vec<(Vec<u8>, V)>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence of [key, value] pairs or a map")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut pairs = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some((k, v)) = seq.next_element::<(ByteBuf, V)>()? {
+            pairs.push((k.0, v));
+        }
+        Ok(pairs)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut pairs = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some((k, v)) = map.next_entry::<ByteBuf, V>()? {
+            pairs.push((k.0, v));
+        }
+        Ok(pairs)
+    }
+}
+
+fn deserialize_pairs<'de, V, D>(deserializer: D) -> Result<Vec<(Vec<u8>, V)>, D::Error>
+where
+    V: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(PairsVisitor(PhantomData))
+}
+
+/// A single byte string.
+pub mod bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        BytesRef(value).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        Ok(ByteBuf::deserialize(deserializer)?.0)
+    }
+}
+
+/// Any collection of byte strings (`Vec`, `HashSet`, ...).
+pub mod bytes_seq {
+    use super::*;
+
+    pub fn serialize<'a, T, S>(items: &'a T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        &'a T: IntoIterator<Item = &'a Vec<u8>>,
+        S: Serializer,
+    {
+        serializer.collect_seq(items.into_iter().map(|item| BytesRef(item)))
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromIterator<Vec<u8>>,
+        D: Deserializer<'de>,
+    {
+        let items = Vec::<ByteBuf>::deserialize(deserializer)?;
+        Ok(items.into_iter().map(|item| item.0).collect())
+    }
+}
+
+/// A map from byte strings to byte strings.
+pub mod bytes_map {
+    use super::*;
+
+    pub fn serialize<'a, T, S>(map: &'a T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        &'a T: IntoIterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>,
+        S: Serializer,
+    {
+        serializer.collect_seq(map.into_iter().map(|(k, v)| (BytesRef(k), BytesRef(v))))
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromIterator<(Vec<u8>, Vec<u8>)>,
+        D: Deserializer<'de>,
+    {
+        let pairs = deserialize_pairs::<ByteBuf, _>(deserializer)?;
+        Ok(pairs.into_iter().map(|(k, v)| (k, v.0)).collect())
+    }
+}
+
+/// A map keyed by byte strings with arbitrary serializable values.
+pub mod keyed_map {
+    use super::*;
+
+    pub fn serialize<'a, T, V, S>(map: &'a T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        &'a T: IntoIterator<Item = (&'a Vec<u8>, &'a V)>,
+        V: Serialize + 'a,
+        S: Serializer,
+    {
+        serializer.collect_seq(map.into_iter().map(|(k, v)| (BytesRef(k), v)))
+    }
+
+    pub fn deserialize<'de, T, V, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromIterator<(Vec<u8>, V)>,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(deserialize_pairs(deserializer)?.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{store::Database, types::RedisValue};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Maps {
+        #[serde(with = "bytes")]
+        value: Vec<u8>,
+        #[serde(with = "bytes_seq")]
+        members: HashSet<Vec<u8>>,
+        #[serde(with = "bytes_map")]
+        fields: HashMap<Vec<u8>, Vec<u8>>,
+        #[serde(with = "keyed_map")]
+        scores: HashMap<Vec<u8>, u64>,
+    }
+
+    #[test]
+    fn binary_bytes_round_trip() {
+        let binary = vec![0xff, 0, b'\r', b'\n'];
+        let maps = Maps {
+            value: binary.clone(),
+            members: HashSet::from([binary.clone(), b"text".to_vec()]),
+            fields: HashMap::from([(binary.clone(), b"v".to_vec()), (b"f".to_vec(), binary.clone())]),
+            scores: HashMap::from([(binary.clone(), 7)]),
+        };
+        let json = serde_json::to_string(&maps).unwrap();
+        assert_eq!(serde_json::from_str::<Maps>(&json).unwrap(), maps);
+    }
+
+    #[test]
+    fn utf8_bytes_are_written_as_strings() {
+        let json = serde_json::to_string(&BytesRef(b"text")).unwrap();
+        assert_eq!(json, r#""text""#);
+        let json = serde_json::to_string(&BytesRef(&[0xff, 1])).unwrap();
+        assert_eq!(json, "[255,1]");
+    }
+
+    #[test]
+    fn maps_load_from_json_objects() {
+        let maps: Maps = serde_json::from_str(
+            r#"{"value": "v", "members": ["m"], "fields": {"f": "x"}, "scores": {"k": 3}}"#,
+        )
+        .unwrap();
+        assert_eq!(maps.fields, HashMap::from([(b"f".to_vec(), b"x".to_vec())]));
+        assert_eq!(maps.scores, HashMap::from([(b"k".to_vec(), 3)]));
+    }
+
+    #[test]
+    fn database_with_binary_keys_round_trips() {
+        let mut db = Database::new();
+        db.store_mut().insert(vec![0xfe, b'k'], RedisValue::String(vec![0, 0xff]));
+        db.expiry_mut().insert(vec![0xfe, b'k'], 42);
+
+        let json = serde_json::to_string(&db).unwrap();
+        let loaded: Database = serde_json::from_str(&json).unwrap();
+        assert!(matches!(
+            loaded.store_ref().get(&vec![0xfe, b'k']),
+            Some(RedisValue::String(v)) if v == &[0, 0xff]
+        ));
+        assert_eq!(loaded.expiry_ref().get(&vec![0xfe, b'k']), Some(&42));
+    }
+
+    // A db.json as written before keys and values were byte strings.
+    #[test]
+    fn loads_snapshots_with_string_keys() {
+        let db = crate::persistence::json::decode(
+            r#"{
+                "store": {
+                    "s": {"String": "hello"},
+                    "l": {"List": ["a", "b"]},
+                    "st": {"Set": ["x"]},
+                    "h": {"Hash": {"f": "v", "fields": "not a list"}}
+                },
+                "expiry": {"s": 1999999999}
+            }"#,
+        )
+        .unwrap();
+
+        let store = db.store_ref();
+        assert!(matches!(&store[&b"s".to_vec()], RedisValue::String(v) if v == b"hello"));
+        assert!(matches!(&store[&b"l".to_vec()], RedisValue::List(l) if l.len() == 2));
+        assert!(matches!(&store[&b"st".to_vec()], RedisValue::Set(s) if s.contains(&b"x".to_vec())));
+        let RedisValue::Hash(hash) = &store[&b"h".to_vec()] else {
+            panic!("not a hash");
+        };
+        assert_eq!(hash.get(b"f"), Some(&b"v".to_vec()));
+        assert_eq!(hash.get(b"fields"), Some(&b"not a list".to_vec()));
+        assert_eq!(db.expiry_ref().get(&b"s".to_vec()), Some(&1999999999000));
+    }
+}
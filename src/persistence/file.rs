@@ -0,0 +1,65 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Replaces `path` with `data` so that a crash at any point leaves either
+/// the old or the new contents, never a mix.
+///
+/// The data is written to a temp file in the same directory, fsynced and
+/// renamed over `path`. With `keep_backup` the previous generation stays
+/// reachable as `<path>.bak`.
+pub fn write_atomically(path: &Path, data: &[u8], keep_backup: bool) -> io::Result<()> {
+    let temp = sibling(path, &format!("temp-{}-", process::id()), "");
+
+    let result = (|| {
+        let mut file = File::create(&temp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+
+        if keep_backup && path.exists() {
+            // A hard link keeps the old generation without copying it, and
+            // `path` itself is never missing in between.
+            let backup = backup_path(path);
+            let _ = fs::remove_file(&backup);
+            if fs::hard_link(path, &backup).is_err() {
+                fs::copy(path, &backup)?;
+            }
+        }
+
+        fs::rename(&temp, path)?;
+        sync_dir(path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Where `write_atomically` keeps the previous generation of `path`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    sibling(path, "", ".bak")
+}
+
+fn sibling(path: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{prefix}{name}{suffix}"))
+}
+
+// Makes the rename itself durable.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
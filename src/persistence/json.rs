@@ -1,16 +1,20 @@
-use std::{fs, path::Path};
+use std::{error::Error, fs, io, path::Path};
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::store::Database;
 
-use super::persister::Persister;
+use super::{
+    file::{backup_path, write_atomically},
+    persister::Persister,
+};
 
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct JsonPersister {
     path: String,
-}  
+}
 
 impl JsonPersister{
     pub fn new(path : &str) -> Self{
@@ -19,28 +23,80 @@ impl JsonPersister{
 }
 
 impl Persister for JsonPersister{
-    fn load(&self) -> Option<Database>{
-        if !Path::new(&self.path).exists(){
-            return None;
-        }
+    fn load(&self) -> Result<Option<Database>, Box<dyn Error>> {
+        let data = match fs::read_to_string(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
 
-        let data = fs::read_to_string(&self.path).ok()?;
-        match serde_json::from_str(&data) {
-            Ok(db) => Some(db),
+        match decode(&data) {
+            Ok(db) => Ok(Some(db)),
             Err(e) => {
-                eprintln!("Failed to parse db.json: {e}");
-                None
+                let backup = backup_path(Path::new(&self.path));
+                let hint = if backup.exists() {
+                    format!(" (the previous generation is in {})", backup.display())
+                } else {
+                    String::new()
+                };
+                Err(format!("{} is corrupt: {e}{hint}", self.path).into())
             }
         }
     }
 
-    fn save(&self, db:&Database) -> Result<(), Box<dyn std::error::Error>> {
+    // Written atomically, keeping the previous generation as `<path>.bak`.
+    fn save(&self, db:&Database) -> Result<(), Box<dyn Error>> {
         let data = serde_json::to_string_pretty(db)?;
-        fs::write(&self.path, data)?;
+        write_atomically(Path::new(&self.path), data.as_bytes(), true)?;
 
         Ok(())
-        
+
     }
 
 
-}
\ No newline at end of file
+}
+
+/// Parses a snapshot. Snapshots written while key expiries were Unix seconds
+/// keep them under `expiry` rather than `expiry_ms`; those are converted.
+pub fn decode(data: &str) -> serde_json::Result<Database> {
+    let mut snapshot: Value = serde_json::from_str(data)?;
+    let legacy = snapshot.as_object_mut().and_then(|fields| fields.remove("expiry"));
+    if let Some(mut expiry) = legacy {
+        let to_millis = |at: &mut Value| {
+            if let Some(seconds) = at.as_u64() {
+                *at = seconds.saturating_mul(1000).into();
+            }
+        };
+        match &mut expiry {
+            Value::Object(map) => map.values_mut().for_each(to_millis),
+            Value::Array(pairs) => {
+                pairs.iter_mut().filter_map(|pair| pair.get_mut(1)).for_each(to_millis)
+            }
+            _ => {}
+        }
+        snapshot["expiry_ms"] = expiry;
+    }
+    serde_json::from_value(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiries_in_seconds_are_upgraded() {
+        let db = decode(r#"{"store": [["k", {"String": "v"}]], "expiry": [["k", 1999999999]]}"#)
+            .unwrap();
+        assert_eq!(db.expiry_ref().get(&b"k"[..]), Some(&1_999_999_999_000));
+    }
+
+    #[test]
+    fn expiries_in_milliseconds_load_as_written() {
+        let mut db = Database::new();
+        db.store_mut().insert(b"k".to_vec(), crate::types::RedisValue::String(b"v".to_vec()));
+        db.expiry_mut().insert(b"k".to_vec(), 1_999_999_999_123);
+
+        let loaded = decode(&serde_json::to_string(&db).unwrap()).unwrap();
+        assert_eq!(loaded.expiry_ref().get(&b"k"[..]), Some(&1_999_999_999_123));
+    }
+}
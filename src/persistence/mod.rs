@@ -1,5 +1,13 @@
+pub mod aof;
+pub mod encoding;
+pub mod file;
 pub mod json;
 pub mod persister;
+pub mod rdb;
+pub mod saver;
 
+pub use aof::{AofPersister, AutoRewrite};
 pub use json::JsonPersister;
 pub use persister::Persister;
+pub use rdb::RdbPersister;
+pub use saver::Saver;
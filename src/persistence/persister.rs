@@ -2,7 +2,42 @@ use std::error::Error;
 
 use crate::store::Database;
 
+/// A command as its parts, the way the RESP layer hands it over.
+pub type Command = Vec<Vec<u8>>;
+
 pub trait Persister {
-    fn load(&self) -> Option<Database>;
+    /// The saved database, or `None` if nothing has been saved yet. A file
+    /// that exists but can't be read back is an error, not an empty database.
+    fn load(&self) -> Result<Option<Database>, Box<dyn Error>>;
     fn save(&self, db: &Database) -> Result<(), Box<dyn Error>>;
+
+    /// Called, with the database locked, after the mutating command `parts`
+    /// has been applied to `db`. Log-based persisters append the command;
+    /// snapshot persisters do nothing here and are saved in the background
+    /// by `Saver` instead, so a slow disk doesn't stall every client.
+    fn record(&self, parts: &[Vec<u8>], db: &Database) -> Result<(), Box<dyn Error>> {
+        let _ = (parts, db);
+        Ok(())
+    }
+
+    /// Starts a group of writes that must be replayed all or nothing, such
+    /// as a script's: `record` holds them back until `end_atomic` logs them
+    /// as one MULTI/EXEC block. Blocks may nest; the outermost one is logged.
+    fn begin_atomic(&self) {}
+
+    fn end_atomic(&self, db: &Database) -> Result<(), Box<dyn Error>> {
+        let _ = db;
+        Ok(())
+    }
+
+    /// Commands to re-apply, oldest first, on top of whatever `load` returned.
+    fn replay(&self) -> Result<Vec<Command>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
+
+    /// Starts compacting the log from `db` in the background (BGREWRITEAOF).
+    fn rewrite(&self, db: &Database) -> Result<(), Box<dyn Error>> {
+        let _ = db;
+        Err("Append only file rewriting requires appendonly yes".into())
+    }
 }
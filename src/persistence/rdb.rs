@@ -0,0 +1,452 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fs,
+    io,
+    path::PathBuf,
+};
+
+use crate::{
+    store::{
+        hash::Hash,
+        stream::{Consumer, ConsumerGroup, PendingEntry, Stream, StreamEntry, StreamId},
+        zset::ZSet,
+        Database,
+    },
+    types::RedisValue,
+};
+
+use super::{file::write_atomically, persister::Persister};
+
+// File layout:
+//
+//   "RRDB" | version: u8 | record* | EOF | crc32 of everything before: u32 LE
+//
+// A record is an optional `EXPIRE_AT unix-milliseconds: u64 LE`, then a type
+// byte, the key and the value. Lengths and counts are LEB128 varints, byte
+// strings are a length followed by the raw bytes, and scores are f64 LE.
+// Hashes with field TTLs follow each value with the field's expiry in unix
+// milliseconds (a varint, 0 for none).
+//
+// Version 2 added TYPE_HASH_WITH_TTL and version 3 moved key expiries from
+// seconds to milliseconds; older files are still loaded.
+const MAGIC: &[u8; 4] = b"RRDB";
+const VERSION: u8 = 3;
+const VERSION_WITHOUT_FIELD_TTL: u8 = 1;
+const VERSION_WITH_SECOND_EXPIRIES: u8 = 2;
+
+const OP_EXPIRE_AT: u8 = 0xFD;
+const OP_EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_HASH: u8 = 3;
+const TYPE_ZSET: u8 = 4;
+const TYPE_STREAM: u8 = 5;
+const TYPE_HASH_WITH_TTL: u8 = 6;
+
+/// Saves the database as a compact, checksummed binary snapshot.
+pub struct RdbPersister {
+    path: PathBuf,
+}
+
+impl RdbPersister {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(path),
+        }
+    }
+}
+
+impl Persister for RdbPersister {
+    fn load(&self) -> Result<Option<Database>, Box<dyn Error>> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        match decode(&data) {
+            Ok(db) => Ok(Some(db)),
+            Err(e) => Err(format!("{} is corrupt: {e}", self.path.display()).into()),
+        }
+    }
+
+    fn save(&self, db: &Database) -> Result<(), Box<dyn Error>> {
+        write_atomically(&self.path, &encode(db), false)?;
+        Ok(())
+    }
+}
+
+pub fn encode(db: &Database) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+
+    for (key, value) in db.store_ref() {
+        if let Some(at) = db.expiry_ref().get(key) {
+            out.push(OP_EXPIRE_AT);
+            out.extend_from_slice(&at.to_le_bytes());
+        }
+
+        match value {
+            RedisValue::String(s) => {
+                out.push(TYPE_STRING);
+                put_bytes(&mut out, key);
+                put_bytes(&mut out, s);
+            }
+            RedisValue::List(items) => {
+                out.push(TYPE_LIST);
+                put_bytes(&mut out, key);
+                put_len(&mut out, items.len() as u64);
+                for item in items {
+                    put_bytes(&mut out, item);
+                }
+            }
+            RedisValue::Set(members) => {
+                out.push(TYPE_SET);
+                put_bytes(&mut out, key);
+                put_len(&mut out, members.len() as u64);
+                for member in members {
+                    put_bytes(&mut out, member);
+                }
+            }
+            RedisValue::Hash(hash) => {
+                let with_ttl = hash.has_expiries();
+                out.push(if with_ttl { TYPE_HASH_WITH_TTL } else { TYPE_HASH });
+                put_bytes(&mut out, key);
+                put_len(&mut out, hash.len() as u64);
+                for (field, value) in hash.iter() {
+                    put_bytes(&mut out, field);
+                    put_bytes(&mut out, value);
+                    if with_ttl {
+                        put_len(&mut out, hash.expire_at(field).unwrap_or(0));
+                    }
+                }
+            }
+            RedisValue::ZSet(zset) => {
+                out.push(TYPE_ZSET);
+                put_bytes(&mut out, key);
+                put_len(&mut out, zset.len() as u64);
+                for (member, score) in zset.iter() {
+                    put_bytes(&mut out, member);
+                    out.extend_from_slice(&score.to_le_bytes());
+                }
+            }
+            RedisValue::Stream(stream) => {
+                out.push(TYPE_STREAM);
+                put_bytes(&mut out, key);
+                put_stream(&mut out, stream);
+            }
+        }
+    }
+
+    out.push(OP_EOF);
+    let checksum = crc32fast::hash(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+fn put_stream(out: &mut Vec<u8>, stream: &Stream) {
+    put_len(out, stream.len() as u64);
+    for (id, entry) in stream.entries() {
+        put_id(out, *id);
+        put_len(out, entry.0.len() as u64);
+        for part in &entry.0 {
+            put_bytes(out, part);
+        }
+    }
+    put_id(out, stream.last_id());
+
+    put_len(out, stream.groups().len() as u64);
+    for (name, group) in stream.groups() {
+        put_bytes(out, name);
+        put_id(out, group.last_delivered);
+
+        put_len(out, group.pending().len() as u64);
+        for (id, pending) in group.pending() {
+            put_id(out, *id);
+            put_bytes(out, &pending.consumer);
+            put_len(out, pending.delivered_at);
+            put_len(out, pending.delivery_count);
+        }
+
+        put_len(out, group.consumers().len() as u64);
+        for (name, consumer) in group.consumers() {
+            put_bytes(out, name);
+            put_len(out, consumer.seen_at);
+        }
+    }
+}
+
+fn put_len(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_len(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn put_id(out: &mut Vec<u8>, id: StreamId) {
+    put_len(out, id.ms);
+    put_len(out, id.seq);
+}
+
+pub fn decode(data: &[u8]) -> io::Result<Database> {
+    if data.len() < MAGIC.len() + 1 + 1 + 4 || &data[..MAGIC.len()] != MAGIC {
+        return Err(corrupt("not a snapshot file"));
+    }
+    let (body, checksum) = data.split_at(data.len() - 4);
+    if crc32fast::hash(body).to_le_bytes() != checksum {
+        return Err(corrupt("checksum mismatch"));
+    }
+
+    let mut r = Reader {
+        data: body,
+        pos: MAGIC.len(),
+    };
+    let version = r.u8()?;
+    if !(VERSION_WITHOUT_FIELD_TTL..=VERSION).contains(&version) {
+        return Err(corrupt(&format!("unsupported snapshot version {version}")));
+    }
+
+    let mut db = Database::new();
+    loop {
+        let mut op = r.u8()?;
+        if op == OP_EOF {
+            break;
+        }
+
+        let mut expire_at = None;
+        if op == OP_EXPIRE_AT {
+            let at = r.u64_le()?;
+            expire_at = Some(if version <= VERSION_WITH_SECOND_EXPIRIES {
+                at.saturating_mul(1000)
+            } else {
+                at
+            });
+            op = r.u8()?;
+        }
+
+        let key = r.bytes()?;
+        let value = match op {
+            TYPE_STRING => RedisValue::String(r.bytes()?),
+            TYPE_LIST => {
+                let n = r.len()?;
+                RedisValue::List((0..n).map(|_| r.bytes()).collect::<io::Result<_>>()?)
+            }
+            TYPE_SET => {
+                let n = r.len()?;
+                RedisValue::Set((0..n).map(|_| r.bytes()).collect::<io::Result<HashSet<_>>>()?)
+            }
+            TYPE_HASH_WITH_TTL if version == VERSION_WITHOUT_FIELD_TTL => {
+                return Err(corrupt(&format!("unknown value type {op}")));
+            }
+            TYPE_HASH | TYPE_HASH_WITH_TTL => {
+                let n = r.len()?;
+                let mut hash = Hash::new();
+                for _ in 0..n {
+                    let field = r.bytes()?;
+                    hash.insert(field.clone(), r.bytes()?);
+                    if op == TYPE_HASH_WITH_TTL {
+                        match r.len()? {
+                            0 => {}
+                            at => hash.set_expire_at(&field, at),
+                        }
+                    }
+                }
+                RedisValue::Hash(hash)
+            }
+            TYPE_ZSET => {
+                let n = r.len()?;
+                let mut zset = ZSet::new();
+                for _ in 0..n {
+                    let member = r.bytes()?;
+                    zset.insert(member, f64::from_bits(r.u64_le()?));
+                }
+                RedisValue::ZSet(zset)
+            }
+            TYPE_STREAM => RedisValue::Stream(r.stream()?),
+            other => return Err(corrupt(&format!("unknown value type {other}"))),
+        };
+
+        if let Some(at) = expire_at {
+            db.expiry_mut().insert(key.clone(), at);
+        }
+        db.store_mut().insert(key, value);
+    }
+
+    if r.pos != body.len() {
+        return Err(corrupt("trailing data after end of snapshot"));
+    }
+    Ok(db)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| corrupt("unexpected end of snapshot"))?;
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64_le(&mut self) -> io::Result<u64> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().expect("took 8 bytes")))
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        let mut n: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            n |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(corrupt("length out of range"))
+    }
+
+    fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let n = self.len()?;
+        let n = usize::try_from(n).map_err(|_| corrupt("length out of range"))?;
+        Ok(self.take(n)?.to_vec())
+    }
+
+    fn id(&mut self) -> io::Result<StreamId> {
+        Ok(StreamId::new(self.len()?, self.len()?))
+    }
+
+    fn stream(&mut self) -> io::Result<Stream> {
+        let mut entries = BTreeMap::new();
+        for _ in 0..self.len()? {
+            let id = self.id()?;
+            let n = self.len()?;
+            let fields = (0..n).map(|_| self.bytes()).collect::<io::Result<_>>()?;
+            entries.insert(id, StreamEntry(fields));
+        }
+        let last_id = self.id()?;
+
+        let mut groups = HashMap::new();
+        for _ in 0..self.len()? {
+            let name = self.bytes()?;
+            let last_delivered = self.id()?;
+
+            let mut pending = BTreeMap::new();
+            for _ in 0..self.len()? {
+                let id = self.id()?;
+                let entry = PendingEntry {
+                    consumer: self.bytes()?,
+                    delivered_at: self.len()?,
+                    delivery_count: self.len()?,
+                };
+                pending.insert(id, entry);
+            }
+
+            let mut consumers = HashMap::new();
+            for _ in 0..self.len()? {
+                let name = self.bytes()?;
+                consumers.insert(name, Consumer { seen_at: self.len()? });
+            }
+
+            groups.insert(name, ConsumerGroup::from_parts(last_delivered, pending, consumers));
+        }
+
+        Ok(Stream::from_parts(entries, last_id, groups))
+    }
+}
+
+fn corrupt(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_version(version: u8, records: &[u8]) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.push(version);
+        data.extend_from_slice(records);
+        data.push(OP_EOF);
+        let checksum = crc32fast::hash(&data);
+        data.extend_from_slice(&checksum.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn field_ttls_round_trip() {
+        let mut db = Database::new();
+        let mut hash = Hash::new();
+        hash.insert(b"f".to_vec(), b"v".to_vec());
+        hash.insert(b"g".to_vec(), b"w".to_vec());
+        hash.set_expire_at(b"f", 4_102_444_800_000);
+        db.store_mut().insert(b"h".to_vec(), RedisValue::Hash(hash));
+
+        let data = encode(&db);
+        assert_eq!(data[MAGIC.len()], VERSION);
+        let loaded = decode(&data).unwrap();
+        let Some(RedisValue::Hash(hash)) = loaded.store_ref().get(&b"h"[..]) else {
+            panic!("hash not loaded");
+        };
+        assert_eq!(hash.expire_at(b"f"), Some(4_102_444_800_000));
+        assert_eq!(hash.expire_at(b"g"), None);
+    }
+
+    #[test]
+    fn loads_version_one_snapshots() {
+        let mut record = vec![TYPE_HASH];
+        put_bytes(&mut record, b"h");
+        put_len(&mut record, 1);
+        put_bytes(&mut record, b"f");
+        put_bytes(&mut record, b"v");
+
+        let db = decode(&with_version(VERSION_WITHOUT_FIELD_TTL, &record)).unwrap();
+        assert!(matches!(db.store_ref().get(&b"h"[..]), Some(RedisValue::Hash(h)) if h.len() == 1));
+    }
+
+    #[test]
+    fn version_one_has_no_field_ttls() {
+        let mut record = vec![TYPE_HASH_WITH_TTL];
+        put_bytes(&mut record, b"h");
+        put_len(&mut record, 0);
+
+        assert!(decode(&with_version(VERSION_WITHOUT_FIELD_TTL, &record)).is_err());
+        assert!(decode(&with_version(VERSION, &record)).is_ok());
+        assert!(decode(&with_version(VERSION + 1, &[])).is_err());
+    }
+
+    #[test]
+    fn key_expiries_before_version_three_are_seconds() {
+        let mut record = vec![OP_EXPIRE_AT];
+        record.extend_from_slice(&1_999_999_999u64.to_le_bytes());
+        record.push(TYPE_STRING);
+        put_bytes(&mut record, b"k");
+        put_bytes(&mut record, b"v");
+
+        let expire_at = |version| {
+            let db = decode(&with_version(version, &record)).unwrap();
+            db.expiry_ref().get(&b"k"[..]).copied()
+        };
+        assert_eq!(expire_at(VERSION_WITH_SECOND_EXPIRIES), Some(1_999_999_999_000));
+        assert_eq!(expire_at(VERSION), Some(1_999_999_999));
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use tokio::sync::Mutex;
+
+use crate::{config::SaveRule, store::Database, utils::current_unix_timestamp};
+
+use super::persister::Persister;
+
+/// After a failed background save the rules wait this long before trying again.
+const RETRY_DELAY_SECS: u64 = 5;
+
+/// Takes snapshots on request (SAVE, BGSAVE) and when a `save` rule is met.
+pub struct Saver {
+    persister: Arc<dyn Persister + Send + Sync>,
+    rules: Vec<SaveRule>,
+    /// Unix time of the last successful save (LASTSAVE).
+    last_save: AtomicU64,
+    /// `Database::dirty` as of the last successful save.
+    saved_dirty: AtomicU64,
+    /// Unix time of the last failed background save, 0 if the last one succeeded.
+    last_failure: AtomicU64,
+    in_progress: AtomicBool,
+}
+
+impl Saver {
+    pub fn new(persister: Arc<dyn Persister + Send + Sync>, rules: Vec<SaveRule>) -> Arc<Self> {
+        Arc::new(Saver {
+            persister,
+            rules,
+            last_save: AtomicU64::new(current_unix_timestamp()),
+            saved_dirty: AtomicU64::new(0),
+            last_failure: AtomicU64::new(0),
+            in_progress: AtomicBool::new(false),
+        })
+    }
+
+    pub fn last_save(&self) -> u64 {
+        self.last_save.load(Ordering::Acquire)
+    }
+
+    pub fn is_saving(&self) -> bool {
+        self.in_progress.load(Ordering::Acquire)
+    }
+
+    /// Writes made since the last successful save.
+    pub fn unsaved_changes(&self, db: &Database) -> u64 {
+        db.dirty() - self.saved_dirty.load(Ordering::Acquire)
+    }
+
+    /// Saves in the foreground (SAVE); the caller holds the database lock.
+    pub fn save(&self, db: &Database) -> Result<(), Box<dyn Error>> {
+        if self.is_saving() {
+            return Err("Background save already in progress".into());
+        }
+        self.persister.save(db)?;
+        self.saved(db.dirty());
+        Ok(())
+    }
+
+    /// Copies the keyspace and writes it on another thread (BGSAVE), so the
+    /// database lock is only held for the copy.
+    pub fn bgsave(self: &Arc<Self>, db: &Database) -> Result<(), Box<dyn Error>> {
+        if self.in_progress.swap(true, Ordering::AcqRel) {
+            return Err("Background save already in progress".into());
+        }
+        let snapshot = db.snapshot();
+
+        let saver = Arc::clone(self);
+        thread::spawn(move || {
+            match saver.persister.save(&snapshot) {
+                Ok(()) => {
+                    saver.saved(snapshot.dirty());
+                    println!("💾 Background saving terminated with success");
+                }
+                Err(e) => {
+                    saver.last_failure.store(current_unix_timestamp(), Ordering::Release);
+                    eprintln!("❌ Background saving failed: {e}");
+                }
+            }
+            saver.in_progress.store(false, Ordering::Release);
+        });
+        Ok(())
+    }
+
+    fn saved(&self, dirty: u64) {
+        self.saved_dirty.store(dirty, Ordering::Release);
+        self.last_save.store(current_unix_timestamp(), Ordering::Release);
+        self.last_failure.store(0, Ordering::Release);
+    }
+
+    /// Checks the `save` rules once a second and starts a BGSAVE when one
+    /// is met.
+    pub fn start(self: Arc<Self>, db: Arc<Mutex<Database>>) {
+        if self.rules.is_empty() {
+            return;
+        }
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+
+                let db = db.lock().await;
+                if self.is_saving() {
+                    continue;
+                }
+                let now = current_unix_timestamp();
+                let failed_at = self.last_failure.load(Ordering::Acquire);
+                if failed_at != 0 && now < failed_at + RETRY_DELAY_SECS {
+                    continue;
+                }
+
+                let changes = self.unsaved_changes(&db);
+                let elapsed = now.saturating_sub(self.last_save());
+                let due = self.rules.iter().find(|rule| {
+                    changes > 0 && changes >= rule.changes && elapsed >= rule.seconds
+                });
+                if let Some(rule) = due {
+                    println!(
+                        "💾 {} changes in {} seconds. Saving...",
+                        rule.changes, rule.seconds
+                    );
+                    if let Err(e) = self.bgsave(&db) {
+                        eprintln!("❌ {e}");
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,183 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Mutex,
+};
+use tokio::{sync::Notify, time::Instant};
+
+use crate::config::OutputBufferLimit;
+
+/// Bytes published to one connection that its writer task hasn't
+/// written yet, checked against the `client-output-buffer-limit pubsub`
+/// policy.
+///
+/// A connection that goes over its limit is evicted: publishers stop
+/// sending to it and the connection loop, waiting in [`evicted`], closes it.
+///
+/// [`evicted`]: OutputBuffer::evicted
+#[derive(Debug)]
+pub struct OutputBuffer {
+    id: u64,
+    limit: OutputBufferLimit,
+    pending: AtomicU64,
+    /// When `pending` last went over the soft limit.
+    over_soft_since: Mutex<Option<Instant>>,
+    evicted: AtomicBool,
+    notify: Notify,
+}
+
+impl OutputBuffer {
+    pub fn new(id: u64, limit: OutputBufferLimit) -> Self {
+        OutputBuffer {
+            id,
+            limit,
+            pending: AtomicU64::new(0),
+            over_soft_since: Mutex::new(None),
+            evicted: AtomicBool::new(false),
+            notify: Notify::new(),
+        }
+    }
+
+    /// The connection's id, which tags its subscriptions in `PubSub`.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Accounts for `bytes` about to be queued. Returns `false`, evicting the
+    /// connection, if that puts it over the hard limit or keeps it over the
+    /// soft limit for longer than allowed.
+    pub fn reserve(&self, bytes: usize) -> bool {
+        if self.is_evicted() {
+            return false;
+        }
+        let pending = self.pending.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64;
+        let limit = self.limit;
+
+        if pending > limit.hard {
+            self.evict(&format!("over the hard limit of {} bytes", limit.hard));
+            return false;
+        }
+
+        if limit.soft > 0 && pending > limit.soft {
+            let since = *self
+                .over_soft_since
+                .lock()
+                .unwrap()
+                .get_or_insert_with(Instant::now);
+            if since.elapsed().as_secs() >= limit.soft_seconds {
+                self.evict(&format!(
+                    "over the soft limit of {} bytes for {}s",
+                    limit.soft, limit.soft_seconds
+                ));
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Gives back `bytes` once they have been written, or were never queued.
+    pub fn release(&self, bytes: usize) {
+        // Saturating, so a stray release can't wrap the count and evict a
+        // connection that has nothing pending.
+        let previous = self
+            .pending
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |pending| {
+                Some(pending.saturating_sub(bytes as u64))
+            })
+            .unwrap_or_default();
+        let pending = previous.saturating_sub(bytes as u64);
+        if pending <= self.limit.soft {
+            *self.over_soft_since.lock().unwrap() = None;
+        }
+    }
+
+    /// Marks the connection for disconnection; only the first call logs.
+    pub fn evict(&self, reason: &str) {
+        if !self.evicted.swap(true, Ordering::Relaxed) {
+            eprintln!(
+                "⚠️  Disconnecting pub/sub client {}: {} ({} bytes pending)",
+                self.id,
+                reason,
+                self.pending.load(Ordering::Relaxed)
+            );
+            self.notify.notify_one();
+        }
+    }
+
+    pub fn is_evicted(&self) -> bool {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    /// Waits until the connection has been evicted.
+    pub async fn evicted(&self) {
+        if !self.is_evicted() {
+            self.notify.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(hard: u64, soft: u64, soft_seconds: u64) -> OutputBuffer {
+        OutputBuffer::new(
+            7,
+            OutputBufferLimit {
+                hard,
+                soft,
+                soft_seconds,
+            },
+        )
+    }
+
+    #[test]
+    fn going_over_the_hard_limit_evicts_at_once() {
+        let buffer = buffer(100, 0, 0);
+        assert!(buffer.reserve(60));
+        assert!(buffer.reserve(40));
+        assert!(!buffer.is_evicted());
+
+        assert!(!buffer.reserve(1));
+        assert!(buffer.is_evicted());
+        // Nothing more is accepted, even once there is room again.
+        buffer.release(101);
+        assert!(!buffer.reserve(1));
+    }
+
+    #[test]
+    fn staying_over_the_soft_limit_evicts() {
+        let patient = buffer(1000, 100, 60);
+        assert!(patient.reserve(150));
+        assert!(patient.reserve(10));
+        assert!(!patient.is_evicted());
+
+        // With no grace period the first byte over the soft limit is too many.
+        let strict = buffer(1000, 100, 0);
+        assert!(strict.reserve(100));
+        assert!(!strict.reserve(1));
+        assert!(strict.is_evicted());
+    }
+
+    #[test]
+    fn dropping_under_the_soft_limit_restarts_its_clock() {
+        let buffer = buffer(1000, 100, 60);
+        assert!(buffer.reserve(150));
+        assert!(buffer.over_soft_since.lock().unwrap().is_some());
+
+        buffer.release(100);
+        assert!(buffer.over_soft_since.lock().unwrap().is_none());
+        assert_eq!(buffer.pending.load(Ordering::Relaxed), 50);
+    }
+
+    #[test]
+    fn releasing_more_than_is_pending_stops_at_zero() {
+        let buffer = buffer(100, 0, 0);
+        assert!(buffer.reserve(10));
+        buffer.release(10);
+        buffer.release(10);
+        assert_eq!(buffer.pending.load(Ordering::Relaxed), 0);
+
+        assert!(buffer.reserve(100));
+        assert!(!buffer.is_evicted());
+    }
+}
@@ -1,67 +1,190 @@
-use std::sync::Arc;
-use tokio::{io::AsyncWriteExt, net::tcp::OwnedWriteHalf, sync::Mutex};
+use crate::pubsub::{PubSub, PubSubSession};
+use crate::resp::utils::{bulk_string, error, format_array, integer, null_bulk, wrong_args};
+use crate::utils::key_hash_slot;
 
-use crate::pubsub::PubSub;
+const CROSSSLOT: &str = "CROSSSLOT Keys in request don't hash to the same slot";
 
-pub async fn handle_subscribe(
-    parts: Vec<&str>,
-    writer: Arc<Mutex<OwnedWriteHalf>>,
-    pubsub: Arc<PubSub>,
-) {
+pub async fn handle_subscribe(parts: &[Vec<u8>], session: &mut PubSubSession) {
     if parts.len() < 2 {
-        let mut w = writer.lock().await;
-        let _ = w.write_all(b"-ERR usage: SUBSCRIBE <channel> [channel ...]\r\n").await;
-        let _ = w.flush().await;
+        reply(session, b"-ERR usage: SUBSCRIBE <channel> [channel ...]\r\n").await;
         return;
     }
 
-    for (i, channel_name) in parts[1..].iter().enumerate() {
-        let channel = channel_name.to_string();
-        let mut rx = pubsub.subscribe(&channel).await;
-
-        // Send subscription confirmation
-        {
-            let mut w = writer.lock().await;
-            let sub_type = "subscribe";
-            let confirmation = format!(
-                "*3\r\n${}\r\n{}\r\n${}\r\n{}\r\n:{}\r\n",
-                sub_type.len(),
-                sub_type,
-                channel.len(),
-                channel,
-                i + 1
-            );
-            let _ = w.write_all(confirmation.as_bytes()).await;
-            let _ = w.flush().await;
+    for channel in &parts[1..] {
+        session.subscribe(channel);
+
+        let count = session.subscription_count();
+        reply(session, &confirmation(b"subscribe", Some(channel), count)).await;
+    }
+}
+
+pub async fn handle_psubscribe(parts: &[Vec<u8>], session: &mut PubSubSession) {
+    if parts.len() < 2 {
+        reply(session, b"-ERR usage: PSUBSCRIBE <pattern> [pattern ...]\r\n").await;
+        return;
+    }
+
+    for pattern in &parts[1..] {
+        session.psubscribe(pattern);
+
+        let count = session.subscription_count();
+        reply(session, &confirmation(b"psubscribe", Some(pattern), count)).await;
+    }
+}
+
+/// UNSUBSCRIBE with no channels drops all of them.
+pub async fn handle_unsubscribe(parts: &[Vec<u8>], session: &mut PubSubSession) {
+    let channels: Vec<Vec<u8>> = if parts.len() > 1 {
+        parts[1..].to_vec()
+    } else {
+        session.channels.iter().cloned().collect()
+    };
+
+    if channels.is_empty() {
+        let count = session.subscription_count();
+        reply(session, &confirmation(b"unsubscribe", None, count)).await;
+        return;
+    }
+
+    for channel in channels {
+        session.unsubscribe(&channel);
+        let count = session.subscription_count();
+        reply(session, &confirmation(b"unsubscribe", Some(&channel), count)).await;
+    }
+}
+
+/// PUNSUBSCRIBE with no patterns drops all of them.
+pub async fn handle_punsubscribe(parts: &[Vec<u8>], session: &mut PubSubSession) {
+    let patterns: Vec<Vec<u8>> = if parts.len() > 1 {
+        parts[1..].to_vec()
+    } else {
+        session.patterns.iter().cloned().collect()
+    };
+
+    if patterns.is_empty() {
+        let count = session.subscription_count();
+        reply(session, &confirmation(b"punsubscribe", None, count)).await;
+        return;
+    }
+
+    for pattern in patterns {
+        session.punsubscribe(&pattern);
+        let count = session.subscription_count();
+        reply(session, &confirmation(b"punsubscribe", Some(&pattern), count)).await;
+    }
+}
+
+/// SSUBSCRIBE: the channels must all hash to the same slot, the one whose
+/// owner would serve them in a cluster.
+pub async fn handle_ssubscribe(parts: &[Vec<u8>], session: &mut PubSubSession) {
+    if parts.len() < 2 {
+        reply(session, b"-ERR usage: SSUBSCRIBE <channel> [channel ...]\r\n").await;
+        return;
+    }
+    if !same_slot(&parts[1..]) {
+        reply(session, &error(CROSSSLOT)).await;
+        return;
+    }
+
+    for channel in &parts[1..] {
+        session.ssubscribe(channel);
+
+        let count = session.shard_subscription_count();
+        reply(session, &confirmation(b"ssubscribe", Some(channel), count)).await;
+    }
+}
+
+/// SUNSUBSCRIBE with no channels drops all shard channels.
+pub async fn handle_sunsubscribe(parts: &[Vec<u8>], session: &mut PubSubSession) {
+    if !same_slot(&parts[1..]) {
+        reply(session, &error(CROSSSLOT)).await;
+        return;
+    }
+    let channels: Vec<Vec<u8>> = if parts.len() > 1 {
+        parts[1..].to_vec()
+    } else {
+        session.shard_channels.iter().cloned().collect()
+    };
+
+    if channels.is_empty() {
+        let count = session.shard_subscription_count();
+        reply(session, &confirmation(b"sunsubscribe", None, count)).await;
+        return;
+    }
+
+    for channel in channels {
+        session.sunsubscribe(&channel);
+        let count = session.shard_subscription_count();
+        reply(session, &confirmation(b"sunsubscribe", Some(&channel), count)).await;
+    }
+}
+
+/// PUBLISH, from a client or a script.
+pub fn handle_publish(parts: &[Vec<u8>], pubsub: &PubSub) -> Vec<u8> {
+    if parts.len() < 3 {
+        return error("ERR usage: PUBLISH <channel> <message>");
+    }
+    let channel = &parts[1];
+    let message = parts[2..].join(&b' ');
+    integer(pubsub.publish(channel, &message) as i64)
+}
+
+/// PUBSUB CHANNELS [pattern], PUBSUB NUMSUB [channel ...] and PUBSUB NUMPAT,
+/// plus SHARDCHANNELS and SHARDNUMSUB for shard channels.
+pub fn handle_pubsub(parts: &[Vec<u8>], pubsub: &PubSub) -> Vec<u8> {
+    let sub = parts
+        .get(1)
+        .map(|s| String::from_utf8_lossy(s).to_uppercase())
+        .unwrap_or_default();
+
+    match (sub.as_str(), parts.len()) {
+        ("CHANNELS", 2 | 3) => {
+            let channels = pubsub.channels(parts.get(2).map(Vec::as_slice));
+            format_array(channels)
+        }
+        ("SHARDCHANNELS", 2 | 3) => {
+            let channels = pubsub.shard_channels(parts.get(2).map(Vec::as_slice));
+            format_array(channels)
         }
+        ("NUMSUB", _) => numsub_reply(&parts[2..], pubsub.numsub(&parts[2..])),
+        ("SHARDNUMSUB", _) => numsub_reply(&parts[2..], pubsub.shard_numsub(&parts[2..])),
+        ("NUMPAT", 2) => integer(pubsub.numpat() as i64),
+        ("CHANNELS" | "SHARDCHANNELS" | "NUMPAT", _) => wrong_args("PUBSUB"),
+        _ => error(format!(
+            "ERR unknown subcommand '{}'. Try PUBSUB CHANNELS, NUMSUB, NUMPAT, SHARDCHANNELS or SHARDNUMSUB.",
+            sub
+        )),
+    }
+}
 
-        // Spawn listener for published messages
-        let writer_clone = Arc::clone(&writer);
-        tokio::spawn(async move {
-            while let Some(msg) = rx.recv().await {
-                let mut w = writer_clone.lock().await;
-                println!("Received message: {} on channel: {}", msg, channel);
-                let msg_type = "message";
-
-                let response = format!(
-                    "*3\r\n${}\r\n{}\r\n${}\r\n{}\r\n${}\r\n{}\r\n",
-                    msg_type.len(),
-                    msg_type,
-                    channel.len(),
-                    channel,
-                    msg.len(),
-                    msg
-                );
-
-                if let Err(e) = w.write_all(response.as_bytes()).await {
-                    eprintln!("Failed to write message: {:?}", e);
-                    break;
-                }
-                if let Err(e) = w.flush().await {
-                    eprintln!("Failed to flush: {:?}", e);
-                    break;
-                }
-            }
-        });
+/// A flat `[channel, count, ...]` array.
+fn numsub_reply(names: &[Vec<u8>], counts: Vec<usize>) -> Vec<u8> {
+    let mut response = format!("*{}\r\n", names.len() * 2).into_bytes();
+    for (name, count) in names.iter().zip(counts) {
+        response.extend(bulk_string(name));
+        response.extend(integer(count as i64));
     }
+    response
+}
+
+fn same_slot(channels: &[Vec<u8>]) -> bool {
+    channels
+        .windows(2)
+        .all(|pair| key_hash_slot(&pair[0]) == key_hash_slot(&pair[1]))
+}
+
+/// A `[kind, name, count]` (un)subscribe reply; `name` is nil when there was
+/// nothing to unsubscribe from.
+fn confirmation(kind: &[u8], name: Option<&[u8]>, count: usize) -> Vec<u8> {
+    let mut frame = b"*3\r\n".to_vec();
+    frame.extend(bulk_string(kind));
+    frame.extend(name.map_or_else(null_bulk, bulk_string));
+    frame.extend(integer(count as i64));
+    frame
+}
+
+// Replies to the (un)subscribe commands are written as they are produced,
+// one per name, in line with the messages the connection is receiving.
+async fn reply(session: &PubSubSession, frame: &[u8]) {
+    let _ = session.write(frame).await;
 }
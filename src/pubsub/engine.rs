@@ -1,56 +1,341 @@
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::{
-    mpsc::{self, unbounded_channel, UnboundedSender},
-    Mutex,
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
 };
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
-type Subscriber = UnboundedSender<String>;
+use crate::{
+    config::OutputBufferLimit,
+    resp::utils::bulk_string,
+    utils::{glob_match, key_hash_slot},
+};
+
+use super::buffer::OutputBuffer;
+
+/// A connection's single queue of ready-to-write frames, with its output
+/// buffer. Every subscription of the connection shares it, so frames reach
+/// the client in the order they were published. The queue itself is
+/// unbounded: what it may hold is capped in bytes by the output buffer.
+#[derive(Debug, Clone)]
+pub struct Subscriber {
+    buffer: Arc<OutputBuffer>,
+    queue: UnboundedSender<Vec<u8>>,
+}
+
+impl Subscriber {
+    pub fn buffer(&self) -> &Arc<OutputBuffer> {
+        &self.buffer
+    }
+
+    /// Queues `frame`, returning `false` if the connection is gone or has
+    /// just been evicted for falling too far behind.
+    pub fn send(&self, frame: &[u8]) -> bool {
+        if self.queue.is_closed() || !self.buffer.reserve(frame.len()) {
+            return false;
+        }
+        if self.queue.send(frame.to_vec()).is_err() {
+            self.buffer.release(frame.len());
+            return false;
+        }
+        true
+    }
+
+    fn is_live(&self) -> bool {
+        !self.queue.is_closed() && !self.buffer.is_evicted()
+    }
+}
+
+/// Channel or pattern name to its subscribers.
+type Channels = HashMap<Vec<u8>, Vec<Subscriber>>;
 
+#[derive(Debug, Default)]
+struct Registry {
+    channels: Channels,
+    patterns: Channels,
+    /// Shard channels, grouped by the hash slot that owns them.
+    shards: HashMap<u16, Channels>,
+}
+
+/// Who is subscribed to what. One lock covers all of it, so a publish
+/// reaches channel and pattern subscribers before the next one starts; it
+/// is never held across an await.
 #[derive(Debug, Default)]
 pub struct PubSub {
-    channels: Mutex<HashMap<String, Vec<Subscriber>>>,
+    registry: Mutex<Registry>,
+    limit: OutputBufferLimit,
+    next_id: AtomicU64,
 }
 
 impl PubSub {
-    pub fn new() -> Arc<Self> {
-        Arc::new(Self::default())
+    pub fn new(limit: OutputBufferLimit) -> Arc<Self> {
+        Arc::new(PubSub {
+            limit,
+            ..Self::default()
+        })
+    }
+
+    /// A fresh queue and output buffer for a connection, and the receiving
+    /// end its writer task reads from.
+    pub fn subscriber(&self) -> (Subscriber, UnboundedReceiver<Vec<u8>>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (queue, rx) = mpsc::unbounded_channel();
+        let buffer = Arc::new(OutputBuffer::new(id, self.limit));
+        (Subscriber { buffer, queue }, rx)
+    }
+
+    pub fn subscribe(&self, subscriber: &Subscriber, channel: &[u8]) {
+        let mut registry = self.registry.lock().unwrap();
+        add(&mut registry.channels, subscriber, channel);
+    }
+
+    pub fn unsubscribe(&self, id: u64, channel: &[u8]) {
+        remove(&mut self.registry.lock().unwrap().channels, id, channel);
+    }
+
+    /// Subscribes a connection to every channel matching the glob `pattern`.
+    pub fn psubscribe(&self, subscriber: &Subscriber, pattern: &[u8]) {
+        let mut registry = self.registry.lock().unwrap();
+        add(&mut registry.patterns, subscriber, pattern);
+    }
+
+    pub fn punsubscribe(&self, id: u64, pattern: &[u8]) {
+        remove(&mut self.registry.lock().unwrap().patterns, id, pattern);
     }
 
-    pub async fn subscribe(&self, channel: &str) -> mpsc::UnboundedReceiver<String>
-        {
-            let (tx, rx) = unbounded_channel();
-            let mut channels = self.channels.lock().await;
-            channels.entry(channel.to_string()).or_default().push(tx);
-            rx
+    /// Subscribes a connection to a shard channel, which lives in the hash
+    /// slot of its name.
+    pub fn ssubscribe(&self, subscriber: &Subscriber, channel: &[u8]) {
+        let mut registry = self.registry.lock().unwrap();
+        let channels = registry.shards.entry(key_hash_slot(channel)).or_default();
+        add(channels, subscriber, channel);
+    }
+
+    pub fn sunsubscribe(&self, id: u64, channel: &[u8]) {
+        let slot = key_hash_slot(channel);
+        let mut registry = self.registry.lock().unwrap();
+        if let Some(channels) = registry.shards.get_mut(&slot) {
+            remove(channels, id, channel);
+            if channels.is_empty() {
+                registry.shards.remove(&slot);
+            }
         }
+    }
 
+    /// Active channels, optionally only those matching the glob `pattern`
+    /// (PUBSUB CHANNELS).
+    pub fn channels(&self, pattern: Option<&[u8]>) -> Vec<Vec<u8>> {
+        let mut registry = self.registry.lock().unwrap();
+        prune(&mut registry.channels);
+        registry
+            .channels
+            .keys()
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+            .cloned()
+            .collect()
+    }
 
-    pub async fn publish(&self, channel: &str, message: String) -> usize {
-        let mut channels = self.channels.lock().await;
-        let mut delivered = 0;
+    /// Subscriber counts for `names`, not counting pattern subscribers
+    /// (PUBSUB NUMSUB).
+    pub fn numsub(&self, names: &[Vec<u8>]) -> Vec<usize> {
+        let mut registry = self.registry.lock().unwrap();
+        prune(&mut registry.channels);
+        names
+            .iter()
+            .map(|name| registry.channels.get(name).map_or(0, Vec::len))
+            .collect()
+    }
+
+    /// How many distinct patterns are subscribed to (PUBSUB NUMPAT).
+    pub fn numpat(&self) -> usize {
+        let mut registry = self.registry.lock().unwrap();
+        prune(&mut registry.patterns);
+        registry.patterns.len()
+    }
+
+    /// Active shard channels, optionally only those matching the glob
+    /// `pattern` (PUBSUB SHARDCHANNELS).
+    pub fn shard_channels(&self, pattern: Option<&[u8]>) -> Vec<Vec<u8>> {
+        let mut registry = self.registry.lock().unwrap();
+        registry.shards.retain(|_, channels| {
+            prune(channels);
+            !channels.is_empty()
+        });
+        registry
+            .shards
+            .values()
+            .flat_map(HashMap::keys)
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+            .cloned()
+            .collect()
+    }
 
-        if let Some(subscribers) = channels.get_mut(channel) {
+    /// Subscriber counts for the shard channels `names` (PUBSUB SHARDNUMSUB).
+    pub fn shard_numsub(&self, names: &[Vec<u8>]) -> Vec<usize> {
+        let mut registry = self.registry.lock().unwrap();
+        names
+            .iter()
+            .map(|name| {
+                let Some(channels) = registry.shards.get_mut(&key_hash_slot(name)) else {
+                    return 0;
+                };
+                prune(channels);
+                channels.get(name).map_or(0, Vec::len)
+            })
+            .collect()
+    }
+
+    /// Delivers `message` to the subscribers of a shard channel, returning
+    /// how many received it. Pattern subscribers never see shard messages.
+    pub fn spublish(&self, channel: &[u8], message: &[u8]) -> usize {
+        let slot = key_hash_slot(channel);
+        let mut registry = self.registry.lock().unwrap();
+        let Some(channels) = registry.shards.get_mut(&slot) else {
+            return 0;
+        };
+
+        let delivered = deliver(channels, channel, &smessage_frame(channel, message));
+        if channels.is_empty() {
+            registry.shards.remove(&slot);
+        }
+        delivered
+    }
+
+    /// Delivers `message` to the channel's subscribers and to every pattern
+    /// subscriber whose pattern matches, returning how many received it.
+    pub fn publish(&self, channel: &[u8], message: &[u8]) -> usize {
+        let mut registry = self.registry.lock().unwrap();
+        let frame = message_frame(channel, message);
+        let mut delivered = deliver(&mut registry.channels, channel, &frame);
+
+        for (pattern, subscribers) in registry.patterns.iter_mut() {
+            if !glob_match(pattern, channel) {
+                continue;
+            }
+            let frame = pmessage_frame(pattern, channel, message);
             subscribers.retain(|subscriber| {
-                if subscriber.is_closed() {
-                    false
-                } else {
-                    match subscriber.send(message.clone()) {
-                        Ok(_) => {
-                            delivered += 1;
-                            true
-                        }
-                        Err(_) => false,
-                    }
-                }
+                let sent = subscriber.send(&frame);
+                delivered += sent as usize;
+                sent
             });
         }
+        registry.patterns.retain(|_, subscribers| !subscribers.is_empty());
+        delivered
+    }
+}
+
+/// Sends `frame` to the subscribers of `name`, dropping those that are gone.
+fn deliver(registry: &mut Channels, name: &[u8], frame: &[u8]) -> usize {
+    let Some(subscribers) = registry.get_mut(name) else {
+        return 0;
+    };
+    let mut delivered = 0;
+    subscribers.retain(|subscriber| {
+        let sent = subscriber.send(frame);
+        delivered += sent as usize;
+        sent
+    });
+    if subscribers.is_empty() {
+        registry.remove(name);
+    }
+    delivered
+}
 
-        println!(
-            "{{ \"channel\": \"{}\", \"message\": \"{}\", \"subscribers\": {} }}",
-            channel, message, delivered
+fn add(registry: &mut Channels, subscriber: &Subscriber, name: &[u8]) {
+    registry.entry(name.to_vec()).or_default().push(subscriber.clone());
+}
+
+fn remove(registry: &mut Channels, id: u64, name: &[u8]) {
+    if let Some(subscribers) = registry.get_mut(name) {
+        subscribers.retain(|subscriber| subscriber.buffer.id() != id);
+        if subscribers.is_empty() {
+            registry.remove(name);
+        }
+    }
+}
+
+fn message_frame(channel: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut frame = b"*3\r\n".to_vec();
+    frame.extend(bulk_string(b"message"));
+    frame.extend(bulk_string(channel));
+    frame.extend(bulk_string(message));
+    frame
+}
+
+fn smessage_frame(channel: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut frame = b"*3\r\n".to_vec();
+    frame.extend(bulk_string(b"smessage"));
+    frame.extend(bulk_string(channel));
+    frame.extend(bulk_string(message));
+    frame
+}
+
+fn pmessage_frame(pattern: &[u8], channel: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut frame = b"*4\r\n".to_vec();
+    frame.extend(bulk_string(b"pmessage"));
+    frame.extend(bulk_string(pattern));
+    frame.extend(bulk_string(channel));
+    frame.extend(bulk_string(message));
+    frame
+}
+
+/// Drops subscribers whose connection is gone or evicted, and names nobody
+/// is left on, so the introspection commands only count live subscribers.
+fn prune(registry: &mut Channels) {
+    registry.retain(|_, subscribers| {
+        subscribers.retain(Subscriber::is_live);
+        !subscribers.is_empty()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_connection_gets_its_messages_in_publish_order() {
+        let pubsub = PubSub::new(OutputBufferLimit::default());
+        let (subscriber, mut rx) = pubsub.subscriber();
+        pubsub.subscribe(&subscriber, b"news");
+        pubsub.psubscribe(&subscriber, b"n*");
+        pubsub.ssubscribe(&subscriber, b"shard");
+
+        assert_eq!(pubsub.publish(b"news", b"1"), 2);
+        assert_eq!(pubsub.publish(b"notes", b"2"), 1);
+        assert_eq!(pubsub.spublish(b"shard", b"3"), 1);
+        assert_eq!(pubsub.publish(b"other", b"4"), 0);
+
+        let frames: Vec<Vec<u8>> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert_eq!(
+            frames,
+            [
+                message_frame(b"news", b"1"),
+                pmessage_frame(b"n*", b"news", b"1"),
+                pmessage_frame(b"n*", b"notes", b"2"),
+                smessage_frame(b"shard", b"3"),
+            ]
         );
-        delivered
     }
 
-   
+    #[test]
+    fn unsubscribing_and_dropped_connections_leave_the_registry() {
+        let pubsub = PubSub::new(OutputBufferLimit::default());
+        let (first, _rx) = pubsub.subscriber();
+        let (second, rx) = pubsub.subscriber();
+        pubsub.subscribe(&first, b"news");
+        pubsub.subscribe(&second, b"news");
+        pubsub.psubscribe(&second, b"*");
+        assert_eq!(pubsub.numsub(&[b"news".to_vec()]), [2]);
+        assert_eq!(pubsub.numpat(), 1);
+
+        drop(rx);
+        assert_eq!(pubsub.numsub(&[b"news".to_vec()]), [1]);
+        assert_eq!(pubsub.numpat(), 0);
+
+        pubsub.unsubscribe(first.buffer().id(), b"news");
+        assert!(pubsub.channels(None).is_empty());
+        assert_eq!(pubsub.publish(b"news", b"hi"), 0);
+    }
 }
@@ -1,4 +1,7 @@
+mod buffer;
 mod engine;
 mod commands;
+mod session;
 pub use engine::PubSub;
-pub use commands::cmd;
\ No newline at end of file
+pub use commands::cmd;
+pub use session::{allowed_when_subscribed, PubSubSession};
\ No newline at end of file
@@ -0,0 +1,213 @@
+use std::{collections::HashSet, io, sync::Arc};
+use tokio::{
+    io::AsyncWriteExt,
+    net::tcp::OwnedWriteHalf,
+    sync::{mpsc::UnboundedReceiver, Mutex},
+    task::JoinHandle,
+};
+
+use super::{buffer::OutputBuffer, engine::Subscriber, PubSub};
+
+/// Commands a connection in subscriber mode may still run.
+pub fn allowed_when_subscribed(cmd: &str) -> bool {
+    matches!(
+        cmd,
+        "SUBSCRIBE"
+            | "PSUBSCRIBE"
+            | "SSUBSCRIBE"
+            | "UNSUBSCRIBE"
+            | "PUNSUBSCRIBE"
+            | "SUNSUBSCRIBE"
+            | "PING"
+            | "QUIT"
+            | "RESET"
+    )
+}
+
+/// One connection's side of pub/sub: where its messages are written and
+/// what it is subscribed to.
+///
+/// The first subscription starts a writer task that owns the socket's write
+/// half from then on. Published messages and the connection's own replies
+/// all go through its one queue, so the client reads them in the order they
+/// happened, whatever channel a message came from.
+pub struct PubSubSession {
+    pubsub: Arc<PubSub>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    subscriber: Subscriber,
+    /// The queue's receiving end, until the writer task takes it.
+    queue: Option<UnboundedReceiver<Vec<u8>>>,
+    delivery: Option<JoinHandle<()>>,
+    pub(super) channels: HashSet<Vec<u8>>,
+    pub(super) patterns: HashSet<Vec<u8>>,
+    pub(super) shard_channels: HashSet<Vec<u8>>,
+}
+
+impl PubSubSession {
+    pub fn new(pubsub: Arc<PubSub>, writer: Arc<Mutex<OwnedWriteHalf>>) -> Self {
+        let (subscriber, queue) = pubsub.subscriber();
+        PubSubSession {
+            pubsub,
+            writer,
+            subscriber,
+            queue: Some(queue),
+            delivery: None,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            shard_channels: HashSet::new(),
+        }
+    }
+
+    pub fn pubsub(&self) -> &Arc<PubSub> {
+        &self.pubsub
+    }
+
+    pub fn output_buffer(&self) -> &Arc<OutputBuffer> {
+        self.subscriber.buffer()
+    }
+
+    /// Waits until the connection is evicted for not keeping up with its
+    /// messages, after which it should be closed.
+    pub async fn evicted(&self) {
+        self.output_buffer().evicted().await
+    }
+
+    /// Sends a reply to the client. Once the connection has subscribed it is
+    /// queued behind the messages already published to it.
+    pub async fn write(&self, frame: &[u8]) -> io::Result<()> {
+        if self.delivery.is_some() {
+            if !self.subscriber.send(frame) {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            return Ok(());
+        }
+        let mut w = self.writer.lock().await;
+        w.write_all(frame).await?;
+        w.flush().await
+    }
+
+    /// Channels plus patterns, as reported in (un)subscribe replies.
+    pub fn subscription_count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// Shard channels, which SSUBSCRIBE and SUNSUBSCRIBE replies count on
+    /// their own.
+    pub fn shard_subscription_count(&self) -> usize {
+        self.shard_channels.len()
+    }
+
+    /// A connection with any subscription is in subscriber mode, where only
+    /// the pub/sub commands, PING, QUIT and RESET are accepted.
+    pub fn is_subscribed(&self) -> bool {
+        self.subscription_count() + self.shard_subscription_count() > 0
+    }
+
+    pub fn subscribe(&mut self, channel: &[u8]) {
+        if !self.channels.contains(channel) {
+            self.start_delivery();
+            self.pubsub.subscribe(&self.subscriber, channel);
+            self.channels.insert(channel.to_vec());
+        }
+    }
+
+    pub fn psubscribe(&mut self, pattern: &[u8]) {
+        if !self.patterns.contains(pattern) {
+            self.start_delivery();
+            self.pubsub.psubscribe(&self.subscriber, pattern);
+            self.patterns.insert(pattern.to_vec());
+        }
+    }
+
+    pub fn ssubscribe(&mut self, channel: &[u8]) {
+        if !self.shard_channels.contains(channel) {
+            self.start_delivery();
+            self.pubsub.ssubscribe(&self.subscriber, channel);
+            self.shard_channels.insert(channel.to_vec());
+        }
+    }
+
+    pub fn unsubscribe(&mut self, channel: &[u8]) {
+        if self.channels.remove(channel) {
+            self.pubsub.unsubscribe(self.output_buffer().id(), channel);
+        }
+    }
+
+    pub fn punsubscribe(&mut self, pattern: &[u8]) {
+        if self.patterns.remove(pattern) {
+            self.pubsub.punsubscribe(self.output_buffer().id(), pattern);
+        }
+    }
+
+    pub fn sunsubscribe(&mut self, channel: &[u8]) {
+        if self.shard_channels.remove(channel) {
+            self.pubsub.sunsubscribe(self.output_buffer().id(), channel);
+        }
+    }
+
+    /// Drops every subscription without replying for each (RESET, and a
+    /// closing connection). The writer task keeps running, so messages
+    /// already queued still arrive before the next reply.
+    pub fn unsubscribe_all(&mut self) {
+        let id = self.output_buffer().id();
+        for channel in self.channels.drain() {
+            self.pubsub.unsubscribe(id, &channel);
+        }
+        for pattern in self.patterns.drain() {
+            self.pubsub.punsubscribe(id, &pattern);
+        }
+        for channel in self.shard_channels.drain() {
+            self.pubsub.sunsubscribe(id, &channel);
+        }
+    }
+
+    /// Drops every subscription; called when the connection goes away. The
+    /// writer task is left to finish what is queued, unless the connection
+    /// was evicted: it isn't reading, so that could take for ever, and its
+    /// output buffer is being thrown away anyway.
+    pub fn close(mut self) {
+        self.unsubscribe_all();
+        if let Some(delivery) = self.delivery.take() {
+            if self.output_buffer().is_evicted() {
+                delivery.abort();
+            }
+        }
+    }
+
+    fn start_delivery(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            let writer = Arc::clone(&self.writer);
+            let buffer = Arc::clone(self.output_buffer());
+            self.delivery = Some(tokio::spawn(deliver(queue, writer, buffer)));
+        }
+    }
+}
+
+/// Writes the frames queued for a connection until every sender is gone or
+/// the socket fails, freeing their room in the output buffer as it goes, and
+/// the room of any left over once it ends.
+async fn deliver(
+    mut queue: UnboundedReceiver<Vec<u8>>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    buffer: Arc<OutputBuffer>,
+) {
+    let mut w = writer.lock().await;
+    while let Some(frame) = queue.recv().await {
+        let written = match w.write_all(&frame).await {
+            Ok(()) => w.flush().await,
+            Err(e) => Err(e),
+        };
+        buffer.release(frame.len());
+        if let Err(e) = written {
+            eprintln!("Failed to write message: {:?}", e);
+            break;
+        }
+    }
+
+    // Whatever is still queued won't be written; its bytes mustn't keep
+    // counting against the connection's limit.
+    queue.close();
+    while let Ok(frame) = queue.try_recv() {
+        buffer.release(frame.len());
+    }
+}
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use crate::{persistence::Persister, store::Database, utils::persist_if_mutating};
+
+use super::commands::{
+    list::{blmove, blpop},
+    stream::{xread, xreadgroup},
+};
+
+/// Returned by a blocking command that has nothing to reply with yet.
+///
+/// The connection parks itself on `keys` (without holding the database lock)
+/// and runs `retry` again once one of them is written to or the timeout
+/// elapses. `retry` is the original command with any "current position"
+/// arguments such as XREAD's `$` already resolved, so a retry only sees data
+/// that arrived after the first attempt.
+#[derive(Debug)]
+pub struct BlockRequest {
+    pub keys: Vec<Vec<u8>>,
+    /// `None` blocks forever.
+    pub timeout: Option<Duration>,
+    pub retry: Vec<Vec<u8>>,
+}
+
+pub fn is_blocking_command(cmd: &str) -> bool {
+    matches!(
+        cmd,
+        "XREAD" | "XREADGROUP" | "BLPOP" | "BRPOP" | "BLMOVE" | "BRPOPLPUSH"
+    )
+}
+
+/// Runs a blocking command once, reporting whether it has to wait.
+pub fn try_blocking_command(
+    cmd: &str,
+    parts: &[Vec<u8>],
+    db: &mut Database,
+) -> Result<Vec<u8>, BlockRequest> {
+    match cmd {
+        "XREAD" => xread(parts, db),
+        "XREADGROUP" => xreadgroup(parts, db),
+        "BLPOP" | "BRPOP" => blpop(cmd, parts, db),
+        "BLMOVE" | "BRPOPLPUSH" => blmove(cmd, parts, db),
+        _ => Ok(format!("-ERR unknown command '{}'\r\n", cmd).into_bytes()),
+    }
+}
+
+/// Serves clients parked on keys that were just written to, longest-waiting
+/// first, by running their commands for them. Called before the writer
+/// releases the database lock, so what it wrote goes to them rather than to
+/// whichever command takes the lock next. A client its command still can't
+/// serve stays parked in its place.
+pub fn serve_blocked(db: &mut Database, persister: &(dyn Persister + Send + Sync)) {
+    // Serving one client can make another key ready (BLMOVE's destination).
+    while let Some(key) = db.blocked_mut().take_ready() {
+        for id in db.blocked_mut().waiting_on(&key) {
+            let Some(parts) = db.blocked_ref().command(id).map(<[_]>::to_vec) else {
+                continue;
+            };
+            let cmd = String::from_utf8_lossy(&parts[0]).to_uppercase();
+            if let Ok(reply) = try_blocking_command(&cmd, &parts, db) {
+                persist_if_mutating(&cmd, &parts, db, persister);
+                db.blocked_mut().serve(id, reply);
+            }
+        }
+    }
+}
+
+/// Parses a BLOCK argument given in milliseconds; 0 means forever.
+pub fn parse_block_millis(arg: &[u8]) -> Result<Option<Duration>, &'static str> {
+    match crate::resp::utils::parse_arg::<i64>(arg) {
+        Some(ms) if ms < 0 => Err("ERR timeout is negative"),
+        Some(0) => Ok(None),
+        Some(ms) => Ok(Some(Duration::from_millis(ms as u64))),
+        None => Err("ERR timeout is not an integer or out of range"),
+    }
+}
+
+/// Parses a timeout given in (possibly fractional) seconds; 0 means forever.
+pub fn parse_block_seconds(arg: &[u8]) -> Result<Option<Duration>, &'static str> {
+    match crate::resp::utils::parse_float(arg) {
+        Some(secs) if secs < 0.0 => Err("ERR timeout is negative"),
+        Some(0.0) => Ok(None),
+        Some(secs) => Duration::try_from_secs_f64(secs)
+            .map(Some)
+            .map_err(|_| "ERR timeout is out of range"),
+        None => Err("ERR timeout is not a float or out of range"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::oneshot::{error::TryRecvError, Receiver};
+
+    use super::*;
+    use crate::{persistence::JsonPersister, resp::handler::execute};
+
+    fn command(args: &[&str]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    /// Runs a command the way a connection does, serving parked clients
+    /// before the lock would be released.
+    fn run(db: &mut Database, args: &[&str]) -> Vec<u8> {
+        // Only snapshots are kept, so recording writes touches no file.
+        let persister = JsonPersister::new("unused.json");
+        let parts = command(args);
+        let reply = execute(args[0], &parts, db);
+        persist_if_mutating(args[0], &parts, db, &persister);
+        serve_blocked(db, &persister);
+        reply
+    }
+
+    /// Tries a blocking command that has to wait, and parks it.
+    fn park(db: &mut Database, args: &[&str]) -> (u64, Receiver<Vec<u8>>) {
+        let req = try_blocking_command(args[0], &command(args), db).unwrap_err();
+        db.blocked_mut().register(req.keys, req.retry)
+    }
+
+    fn popped(key: &str, value: &str) -> Vec<u8> {
+        format!("*2\r\n${}\r\n{key}\r\n${}\r\n{value}\r\n", key.len(), value.len()).into_bytes()
+    }
+
+    #[test]
+    fn pushed_elements_go_to_parked_clients_in_order() {
+        let mut db = Database::new();
+        let (_, mut first) = park(&mut db, &["BLPOP", "l", "0"]);
+        let (_, mut second) = park(&mut db, &["BLPOP", "l", "0"]);
+
+        // The LPOP right behind the push finds the element already handed
+        // to the longest-waiting client.
+        run(&mut db, &["RPUSH", "l", "a"]);
+        assert_eq!(run(&mut db, &["LPOP", "l"]), b"$-1\r\n");
+        assert_eq!(first.try_recv().unwrap(), popped("l", "a"));
+        assert_eq!(second.try_recv(), Err(TryRecvError::Empty));
+
+        run(&mut db, &["RPUSH", "l", "b", "c"]);
+        assert_eq!(second.try_recv().unwrap(), popped("l", "b"));
+        assert_eq!(run(&mut db, &["LPOP", "l"]), b"$1\r\nc\r\n");
+        assert!(db.store_ref().is_empty());
+    }
+
+    #[test]
+    fn clients_that_left_are_skipped() {
+        let mut db = Database::new();
+        let (timed_out, _kept) = park(&mut db, &["BLPOP", "l", "0"]);
+        let (_, gone) = park(&mut db, &["BLPOP", "l", "0"]);
+        let (_, mut waiting) = park(&mut db, &["BLPOP", "other", "l", "0"]);
+
+        assert!(db.blocked_mut().leave(timed_out));
+        drop(gone);
+        run(&mut db, &["RPUSH", "l", "a"]);
+        assert_eq!(waiting.try_recv().unwrap(), popped("l", "a"));
+        // Served, so there is nothing left to leave.
+        assert!(!db.blocked_mut().leave(timed_out));
+    }
+
+    #[test]
+    fn serving_a_move_serves_its_destination_too() {
+        let mut db = Database::new();
+        let (_, mut mover) = park(&mut db, &["BLMOVE", "src", "dst", "LEFT", "RIGHT", "0"]);
+        let (_, mut popper) = park(&mut db, &["BLPOP", "dst", "0"]);
+
+        run(&mut db, &["RPUSH", "src", "x"]);
+        assert_eq!(mover.try_recv().unwrap(), b"$1\r\nx\r\n");
+        assert_eq!(popper.try_recv().unwrap(), popped("dst", "x"));
+        assert!(db.store_ref().is_empty());
+    }
+}
@@ -1,36 +1,152 @@
-use crate::{resp::utils::{bulk_string, format_array, wrong_args}, store::Database};
+use crate::{
+    resp::utils::{
+        bulk_string, error, format_array, integer, null_bulk, parse_arg, parse_float,
+        simple_string, wrong_args,
+    },
+    store::{commands::hash_set::ExpireCondition, Database},
+    utils::{current_unix_millis, glob_match},
+};
 
-pub fn handle_hash_set(cmd: &str, parts: &[String], db: &mut Database) -> String {
+pub fn handle_hash_set(cmd: &str, parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
     match cmd {
-       
-        "HSET" => {
+
+        "HSET" | "HMSET" => {
             if let Some(key) = parts.get(1) {
-                if parts.len() < 4 || parts.len() % 2 != 0 {
-                    return wrong_args("HSET");
+                if parts.len() < 4 || !parts.len().is_multiple_of(2) {
+                    return wrong_args(cmd);
                 }
 
                 let mut inserted = 0;
                 let mut i = 2;
                 while i < parts.len() {
                     if let (Some(field), Some(value)) = (parts.get(i), parts.get(i + 1)) {
-                        inserted += db.hset(key, field, value);
+                        match db.hset(key, field, value) {
+                            Ok(n) => inserted += n,
+                            Err(e) => return error(e),
+                        }
                         i += 2;
                     } else {
-                        return wrong_args("HSET");
+                        return wrong_args(cmd);
                     }
                 }
 
-                format!(":{}\r\n", inserted)
+                if cmd == "HMSET" {
+                    simple_string("OK")
+                } else {
+                    integer(inserted as i64)
+                }
             } else {
-                wrong_args("HSET")
+                wrong_args(cmd)
+            }
+        }
+
+        "HSETNX" => {
+            let [_, key, field, value] = parts else {
+                return wrong_args("HSETNX");
+            };
+            match db.hsetnx(key, field, value) {
+                Ok(set) => integer(set as i64),
+                Err(e) => error(e),
+            }
+        }
+
+        "HMGET" => {
+            let (Some(key), Some(fields)) = (parts.get(1), parts.get(2..).filter(|f| !f.is_empty()))
+            else {
+                return wrong_args("HMGET");
+            };
+            match db.hmget(key, fields) {
+                Ok(values) => {
+                    let mut reply = format!("*{}\r\n", values.len()).into_bytes();
+                    for value in values {
+                        reply.extend(value.map_or_else(null_bulk, |v| bulk_string(&v)));
+                    }
+                    reply
+                }
+                Err(e) => error(e),
+            }
+        }
+
+        "HINCRBY" => {
+            let [_, key, field, by] = parts else {
+                return wrong_args("HINCRBY");
+            };
+            let Some(by) = parse_arg::<i64>(by) else {
+                return error("ERR value is not an integer or out of range");
+            };
+            match db.hincrby(key, field, by) {
+                Ok(value) => integer(value),
+                Err(e) => error(e),
+            }
+        }
+
+        "HINCRBYFLOAT" => {
+            let [_, key, field, by] = parts else {
+                return wrong_args("HINCRBYFLOAT");
+            };
+            let Some(by) = parse_float(by) else {
+                return error("ERR value is not a valid float");
+            };
+            match db.hincrbyfloat(key, field, by) {
+                Ok(value) => bulk_string(value.to_string().as_bytes()),
+                Err(e) => error(e),
+            }
+        }
+
+        "HSTRLEN" => {
+            let [_, key, field] = parts else {
+                return wrong_args("HSTRLEN");
+            };
+            match db.hstrlen(key, field) {
+                Ok(len) => integer(len as i64),
+                Err(e) => error(e),
+            }
+        }
+
+        "HRANDFIELD" => hrandfield(parts, db),
+        "HSCAN" => hscan(parts, db),
+        "HEXPIRE" | "HPEXPIRE" | "HEXPIREAT" | "HPEXPIREAT" => hexpire(cmd, parts, db),
+
+        "HTTL" | "HPTTL" => {
+            let Some(key) = parts.get(1) else {
+                return wrong_args(cmd);
+            };
+            let fields = match parse_fields(parts, 2) {
+                Ok(fields) => fields,
+                Err(reply) => return reply,
+            };
+            match db.hpttl(key, fields) {
+                // Whole seconds, rounded up like HTTL in Redis.
+                Ok(ttls) if cmd == "HTTL" => integer_array(
+                    ttls.into_iter()
+                        .map(|ms| if ms > 0 { (ms + 999) / 1000 } else { ms })
+                        .collect(),
+                ),
+                Ok(ttls) => integer_array(ttls),
+                Err(e) => error(e),
+            }
+        }
+
+        "HPERSIST" => {
+            let Some(key) = parts.get(1) else {
+                return wrong_args("HPERSIST");
+            };
+            let fields = match parse_fields(parts, 2) {
+                Ok(fields) => fields,
+                Err(reply) => return reply,
+            };
+            match db.hpersist(key, fields) {
+                Ok(replies) => integer_array(replies),
+                Err(e) => error(e),
             }
         }
 
         "HGET" => {
             if let (Some(key), Some(field)) = (parts.get(1), parts.get(2)) {
                 match db.hget(key, field) {
-                    Some(val) => bulk_string(&val),
-                    None => "$-1\r\n".to_string(),
+                    Ok(Some(val)) => bulk_string(&val),
+                    Ok(None) => null_bulk(),
+                    Err(e) => error(e),
                 }
             } else {
                 wrong_args("HGET")
@@ -39,12 +155,14 @@ pub fn handle_hash_set(cmd: &str, parts: &[String], db: &mut Database) -> String
 
         "HDEL" => {
             if let Some(key) = parts.get(1) {
-                let fields: Vec<String> = parts.iter().skip(2).cloned().collect();
+                let fields = &parts[2..];
                 if fields.is_empty() {
                     wrong_args("HDEL")
                 } else {
-                    let removed = db.hdel(key, &fields);
-                    format!(":{}\r\n", removed)
+                    match db.hdel(key, fields) {
+                        Ok(removed) => integer(removed as i64),
+                        Err(e) => error(e),
+                    }
                 }
             } else {
                 wrong_args("HDEL")
@@ -53,7 +171,10 @@ pub fn handle_hash_set(cmd: &str, parts: &[String], db: &mut Database) -> String
 
         "HKEYS" => {
             if let Some(key) = parts.get(1) {
-                format_array(db.hkeys(key))
+                match db.hkeys(key) {
+                    Ok(values) => format_array(values),
+                    Err(e) => error(e),
+                }
             } else {
                 wrong_args("HKEYS")
             }
@@ -61,7 +182,10 @@ pub fn handle_hash_set(cmd: &str, parts: &[String], db: &mut Database) -> String
 
         "HVALS" => {
             if let Some(key) = parts.get(1) {
-                format_array(db.hvals(key))
+                match db.hvals(key) {
+                    Ok(values) => format_array(values),
+                    Err(e) => error(e),
+                }
             } else {
                 wrong_args("HVALS")
             }
@@ -69,8 +193,10 @@ pub fn handle_hash_set(cmd: &str, parts: &[String], db: &mut Database) -> String
 
         "HLEN" => {
             if let Some(key) = parts.get(1) {
-                let len = db.hlen(key);
-                format!(":{}\r\n", len)
+                match db.hlen(key) {
+                    Ok(len) => integer(len as i64),
+                    Err(e) => error(e),
+                }
             } else {
                 wrong_args("HLEN")
             }
@@ -78,13 +204,16 @@ pub fn handle_hash_set(cmd: &str, parts: &[String], db: &mut Database) -> String
 
         "HGETALL" => {
             if let Some(key) = parts.get(1) {
-                let hash = db.hgetall(key);
-                let mut resp = format!("*{}\r\n", hash.len() * 2);
+                let hash = match db.hgetall(key) {
+                    Ok(hash) => hash,
+                    Err(e) => return error(e),
+                };
+                let mut flat = Vec::with_capacity(hash.len() * 2);
                 for (k, v) in hash {
-                    resp.push_str(&bulk_string(&k));
-                    resp.push_str(&bulk_string(&v));
+                    flat.push(k);
+                    flat.push(v);
                 }
-                resp
+                format_array(flat)
             } else {
                 wrong_args("HGETALL")
             }
@@ -92,16 +221,364 @@ pub fn handle_hash_set(cmd: &str, parts: &[String], db: &mut Database) -> String
 
         "HEXISTS" => {
             if let (Some(key), Some(field)) = (parts.get(1), parts.get(2)) {
-                let exists = db.hexists(key, field);
-                format!(":{}\r\n", if exists { 1 } else { 0 })
+                match db.hexists(key, field) {
+                    Ok(exists) => integer(if exists { 1 } else { 0 }),
+                    Err(e) => error(e),
+                }
             } else {
                 wrong_args("HEXISTS")
             }
         }
 
         _ => {
-            format!("-ERR unknown command '{}'\r\n", cmd)
+            error(format!("ERR unknown command '{}'", cmd))
         }
 
     }
-}
\ No newline at end of file
+}
+
+fn integer_array(values: Vec<i64>) -> Vec<u8> {
+    let mut reply = format!("*{}\r\n", values.len()).into_bytes();
+    for value in values {
+        reply.extend(integer(value));
+    }
+    reply
+}
+
+// HRANDFIELD key [count [WITHVALUES]]
+fn hrandfield(parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    let Some(key) = parts.get(1) else {
+        return wrong_args("HRANDFIELD");
+    };
+    let (count, with_values) = match &parts[2..] {
+        [] => (None, false),
+        [count] => (Some(count), false),
+        [count, opt] if opt.eq_ignore_ascii_case(b"WITHVALUES") => (Some(count), true),
+        _ => return error("ERR syntax error"),
+    };
+
+    // Without a count the reply is a single field.
+    let Some(count) = count else {
+        return match db.hrandfield(key, 1) {
+            Ok(mut picked) if !picked.is_empty() => bulk_string(&picked.remove(0).0),
+            Ok(_) => null_bulk(),
+            Err(e) => error(e),
+        };
+    };
+    let Some(count) = parse_arg::<i64>(count) else {
+        return error("ERR value is not an integer or out of range");
+    };
+    match db.hrandfield(key, count) {
+        Ok(picked) => {
+            let mut flat = Vec::with_capacity(picked.len() * 2);
+            for (field, value) in picked {
+                flat.push(field);
+                if with_values {
+                    flat.push(value);
+                }
+            }
+            format_array(flat)
+        }
+        Err(e) => error(e),
+    }
+}
+
+// HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES]
+fn hscan(parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    let (Some(key), Some(cursor)) = (parts.get(1), parts.get(2)) else {
+        return wrong_args("HSCAN");
+    };
+    let Some(cursor) = parse_arg::<u64>(cursor) else {
+        return error("ERR invalid cursor");
+    };
+
+    let mut pattern: Option<&[u8]> = None;
+    let mut count = 10;
+    let mut with_values = true;
+    let mut i = 3;
+    while i < parts.len() {
+        match parts[i].to_ascii_uppercase().as_slice() {
+            b"MATCH" if i + 1 < parts.len() => {
+                pattern = Some(&parts[i + 1]);
+                i += 2;
+            }
+            b"COUNT" if i + 1 < parts.len() => match parse_arg::<i64>(&parts[i + 1]) {
+                Some(n) if n >= 1 => {
+                    count = n as usize;
+                    i += 2;
+                }
+                Some(_) => return error("ERR syntax error"),
+                None => return error("ERR value is not an integer or out of range"),
+            },
+            b"NOVALUES" => {
+                with_values = false;
+                i += 1;
+            }
+            _ => return error("ERR syntax error"),
+        }
+    }
+
+    let (next, entries) = match db.hscan(key, cursor, count) {
+        Ok(step) => step,
+        Err(e) => return error(e),
+    };
+    // MATCH filters what the step returned; it doesn't change how far it got.
+    let mut flat = Vec::new();
+    for (field, value) in entries {
+        if pattern.is_some_and(|pattern| !glob_match(pattern, &field)) {
+            continue;
+        }
+        flat.push(field);
+        if with_values {
+            flat.push(value);
+        }
+    }
+    let mut reply = b"*2\r\n".to_vec();
+    reply.extend(bulk_string(next.to_string().as_bytes()));
+    reply.extend(format_array(flat));
+    reply
+}
+
+// HEXPIRE / HPEXPIRE / HEXPIREAT / HPEXPIREAT key time [NX|XX|GT|LT] FIELDS numfields field [field ...]
+fn hexpire(cmd: &str, parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    let (Some(key), Some(time)) = (parts.get(1), parts.get(2)) else {
+        return wrong_args(cmd);
+    };
+    let Some(time) = parse_arg::<i64>(time) else {
+        return error("ERR value is not an integer or out of range");
+    };
+    let invalid = || error(format!("ERR invalid expire time in '{}' command", cmd.to_lowercase()));
+    if time < 0 {
+        return invalid();
+    }
+    let time = time as u64;
+    let at = match cmd {
+        "HEXPIRE" => time.checked_mul(1000).and_then(|ms| ms.checked_add(current_unix_millis())),
+        "HPEXPIRE" => time.checked_add(current_unix_millis()),
+        "HEXPIREAT" => time.checked_mul(1000),
+        _ => Some(time),
+    };
+    let Some(at) = at.filter(|at| *at <= i64::MAX as u64) else {
+        return invalid();
+    };
+
+    let condition = match parts.get(3).map(|arg| arg.to_ascii_uppercase()).as_deref() {
+        Some(b"NX") => Some(ExpireCondition::Nx),
+        Some(b"XX") => Some(ExpireCondition::Xx),
+        Some(b"GT") => Some(ExpireCondition::Gt),
+        Some(b"LT") => Some(ExpireCondition::Lt),
+        _ => None,
+    };
+    let fields = match parse_fields(parts, if condition.is_some() { 4 } else { 3 }) {
+        Ok(fields) => fields,
+        Err(reply) => return reply,
+    };
+    match db.hexpire(key, at, condition, fields) {
+        Ok(replies) => integer_array(replies),
+        Err(e) => error(e),
+    }
+}
+
+/// Parses `FIELDS numfields field [field ...]` starting at `parts[i]`, which
+/// must run to the end of the command.
+fn parse_fields(parts: &[Vec<u8>], i: usize) -> Result<&[Vec<u8>], Vec<u8>> {
+    if !parts.get(i).is_some_and(|arg| arg.eq_ignore_ascii_case(b"FIELDS")) {
+        return Err(error(
+            "ERR Mandatory argument FIELDS is missing or not at the right position",
+        ));
+    }
+    let numfields = match parts.get(i + 1).and_then(|n| parse_arg::<i64>(n)) {
+        Some(n) if n > 0 => n as usize,
+        _ => return Err(error("ERR Parameter `numFields` should be greater than 0")),
+    };
+    let fields = &parts[(i + 2).min(parts.len())..];
+    if fields.len() != numfields {
+        return Err(error(
+            "ERR The `numfields` parameter must match the number of arguments",
+        ));
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, fs, process};
+
+    use super::*;
+    use crate::{
+        config::FsyncPolicy,
+        persistence::{persister::Command, AofPersister, AutoRewrite, Persister},
+        store::commands::hash_set::{
+            FIELD_CONDITION_NOT_MET, FIELD_DELETED, FIELD_EXPIRE_SET, FIELD_NO_TTL, NO_SUCH_FIELD,
+        },
+        types::RedisValue,
+    };
+
+    fn run(db: &mut Database, args: &[&str]) -> Vec<u8> {
+        let parts: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        handle_hash_set(&args[0].to_uppercase(), &parts, db)
+    }
+
+    fn hpexpire(db: &mut Database, ms: u64, condition: Option<&str>, field: &str) -> Vec<u8> {
+        let ms = ms.to_string();
+        let mut args = vec!["HPEXPIRE", "h", &ms];
+        args.extend(condition);
+        args.extend(["FIELDS", "1", field]);
+        run(db, &args)
+    }
+
+    fn ttl(db: &mut Database, field: &str) -> i64 {
+        db.hpttl(b"h", &[field.as_bytes().to_vec()]).unwrap()[0]
+    }
+
+    /// Moves the TTL of `field` into the past, as if it had run out.
+    fn lapse(db: &mut Database, field: &str) {
+        let Some(RedisValue::Hash(hash)) = db.store_mut().get_mut(&b"h"[..]) else {
+            panic!("no hash");
+        };
+        hash.set_expire_at(field.as_bytes(), 1);
+    }
+
+    #[test]
+    fn hexpire_conditions() {
+        let mut db = Database::new();
+        run(&mut db, &["HSET", "h", "f", "v"]);
+        let replied = |code| integer_array(vec![code]);
+
+        // A field without a TTL counts as never expiring.
+        assert_eq!(hpexpire(&mut db, 50_000, Some("XX"), "f"), replied(FIELD_CONDITION_NOT_MET));
+        assert_eq!(hpexpire(&mut db, 50_000, Some("GT"), "f"), replied(FIELD_CONDITION_NOT_MET));
+        assert_eq!(hpexpire(&mut db, 90_000, Some("LT"), "f"), replied(FIELD_EXPIRE_SET));
+        run(&mut db, &["HPERSIST", "h", "FIELDS", "1", "f"]);
+        assert_eq!(hpexpire(&mut db, 50_000, Some("NX"), "f"), replied(FIELD_EXPIRE_SET));
+        assert!((49_000..=50_000).contains(&ttl(&mut db, "f")));
+
+        assert_eq!(hpexpire(&mut db, 90_000, Some("NX"), "f"), replied(FIELD_CONDITION_NOT_MET));
+        assert_eq!(hpexpire(&mut db, 40_000, Some("GT"), "f"), replied(FIELD_CONDITION_NOT_MET));
+        assert_eq!(hpexpire(&mut db, 90_000, Some("GT"), "f"), replied(FIELD_EXPIRE_SET));
+        assert_eq!(hpexpire(&mut db, 95_000, Some("LT"), "f"), replied(FIELD_CONDITION_NOT_MET));
+        assert_eq!(hpexpire(&mut db, 70_000, Some("LT"), "f"), replied(FIELD_EXPIRE_SET));
+        assert_eq!(hpexpire(&mut db, 60_000, Some("XX"), "f"), replied(FIELD_EXPIRE_SET));
+        assert!((59_000..=60_000).contains(&ttl(&mut db, "f")));
+
+        assert_eq!(hpexpire(&mut db, 1_000, None, "nope"), replied(NO_SUCH_FIELD));
+        assert_eq!(hpexpire(&mut db, 0, None, "f"), replied(FIELD_DELETED));
+        assert!(!db.store_ref().contains_key(&b"h"[..]));
+        assert_eq!(hpexpire(&mut db, 1_000, None, "f"), replied(NO_SUCH_FIELD));
+    }
+
+    #[test]
+    fn hexpire_argument_errors() {
+        let mut db = Database::new();
+        run(&mut db, &["HSET", "h", "f", "v"]);
+        let misplaced = "ERR Mandatory argument FIELDS is missing or not at the right position";
+        let cases = [
+            (
+                &["HEXPIRE", "h", "-1", "FIELDS", "1", "f"][..],
+                "ERR invalid expire time in 'hexpire' command",
+            ),
+            (&["HEXPIRE", "h", "10", "NX", "XX", "FIELDS", "1", "f"], misplaced),
+            (
+                &["HEXPIRE", "h", "10", "FIELDS", "0"],
+                "ERR Parameter `numFields` should be greater than 0",
+            ),
+            (
+                &["HEXPIRE", "h", "10", "FIELDS", "2", "f"],
+                "ERR The `numfields` parameter must match the number of arguments",
+            ),
+            (&["HPERSIST", "h", "f"], misplaced),
+        ];
+        for (args, message) in cases {
+            assert_eq!(run(&mut db, args), error(message), "{args:?}");
+        }
+        assert_eq!(ttl(&mut db, "f"), FIELD_NO_TTL);
+    }
+
+    #[test]
+    fn hpersist_and_writes_drop_field_ttls() {
+        let mut db = Database::new();
+        run(&mut db, &["HSET", "h", "f", "v", "g", "w", "n", "1"]);
+        run(&mut db, &["HPEXPIRE", "h", "50000", "FIELDS", "3", "f", "g", "n"]);
+        assert_eq!(
+            run(&mut db, &["HPERSIST", "h", "FIELDS", "3", "f", "nope", "f"]),
+            integer_array(vec![1, NO_SUCH_FIELD, FIELD_NO_TTL])
+        );
+        assert_eq!(
+            run(&mut db, &["HPERSIST", "nope", "FIELDS", "1", "f"]),
+            integer_array(vec![NO_SUCH_FIELD])
+        );
+
+        // Overwriting a field drops its TTL; incrementing it keeps it.
+        run(&mut db, &["HSET", "h", "g", "x"]);
+        assert_eq!(ttl(&mut db, "g"), FIELD_NO_TTL);
+        run(&mut db, &["HINCRBY", "h", "n", "1"]);
+        assert!(ttl(&mut db, "n") > 0);
+        assert_eq!(
+            run(&mut db, &["HTTL", "h", "FIELDS", "2", "n", "f"]),
+            integer_array(vec![50, FIELD_NO_TTL])
+        );
+    }
+
+    #[test]
+    fn expired_fields_are_removed_by_the_worker_and_logged_as_hdel() {
+        let path = std::env::temp_dir().join(format!("hash-test-{}.aof", process::id()));
+        let _ = fs::remove_file(&path);
+        let no_rewrite = AutoRewrite { percentage: 0, min_size: 0 };
+        let aof = AofPersister::new(path.to_str().unwrap(), FsyncPolicy::No, no_rewrite).unwrap();
+        let mut db = Database::new();
+        run(&mut db, &["HSET", "h", "f", "v", "g", "w", "k", "x"]);
+        run(&mut db, &["HPEXPIRE", "h", "50000", "FIELDS", "2", "f", "g"]);
+
+        lapse(&mut db, "f");
+        db.remove_expired_fields(&aof);
+        assert_eq!(run(&mut db, &["HGET", "h", "f"]), null_bulk());
+        assert_eq!(run(&mut db, &["HLEN", "h"]), integer(2));
+        let hdel = |field: &str| -> Command { vec![b"HDEL".to_vec(), b"h".to_vec(), field.into()] };
+        assert_eq!(aof.replay().unwrap(), [hdel("f")]);
+
+        // Nothing is due now, so nothing more is logged.
+        db.remove_expired_fields(&aof);
+        assert_eq!(aof.replay().unwrap().len(), 1);
+
+        run(&mut db, &["HDEL", "h", "k"]);
+        lapse(&mut db, "g");
+        db.remove_expired_fields(&aof);
+        assert!(!db.store_ref().contains_key(&b"h"[..]));
+        assert_eq!(aof.replay().unwrap(), [hdel("f"), hdel("g")]);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn hscan_returns_fields_present_throughout_exactly_once() {
+        let mut db = Database::new();
+        for i in 0..200 {
+            let field = format!("f{i}");
+            run(&mut db, &["HSET", "h", &field, "v"]);
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = "0".to_string();
+        let mut step = 0;
+        loop {
+            let reply = run(&mut db, &["HSCAN", "h", &cursor, "COUNT", "7", "NOVALUES"]);
+            let reply = String::from_utf8(reply).unwrap();
+            let lines: Vec<&str> = reply.split("\r\n").collect();
+            cursor = lines[2].to_string();
+            seen.extend(lines[5..].iter().step_by(2).map(|field| field.to_string()));
+
+            // Change the hash under the scan: drop old fields, add new ones.
+            step += 1;
+            run(&mut db, &["HDEL", "h", &format!("f{}", 150 + step)]);
+            run(&mut db, &["HSET", "h", &format!("new{step}"), "v"]);
+            if cursor == "0" {
+                break;
+            }
+        }
+
+        let unique: HashSet<&String> = seen.iter().collect();
+        assert_eq!(unique.len(), seen.len(), "a field came back twice");
+        for i in (0..=150).chain(151 + step..200) {
+            assert!(unique.contains(&format!("f{i}")), "f{i} was never returned");
+        }
+    }
+}
@@ -1,56 +1,113 @@
-use crate::{resp::utils::{bulk_string, wrong_args}, store::Database};
+use crate::{
+    resp::utils::{error, format_array, integer, parse_arg, simple_string, wrong_args},
+    store::Database,
+    utils::current_unix_millis,
+};
 
-pub fn handle_key (cmd: &str, parts: &[String], db: &mut Database) -> String {
+pub fn handle_key (cmd: &str, parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
     match cmd{
         "EXISTS" => {
             if parts.len() < 2 {
                 wrong_args("EXISTS")
             } else {
                 let exists = db.exists(&parts[1..]);
-                format!(":{}\r\n", exists)
+                integer(exists as i64)
             }
         }
 
         "KEYS" => {
             let keys = db.keys();
-            let mut response = format!("*{}\r\n", keys.len());
-            for key in keys {
-                response.push_str(&bulk_string(&key));
-            }
-            response
+            format_array(keys)
         }
 
-        "EXPIRE" => {
-            if let (Some(key), Some(seconds_str)) = (parts.get(1), parts.get(2)) {
-                if let Ok(seconds) = seconds_str.parse::<u64>() {
-                    let result = db.expire(key, seconds);
-                    format!(":{}\r\n", result)
-                } else {
-                    "-ERR invalid seconds\r\n".to_string()
+        "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" => {
+            let (Some(key), Some(time)) = (parts.get(1), parts.get(2)) else {
+                return wrong_args(cmd);
+            };
+            let Some(time) = parse_arg::<u64>(time) else {
+                return match cmd {
+                    "EXPIRE" => error("ERR invalid seconds"),
+                    "PEXPIRE" => error("ERR invalid milliseconds"),
+                    _ => error("ERR invalid timestamp"),
+                };
+            };
+            // Key expiries are kept in Unix milliseconds.
+            let expire_at = match cmd {
+                "EXPIRE" => {
+                    time.checked_mul(1000).and_then(|ms| ms.checked_add(current_unix_millis()))
+                }
+                "PEXPIRE" => time.checked_add(current_unix_millis()),
+                "EXPIREAT" => time.checked_mul(1000),
+                _ => Some(time),
+            };
+            match expire_at {
+                Some(at) => integer(db.expire_at(key, at) as i64),
+                None => {
+                    error(format!("ERR invalid expire time in '{}' command", cmd.to_lowercase()))
                 }
-            } else {
-                wrong_args("EXPIRE")
             }
         }
-        "TTL" => {
+        "TTL" | "PTTL" => {
             if let Some(key) = parts.get(1) {
-                let ttl = db.ttl(key);
-                format!(":{}\r\n", ttl)
+                match db.pttl(key) {
+                    // Whole seconds, rounded to the nearest like TTL in Redis.
+                    ms if cmd == "TTL" && ms >= 0 => integer((ms + 500) / 1000),
+                    ttl => integer(ttl),
+                }
             } else {
-                wrong_args("TTL")
+                wrong_args(cmd)
+            }
+        }
+        "TYPE" => {
+            if let (Some(key), None) = (parts.get(1), parts.get(2)) {
+                simple_string(db.key_type(key))
+            } else {
+                wrong_args("TYPE")
             }
         }
         "PERSIST" => {
             if let Some(key) = parts.get(1) {
                 let result = db.persist(key);
-                format!(":{}\r\n", result)
+                integer(result as i64)
             } else {
                 wrong_args("PERSIST")
             }
         }
 
         _ => {
-            format!("-ERR unknown command '{}'\r\n", cmd)
+            error(format!("ERR unknown command '{}'", cmd))
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::commands::string::KeyExpiry;
+
+    fn run(db: &mut Database, args: &[&str]) -> Vec<u8> {
+        let parts: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        handle_key(&args[0].to_uppercase(), &parts, db)
+    }
+
+    #[test]
+    fn expiries_are_set_in_milliseconds() {
+        let mut db = Database::new();
+        db.set(b"k", b"v".to_vec(), KeyExpiry::Clear);
+        assert_eq!(run(&mut db, &["TTL", "k"]), integer(-1));
+        assert_eq!(run(&mut db, &["PTTL", "nope"]), integer(-2));
+
+        assert_eq!(run(&mut db, &["PEXPIRE", "k", "1500"]), integer(1));
+        let ttl = db.pttl(b"k");
+        assert!(ttl > 1000 && ttl <= 1500, "{ttl} ms left");
+
+        run(&mut db, &["EXPIREAT", "k", "4102444800"]);
+        assert_eq!(db.expiry_ref().get(&b"k"[..]), Some(&4_102_444_800_000));
+        run(&mut db, &["PEXPIREAT", "k", "4102444800789"]);
+        assert_eq!(db.expiry_ref().get(&b"k"[..]), Some(&4_102_444_800_789));
+
+        let invalid = error("ERR invalid expire time in 'expire' command");
+        assert_eq!(run(&mut db, &["EXPIRE", "k", "18446744073709551615"]), invalid);
+        assert_eq!(run(&mut db, &["PEXPIRE", "nope", "10"]), integer(0));
+    }
+}
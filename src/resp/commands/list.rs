@@ -1,54 +1,85 @@
-use crate::{resp::utils::{bulk_string, wrong_args}, store::Database};
+use crate::{
+    resp::{
+        blocking::{parse_block_seconds, BlockRequest},
+        utils::{
+            bulk_string, error, format_array, integer, null_bulk, parse_arg, simple_string,
+            wrong_args,
+        },
+    },
+    store::{commands::list::ListEnd, Database},
+};
 
-pub fn handle_list(cmd: &str, parts: &[String], db: &mut Database) -> String {
+const NULL_ARRAY: &[u8] = b"*-1\r\n";
+
+pub fn handle_list(cmd: &str, parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
     match cmd {
-       
-        "LPOP" => {
-            if let Some(key) = parts.get(1) {
-                if let Some(value) = db.lpop(key) {
-                    bulk_string(&value)
-                } else {
-                    "$-1\r\n".to_string()
-                }
-            } else {
-                wrong_args("LPOP")
-            }
-        }
 
-        "RPOP" => {
-            if let Some(key) = parts.get(1) {
-                if let Some(value) = db.rpop(key) {
-                    bulk_string(&value)
-                } else {
-                    "$-1\r\n".to_string()
-                }
-            } else {
-                wrong_args("RPOP")
+        "LPOP" | "RPOP" => {
+            let Some(key) = parts.get(1) else {
+                return wrong_args(cmd);
+            };
+            let end = if cmd == "LPOP" { ListEnd::Left } else { ListEnd::Right };
+            match parts.get(2..) {
+                // Without a count the reply is a single element.
+                Some([]) => match db.list_pop(key, end, 1) {
+                    Ok(Some(mut popped)) if !popped.is_empty() => bulk_string(&popped.remove(0)),
+                    Ok(_) => null_bulk(),
+                    Err(e) => error(e),
+                },
+                Some([count]) => match parse_arg::<i64>(count) {
+                    Some(count) if count >= 0 => match db.list_pop(key, end, count as usize) {
+                        Ok(Some(popped)) => format_array(popped),
+                        Ok(None) => NULL_ARRAY.to_vec(),
+                        Err(e) => error(e),
+                    },
+                    _ => error("ERR value is out of range, must be positive"),
+                },
+                _ => wrong_args(cmd),
             }
         }
 
+        // At least one element: an empty push would leave an empty list.
         "LPUSH" => {
-            if let Some(key) = parts.get(1) {
-                let len = db.lpush(key, &parts[2..]);
-                format!(":{}\r\n", len)
-            } else {
-                wrong_args("LPUSH")
+            if parts.len() < 3 {
+                return wrong_args("LPUSH");
+            }
+            match db.lpush(&parts[1], &parts[2..]) {
+                Ok(len) => integer(len as i64),
+                Err(e) => error(e),
             }
         }
 
         "RPUSH" => {
-            if let Some(key) = parts.get(1) {
-                let len = db.rpush(key, &parts[2..]);
-                format!(":{}\r\n", len)
+            if parts.len() < 3 {
+                return wrong_args("RPUSH");
+            }
+            match db.rpush(&parts[1], &parts[2..]) {
+                Ok(len) => integer(len as i64),
+                Err(e) => error(e),
+            }
+        }
+
+        "LPUSHX" | "RPUSHX" => {
+            if parts.len() < 3 {
+                return wrong_args(cmd);
+            }
+            let pushed = if cmd == "LPUSHX" {
+                db.lpushx(&parts[1], &parts[2..])
             } else {
-                wrong_args("RPUSH")
+                db.rpushx(&parts[1], &parts[2..])
+            };
+            match pushed {
+                Ok(len) => integer(len as i64),
+                Err(e) => error(e),
             }
         }
 
         "LLEN" => {
             if let Some(key) = parts.get(1) {
-                let len = db.llen(key);
-                format!(":{}\r\n", len)
+                match db.llen(key) {
+                    Ok(len) => integer(len as i64),
+                    Err(e) => error(e),
+                }
             } else {
                 wrong_args("LLEN")
             }
@@ -56,14 +87,14 @@ pub fn handle_list(cmd: &str, parts: &[String], db: &mut Database) -> String {
 
         "LINDEX" => {
             if let (Some(key), Some(index_str)) = (parts.get(1), parts.get(2)) {
-                if let Ok(index) = index_str.parse::<isize>() {
-                    if let Some(value) = db.lindex(key, index) {
-                        bulk_string(&value)
-                    } else {
-                        "$-1\r\n".to_string()
+                if let Some(index) = parse_arg::<isize>(index_str) {
+                    match db.lindex(key, index) {
+                        Ok(Some(value)) => bulk_string(&value),
+                        Ok(None) => null_bulk(),
+                        Err(e) => error(e),
                     }
                 } else {
-                    "-ERR invalid index\r\n".to_string()
+                    error("ERR invalid index")
                 }
             } else {
                 wrong_args("LINDEX")
@@ -74,33 +105,100 @@ pub fn handle_list(cmd: &str, parts: &[String], db: &mut Database) -> String {
             if let (Some(key), Some(index_str), Some(value)) =
                 (parts.get(1), parts.get(2), parts.get(3))
             {
-                if let Ok(index) = index_str.parse::<isize>() {
-                    match db.lset(key, index, value.to_string()) {
-                        Ok(()) => "+OK\r\n".to_string(),
-                        Err(e) => format!("-ERR {}\r\n", e),
+                if let Some(index) = parse_arg::<isize>(index_str) {
+                    match db.lset(key, index, value.to_vec()) {
+                        Ok(()) => simple_string("OK"),
+                        Err(e) => error(e),
                     }
                 } else {
-                    "-ERR invalid index\r\n".to_string()
+                    error("ERR invalid index")
                 }
             } else {
                 wrong_args("LSET")
             }
         }
 
+        "LINSERT" => {
+            let [_, key, position, pivot, value] = parts else {
+                return wrong_args("LINSERT");
+            };
+            let before = if position.eq_ignore_ascii_case(b"BEFORE") {
+                true
+            } else if position.eq_ignore_ascii_case(b"AFTER") {
+                false
+            } else {
+                return error("ERR syntax error");
+            };
+            match db.linsert(key, before, pivot, value) {
+                Ok(len) => integer(len),
+                Err(e) => error(e),
+            }
+        }
+
+        "LREM" => {
+            let [_, key, count, value] = parts else {
+                return wrong_args("LREM");
+            };
+            let Some(count) = parse_arg::<i64>(count) else {
+                return error("ERR value is not an integer or out of range");
+            };
+            match db.lrem(key, count, value) {
+                Ok(removed) => integer(removed as i64),
+                Err(e) => error(e),
+            }
+        }
+
+        "LTRIM" => {
+            let [_, key, start, end] = parts else {
+                return wrong_args("LTRIM");
+            };
+            let (Some(start), Some(end)) = (parse_arg::<isize>(start), parse_arg::<isize>(end))
+            else {
+                return error("ERR value is not an integer or out of range");
+            };
+            match db.ltrim(key, start, end) {
+                Ok(()) => simple_string("OK"),
+                Err(e) => error(e),
+            }
+        }
+
+        "LPOS" => lpos(parts, db),
+
+        "LMOVE" | "RPOPLPUSH" => {
+            let (from, to) = match (cmd, parts.len()) {
+                ("LMOVE", 5) => match (parse_list_end(&parts[3]), parse_list_end(&parts[4])) {
+                    (Some(from), Some(to)) => (from, to),
+                    _ => return error("ERR syntax error"),
+                },
+                ("RPOPLPUSH", 3) => (ListEnd::Right, ListEnd::Left),
+                _ => return wrong_args(cmd),
+            };
+            match db.lmove(&parts[1], &parts[2], from, to) {
+                Ok(Some(value)) => bulk_string(&value),
+                Ok(None) => null_bulk(),
+                Err(e) => error(e),
+            }
+        }
+
+        "LMPOP" => lmpop(parts, db),
+
+        // Outside a connection (replaying the log) these never wait.
+        "BLPOP" | "BRPOP" => blpop(cmd, parts, db).unwrap_or_else(|_| NULL_ARRAY.to_vec()),
+        "BLMOVE" | "BRPOPLPUSH" => blmove(cmd, parts, db).unwrap_or_else(|_| NULL_ARRAY.to_vec()),
+
         "LRANGE" => {
             if let (Some(key), Some(start_str), Some(end_str)) =
                 (parts.get(1), parts.get(2), parts.get(3))
             {
-                if let (Ok(start), Ok(end)) = (start_str.parse::<isize>(), end_str.parse::<isize>())
+                if let (Some(start), Some(end)) =
+                    (parse_arg::<isize>(start_str), parse_arg::<isize>(end_str))
                 {
-                    let values = db.lrange(key, start, end);
-                    let mut response = format!("*{}\r\n", values.len());
-                    for value in values {
-                        response.push_str(&bulk_string(&value));
+                    match db.lrange(key, start, end) {
+                        Ok(values) => format_array(values),
+                        Err(e) => error(e),
                     }
-                    response
                 } else {
-                    "-ERR invalid range\r\n".to_string()
+                    error("ERR invalid range")
                 }
             } else {
                 wrong_args("LRANGE")
@@ -108,7 +206,447 @@ pub fn handle_list(cmd: &str, parts: &[String], db: &mut Database) -> String {
         }
 
         _ => {
-            format!("-ERR unknown command '{}'\r\n", cmd)
+            error(format!("ERR unknown command '{}'", cmd))
+        }
+    }
+}
+
+// LPOS key element [RANK rank] [COUNT num-matches] [MAXLEN len]
+fn lpos(parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    let (Some(key), Some(value)) = (parts.get(1), parts.get(2)) else {
+        return wrong_args("LPOS");
+    };
+
+    let mut rank = 1;
+    let mut count = None;
+    let mut maxlen = 0;
+    for option in parts[3..].chunks(2) {
+        let [name, arg] = option else {
+            return error("ERR syntax error");
+        };
+        let Some(n) = parse_arg::<i64>(arg) else {
+            return error("ERR value is not an integer or out of range");
+        };
+        if name.eq_ignore_ascii_case(b"RANK") {
+            if n == 0 || n == i64::MIN {
+                return error("ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list");
+            }
+            rank = n;
+        } else if name.eq_ignore_ascii_case(b"COUNT") {
+            if n < 0 {
+                return error("ERR COUNT can't be negative");
+            }
+            count = Some(n as usize);
+        } else if name.eq_ignore_ascii_case(b"MAXLEN") {
+            if n < 0 {
+                return error("ERR MAXLEN can't be negative");
+            }
+            maxlen = n as usize;
+        } else {
+            return error("ERR syntax error");
         }
     }
-}
\ No newline at end of file
+
+    // Without COUNT the reply is the first position alone.
+    match db.lpos(key, value, rank, count.unwrap_or(1), maxlen) {
+        Ok(positions) => match count {
+            Some(_) => {
+                let mut reply = format!("*{}\r\n", positions.len()).into_bytes();
+                for position in positions {
+                    reply.extend(integer(position as i64));
+                }
+                reply
+            }
+            None => positions.first().map_or_else(null_bulk, |&p| integer(p as i64)),
+        },
+        Err(e) => error(e),
+    }
+}
+
+// LMPOP numkeys key [key ...] LEFT|RIGHT [COUNT count]
+fn lmpop(parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    let Some(numkeys) = parts.get(1) else {
+        return wrong_args("LMPOP");
+    };
+    let numkeys = match parse_arg::<i64>(numkeys) {
+        Some(n) if n > 0 => n as usize,
+        _ => return error("ERR numkeys should be greater than 0"),
+    };
+    let Some(keys) = parts.get(2..2 + numkeys) else {
+        return error("ERR syntax error");
+    };
+    let Some(end) = parts.get(2 + numkeys).and_then(|arg| parse_list_end(arg)) else {
+        return error("ERR syntax error");
+    };
+    let count = match &parts[3 + numkeys..] {
+        [] => 1,
+        [name, count] if name.eq_ignore_ascii_case(b"COUNT") => match parse_arg::<i64>(count) {
+            Some(n) if n > 0 => n as usize,
+            _ => return error("ERR count should be greater than 0"),
+        },
+        _ => return error("ERR syntax error"),
+    };
+
+    // Elements come from the first non-empty list.
+    for key in keys {
+        match db.list_pop(key, end, count) {
+            Ok(Some(popped)) if !popped.is_empty() => {
+                let mut reply = b"*2\r\n".to_vec();
+                reply.extend(bulk_string(key));
+                reply.extend(format_array(popped));
+                return reply;
+            }
+            Ok(_) => {}
+            Err(e) => return error(e),
+        }
+    }
+    NULL_ARRAY.to_vec()
+}
+
+// BLPOP|BRPOP key [key ...] timeout
+pub fn blpop(cmd: &str, parts: &[Vec<u8>], db: &mut Database) -> Result<Vec<u8>, BlockRequest> {
+    if parts.len() < 3 {
+        return Ok(wrong_args(cmd));
+    }
+    let (keys, timeout) = parts[1..].split_at(parts.len() - 2);
+    let timeout = match parse_block_seconds(&timeout[0]) {
+        Ok(timeout) => timeout,
+        Err(e) => return Ok(error(e)),
+    };
+
+    // The first non-empty list, in the order the keys were given, is served.
+    for key in keys {
+        let popped = match cmd {
+            "BLPOP" => db.lpop(key),
+            _ => db.rpop(key),
+        };
+        match popped {
+            Ok(Some(value)) => return Ok(format_array(vec![key.clone(), value])),
+            Ok(None) => {}
+            Err(e) => return Ok(error(e)),
+        }
+    }
+
+    Err(BlockRequest {
+        keys: keys.to_vec(),
+        timeout,
+        retry: parts.to_vec(),
+    })
+}
+
+// BLMOVE source destination LEFT|RIGHT LEFT|RIGHT timeout
+// BRPOPLPUSH source destination timeout
+pub fn blmove(cmd: &str, parts: &[Vec<u8>], db: &mut Database) -> Result<Vec<u8>, BlockRequest> {
+    let (from, to, timeout) = match (cmd, parts.len()) {
+        ("BLMOVE", 6) => match (parse_list_end(&parts[3]), parse_list_end(&parts[4])) {
+            (Some(from), Some(to)) => (from, to, &parts[5]),
+            _ => return Ok(error("ERR syntax error")),
+        },
+        ("BRPOPLPUSH", 4) => (ListEnd::Right, ListEnd::Left, &parts[3]),
+        _ => return Ok(wrong_args(cmd)),
+    };
+    let timeout = match parse_block_seconds(timeout) {
+        Ok(timeout) => timeout,
+        Err(e) => return Ok(error(e)),
+    };
+
+    match db.lmove(&parts[1], &parts[2], from, to) {
+        Ok(Some(value)) => Ok(bulk_string(&value)),
+        Ok(None) => Err(BlockRequest {
+            keys: vec![parts[1].clone()],
+            timeout,
+            retry: parts.to_vec(),
+        }),
+        Err(e) => Ok(error(e)),
+    }
+}
+
+fn parse_list_end(arg: &[u8]) -> Option<ListEnd> {
+    if arg.eq_ignore_ascii_case(b"LEFT") {
+        Some(ListEnd::Left)
+    } else if arg.eq_ignore_ascii_case(b"RIGHT") {
+        Some(ListEnd::Right)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{commands::string::KeyExpiry, StoreError};
+
+    fn run(db: &mut Database, args: &[&str]) -> Vec<u8> {
+        let parts: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        handle_list(&args[0].to_uppercase(), &parts, db)
+    }
+
+    fn exists(db: &Database, key: &str) -> bool {
+        db.store_ref().contains_key(key.as_bytes())
+    }
+
+    fn items(values: &[&str]) -> Vec<u8> {
+        format_array(
+            values
+                .iter()
+                .map(|value| value.as_bytes().to_vec())
+                .collect(),
+        )
+    }
+
+    fn contents(db: &mut Database, key: &str) -> Vec<u8> {
+        run(db, &["LRANGE", key, "0", "-1"])
+    }
+
+    #[test]
+    fn pushing_nothing_creates_no_list() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, &["LPUSH", "l"]), wrong_args("LPUSH"));
+        assert_eq!(run(&mut db, &["RPUSH", "l"]), wrong_args("RPUSH"));
+        assert_eq!(run(&mut db, &["LPUSHX", "l", "a"]), integer(0));
+        assert!(!exists(&db, "l"));
+    }
+
+    #[test]
+    fn popping_the_last_element_deletes_the_list() {
+        let mut db = Database::new();
+        run(&mut db, &["RPUSH", "l", "a", "b"]);
+        run(&mut db, &["LPOP", "l"]);
+        assert!(exists(&db, "l"));
+        assert_eq!(run(&mut db, &["RPOP", "l"]), bulk_string(b"b"));
+        assert!(!exists(&db, "l"));
+
+        run(&mut db, &["RPUSH", "l", "a", "b", "c"]);
+        let popped = format_array(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+        assert_eq!(run(&mut db, &["LPOP", "l", "5"]), popped);
+        assert!(!exists(&db, "l"));
+    }
+
+    #[test]
+    fn removing_every_element_deletes_the_list() {
+        let mut db = Database::new();
+        run(&mut db, &["RPUSH", "l", "x", "x"]);
+        assert_eq!(run(&mut db, &["LREM", "l", "0", "x"]), integer(2));
+        assert!(!exists(&db, "l"));
+
+        run(&mut db, &["RPUSH", "l", "a", "b"]);
+        assert_eq!(
+            run(&mut db, &["LTRIM", "l", "5", "10"]),
+            simple_string("OK")
+        );
+        assert!(!exists(&db, "l"));
+
+        run(&mut db, &["RPUSH", "l", "a"]);
+        run(&mut db, &["LMOVE", "l", "dst", "LEFT", "RIGHT"]);
+        assert!(!exists(&db, "l"));
+        assert!(exists(&db, "dst"));
+    }
+
+    #[test]
+    fn moving_within_one_list_keeps_it() {
+        let mut db = Database::new();
+        run(&mut db, &["RPUSH", "l", "a"]);
+        assert_eq!(run(&mut db, &["RPOPLPUSH", "l", "l"]), bulk_string(b"a"));
+        assert_eq!(run(&mut db, &["LLEN", "l"]), integer(1));
+
+        // A one-element list rotated onto itself keeps its expiry.
+        db.expire_at(b"l", u64::MAX);
+        assert_eq!(
+            run(&mut db, &["LMOVE", "l", "l", "LEFT", "RIGHT"]),
+            bulk_string(b"a")
+        );
+        assert_eq!(db.expiry_ref().get(b"l".as_slice()), Some(&u64::MAX));
+
+        run(&mut db, &["RPUSH", "l", "b", "c"]);
+        assert_eq!(
+            run(&mut db, &["LMOVE", "l", "l", "LEFT", "RIGHT"]),
+            bulk_string(b"a")
+        );
+        assert_eq!(contents(&mut db, "l"), items(&["b", "c", "a"]));
+        assert_eq!(
+            run(&mut db, &["LMOVE", "l", "l", "RIGHT", "LEFT"]),
+            bulk_string(b"a")
+        );
+        assert_eq!(contents(&mut db, "l"), items(&["a", "b", "c"]));
+        assert_eq!(
+            run(&mut db, &["LMOVE", "missing", "missing", "LEFT", "LEFT"]),
+            null_bulk()
+        );
+    }
+
+    #[test]
+    fn lmove_between_lists() {
+        let mut db = Database::new();
+        run(&mut db, &["RPUSH", "src", "a", "b"]);
+        assert_eq!(
+            run(&mut db, &["LMOVE", "src", "dst", "RIGHT", "LEFT"]),
+            bulk_string(b"b")
+        );
+        assert_eq!(
+            run(&mut db, &["LMOVE", "src", "dst", "LEFT", "LEFT"]),
+            bulk_string(b"a")
+        );
+        assert_eq!(contents(&mut db, "dst"), items(&["a", "b"]));
+        assert!(!exists(&db, "src"));
+
+        // Nothing is popped when the destination can't take it.
+        db.set(b"str", b"v".to_vec(), KeyExpiry::Clear);
+        assert_eq!(
+            run(&mut db, &["LMOVE", "dst", "str", "LEFT", "LEFT"]),
+            error(StoreError::WrongType)
+        );
+        assert_eq!(contents(&mut db, "dst"), items(&["a", "b"]));
+        assert_eq!(
+            run(&mut db, &["LMOVE", "dst", "x", "UP", "LEFT"]),
+            error("ERR syntax error")
+        );
+    }
+
+    #[test]
+    fn linsert_places_around_the_first_pivot() {
+        let mut db = Database::new();
+        assert_eq!(
+            run(&mut db, &["LINSERT", "l", "BEFORE", "a", "x"]),
+            integer(0)
+        );
+        assert!(!exists(&db, "l"));
+
+        run(&mut db, &["RPUSH", "l", "a", "b", "a"]);
+        assert_eq!(
+            run(&mut db, &["LINSERT", "l", "BEFORE", "a", "x"]),
+            integer(4)
+        );
+        assert_eq!(
+            run(&mut db, &["LINSERT", "l", "after", "b", "y"]),
+            integer(5)
+        );
+        assert_eq!(contents(&mut db, "l"), items(&["x", "a", "b", "y", "a"]));
+        assert_eq!(
+            run(&mut db, &["LINSERT", "l", "AFTER", "nope", "z"]),
+            integer(-1)
+        );
+        assert_eq!(
+            run(&mut db, &["LINSERT", "l", "AROUND", "a", "z"]),
+            error("ERR syntax error")
+        );
+    }
+
+    #[test]
+    fn lrem_counts_from_either_end() {
+        let mut db = Database::new();
+        run(&mut db, &["RPUSH", "l", "x", "a", "x", "b", "x"]);
+        assert_eq!(run(&mut db, &["LREM", "l", "1", "x"]), integer(1));
+        assert_eq!(contents(&mut db, "l"), items(&["a", "x", "b", "x"]));
+        assert_eq!(run(&mut db, &["LREM", "l", "-1", "x"]), integer(1));
+        assert_eq!(contents(&mut db, "l"), items(&["a", "x", "b"]));
+        assert_eq!(run(&mut db, &["LREM", "l", "0", "nope"]), integer(0));
+        assert_eq!(run(&mut db, &["LREM", "missing", "0", "x"]), integer(0));
+    }
+
+    #[test]
+    fn ltrim_keeps_an_inclusive_range() {
+        let mut db = Database::new();
+        run(&mut db, &["RPUSH", "l", "a", "b", "c", "d", "e"]);
+        assert_eq!(
+            run(&mut db, &["LTRIM", "l", "1", "-2"]),
+            simple_string("OK")
+        );
+        assert_eq!(contents(&mut db, "l"), items(&["b", "c", "d"]));
+        assert_eq!(
+            run(&mut db, &["LTRIM", "l", "-100", "100"]),
+            simple_string("OK")
+        );
+        assert_eq!(contents(&mut db, "l"), items(&["b", "c", "d"]));
+        assert_eq!(run(&mut db, &["LTRIM", "l", "2", "1"]), simple_string("OK"));
+        assert!(!exists(&db, "l"));
+    }
+
+    #[test]
+    fn lpos_options() {
+        let mut db = Database::new();
+        run(
+            &mut db,
+            &["RPUSH", "l", "a", "b", "c", "1", "2", "3", "c", "c"],
+        );
+        assert_eq!(run(&mut db, &["LPOS", "l", "c"]), integer(2));
+        assert_eq!(run(&mut db, &["LPOS", "l", "c", "RANK", "2"]), integer(6));
+        assert_eq!(run(&mut db, &["LPOS", "l", "c", "RANK", "-1"]), integer(7));
+        assert_eq!(run(&mut db, &["LPOS", "l", "nope"]), null_bulk());
+
+        let positions = |list: &[i64]| {
+            let mut reply = format!("*{}\r\n", list.len()).into_bytes();
+            list.iter().for_each(|&p| reply.extend(integer(p)));
+            reply
+        };
+        assert_eq!(
+            run(&mut db, &["LPOS", "l", "c", "COUNT", "2"]),
+            positions(&[2, 6])
+        );
+        assert_eq!(
+            run(&mut db, &["LPOS", "l", "c", "COUNT", "0"]),
+            positions(&[2, 6, 7])
+        );
+        assert_eq!(
+            run(&mut db, &["LPOS", "l", "c", "RANK", "-1", "COUNT", "2"]),
+            positions(&[7, 6])
+        );
+        assert_eq!(
+            run(&mut db, &["LPOS", "l", "c", "COUNT", "0", "MAXLEN", "3"]),
+            positions(&[2])
+        );
+        assert_eq!(
+            run(&mut db, &["LPOS", "missing", "c", "COUNT", "0"]),
+            positions(&[])
+        );
+
+        assert!(
+            run(&mut db, &["LPOS", "l", "c", "RANK", "0"]).starts_with(b"-ERR RANK can't be zero")
+        );
+        assert_eq!(
+            run(&mut db, &["LPOS", "l", "c", "COUNT", "-1"]),
+            error("ERR COUNT can't be negative")
+        );
+        assert_eq!(
+            run(&mut db, &["LPOS", "l", "c", "RANK"]),
+            error("ERR syntax error")
+        );
+    }
+
+    #[test]
+    fn lmpop_pops_from_the_first_non_empty_list() {
+        let mut db = Database::new();
+        run(&mut db, &["RPUSH", "b", "1", "2", "3"]);
+        let reply = |key: &str, values: &[&str]| {
+            let mut reply = b"*2\r\n".to_vec();
+            reply.extend(bulk_string(key.as_bytes()));
+            reply.extend(items(values));
+            reply
+        };
+        assert_eq!(
+            run(&mut db, &["LMPOP", "2", "a", "b", "LEFT"]),
+            reply("b", &["1"])
+        );
+        assert_eq!(
+            run(&mut db, &["LMPOP", "2", "a", "b", "RIGHT", "COUNT", "5"]),
+            reply("b", &["3", "2"])
+        );
+        assert!(!exists(&db, "b"));
+        assert_eq!(
+            run(&mut db, &["LMPOP", "2", "a", "b", "LEFT"]),
+            NULL_ARRAY.to_vec()
+        );
+
+        assert_eq!(
+            run(&mut db, &["LMPOP", "0", "a", "LEFT"]),
+            error("ERR numkeys should be greater than 0")
+        );
+        assert_eq!(
+            run(&mut db, &["LMPOP", "1", "a", "LEFT", "COUNT", "0"]),
+            error("ERR count should be greater than 0")
+        );
+        assert_eq!(
+            run(&mut db, &["LMPOP", "3", "a", "LEFT"]),
+            error("ERR syntax error")
+        );
+    }
+}
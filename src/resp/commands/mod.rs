@@ -4,3 +4,6 @@ pub mod list;
 pub mod set;
 pub mod number;
 pub mod key;
+pub mod zset;
+pub mod stream;
+pub mod scripting;
@@ -1,12 +1,15 @@
-use crate::{resp::utils::wrong_args, store::Database};
+use crate::{
+    resp::utils::{error, integer, parse_arg, wrong_args},
+    store::Database,
+};
 
-pub fn handle_number (cmd: &str, parts: &[String], db: &mut Database) -> String {
+pub fn handle_number (cmd: &str, parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
     match cmd {
         "INCR" => {
             if let Some(key) = parts.get(1) {
                 match db.incr(key) {
-                    Ok(val) => format!(":{}\r\n", val),
-                    Err(e) => format!("-ERR {}\r\n", e),
+                    Ok(val) => integer(val),
+                    Err(e) => error(e),
                 }
             } else {
                 wrong_args("INCR")
@@ -15,12 +18,12 @@ pub fn handle_number (cmd: &str, parts: &[String], db: &mut Database) -> String
 
         "INCRBY" => {
             if let (Some(key), Some(arg)) = (parts.get(1), parts.get(2)) {
-                match arg.parse::<i64>() {
-                    Ok(by) => match db.incr_by(key, by) {
-                        Ok(val) => format!(":{}\r\n", val),
-                        Err(e) => format!("-ERR {}\r\n", e),
+                match parse_arg::<i64>(arg) {
+                    Some(by) => match db.incr_by(key, by) {
+                        Ok(val) => integer(val),
+                        Err(e) => error(e),
                     },
-                    Err(_) => "-ERR value is not an integer or out of range\r\n".to_string(),
+                    None => error("ERR value is not an integer or out of range"),
                 }
             } else {
                 wrong_args("INCRBY")
@@ -29,12 +32,12 @@ pub fn handle_number (cmd: &str, parts: &[String], db: &mut Database) -> String
 
         "DECRBY" => {
             if let (Some(key), Some(arg)) = (parts.get(1), parts.get(2)) {
-                match arg.parse::<i64>() {
-                    Ok(by) => match db.incr_by(key, -by) {
-                        Ok(val) => format!(":{}\r\n", val),
-                        Err(e) => format!("-ERR {}\r\n", e),
+                match parse_arg::<i64>(arg) {
+                    Some(by) => match db.incr_by(key, -by) {
+                        Ok(val) => integer(val),
+                        Err(e) => error(e),
                     },
-                    Err(_) => "-ERR value is not an integer or out of range\r\n".to_string(),
+                    None => error("ERR value is not an integer or out of range"),
                 }
             } else {
                 wrong_args("DECRBY")
@@ -44,8 +47,8 @@ pub fn handle_number (cmd: &str, parts: &[String], db: &mut Database) -> String
         "DECR" => {
             if let Some(key) = parts.get(1) {
                 match db.incr_by(key, -1) {
-                    Ok(val) => format!(":{}\r\n", val),
-                    Err(e) => format!("-ERR {}\r\n", e),
+                    Ok(val) => integer(val),
+                    Err(e) => error(e),
                 }
             } else {
                 wrong_args("DECR")
@@ -53,8 +56,8 @@ pub fn handle_number (cmd: &str, parts: &[String], db: &mut Database) -> String
         }
 
         _ => {
-            format!("-ERR unknown command '{}'\r\n", cmd)
+            error(format!("ERR unknown command '{}'", cmd))
         }
 }
 
-}
\ No newline at end of file
+}
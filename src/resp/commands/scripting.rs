@@ -0,0 +1,256 @@
+use std::sync::Arc;
+
+use crate::{
+    persistence::Persister,
+    pubsub::{cmd::handle_publish, PubSub},
+    resp::{
+        blocking::{is_blocking_command, try_blocking_command},
+        handler::execute,
+        transaction::is_transaction_command,
+        utils::{bulk_string, error, integer, parse_arg, simple_string, wrong_args},
+    },
+    scripting::{run_script, ScriptMonitor},
+    store::Database,
+    utils::{is_mutating_command, persist_if_mutating},
+};
+
+pub fn handle_scripting(
+    cmd: &str,
+    parts: &[Vec<u8>],
+    db: &mut Database,
+    persister: &(dyn Persister + Send + Sync),
+    pubsub: &PubSub,
+) -> Vec<u8> {
+    match cmd {
+        "EVAL" => {
+            if parts.len() < 3 {
+                return wrong_args("EVAL");
+            }
+            let body = parts[1].clone();
+            db.scripts_mut().load(&body);
+            eval(&body, &parts[2..], db, persister, pubsub)
+        }
+
+        "EVALSHA" => {
+            if parts.len() < 3 {
+                return wrong_args("EVALSHA");
+            }
+            let Some(body) = db.scripts_ref().get(&parts[1]).map(<[u8]>::to_vec) else {
+                return error("NOSCRIPT No matching script. Please use EVAL.");
+            };
+            eval(&body, &parts[2..], db, persister, pubsub)
+        }
+
+        "SCRIPT" => {
+            let sub = parts
+                .get(1)
+                .map(|s| String::from_utf8_lossy(s).to_uppercase())
+                .unwrap_or_default();
+            match (sub.as_str(), parts.len()) {
+                ("LOAD", 3) => {
+                    let sha = db.scripts_mut().load(&parts[2]);
+                    bulk_string(sha.as_bytes())
+                }
+                ("EXISTS", n) if n > 2 => {
+                    let mut resp = format!("*{}\r\n", n - 2).into_bytes();
+                    for sha in &parts[2..] {
+                        let exists = db.scripts_ref().get(sha).is_some();
+                        resp.extend(integer(exists as i64));
+                    }
+                    resp
+                }
+                ("FLUSH", 2) => {
+                    db.scripts_mut().flush();
+                    simple_string("OK")
+                }
+                ("FLUSH", 3)
+                    if parts[2].eq_ignore_ascii_case(b"ASYNC")
+                        || parts[2].eq_ignore_ascii_case(b"SYNC") =>
+                {
+                    db.scripts_mut().flush();
+                    simple_string("OK")
+                }
+                // Only reached from EXEC: connections answer SCRIPT KILL
+                // themselves, without waiting for the database.
+                ("KILL", 2) => db.scripts_ref().monitor().kill(),
+                ("LOAD" | "EXISTS" | "FLUSH" | "KILL", _) => wrong_args("SCRIPT"),
+                _ => error(format!(
+                    "ERR unknown subcommand '{}'. Try SCRIPT LOAD, EXISTS, FLUSH or KILL.",
+                    sub
+                )),
+            }
+        }
+
+        _ => error("ERR unknown command"),
+    }
+}
+
+/// `rest` is `numkeys key [key ...] arg [arg ...]`.
+///
+/// The script's writes are logged together as one MULTI/EXEC block, so
+/// replaying the append-only file applies all of them or none.
+fn eval(
+    body: &[u8],
+    rest: &[Vec<u8>],
+    db: &mut Database,
+    persister: &(dyn Persister + Send + Sync),
+    pubsub: &PubSub,
+) -> Vec<u8> {
+    let Some(numkeys) = parse_arg::<i64>(&rest[0]) else {
+        return error("ERR value is not an integer or out of range");
+    };
+    if numkeys < 0 {
+        return error("ERR Number of keys can't be negative");
+    }
+    if numkeys as usize > rest.len() - 1 {
+        return error("ERR Number of keys can't be greater than number of args");
+    }
+    let (keys, args) = rest[1..].split_at(numkeys as usize);
+
+    let monitor = Arc::clone(db.scripts_ref().monitor());
+    persister.begin_atomic();
+    let reply = run_script(body, keys, args, &monitor, |parts| {
+        call_from_script(parts, db, persister, pubsub, &monitor)
+    });
+    if let Err(e) = persister.end_atomic(db) {
+        eprintln!("❌ Failed to persist script: {e}");
+    }
+    reply
+}
+
+/// Runs one `redis.call` against the database the script already holds.
+/// Each write is counted and logged as it happens, so the append-only file
+/// replays the script's effects rather than the script.
+fn call_from_script(
+    parts: &[Vec<u8>],
+    db: &mut Database,
+    persister: &(dyn Persister + Send + Sync),
+    pubsub: &PubSub,
+    monitor: &ScriptMonitor,
+) -> Vec<u8> {
+    let cmd = String::from_utf8_lossy(&parts[0]).to_uppercase();
+
+    // SCRIPT KILL only stops a script that hasn't written, and a killed one
+    // mustn't start.
+    if is_mutating_command(&cmd) && !monitor.note_write() {
+        return error("ERR Script killed by user with SCRIPT KILL");
+    }
+
+    let reply = if is_blocking_command(&cmd) {
+        // Scripts can't wait, so a blocking command that would block is nil.
+        try_blocking_command(&cmd, parts, db).unwrap_or_else(|_| b"*-1\r\n".to_vec())
+    } else if is_transaction_command(&cmd)
+        || matches!(
+            cmd.as_str(),
+            "EVAL" | "EVALSHA" | "SCRIPT" | "SUBSCRIBE" | "PSUBSCRIBE" | "SSUBSCRIBE"
+                | "UNSUBSCRIBE" | "PUNSUBSCRIBE" | "SUNSUBSCRIBE" | "SPUBLISH" | "PUBSUB"
+                | "SAVE" | "BGSAVE" | "LASTSAVE" | "BGREWRITEAOF" | "QUIT" | "RESET"
+        )
+    {
+        error("ERR This Redis command is not allowed from script")
+    } else if cmd == "PUBLISH" {
+        handle_publish(parts, pubsub)
+    } else {
+        execute(&cmd, parts, db)
+    };
+
+    persist_if_mutating(&cmd, parts, db, persister);
+    reply
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process, thread, time::Duration};
+
+    use super::*;
+    use crate::{
+        config::{FsyncPolicy, OutputBufferLimit},
+        persistence::{AofPersister, AutoRewrite},
+    };
+
+    fn log_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("script-test-{}-{name}.aof", process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    fn eval(db: &mut Database, persister: &AofPersister, body: &str) -> Vec<u8> {
+        let parts = [b"EVAL".to_vec(), body.as_bytes().to_vec(), b"0".to_vec()];
+        let pubsub = PubSub::new(OutputBufferLimit::default());
+        handle_scripting("EVAL", &parts, db, persister, &pubsub)
+    }
+
+    /// Sends SCRIPT KILL once the running script counts as busy.
+    fn kill_when_busy(db: &Database) -> thread::JoinHandle<Vec<u8>> {
+        let monitor = Arc::clone(db.scripts_ref().monitor());
+        monitor.set_busy_threshold(Duration::from_millis(20));
+        thread::spawn(move || {
+            while !monitor.is_busy() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            monitor.kill()
+        })
+    }
+
+    const NO_REWRITE: AutoRewrite = AutoRewrite { percentage: 0, min_size: 0 };
+
+    #[test]
+    fn a_script_that_wrote_is_never_cut_short() {
+        let path = log_path("wrote");
+        let aof = AofPersister::new(&path, FsyncPolicy::No, NO_REWRITE).unwrap();
+        let mut db = Database::new();
+        let killer = kill_when_busy(&db);
+
+        // Long enough to be busy when SCRIPT KILL comes, then writes again.
+        let body = "redis.call('SET', 'a', '1') \
+            local n = 0 for i = 1, 2e7 do n = n + 1 end \
+            redis.call('SET', 'b', '2') return 'done'";
+        let reply = eval(&mut db, &aof, body);
+        let killed = killer.join().unwrap();
+
+        assert!(killed.starts_with(b"-UNKILLABLE"), "{}", String::from_utf8_lossy(&killed));
+        assert_eq!(reply, bulk_string(b"done"));
+        assert!(db.store_ref().contains_key(b"a".as_slice()));
+        assert!(db.store_ref().contains_key(b"b".as_slice()));
+
+        let log = fs::read(&path).unwrap();
+        assert!(log.starts_with(b"*1\r\n$5\r\nMULTI\r\n"));
+        assert!(log.ends_with(b"*1\r\n$4\r\nEXEC\r\n"));
+        assert_eq!(aof.replay().unwrap().len(), 2);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn a_killed_script_writes_nothing() {
+        let path = log_path("killed");
+        let aof = AofPersister::new(&path, FsyncPolicy::No, NO_REWRITE).unwrap();
+        let mut db = Database::new();
+        let killer = kill_when_busy(&db);
+
+        let body = "redis.call('GET', 'a') while true do end redis.call('SET', 'a', '1')";
+        let reply = eval(&mut db, &aof, body);
+        assert_eq!(killer.join().unwrap(), simple_string("OK"));
+        assert_eq!(reply, error("ERR Script killed by user with SCRIPT KILL"));
+        assert!(db.store_ref().is_empty());
+        assert!(fs::read(&path).unwrap().is_empty());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn pub_sub_from_scripts() {
+        let path = log_path("pubsub");
+        let aof = AofPersister::new(&path, FsyncPolicy::No, NO_REWRITE).unwrap();
+        let mut db = Database::new();
+
+        assert_eq!(eval(&mut db, &aof, "return redis.call('PUBLISH', 'news', 'hi')"), integer(0));
+        for cmd in ["SUBSCRIBE", "PSUBSCRIBE", "SSUBSCRIBE", "SPUBLISH", "PUBSUB"] {
+            let body = format!("return redis.call('{cmd}', 'news', 'hi')");
+            assert_eq!(
+                eval(&mut db, &aof, &body),
+                error("ERR This Redis command is not allowed from script"),
+                "{cmd}"
+            );
+        }
+        let _ = fs::remove_file(path);
+    }
+}
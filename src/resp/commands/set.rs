@@ -1,11 +1,16 @@
-use crate::{resp::utils::{format_array, wrong_args}, store::Database};
+use crate::{
+    resp::utils::{bulk_string, error, format_array, integer, null_bulk, parse_arg, wrong_args},
+    store::Database,
+};
 
-pub fn handle_set(cmd: &str, parts: &[String], db: &mut Database) -> String {
+pub fn handle_set(cmd: &str, parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
     match cmd {
         "SADD" => {
             if let Some(key) = parts.get(1) {
-                let len = db.sadd(key, &parts[2..]);
-                format!(":{}\r\n", len)
+                match db.sadd(key, &parts[2..]) {
+                    Ok(len) => integer(len as i64),
+                    Err(e) => error(e),
+                }
             } else {
                 wrong_args("SADD")
             }
@@ -13,8 +18,10 @@ pub fn handle_set(cmd: &str, parts: &[String], db: &mut Database) -> String {
 
         "SREM" => {
             if let Some(key) = parts.get(1) {
-                let len = db.srem(key, &parts[2..]);
-                format!(":{}\r\n", len)
+                match db.srem(key, &parts[2..]) {
+                    Ok(len) => integer(len as i64),
+                    Err(e) => error(e),
+                }
             } else {
                 wrong_args("SREM")
             }
@@ -22,8 +29,10 @@ pub fn handle_set(cmd: &str, parts: &[String], db: &mut Database) -> String {
 
         "SMEMBERS" => {
             if let Some(key) = parts.get(1) {
-                let members = db.smembers(key);
-                format_array(members)
+                match db.smembers(key) {
+                    Ok(members) => format_array(members),
+                    Err(e) => error(e),
+                }
             } else {
                 wrong_args("SMEMBERS")
             }
@@ -31,8 +40,10 @@ pub fn handle_set(cmd: &str, parts: &[String], db: &mut Database) -> String {
 
         "SISMEMBER" => {
             if let (Some(key), Some(member)) = (parts.get(1), parts.get(2)) {
-                let is_member = db.sismember(key, member);
-                format!(":{}\r\n", if is_member { 1 } else { 0 })
+                match db.sismember(key, member) {
+                    Ok(is_member) => integer(if is_member { 1 } else { 0 }),
+                    Err(e) => error(e),
+                }
             } else {
                 wrong_args("SISMEMBER")
             }
@@ -40,15 +51,287 @@ pub fn handle_set(cmd: &str, parts: &[String], db: &mut Database) -> String {
 
         "SCARD" => {
             if let Some(key) = parts.get(1) {
-                let count = db.scard(key);
-                format!(":{}\r\n", count)
+                match db.scard(key) {
+                    Ok(count) => integer(count as i64),
+                    Err(e) => error(e),
+                }
             } else {
                 wrong_args("SCARD")
             }
         }
 
+        "SMISMEMBER" => {
+            let (Some(key), Some(members)) = (parts.get(1), parts.get(2..).filter(|m| !m.is_empty()))
+            else {
+                return wrong_args("SMISMEMBER");
+            };
+            match db.smismember(key, members) {
+                Ok(found) => {
+                    let mut reply = format!("*{}\r\n", found.len()).into_bytes();
+                    for is_member in found {
+                        reply.extend(integer(is_member as i64));
+                    }
+                    reply
+                }
+                Err(e) => error(e),
+            }
+        }
+
+        "SINTER" | "SUNION" | "SDIFF" => {
+            let keys = &parts[1..];
+            if keys.is_empty() {
+                return wrong_args(cmd);
+            }
+            let members = match cmd {
+                "SINTER" => db.sinter(keys),
+                "SUNION" => db.sunion(keys),
+                _ => db.sdiff(keys),
+            };
+            match members {
+                Ok(members) => format_array(members.into_iter().collect()),
+                Err(e) => error(e),
+            }
+        }
+
+        "SINTERSTORE" | "SUNIONSTORE" | "SDIFFSTORE" => {
+            let (Some(destination), Some(keys)) = (parts.get(1), parts.get(2..).filter(|k| !k.is_empty()))
+            else {
+                return wrong_args(cmd);
+            };
+            let members = match cmd {
+                "SINTERSTORE" => db.sinter(keys),
+                "SUNIONSTORE" => db.sunion(keys),
+                _ => db.sdiff(keys),
+            };
+            match members {
+                Ok(members) => {
+                    let event = cmd.to_lowercase();
+                    integer(db.store_set(destination, members, &event) as i64)
+                }
+                Err(e) => error(e),
+            }
+        }
+
+        "SINTERCARD" => sintercard(parts, db),
+
+        "SMOVE" => {
+            let [_, source, destination, member] = parts else {
+                return wrong_args("SMOVE");
+            };
+            match db.smove(source, destination, member) {
+                Ok(moved) => integer(moved as i64),
+                Err(e) => error(e),
+            }
+        }
+
+        "SPOP" => {
+            let Some(key) = parts.get(1) else {
+                return wrong_args("SPOP");
+            };
+            match parts.get(2..) {
+                // Without a count the reply is a single member.
+                Some([]) => match db.spop(key, 1) {
+                    Ok(Some(mut popped)) if !popped.is_empty() => bulk_string(&popped.remove(0)),
+                    Ok(_) => null_bulk(),
+                    Err(e) => error(e),
+                },
+                Some([count]) => match parse_arg::<i64>(count) {
+                    Some(count) if count >= 0 => match db.spop(key, count as usize) {
+                        Ok(popped) => format_array(popped.unwrap_or_default()),
+                        Err(e) => error(e),
+                    },
+                    _ => error("ERR value is out of range, must be positive"),
+                },
+                _ => error("ERR syntax error"),
+            }
+        }
+
+        "SRANDMEMBER" => {
+            let Some(key) = parts.get(1) else {
+                return wrong_args("SRANDMEMBER");
+            };
+            match parts.get(2..) {
+                Some([]) => match db.srandmember(key, 1) {
+                    Ok(mut members) if !members.is_empty() => bulk_string(&members.remove(0)),
+                    Ok(_) => null_bulk(),
+                    Err(e) => error(e),
+                },
+                Some([count]) => match parse_arg::<i64>(count) {
+                    Some(count) => match db.srandmember(key, count) {
+                        Ok(members) => format_array(members),
+                        Err(e) => error(e),
+                    },
+                    None => error("ERR value is not an integer or out of range"),
+                },
+                _ => error("ERR syntax error"),
+            }
+        }
+
         _ => {
-            format!("-ERR unknown command '{}'\r\n", cmd)
+            error(format!("ERR unknown command '{}'", cmd))
+        }
+    }
+}
+
+// SINTERCARD numkeys key [key ...] [LIMIT limit]
+fn sintercard(parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    let Some(numkeys) = parts.get(1) else {
+        return wrong_args("SINTERCARD");
+    };
+    let numkeys = match parse_arg::<i64>(numkeys) {
+        Some(n) if n > 0 => n as usize,
+        _ => return error("ERR numkeys should be greater than 0"),
+    };
+    let Some(keys) = parts.get(2..2 + numkeys) else {
+        return error("ERR Number of keys can't be greater than number of args");
+    };
+    let limit = match &parts[2 + numkeys..] {
+        [] => 0,
+        [name, limit] if name.eq_ignore_ascii_case(b"LIMIT") => match parse_arg::<i64>(limit) {
+            Some(n) if n >= 0 => n as usize,
+            _ => return error("ERR LIMIT can't be negative"),
+        },
+        _ => return error("ERR syntax error"),
+    };
+    match db.sintercard(keys, limit) {
+        Ok(count) => integer(count as i64),
+        Err(e) => error(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{store::StoreError, types::RedisValue};
+
+    fn run(db: &mut Database, args: &[&str]) -> Vec<u8> {
+        let parts: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        handle_set(&args[0].to_uppercase(), &parts, db)
+    }
+
+    /// The members in an array reply, sorted since sets have no order.
+    fn members(reply: Vec<u8>) -> Vec<String> {
+        let text = String::from_utf8(reply).unwrap();
+        let lines: Vec<&str> = text.split("\r\n").collect();
+        let mut members: Vec<String> = lines[1..]
+            .chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .map(|pair| pair[1].to_string())
+            .collect();
+        members.sort();
+        members
+    }
+
+    fn set(db: &mut Database, key: &str) -> Vec<String> {
+        members(run(db, &["SMEMBERS", key]))
+    }
+
+    /// Sets `a`, `b` and `c`, a string `str` and a set `gone` that has
+    /// expired but hasn't been removed yet.
+    fn sets() -> Database {
+        let mut db = Database::new();
+        run(&mut db, &["SADD", "a", "1", "2", "3", "4"]);
+        run(&mut db, &["SADD", "b", "2", "3", "5"]);
+        run(&mut db, &["SADD", "c", "3", "4", "6"]);
+        run(&mut db, &["SADD", "gone", "1", "2", "3"]);
+        db.expiry_mut().insert(b"gone".to_vec(), 1);
+        db.store_mut().insert(b"str".to_vec(), RedisValue::String(b"v".to_vec()));
+        db
+    }
+
+    #[test]
+    fn algebra_treats_missing_and_expired_keys_as_empty() {
+        let mut db = sets();
+        assert_eq!(members(run(&mut db, &["SINTER", "a", "b"])), ["2", "3"]);
+        assert_eq!(members(run(&mut db, &["SINTER", "a", "b", "c"])), ["3"]);
+        assert_eq!(members(run(&mut db, &["SINTER", "a", "nope"])), Vec::<String>::new());
+        assert_eq!(members(run(&mut db, &["SINTER", "a", "gone"])), Vec::<String>::new());
+
+        assert_eq!(members(run(&mut db, &["SUNION", "b", "nope", "c"])), ["2", "3", "4", "5", "6"]);
+        assert_eq!(members(run(&mut db, &["SUNION", "gone", "nope"])), Vec::<String>::new());
+
+        assert_eq!(members(run(&mut db, &["SDIFF", "a", "b", "gone"])), ["1", "4"]);
+        assert_eq!(members(run(&mut db, &["SDIFF", "a", "nope"])), ["1", "2", "3", "4"]);
+        assert_eq!(members(run(&mut db, &["SDIFF", "nope", "a"])), Vec::<String>::new());
+        assert!(!db.store_ref().contains_key(&b"gone"[..]));
+    }
+
+    #[test]
+    fn algebra_checks_every_key_for_type() {
+        let mut db = sets();
+        let wrong_type = error(StoreError::WrongType);
+        for cmd in ["SINTER", "SUNION", "SDIFF"] {
+            assert_eq!(run(&mut db, &[cmd, "nope", "a", "str"]), wrong_type, "{cmd}");
+            let store = format!("{cmd}STORE");
+            assert_eq!(run(&mut db, &[&store, "dest", "a", "str"]), wrong_type, "{store}");
+        }
+        assert_eq!(run(&mut db, &["SINTERCARD", "2", "nope", "str"]), wrong_type);
+        assert!(!db.store_ref().contains_key(&b"dest"[..]));
+    }
+
+    #[test]
+    fn store_variants_replace_the_destination() {
+        let mut db = sets();
+        db.store_mut().insert(b"dest".to_vec(), RedisValue::String(b"v".to_vec()));
+        db.expiry_mut().insert(b"dest".to_vec(), u64::MAX);
+        assert_eq!(run(&mut db, &["SINTERSTORE", "dest", "a", "b"]), integer(2));
+        assert_eq!(set(&mut db, "dest"), ["2", "3"]);
+        assert_eq!(db.expiry_ref().get(&b"dest"[..]), None);
+
+        assert_eq!(run(&mut db, &["SUNIONSTORE", "dest", "dest", "c"]), integer(4));
+        assert_eq!(set(&mut db, "dest"), ["2", "3", "4", "6"]);
+        assert_eq!(run(&mut db, &["SDIFFSTORE", "dest", "dest", "a", "c"]), integer(0));
+        assert!(!db.store_ref().contains_key(&b"dest"[..]));
+
+        // The sources may include the destination's old value.
+        assert_eq!(run(&mut db, &["SINTERSTORE", "b", "b", "gone"]), integer(0));
+        assert!(!db.store_ref().contains_key(&b"b"[..]));
+    }
+
+    #[test]
+    fn sintercard_stops_at_its_limit() {
+        let mut db = sets();
+        assert_eq!(run(&mut db, &["SINTERCARD", "2", "a", "b"]), integer(2));
+        assert_eq!(run(&mut db, &["SINTERCARD", "1", "a", "LIMIT", "3"]), integer(3));
+        assert_eq!(run(&mut db, &["SINTERCARD", "1", "a", "LIMIT", "0"]), integer(4));
+        assert_eq!(run(&mut db, &["SINTERCARD", "1", "a", "LIMIT", "9"]), integer(4));
+        assert_eq!(run(&mut db, &["SINTERCARD", "2", "a", "gone"]), integer(0));
+        assert_eq!(run(&mut db, &["SINTERCARD", "2", "nope", "a"]), integer(0));
+
+        let cases = [
+            (&["SINTERCARD", "0", "a"][..], "ERR numkeys should be greater than 0"),
+            (&["SINTERCARD", "x", "a"], "ERR numkeys should be greater than 0"),
+            (
+                &["SINTERCARD", "3", "a", "b"],
+                "ERR Number of keys can't be greater than number of args",
+            ),
+            (&["SINTERCARD", "1", "a", "LIMIT", "-1"], "ERR LIMIT can't be negative"),
+            (&["SINTERCARD", "1", "a", "LIMIT"], "ERR syntax error"),
+            (&["SINTERCARD", "1", "a", "b"], "ERR syntax error"),
+        ];
+        for (args, message) in cases {
+            assert_eq!(run(&mut db, args), error(message), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn negative_count_repeats_members() {
+        let mut db = Database::new();
+        run(&mut db, &["SADD", "s", "a"]);
+        let repeated = format_array(vec![b"a".to_vec(); 3]);
+        assert_eq!(run(&mut db, &["SRANDMEMBER", "s", "-3"]), repeated);
+        assert_eq!(run(&mut db, &["SRANDMEMBER", "s", "3"]), format_array(vec![b"a".to_vec()]));
+    }
+
+    #[test]
+    fn huge_negative_counts_are_refused() {
+        let mut db = Database::new();
+        run(&mut db, &["SADD", "s", "a"]);
+        let out_of_range = error("ERR value is out of range");
+        for count in ["-9223372036854775807", "-9223372036854775808", "-1048577"] {
+            assert_eq!(run(&mut db, &["SRANDMEMBER", "s", count]), out_of_range);
         }
+        let reply = run(&mut db, &["SRANDMEMBER", "s", "-1048576"]);
+        assert!(reply.starts_with(b"*1048576\r\n"));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,1027 @@
+use std::time::Duration;
+
+use crate::{
+    resp::{
+        blocking::{parse_block_millis, BlockRequest},
+        utils::{bulk_string, error, format_array, integer, null_bulk, parse_arg, simple_string, wrong_args},
+    },
+    store::{
+        stream::{ClaimOptions, Delivered, Entry, IdSpec, StreamId, TrimSpec, TrimStrategy},
+        Database,
+    },
+    utils::current_unix_millis,
+};
+
+const SYNTAX_ERR: &str = "ERR syntax error";
+const NOT_INTEGER: &str = "ERR value is not an integer or out of range";
+const INVALID_ID: &str = "ERR Invalid stream ID specified as stream command argument";
+const NULL_ARRAY: &[u8] = b"*-1\r\n";
+
+pub fn handle_stream(cmd: &str, parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    match cmd {
+        "XADD" => xadd(parts, db),
+
+        "XLEN" => {
+            if let (Some(key), None) = (parts.get(1), parts.get(2)) {
+                match db.xlen(key) {
+                    Ok(len) => integer(len as i64),
+                    Err(e) => error(e),
+                }
+            } else {
+                wrong_args("XLEN")
+            }
+        }
+
+        "XRANGE" | "XREVRANGE" => xrange(cmd, parts, db),
+
+        "XDEL" => {
+            if parts.len() < 3 {
+                return wrong_args("XDEL");
+            }
+            let mut ids = Vec::with_capacity(parts.len() - 2);
+            for arg in &parts[2..] {
+                match StreamId::parse(arg, 0) {
+                    Some(id) => ids.push(id),
+                    None => return error(INVALID_ID),
+                }
+            }
+            match db.xdel(&parts[1], &ids) {
+                Ok(removed) => integer(removed as i64),
+                Err(e) => error(e),
+            }
+        }
+
+        "XSETID" => {
+            let (Some(key), Some(id), None) = (parts.get(1), parts.get(2), parts.get(3)) else {
+                return wrong_args("XSETID");
+            };
+            let Some(id) = StreamId::parse(id, 0) else {
+                return error(INVALID_ID);
+            };
+            match db.xsetid(key, id) {
+                Ok(()) => simple_string("OK"),
+                Err(e) => error(e),
+            }
+        }
+
+        "XTRIM" => {
+            if parts.len() < 4 {
+                return wrong_args("XTRIM");
+            }
+            let spec = match parse_trim(parts, 2) {
+                Ok((spec, next)) if next == parts.len() => spec,
+                Ok(_) => return error(SYNTAX_ERR),
+                Err(e) => return e,
+            };
+            match db.xtrim(&parts[1], spec) {
+                Ok(removed) => integer(removed as i64),
+                Err(e) => error(e),
+            }
+        }
+
+        // Outside a blocking context (e.g. replayed from disk) a timeout is
+        // the only possible outcome of waiting.
+        "XREAD" => xread(parts, db).unwrap_or_else(|_| NULL_ARRAY.to_vec()),
+        "XREADGROUP" => xreadgroup(parts, db).unwrap_or_else(|_| NULL_ARRAY.to_vec()),
+
+        "XGROUP" => xgroup(parts, db),
+
+        "XACK" => {
+            if parts.len() < 4 {
+                return wrong_args("XACK");
+            }
+            let mut ids = Vec::with_capacity(parts.len() - 3);
+            for arg in &parts[3..] {
+                match StreamId::parse(arg, 0) {
+                    Some(id) => ids.push(id),
+                    None => return error(INVALID_ID),
+                }
+            }
+            match db.xack(&parts[1], &parts[2], &ids) {
+                Ok(acked) => integer(acked as i64),
+                Err(e) => error(e),
+            }
+        }
+
+        "XPENDING" => xpending(parts, db),
+        "XCLAIM" => xclaim(parts, db),
+        "XAUTOCLAIM" => xautoclaim(parts, db),
+
+        _ => {
+            error(format!("ERR unknown command '{}'", cmd))
+        }
+    }
+}
+
+// XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] <*|id> field value [...]
+fn xadd(parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    let Some(key) = parts.get(1) else {
+        return wrong_args("XADD");
+    };
+
+    let mut nomkstream = false;
+    let mut trim = None;
+    let mut i = 2;
+    while let Some(arg) = parts.get(i) {
+        if arg.eq_ignore_ascii_case(b"NOMKSTREAM") {
+            nomkstream = true;
+            i += 1;
+        } else if arg.eq_ignore_ascii_case(b"MAXLEN") || arg.eq_ignore_ascii_case(b"MINID") {
+            match parse_trim(parts, i) {
+                Ok((spec, next)) => {
+                    trim = Some(spec);
+                    i = next;
+                }
+                Err(e) => return e,
+            }
+        } else {
+            break;
+        }
+    }
+
+    let Some(id_arg) = parts.get(i) else {
+        return wrong_args("XADD");
+    };
+    let fields = &parts[i + 1..];
+    if fields.is_empty() || !fields.len().is_multiple_of(2) {
+        return wrong_args("XADD");
+    }
+    let Some(spec) = parse_id_spec(id_arg) else {
+        return error(INVALID_ID);
+    };
+
+    match db.xadd(key, spec, fields.to_vec(), nomkstream, trim) {
+        Ok(Some(id)) => bulk_string(id.to_string().as_bytes()),
+        Ok(None) => null_bulk(),
+        Err(e) => error(e),
+    }
+}
+
+// XRANGE key start end [COUNT n] / XREVRANGE key end start [COUNT n]
+fn xrange(cmd: &str, parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    if parts.len() != 4 && parts.len() != 6 {
+        return if parts.len() < 4 { wrong_args(cmd) } else { error(SYNTAX_ERR) };
+    }
+    let rev = cmd == "XREVRANGE";
+    let (start_arg, end_arg) = if rev { (&parts[3], &parts[2]) } else { (&parts[2], &parts[3]) };
+
+    let count = match parts.get(4) {
+        None => None,
+        Some(opt) if opt.eq_ignore_ascii_case(b"COUNT") => match parse_arg::<i64>(&parts[5]) {
+            Some(n) => Some(n.max(0) as usize),
+            None => return error(NOT_INTEGER),
+        },
+        Some(_) => return error(SYNTAX_ERR),
+    };
+
+    let (Some(start), Some(end)) = (parse_range_start(start_arg), parse_range_end(end_arg)) else {
+        return error(INVALID_ID);
+    };
+    if count == Some(0) {
+        return format_array(vec![]);
+    }
+
+    match db.xrange(&parts[1], start, end, rev, count) {
+        Ok(entries) => format_entries(&entries),
+        Err(e) => error(e),
+    }
+}
+
+// XREAD [COUNT n] [BLOCK ms] STREAMS key [key ...] id [id ...]
+pub fn xread(parts: &[Vec<u8>], db: &mut Database) -> Result<Vec<u8>, BlockRequest> {
+    let mut count = None;
+    let mut block = None;
+    let mut i = 1;
+    let streams_at = loop {
+        let Some(arg) = parts.get(i) else {
+            return Ok(error(SYNTAX_ERR));
+        };
+        if arg.eq_ignore_ascii_case(b"STREAMS") {
+            break i + 1;
+        }
+        let Some(value) = parts.get(i + 1) else {
+            return Ok(error(SYNTAX_ERR));
+        };
+        if arg.eq_ignore_ascii_case(b"COUNT") {
+            match parse_arg::<i64>(value) {
+                Some(n) => count = (n > 0).then_some(n as usize),
+                None => return Ok(error(NOT_INTEGER)),
+            }
+        } else if arg.eq_ignore_ascii_case(b"BLOCK") {
+            match parse_block_millis(value) {
+                Ok(timeout) => block = Some(timeout),
+                Err(e) => return Ok(error(e)),
+            }
+        } else {
+            return Ok(error(SYNTAX_ERR));
+        }
+        i += 2;
+    };
+
+    let streams = &parts[streams_at..];
+    if streams.is_empty() || !streams.len().is_multiple_of(2) {
+        return Ok(error(
+            "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.",
+        ));
+    }
+    let (keys, ids) = streams.split_at(streams.len() / 2);
+
+    let mut after = Vec::with_capacity(ids.len());
+    for (key, id) in keys.iter().zip(ids) {
+        if id.as_slice() == b"$" {
+            match db.stream_last_id(key) {
+                Ok(last) => after.push(last),
+                Err(e) => return Ok(error(e)),
+            }
+        } else {
+            match StreamId::parse(id, 0) {
+                Some(id) => after.push(id),
+                None => return Ok(error(INVALID_ID)),
+            }
+        }
+    }
+
+    let mut reply = Vec::new();
+    for (key, id) in keys.iter().zip(&after) {
+        match db.xread(key, *id, count) {
+            Ok(entries) if entries.is_empty() => {}
+            Ok(entries) => reply.push(format_stream(key, &entries)),
+            Err(e) => return Ok(error(e)),
+        }
+    }
+    if !reply.is_empty() {
+        return Ok(join_arrays(reply));
+    }
+
+    let Some(timeout) = block else {
+        return Ok(NULL_ARRAY.to_vec());
+    };
+
+    // Pin `$` to what it meant now, so the retry only sees newer entries.
+    let mut retry = parts[..streams_at + keys.len()].to_vec();
+    retry.extend(after.iter().map(|id| id.to_string().into_bytes()));
+    Err(BlockRequest {
+        keys: keys.to_vec(),
+        timeout,
+        retry,
+    })
+}
+
+// XREADGROUP GROUP group consumer [COUNT n] [BLOCK ms] [NOACK] STREAMS key [key ...] id [id ...]
+pub fn xreadgroup(parts: &[Vec<u8>], db: &mut Database) -> Result<Vec<u8>, BlockRequest> {
+    let (Some(group_kw), Some(group), Some(consumer)) = (parts.get(1), parts.get(2), parts.get(3))
+    else {
+        return Ok(wrong_args("XREADGROUP"));
+    };
+    if !group_kw.eq_ignore_ascii_case(b"GROUP") {
+        return Ok(error(SYNTAX_ERR));
+    }
+
+    let mut count = None;
+    let mut block: Option<Option<Duration>> = None;
+    let mut noack = false;
+    let mut i = 4;
+    let streams_at = loop {
+        let Some(arg) = parts.get(i) else {
+            return Ok(error(SYNTAX_ERR));
+        };
+        if arg.eq_ignore_ascii_case(b"STREAMS") {
+            break i + 1;
+        }
+        if arg.eq_ignore_ascii_case(b"NOACK") {
+            noack = true;
+            i += 1;
+            continue;
+        }
+        let Some(value) = parts.get(i + 1) else {
+            return Ok(error(SYNTAX_ERR));
+        };
+        if arg.eq_ignore_ascii_case(b"COUNT") {
+            match parse_arg::<i64>(value) {
+                Some(n) => count = (n > 0).then_some(n as usize),
+                None => return Ok(error(NOT_INTEGER)),
+            }
+        } else if arg.eq_ignore_ascii_case(b"BLOCK") {
+            match parse_block_millis(value) {
+                Ok(timeout) => block = Some(timeout),
+                Err(e) => return Ok(error(e)),
+            }
+        } else {
+            return Ok(error(SYNTAX_ERR));
+        }
+        i += 2;
+    };
+
+    let streams = &parts[streams_at..];
+    if streams.is_empty() || !streams.len().is_multiple_of(2) {
+        return Ok(error(
+            "ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified.",
+        ));
+    }
+    let (keys, ids) = streams.split_at(streams.len() / 2);
+
+    let mut starts = Vec::with_capacity(ids.len());
+    for (key, id) in keys.iter().zip(ids) {
+        match db.has_group(key, group) {
+            Ok(true) => {}
+            Ok(false) => {
+                return Ok(error(format!(
+                    "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                    String::from_utf8_lossy(key),
+                    String::from_utf8_lossy(group)
+                )))
+            }
+            Err(e) => return Ok(error(e)),
+        }
+        if id.as_slice() == b">" {
+            starts.push(None);
+        } else {
+            match StreamId::parse(id, 0) {
+                Some(id) => starts.push(Some(id)),
+                None => return Ok(error(INVALID_ID)),
+            }
+        }
+    }
+
+    let mut reply = Vec::new();
+    for (key, start) in keys.iter().zip(&starts) {
+        match db.xreadgroup(key, group, consumer, *start, count, noack) {
+            // New-message reads only report streams that produced something;
+            // history reads always report the stream.
+            Ok(entries) if entries.is_empty() && start.is_none() => {}
+            Ok(entries) => reply.push(format_delivered_stream(key, &entries)),
+            Err(e) => return Ok(error(e)),
+        }
+    }
+    if !reply.is_empty() {
+        return Ok(join_arrays(reply));
+    }
+
+    // Only `>` reads wait; history reads always answer immediately.
+    match block {
+        Some(timeout) if starts.iter().all(Option::is_none) => Err(BlockRequest {
+            keys: keys.to_vec(),
+            timeout,
+            retry: parts.to_vec(),
+        }),
+        _ => Ok(NULL_ARRAY.to_vec()),
+    }
+}
+
+fn xgroup(parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    let Some(sub) = parts.get(1) else {
+        return wrong_args("XGROUP");
+    };
+    let sub = String::from_utf8_lossy(sub).to_uppercase();
+
+    match sub.as_str() {
+        // XGROUP CREATE key group <id|$> [MKSTREAM] [ENTRIESREAD n]
+        "CREATE" => {
+            let (Some(key), Some(group), Some(id)) = (parts.get(2), parts.get(3), parts.get(4)) else {
+                return wrong_args("XGROUP CREATE");
+            };
+            let mut mkstream = false;
+            let mut i = 5;
+            while let Some(opt) = parts.get(i) {
+                if opt.eq_ignore_ascii_case(b"MKSTREAM") {
+                    mkstream = true;
+                    i += 1;
+                } else if opt.eq_ignore_ascii_case(b"ENTRIESREAD") && parts.get(i + 1).is_some() {
+                    // Lag tracking isn't implemented; the value is accepted for compatibility.
+                    i += 2;
+                } else {
+                    return error(SYNTAX_ERR);
+                }
+            }
+            let start = match parse_group_id(id) {
+                Ok(start) => start,
+                Err(e) => return error(e),
+            };
+            match db.xgroup_create(key, group, start, mkstream) {
+                Ok(()) => simple_string("OK"),
+                Err(e) => error(e),
+            }
+        }
+
+        // XGROUP SETID key group <id|$> [ENTRIESREAD n]
+        "SETID" => {
+            let (Some(key), Some(group), Some(id)) = (parts.get(2), parts.get(3), parts.get(4)) else {
+                return wrong_args("XGROUP SETID");
+            };
+            let start = match parse_group_id(id) {
+                Ok(start) => start,
+                Err(e) => return error(e),
+            };
+            match db.xgroup_setid(key, group, start) {
+                Ok(()) => simple_string("OK"),
+                Err(e) => error(e),
+            }
+        }
+
+        "DESTROY" => {
+            let (Some(key), Some(group), None) = (parts.get(2), parts.get(3), parts.get(4)) else {
+                return wrong_args("XGROUP DESTROY");
+            };
+            match db.xgroup_destroy(key, group) {
+                Ok(destroyed) => integer(destroyed as i64),
+                Err(e) => error(e),
+            }
+        }
+
+        "CREATECONSUMER" => {
+            let (Some(key), Some(group), Some(consumer), None) =
+                (parts.get(2), parts.get(3), parts.get(4), parts.get(5))
+            else {
+                return wrong_args("XGROUP CREATECONSUMER");
+            };
+            match db.xgroup_createconsumer(key, group, consumer) {
+                Ok(created) => integer(created as i64),
+                Err(e) => error(e),
+            }
+        }
+
+        "DELCONSUMER" => {
+            let (Some(key), Some(group), Some(consumer), None) =
+                (parts.get(2), parts.get(3), parts.get(4), parts.get(5))
+            else {
+                return wrong_args("XGROUP DELCONSUMER");
+            };
+            match db.xgroup_delconsumer(key, group, consumer) {
+                Ok(pending) => integer(pending as i64),
+                Err(e) => error(e),
+            }
+        }
+
+        _ => error(format!("ERR unknown subcommand '{}'", sub)),
+    }
+}
+
+// XPENDING key group [[IDLE min-idle] start end count [consumer]]
+fn xpending(parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    let (Some(key), Some(group)) = (parts.get(1), parts.get(2)) else {
+        return wrong_args("XPENDING");
+    };
+
+    let pending = match db.xpending(key, group) {
+        Ok(pending) => pending,
+        Err(e) => return error(e),
+    };
+
+    if parts.len() == 3 {
+        let (Some((first, _)), Some((last, _))) = (pending.first(), pending.last()) else {
+            return b"*4\r\n:0\r\n$-1\r\n$-1\r\n*-1\r\n".to_vec();
+        };
+
+        let mut per_consumer: Vec<(Vec<u8>, usize)> = Vec::new();
+        for (_, entry) in &pending {
+            match per_consumer.iter_mut().find(|(c, _)| *c == entry.consumer) {
+                Some((_, n)) => *n += 1,
+                None => per_consumer.push((entry.consumer.clone(), 1)),
+            }
+        }
+        per_consumer.sort();
+
+        let mut resp = b"*4\r\n".to_vec();
+        resp.extend(integer(pending.len() as i64));
+        resp.extend(bulk_string(first.to_string().as_bytes()));
+        resp.extend(bulk_string(last.to_string().as_bytes()));
+        resp.extend(format!("*{}\r\n", per_consumer.len()).into_bytes());
+        for (consumer, n) in per_consumer {
+            resp.extend(format_array(vec![consumer, n.to_string().into_bytes()]));
+        }
+        return resp;
+    }
+
+    let mut i = 3;
+    let mut min_idle = 0;
+    if parts[i].eq_ignore_ascii_case(b"IDLE") {
+        match parts.get(i + 1).and_then(|a| parse_arg::<u64>(a)) {
+            Some(idle) => min_idle = idle,
+            None => return error(NOT_INTEGER),
+        }
+        i += 2;
+    }
+    let (Some(start), Some(end), Some(count)) = (parts.get(i), parts.get(i + 1), parts.get(i + 2))
+    else {
+        return error(SYNTAX_ERR);
+    };
+    let consumer = parts.get(i + 3);
+    if parts.len() > i + 4 {
+        return error(SYNTAX_ERR);
+    }
+    let (Some(start), Some(end)) = (parse_range_start(start), parse_range_end(end)) else {
+        return error(INVALID_ID);
+    };
+    let Some(count) = parse_arg::<i64>(count) else {
+        return error(NOT_INTEGER);
+    };
+
+    let now = current_unix_millis();
+    let mut resp = Vec::new();
+    let mut n = 0;
+    for (id, entry) in pending
+        .iter()
+        .filter(|(id, _)| *id >= start && *id <= end)
+        .filter(|(_, e)| consumer.is_none_or(|c| *c == e.consumer))
+        .filter(|(_, e)| now.saturating_sub(e.delivered_at) >= min_idle)
+        .take(count.max(0) as usize)
+    {
+        resp.extend(b"*4\r\n");
+        resp.extend(bulk_string(id.to_string().as_bytes()));
+        resp.extend(bulk_string(&entry.consumer));
+        resp.extend(integer(now.saturating_sub(entry.delivered_at) as i64));
+        resp.extend(integer(entry.delivery_count as i64));
+        n += 1;
+    }
+    let mut out = format!("*{}\r\n", n).into_bytes();
+    out.extend(resp);
+    out
+}
+
+// XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME ms]
+//        [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID id]
+fn xclaim(parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    if parts.len() < 6 {
+        return wrong_args("XCLAIM");
+    }
+    let Some(min_idle) = parse_arg::<i64>(&parts[4]) else {
+        return error("ERR Invalid min-idle-time argument for XCLAIM");
+    };
+
+    let mut opts = ClaimOptions {
+        min_idle: min_idle.max(0) as u64,
+        ..ClaimOptions::default()
+    };
+    let mut ids = Vec::new();
+    let mut i = 5;
+    while let Some(arg) = parts.get(i) {
+        match StreamId::parse(arg, 0) {
+            Some(id) => ids.push(id),
+            None => break,
+        }
+        i += 1;
+    }
+    if ids.is_empty() {
+        return error(INVALID_ID);
+    }
+
+    let now = current_unix_millis();
+    while let Some(arg) = parts.get(i) {
+        let opt = arg.to_ascii_uppercase();
+        match opt.as_slice() {
+            b"FORCE" => opts.force = true,
+            b"JUSTID" => opts.just_id = true,
+            b"IDLE" | b"TIME" | b"RETRYCOUNT" => {
+                let Some(value) = parts.get(i + 1).and_then(|v| parse_arg::<i64>(v)) else {
+                    return error(NOT_INTEGER);
+                };
+                let value = value.max(0) as u64;
+                match opt.as_slice() {
+                    b"IDLE" => opts.delivered_at = Some(now.saturating_sub(value)),
+                    b"TIME" => opts.delivered_at = Some(value),
+                    _ => opts.retry_count = Some(value),
+                }
+                i += 1;
+            }
+            // Only meaningful for replication; accepted and ignored.
+            b"LASTID" if parts.get(i + 1).is_some() => i += 1,
+            _ => return error(format!("ERR Unrecognized XCLAIM option '{}'", String::from_utf8_lossy(arg))),
+        }
+        i += 1;
+    }
+
+    match db.xclaim(&parts[1], &parts[2], &parts[3], &ids, opts) {
+        Ok(claimed) => format_claimed(&claimed, opts.just_id),
+        Err(e) => error(e),
+    }
+}
+
+// XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]
+fn xautoclaim(parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    if parts.len() < 6 {
+        return wrong_args("XAUTOCLAIM");
+    }
+    let Some(min_idle) = parse_arg::<i64>(&parts[4]) else {
+        return error("ERR Invalid min-idle-time argument for XAUTOCLAIM");
+    };
+    let Some(start) = parse_range_start(&parts[5]) else {
+        return error(INVALID_ID);
+    };
+
+    let mut opts = ClaimOptions {
+        min_idle: min_idle.max(0) as u64,
+        ..ClaimOptions::default()
+    };
+    let mut count = 100;
+    let mut i = 6;
+    while let Some(arg) = parts.get(i) {
+        if arg.eq_ignore_ascii_case(b"JUSTID") {
+            opts.just_id = true;
+        } else if arg.eq_ignore_ascii_case(b"COUNT") {
+            match parts.get(i + 1).and_then(|v| parse_arg::<i64>(v)) {
+                Some(n) if n > 0 => count = n as usize,
+                Some(_) => return error("ERR COUNT must be > 0"),
+                None => return error(NOT_INTEGER),
+            }
+            i += 1;
+        } else {
+            return error(SYNTAX_ERR);
+        }
+        i += 1;
+    }
+
+    match db.xautoclaim(&parts[1], &parts[2], &parts[3], start, count, opts) {
+        Ok((next, claimed, deleted)) => {
+            let mut resp = b"*3\r\n".to_vec();
+            resp.extend(bulk_string(next.to_string().as_bytes()));
+            resp.extend(format_claimed(&claimed, opts.just_id));
+            resp.extend(format_array(
+                deleted.iter().map(|id| id.to_string().into_bytes()).collect(),
+            ));
+            resp
+        }
+        Err(e) => error(e),
+    }
+}
+
+/// Parses `MAXLEN|MINID [=|~] threshold [LIMIT count]` starting at `parts[i]`.
+/// Returns the spec and the index of the first unconsumed argument.
+fn parse_trim(parts: &[Vec<u8>], mut i: usize) -> Result<(TrimSpec, usize), Vec<u8>> {
+    let Some(kind) = parts.get(i) else {
+        return Err(error(SYNTAX_ERR));
+    };
+    let maxlen = kind.eq_ignore_ascii_case(b"MAXLEN");
+    if !maxlen && !kind.eq_ignore_ascii_case(b"MINID") {
+        return Err(error(SYNTAX_ERR));
+    }
+    i += 1;
+
+    let mut approx = false;
+    match parts.get(i).map(|a| a.as_slice()) {
+        Some(b"~") => {
+            approx = true;
+            i += 1;
+        }
+        Some(b"=") => i += 1,
+        _ => {}
+    }
+
+    let Some(threshold) = parts.get(i) else {
+        return Err(error(SYNTAX_ERR));
+    };
+    let strategy = if maxlen {
+        match parse_arg::<i64>(threshold) {
+            Some(n) if n >= 0 => TrimStrategy::MaxLen(n as usize),
+            Some(_) => return Err(error("ERR The MAXLEN argument must be >= 0.")),
+            None => return Err(error(NOT_INTEGER)),
+        }
+    } else {
+        match StreamId::parse(threshold, 0) {
+            Some(id) => TrimStrategy::MinId(id),
+            None => return Err(error(INVALID_ID)),
+        }
+    };
+    i += 1;
+
+    let mut limit = None;
+    if parts.get(i).is_some_and(|a| a.eq_ignore_ascii_case(b"LIMIT")) {
+        if !approx {
+            return Err(error("ERR syntax error, LIMIT cannot be used without the special ~ option"));
+        }
+        match parts.get(i + 1).and_then(|a| parse_arg::<i64>(a)) {
+            Some(n) if n >= 0 => limit = (n > 0).then_some(n as usize),
+            _ => return Err(error(NOT_INTEGER)),
+        }
+        i += 2;
+    }
+
+    Ok((TrimSpec { strategy, limit }, i))
+}
+
+fn parse_id_spec(arg: &[u8]) -> Option<IdSpec> {
+    if arg == b"*" {
+        return Some(IdSpec::Auto);
+    }
+    if let Some(ms) = arg.strip_suffix(b"-*") {
+        return parse_arg::<u64>(ms).map(IdSpec::AutoSeq);
+    }
+    StreamId::parse(arg, 0).map(IdSpec::Explicit)
+}
+
+/// `$` means "the stream's last ID" and is returned as `None`.
+fn parse_group_id(arg: &[u8]) -> Result<Option<StreamId>, &'static str> {
+    if arg == b"$" {
+        Ok(None)
+    } else {
+        StreamId::parse(arg, 0).map(Some).ok_or(INVALID_ID)
+    }
+}
+
+fn parse_range_start(arg: &[u8]) -> Option<StreamId> {
+    match arg {
+        b"-" => Some(StreamId::MIN),
+        _ => match arg.strip_prefix(b"(") {
+            Some(id) => StreamId::parse(id, 0)?.next(),
+            None => StreamId::parse(arg, 0),
+        },
+    }
+}
+
+fn parse_range_end(arg: &[u8]) -> Option<StreamId> {
+    match arg {
+        b"+" => Some(StreamId::MAX),
+        _ => match arg.strip_prefix(b"(") {
+            Some(id) => StreamId::parse(id, u64::MAX)?.prev(),
+            None => StreamId::parse(arg, u64::MAX),
+        },
+    }
+}
+
+fn format_entry(id: StreamId, fields: Option<&Vec<Vec<u8>>>) -> Vec<u8> {
+    let mut resp = b"*2\r\n".to_vec();
+    resp.extend(bulk_string(id.to_string().as_bytes()));
+    match fields {
+        Some(fields) => resp.extend(format_array(fields.clone())),
+        None => resp.extend(NULL_ARRAY),
+    }
+    resp
+}
+
+fn format_entries(entries: &[Entry]) -> Vec<u8> {
+    let mut resp = format!("*{}\r\n", entries.len()).into_bytes();
+    for (id, fields) in entries {
+        resp.extend(format_entry(*id, Some(fields)));
+    }
+    resp
+}
+
+fn format_stream(key: &[u8], entries: &[Entry]) -> Vec<u8> {
+    let mut resp = b"*2\r\n".to_vec();
+    resp.extend(bulk_string(key));
+    resp.extend(format_entries(entries));
+    resp
+}
+
+fn format_delivered_stream(key: &[u8], entries: &Delivered) -> Vec<u8> {
+    let mut resp = b"*2\r\n".to_vec();
+    resp.extend(bulk_string(key));
+    resp.extend(format!("*{}\r\n", entries.len()).into_bytes());
+    for (id, fields) in entries {
+        resp.extend(format_entry(*id, fields.as_ref()));
+    }
+    resp
+}
+
+fn format_claimed(claimed: &Delivered, just_id: bool) -> Vec<u8> {
+    if just_id {
+        return format_array(claimed.iter().map(|(id, _)| id.to_string().into_bytes()).collect());
+    }
+    let mut resp = format!("*{}\r\n", claimed.len()).into_bytes();
+    for (id, fields) in claimed {
+        resp.extend(format_entry(*id, fields.as_ref()));
+    }
+    resp
+}
+
+fn join_arrays(items: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut resp = format!("*{}\r\n", items.len()).into_bytes();
+    for item in items {
+        resp.extend(item);
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::StoreError;
+
+    fn run(db: &mut Database, args: &[&str]) -> Vec<u8> {
+        let parts: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        handle_stream(&args[0].to_uppercase(), &parts, db)
+    }
+
+    fn ids(db: &mut Database, key: &str) -> Vec<String> {
+        let entries = db.xrange(key.as_bytes(), StreamId::MIN, StreamId::MAX, false, None);
+        entries.unwrap().iter().map(|(id, _)| id.to_string()).collect()
+    }
+
+    /// The group's PEL as `(id, consumer, delivery count)`.
+    fn pending(db: &mut Database, key: &str) -> Vec<(String, String, u64)> {
+        let pending = db.xpending(key.as_bytes(), b"g").unwrap();
+        pending
+            .into_iter()
+            .map(|(id, p)| {
+                let consumer = String::from_utf8(p.consumer).unwrap();
+                (id.to_string(), consumer, p.delivery_count)
+            })
+            .collect()
+    }
+
+    fn entry(id: &str) -> Vec<u8> {
+        let id = StreamId::parse(id.as_bytes(), 0).unwrap();
+        format_entry(id, Some(&vec![b"f".to_vec(), b"v".to_vec()]))
+    }
+
+    /// Four entries 1-0 .. 4-0 and a group `g` that hasn't read any.
+    fn stream_with_group(db: &mut Database) {
+        for id in ["1-0", "2-0", "3-0", "4-0"] {
+            run(db, &["XADD", "s", id, "f", "v"]);
+        }
+        run(db, &["XGROUP", "CREATE", "s", "g", "0"]);
+    }
+
+    #[test]
+    fn xadd_only_takes_increasing_ids() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, &["XADD", "s", "0-0", "f", "v"]), error(StoreError::StreamIdZero));
+        assert!(!db.store_ref().contains_key(&b"s"[..]));
+        assert_eq!(run(&mut db, &["XADD", "s", "nope", "f", "v"]), error(INVALID_ID));
+
+        assert_eq!(run(&mut db, &["XADD", "s", "5-1", "f", "v"]), bulk_string(b"5-1"));
+        for stale in ["5-1", "5-0", "5", "4-9"] {
+            let reply = run(&mut db, &["XADD", "s", stale, "f", "v"]);
+            assert_eq!(reply, error(StoreError::StreamIdTooSmall), "{stale}");
+        }
+        assert_eq!(run(&mut db, &["XADD", "s", "6", "f", "v"]), bulk_string(b"6-0"));
+        assert_eq!(ids(&mut db, "s"), ["5-1", "6-0"]);
+    }
+
+    #[test]
+    fn xadd_generates_ids() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, &["XADD", "s", "5-*", "f", "v"]), bulk_string(b"5-0"));
+        assert_eq!(run(&mut db, &["XADD", "s", "5-*", "f", "v"]), bulk_string(b"5-1"));
+        assert_eq!(run(&mut db, &["XADD", "s", "7-*", "f", "v"]), bulk_string(b"7-0"));
+        let reply = run(&mut db, &["XADD", "s", "6-*", "f", "v"]);
+        assert_eq!(reply, error(StoreError::StreamIdTooSmall));
+
+        // `*` uses the clock, unless the last ID is ahead of it.
+        let before = current_unix_millis();
+        run(&mut db, &["XADD", "s", "*", "f", "v"]);
+        let last = db.stream_last_id(b"s").unwrap();
+        assert!(last.ms >= before && last.ms <= current_unix_millis(), "{last}");
+        run(&mut db, &["XADD", "f", "99999999999999-5", "f", "v"]);
+        let reply = run(&mut db, &["XADD", "f", "*", "f", "v"]);
+        assert_eq!(reply, bulk_string(b"99999999999999-6"));
+    }
+
+    #[test]
+    fn trimming_by_length_and_minimum_id() {
+        let mut db = Database::new();
+        for ms in 1..=5 {
+            let id = format!("{ms}-0");
+            run(&mut db, &["XADD", "s", "MAXLEN", "3", &id, "f", "v"]);
+        }
+        assert_eq!(ids(&mut db, "s"), ["3-0", "4-0", "5-0"]);
+
+        assert_eq!(run(&mut db, &["XTRIM", "s", "MINID", "5"]), integer(2));
+        assert_eq!(ids(&mut db, "s"), ["5-0"]);
+        run(&mut db, &["XADD", "s", "MINID", "=", "7", "6-0", "f", "v"]);
+        assert_eq!(ids(&mut db, "s"), Vec::<String>::new());
+        assert_eq!(run(&mut db, &["XLEN", "s"]), integer(0));
+
+        for ms in 7..=10 {
+            run(&mut db, &["XADD", "s", &format!("{ms}-0"), "f", "v"]);
+        }
+        // LIMIT caps how much one call removes.
+        assert_eq!(run(&mut db, &["XTRIM", "s", "MAXLEN", "~", "0", "LIMIT", "3"]), integer(3));
+        assert_eq!(ids(&mut db, "s"), ["10-0"]);
+        assert_eq!(
+            run(&mut db, &["XTRIM", "s", "MAXLEN", "0", "LIMIT", "3"]),
+            error("ERR syntax error, LIMIT cannot be used without the special ~ option")
+        );
+        assert_eq!(
+            run(&mut db, &["XTRIM", "s", "MAXLEN", "-1"]),
+            error("ERR The MAXLEN argument must be >= 0.")
+        );
+    }
+
+    #[test]
+    fn xreadgroup_tracks_pending_entries_until_acked() {
+        let mut db = Database::new();
+        stream_with_group(&mut db);
+        let read = |db: &mut Database, consumer: &str, start: &str| {
+            let args = ["XREADGROUP", "GROUP", "g", consumer, "COUNT", "2", "STREAMS", "s", start];
+            run(db, &args)
+        };
+
+        let mut alice = b"*1\r\n*2\r\n".to_vec();
+        alice.extend(bulk_string(b"s"));
+        alice.extend(b"*2\r\n");
+        alice.extend(entry("1-0"));
+        alice.extend(entry("2-0"));
+        assert_eq!(read(&mut db, "alice", ">"), alice);
+        // Reading from an ID replays the consumer's own pending entries.
+        assert_eq!(read(&mut db, "alice", "0"), alice);
+        read(&mut db, "bob", ">");
+        assert_eq!(
+            pending(&mut db, "s"),
+            [
+                ("1-0".into(), "alice".into(), 1),
+                ("2-0".into(), "alice".into(), 1),
+                ("3-0".into(), "bob".into(), 1),
+                ("4-0".into(), "bob".into(), 1),
+            ]
+        );
+        // Nothing new is left for anyone.
+        assert_eq!(read(&mut db, "carol", ">"), NULL_ARRAY);
+
+        assert_eq!(run(&mut db, &["XACK", "s", "g", "1-0", "3-0", "9-0"]), integer(2));
+        assert_eq!(run(&mut db, &["XACK", "s", "g", "1-0"]), integer(0));
+        run(&mut db, &["XDEL", "s", "2-0"]);
+        let mut history = b"*1\r\n*2\r\n".to_vec();
+        history.extend(bulk_string(b"s"));
+        history.extend(b"*1\r\n");
+        history.extend(format_entry(StreamId::new(2, 0), None));
+        assert_eq!(read(&mut db, "alice", "0"), history);
+
+        run(&mut db, &["XADD", "s", "5-0", "f", "v"]);
+        let noack = ["XREADGROUP", "GROUP", "g", "carol", "NOACK", "STREAMS", "s", ">"];
+        run(&mut db, &noack);
+        assert_eq!(pending(&mut db, "s").len(), 2);
+        let missing = run(&mut db, &["XREADGROUP", "GROUP", "nope", "c", "STREAMS", "s", ">"]);
+        assert!(missing.starts_with(b"-NOGROUP No such key 's' or consumer group 'nope'"));
+    }
+
+    #[test]
+    fn xclaim_moves_pending_entries() {
+        let mut db = Database::new();
+        stream_with_group(&mut db);
+        run(&mut db, &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"]);
+
+        // Only entries idle for min-idle-time are taken.
+        assert_eq!(run(&mut db, &["XCLAIM", "s", "g", "bob", "60000", "1-0"]), b"*0\r\n");
+        let mut claimed = b"*1\r\n".to_vec();
+        claimed.extend(entry("1-0"));
+        assert_eq!(run(&mut db, &["XCLAIM", "s", "g", "bob", "0", "1-0"]), claimed);
+        let just_id = ["XCLAIM", "s", "g", "bob", "0", "2-0", "RETRYCOUNT", "7", "JUSTID"];
+        assert_eq!(run(&mut db, &just_id), format_array(vec![b"2-0".to_vec()]));
+        run(&mut db, &["XCLAIM", "s", "g", "bob", "0", "3-0", "JUSTID"]);
+
+        // An acked entry comes back only with FORCE; a deleted one leaves
+        // the PEL instead.
+        run(&mut db, &["XACK", "s", "g", "4-0"]);
+        assert_eq!(run(&mut db, &["XCLAIM", "s", "g", "bob", "0", "4-0"]), b"*0\r\n");
+        run(&mut db, &["XCLAIM", "s", "g", "carol", "0", "4-0", "FORCE", "JUSTID"]);
+        run(&mut db, &["XDEL", "s", "3-0"]);
+        assert_eq!(run(&mut db, &["XCLAIM", "s", "g", "bob", "0", "3-0"]), b"*0\r\n");
+        assert_eq!(
+            pending(&mut db, "s"),
+            [
+                ("1-0".into(), "bob".into(), 2),
+                ("2-0".into(), "bob".into(), 7),
+                ("4-0".into(), "carol".into(), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn xautoclaim_scans_the_pel_from_a_cursor() {
+        let mut db = Database::new();
+        stream_with_group(&mut db);
+        run(&mut db, &["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"]);
+        let idle = ["XCLAIM", "s", "g", "alice", "0", "1-0", "2-0", "3-0", "TIME", "1", "JUSTID"];
+        run(&mut db, &idle);
+        run(&mut db, &["XDEL", "s", "2-0"]);
+
+        let autoclaim = |db: &mut Database, start: &str, count: &str| {
+            let args = ["XAUTOCLAIM", "s", "g", "bob", "60000", start, "COUNT", count, "JUSTID"];
+            run(db, &args)
+        };
+        let reply = |next: &str, claimed: &[&str], deleted: &[&str]| {
+            let ids = |ids: &[&str]| ids.iter().map(|id| id.as_bytes().to_vec()).collect();
+            let mut reply = b"*3\r\n".to_vec();
+            reply.extend(bulk_string(next.as_bytes()));
+            reply.extend(format_array(ids(claimed)));
+            reply.extend(format_array(ids(deleted)));
+            reply
+        };
+
+        assert_eq!(autoclaim(&mut db, "0", "1"), reply("2-0", &["1-0"], &[]));
+        // 4-0 was delivered just now, so the scan passes it by and ends.
+        assert_eq!(autoclaim(&mut db, "2-0", "5"), reply("0-0", &["3-0"], &["2-0"]));
+        assert_eq!(
+            pending(&mut db, "s"),
+            [
+                ("1-0".into(), "bob".into(), 1),
+                ("3-0".into(), "bob".into(), 1),
+                ("4-0".into(), "alice".into(), 1),
+            ]
+        );
+
+        // Without JUSTID the entries come back and count as delivered again.
+        run(&mut db, &["XCLAIM", "s", "g", "alice", "0", "1-0", "TIME", "1", "JUSTID"]);
+        let mut claimed = b"*3\r\n".to_vec();
+        claimed.extend(bulk_string(b"0-0"));
+        claimed.extend(b"*1\r\n");
+        claimed.extend(entry("1-0"));
+        claimed.extend(b"*0\r\n");
+        assert_eq!(run(&mut db, &["XAUTOCLAIM", "s", "g", "carol", "60000", "-"]), claimed);
+        assert_eq!(pending(&mut db, "s")[0], ("1-0".into(), "carol".into(), 2));
+        assert_eq!(
+            run(&mut db, &["XAUTOCLAIM", "s", "g", "c", "0", "-", "COUNT", "0"]),
+            error("ERR COUNT must be > 0")
+        );
+    }
+}
@@ -1,8 +1,15 @@
-use crate::{resp::utils::{bulk_string, wrong_args}, store::Database};
+use crate::{
+    resp::utils::{bulk_string, error, integer, null_bulk, parse_arg, simple_string, wrong_args},
+    store::{
+        commands::string::{KeyExpiry, SetCondition},
+        Database, StoreError,
+    },
+    utils::current_unix_millis,
+};
 
-pub fn handle_string(cmd: &str, parts: &[String], db: &mut Database) -> String {
+pub fn handle_string(cmd: &str, parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
     match cmd{
-        "PING" => "+PONG\r\n".to_string(),
+        "PING" => simple_string("PONG"),
 
         "ECHO" => {
             if let Some(arg) = parts.get(1) {
@@ -12,34 +19,150 @@ pub fn handle_string(cmd: &str, parts: &[String], db: &mut Database) -> String {
             }
         }
 
-        "SET" => {
-            if let (Some(key), Some(value)) = (parts.get(1), parts.get(2)) {
-                let mut expiry = None;
+        "SET" => set(parts, db),
 
-                if let (Some(option), Some(seconds)) = (parts.get(3), parts.get(4)) {
-                    if option.to_uppercase() == "EX" {
-                        if let Ok(sec) = seconds.parse::<u64>() {
-                            expiry = Some(sec);
-                        }
-                    }
+        "GET" => {
+            if let Some(key) = parts.get(1) {
+                match db.get(key) {
+                    Ok(Some(value)) => bulk_string(&value),
+                    Ok(None) => null_bulk(),
+                    Err(e) => error(e),
                 }
-
-                db.set(key, value.clone(), expiry);
-                "+OK\r\n".to_string()
             } else {
-                wrong_args("SET")
+                wrong_args("GET")
             }
         }
 
-        "GET" => {
-            if let Some(key) = parts.get(1) {
-                if let Some(value) = db.get(key) {
-                    bulk_string(&value)
-                } else {
-                    "$-1\r\n".to_string()
+        "SETNX" => {
+            let [_, key, value] = parts else {
+                return wrong_args("SETNX");
+            };
+            integer(db.setnx(key, value.clone()) as i64)
+        }
+
+        "SETEX" | "PSETEX" => {
+            let [_, key, time, value] = parts else {
+                return wrong_args(cmd);
+            };
+            let unit = if cmd == "SETEX" { "EX" } else { "PX" };
+            match parse_expire_at(unit, time, cmd) {
+                Ok(at) => {
+                    db.set(key, value.clone(), KeyExpiry::At(at));
+                    simple_string("OK")
                 }
+                Err(reply) => reply,
+            }
+        }
+
+        "MGET" => {
+            if parts.len() < 2 {
+                return wrong_args("MGET");
+            }
+            let values = db.mget(&parts[1..]);
+            let mut reply = format!("*{}\r\n", values.len()).into_bytes();
+            for value in values {
+                reply.extend(value.map_or_else(null_bulk, |v| bulk_string(&v)));
+            }
+            reply
+        }
+
+        "MSET" | "MSETNX" => {
+            if parts.len() < 3 || parts.len().is_multiple_of(2) {
+                return wrong_args(cmd);
+            }
+            let pairs: Vec<_> = parts[1..]
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect();
+            if cmd == "MSET" {
+                db.mset(&pairs);
+                simple_string("OK")
             } else {
-                wrong_args("GET")
+                integer(db.msetnx(&pairs) as i64)
+            }
+        }
+
+        "GETSET" => {
+            let [_, key, value] = parts else {
+                return wrong_args("GETSET");
+            };
+            reply_value(db.getset(key, value.clone()))
+        }
+
+        "GETDEL" => {
+            let [_, key] = parts else {
+                return wrong_args("GETDEL");
+            };
+            reply_value(db.getdel(key))
+        }
+
+        "GETEX" => {
+            let Some(key) = parts.get(1) else {
+                return wrong_args("GETEX");
+            };
+            let expiry = match &parts[2..] {
+                [] => KeyExpiry::Keep,
+                [option] if option.eq_ignore_ascii_case(b"PERSIST") => KeyExpiry::Clear,
+                [option, time] => {
+                    let option = String::from_utf8_lossy(option).to_uppercase();
+                    if !matches!(option.as_str(), "EX" | "PX" | "EXAT" | "PXAT") {
+                        return error("ERR syntax error");
+                    }
+                    match parse_expire_at(&option, time, cmd) {
+                        Ok(at) => KeyExpiry::At(at),
+                        Err(reply) => return reply,
+                    }
+                }
+                _ => return error("ERR syntax error"),
+            };
+            reply_value(db.getex(key, expiry))
+        }
+
+        "APPEND" => {
+            let [_, key, suffix] = parts else {
+                return wrong_args("APPEND");
+            };
+            match db.append(key, suffix) {
+                Ok(len) => integer(len as i64),
+                Err(e) => error(e),
+            }
+        }
+
+        "STRLEN" => {
+            let [_, key] = parts else {
+                return wrong_args("STRLEN");
+            };
+            match db.strlen(key) {
+                Ok(len) => integer(len as i64),
+                Err(e) => error(e),
+            }
+        }
+
+        "GETRANGE" => {
+            let [_, key, start, end] = parts else {
+                return wrong_args("GETRANGE");
+            };
+            let (Some(start), Some(end)) = (parse_arg::<i64>(start), parse_arg::<i64>(end)) else {
+                return error("ERR value is not an integer or out of range");
+            };
+            match db.getrange(key, start, end) {
+                Ok(range) => bulk_string(&range),
+                Err(e) => error(e),
+            }
+        }
+
+        "SETRANGE" => {
+            let [_, key, offset, data] = parts else {
+                return wrong_args("SETRANGE");
+            };
+            let offset = match parse_arg::<i64>(offset) {
+                Some(offset) if offset >= 0 => offset as usize,
+                Some(_) => return error("ERR offset is out of range"),
+                None => return error("ERR value is not an integer or out of range"),
+            };
+            match db.setrange(key, offset, data) {
+                Ok(len) => integer(len as i64),
+                Err(e) => error(e),
             }
         }
 
@@ -48,12 +171,296 @@ pub fn handle_string(cmd: &str, parts: &[String], db: &mut Database) -> String {
                 wrong_args("DEL")
             } else {
                 let deleted = db.delete(&parts[1..]);
-                format!(":{}\r\n", deleted)
+                integer(deleted as i64)
+            }
+        },
+        _ => b"-ERR unknown command\r\n".to_vec()
+
+    }
+
+    }
+
+// SET key value [NX|XX] [GET] [EX seconds|PX ms|EXAT unix-seconds|PXAT unix-ms|KEEPTTL]
+// with the options in any order.
+fn set(parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    let (Some(key), Some(value)) = (parts.get(1), parts.get(2)) else {
+        return wrong_args("SET");
+    };
+
+    let mut condition = None;
+    let mut get = false;
+    let mut expiry: Option<(String, Option<&Vec<u8>>)> = None;
+    let mut i = 3;
+    while i < parts.len() {
+        let option = String::from_utf8_lossy(&parts[i]).to_uppercase();
+        match option.as_str() {
+            "NX" | "XX" => {
+                let wanted = if option == "NX" { SetCondition::Nx } else { SetCondition::Xx };
+                if condition.is_some_and(|c| c != wanted) {
+                    return error("ERR syntax error");
+                }
+                condition = Some(wanted);
             }
+            "GET" => get = true,
+            "KEEPTTL" | "EX" | "PX" | "EXAT" | "PXAT" => {
+                let time = if option == "KEEPTTL" {
+                    None
+                } else {
+                    i += 1;
+                    match parts.get(i) {
+                        Some(time) => Some(time),
+                        None => return error("ERR syntax error"),
+                    }
+                };
+                // Only one expiry option, even the same one twice.
+                if expiry.is_some() {
+                    return error("ERR syntax error");
+                }
+                expiry = Some((option, time));
+            }
+            _ => return error("ERR syntax error"),
+        }
+        i += 1;
+    }
+
+    let expiry = match expiry {
+        None => KeyExpiry::Clear,
+        Some((_, None)) => KeyExpiry::Keep,
+        Some((option, Some(time))) => match parse_expire_at(&option, time, "SET") {
+            Ok(at) => KeyExpiry::At(at),
+            Err(reply) => return reply,
         },
-        _ => "-ERR unknown command\r\n".to_string()
+    };
+    match db.set_with_options(key, value.clone(), expiry, condition, get) {
+        Ok((_, old)) if get => old.map_or_else(null_bulk, |old| bulk_string(&old)),
+        Ok((true, _)) => simple_string("OK"),
+        Ok((false, _)) => null_bulk(),
+        Err(e) => error(e),
+    }
+}
+
+fn reply_value(value: Result<Option<Vec<u8>>, StoreError>) -> Vec<u8> {
+    match value {
+        Ok(Some(value)) => bulk_string(&value),
+        Ok(None) => null_bulk(),
+        Err(e) => error(e),
+    }
+}
 
+/// Parses the time given to an `EX`, `PX`, `EXAT` or `PXAT` option into the
+/// Unix millisecond the key expires at.
+fn parse_expire_at(option: &str, time: &[u8], cmd: &str) -> Result<u64, Vec<u8>> {
+    let Some(time) = parse_arg::<i64>(time) else {
+        return Err(error("ERR value is not an integer or out of range"));
+    };
+    let invalid = || error(format!("ERR invalid expire time in '{}' command", cmd.to_lowercase()));
+    if time <= 0 {
+        return Err(invalid());
+    }
+    let time = time as u64;
+    match option {
+        "EX" => time.checked_mul(1000).and_then(|ms| ms.checked_add(current_unix_millis())),
+        "PX" => time.checked_add(current_unix_millis()),
+        "EXAT" => time.checked_mul(1000),
+        _ => Some(time),
     }
-    
+    .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::RedisValue;
+
+    fn run(db: &mut Database, args: &[&str]) -> Vec<u8> {
+        let parts: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        handle_string(&args[0].to_uppercase(), &parts, db)
+    }
+
+    fn expire_at(db: &Database, key: &str) -> Option<u64> {
+        db.expiry_ref().get(key.as_bytes()).copied()
+    }
+
+    #[test]
+    fn set_takes_its_options_in_any_order() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, &["SET", "k", "a", "get", "EX", "100", "nx"]), null_bulk());
+        assert_eq!(run(&mut db, &["SET", "k", "b", "XX", "KEEPTTL", "GET"]), bulk_string(b"a"));
+        assert!(expire_at(&db, "k").is_some());
+        assert_eq!(run(&mut db, &["SET", "k", "c", "NX"]), null_bulk());
+        assert_eq!(run(&mut db, &["SET", "k", "c"]), simple_string("OK"));
+        assert_eq!(expire_at(&db, "k"), None);
+    }
+
+    #[test]
+    fn set_get_replies_with_the_old_value_whether_or_not_it_writes() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, &["SET", "k", "a", "NX", "GET"]), null_bulk());
+        assert_eq!(run(&mut db, &["GET", "k"]), bulk_string(b"a"));
+        assert_eq!(run(&mut db, &["SET", "k", "b", "GET", "NX"]), bulk_string(b"a"));
+        assert_eq!(run(&mut db, &["GET", "k"]), bulk_string(b"a"));
+        assert_eq!(run(&mut db, &["SET", "k", "b", "GET"]), bulk_string(b"a"));
+        assert_eq!(run(&mut db, &["GET", "k"]), bulk_string(b"b"));
+
+        assert_eq!(run(&mut db, &["SET", "new", "v", "XX", "GET"]), null_bulk());
+        assert!(!db.store_ref().contains_key(&b"new"[..]));
+
+        // GET needs the old value to be a string, and nothing is written if not.
+        db.store_mut().insert(b"set".to_vec(), RedisValue::Set(Default::default()));
+        let reply = run(&mut db, &["SET", "set", "v", "GET"]);
+        assert_eq!(reply, error(StoreError::WrongType));
+        assert!(matches!(db.store_ref().get(&b"set"[..]), Some(RedisValue::Set(_))));
+        assert_eq!(run(&mut db, &["SET", "set", "v"]), simple_string("OK"));
+    }
+
+    #[test]
+    fn set_keepttl_keeps_the_ttl_and_anything_else_replaces_it() {
+        let mut db = Database::new();
+        run(&mut db, &["SET", "k", "a", "PXAT", "4102444800123"]);
+        run(&mut db, &["SET", "k", "b", "keepttl"]);
+        assert_eq!(expire_at(&db, "k"), Some(4_102_444_800_123));
+        run(&mut db, &["SET", "k", "c", "xx", "pxat", "4102444800456"]);
+        assert_eq!(expire_at(&db, "k"), Some(4_102_444_800_456));
+        // A condition that isn't met leaves the TTL alone too.
+        run(&mut db, &["SET", "k", "d", "NX", "PX", "10"]);
+        assert_eq!(expire_at(&db, "k"), Some(4_102_444_800_456));
+        run(&mut db, &["SET", "k", "e", "XX"]);
+        assert_eq!(expire_at(&db, "k"), None);
+
+        run(&mut db, &["SET", "fresh", "v", "KEEPTTL"]);
+        assert_eq!(expire_at(&db, "fresh"), None);
     }
 
+    #[test]
+    fn set_accepts_a_repeated_condition_but_not_a_conflicting_one() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, &["SET", "k", "v", "NX", "nx"]), simple_string("OK"));
+        assert_eq!(run(&mut db, &["SET", "k", "w", "XX", "GET", "XX"]), bulk_string(b"v"));
+        let syntax_error = error("ERR syntax error");
+        for args in [
+            &["SET", "k", "x", "XX", "GET", "NX"][..],
+            &["SET", "k", "x", "KEEPTTL", "EX", "10"],
+            &["SET", "k", "x", "EXAT", "4102444800", "PX", "10"],
+            &["SET", "k", "x", "EX", "10", "GET", "EX", "10"],
+        ] {
+            assert_eq!(run(&mut db, args), syntax_error, "{args:?}");
+        }
+        assert_eq!(run(&mut db, &["GET", "k"]), bulk_string(b"w"));
+    }
+
+    #[test]
+    fn set_allows_one_expiry_option() {
+        let mut db = Database::new();
+        let syntax_error = error("ERR syntax error");
+        for args in [
+            &["SET", "k", "v", "EX", "5", "EX", "10"][..],
+            &["SET", "k", "v", "EX", "5", "PX", "10"],
+            &["SET", "k", "v", "KEEPTTL", "KEEPTTL"],
+            &["SET", "k", "v", "PXAT", "5", "KEEPTTL"],
+            &["SET", "k", "v", "NX", "XX"],
+            &["SET", "k", "v", "EX"],
+            &["SET", "k", "v", "PERSIST"],
+        ] {
+            assert_eq!(run(&mut db, args), syntax_error, "{args:?}");
+        }
+        assert!(db.store_ref().is_empty());
+    }
+
+    #[test]
+    fn set_rejects_bad_expire_times() {
+        let mut db = Database::new();
+        let invalid = error("ERR invalid expire time in 'set' command");
+        assert_eq!(run(&mut db, &["SET", "k", "v", "EX", "0"]), invalid);
+        assert_eq!(run(&mut db, &["SET", "k", "v", "PX", "-5"]), invalid);
+        assert_eq!(run(&mut db, &["SET", "k", "v", "EX", "9223372036854775807"]), invalid);
+        let not_integer = error("ERR value is not an integer or out of range");
+        assert_eq!(run(&mut db, &["SET", "k", "v", "EX", "1.5"]), not_integer);
+        assert!(db.store_ref().is_empty());
+    }
+
+    #[test]
+    fn expiries_keep_their_milliseconds() {
+        let mut db = Database::new();
+        run(&mut db, &["SET", "k", "v", "PXAT", "4102444800123"]);
+        assert_eq!(expire_at(&db, "k"), Some(4_102_444_800_123));
+        run(&mut db, &["SET", "k", "v", "EXAT", "4102444800"]);
+        assert_eq!(expire_at(&db, "k"), Some(4_102_444_800_000));
+
+        run(&mut db, &["SET", "k", "v", "PX", "1500"]);
+        let ttl = db.pttl(b"k");
+        assert!(ttl > 1000 && ttl <= 1500, "{ttl} ms left");
+    }
+
+    #[test]
+    fn psetex_and_getex_keep_milliseconds() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, &["PSETEX", "k", "1500", "v"]), simple_string("OK"));
+        let ttl = db.pttl(b"k");
+        assert!(ttl > 1000 && ttl <= 1500, "PSETEX left {ttl} ms");
+
+        run(&mut db, &["GETEX", "k", "PX", "2500"]);
+        let ttl = db.pttl(b"k");
+        assert!(ttl > 2000 && ttl <= 2500, "GETEX PX left {ttl} ms");
+
+        assert_eq!(run(&mut db, &["GETEX", "k", "PXAT", "4102444800456"]), bulk_string(b"v"));
+        assert_eq!(expire_at(&db, "k"), Some(4_102_444_800_456));
+        run(&mut db, &["GETEX", "k", "PERSIST"]);
+        assert_eq!(expire_at(&db, "k"), None);
+    }
+
+    #[test]
+    fn setrange_pads_with_zero_bytes() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, &["SETRANGE", "k", "5", "hi"]), integer(7));
+        assert_eq!(run(&mut db, &["GET", "k"]), bulk_string(b"\0\0\0\0\0hi"));
+
+        run(&mut db, &["SET", "k", "Hello World", "PX", "50000"]);
+        assert_eq!(run(&mut db, &["SETRANGE", "k", "6", "Redis"]), integer(11));
+        assert_eq!(run(&mut db, &["GET", "k"]), bulk_string(b"Hello Redis"));
+        assert_eq!(run(&mut db, &["SETRANGE", "k", "13", "!"]), integer(14));
+        assert_eq!(run(&mut db, &["GET", "k"]), bulk_string(b"Hello Redis\0\0!"));
+        // The key keeps its TTL.
+        assert!(expire_at(&db, "k").is_some());
+    }
+
+    #[test]
+    fn setrange_edge_cases() {
+        let mut db = Database::new();
+        // Writing nothing reports the length and creates no key.
+        assert_eq!(run(&mut db, &["SETRANGE", "k", "10", ""]), integer(0));
+        assert!(!db.store_ref().contains_key(&b"k"[..]));
+        run(&mut db, &["SET", "k", "abc"]);
+        assert_eq!(run(&mut db, &["SETRANGE", "k", "10", ""]), integer(3));
+
+        let out_of_range = error("ERR offset is out of range");
+        assert_eq!(run(&mut db, &["SETRANGE", "k", "-1", "x"]), out_of_range);
+        assert_eq!(
+            run(&mut db, &["SETRANGE", "k", "536870911", "xy"]),
+            error(StoreError::StringTooLong)
+        );
+        assert_eq!(run(&mut db, &["GET", "k"]), bulk_string(b"abc"));
+
+        db.store_mut().insert(b"set".to_vec(), RedisValue::Set(Default::default()));
+        assert_eq!(run(&mut db, &["SETRANGE", "set", "0", "x"]), error(StoreError::WrongType));
+        assert_eq!(run(&mut db, &["GETRANGE", "set", "0", "1"]), error(StoreError::WrongType));
+    }
+
+    #[test]
+    fn getrange_counts_negative_offsets_from_the_end() {
+        let mut db = Database::new();
+        run(&mut db, &["SET", "k", "Hello World"]);
+        let range =
+            |db: &mut Database, start: &str, end: &str| run(db, &["GETRANGE", "k", start, end]);
+        assert_eq!(range(&mut db, "0", "4"), bulk_string(b"Hello"));
+        assert_eq!(range(&mut db, "-5", "-1"), bulk_string(b"World"));
+        assert_eq!(range(&mut db, "-100", "4"), bulk_string(b"Hello"));
+        assert_eq!(range(&mut db, "6", "100"), bulk_string(b"World"));
+        assert_eq!(range(&mut db, "0", "-1"), bulk_string(b"Hello World"));
+        for (start, end) in [("5", "3"), ("-1", "-5"), ("11", "20"), ("0", "-12")] {
+            assert_eq!(range(&mut db, start, end), bulk_string(b""), "{start} {end}");
+        }
+        assert_eq!(run(&mut db, &["GETRANGE", "nope", "0", "-1"]), bulk_string(b""));
+        let not_integer = error("ERR value is not an integer or out of range");
+        assert_eq!(range(&mut db, "x", "1"), not_integer);
+    }
+}
@@ -0,0 +1,542 @@
+use crate::{
+    resp::utils::{
+        bulk_string, error, format_array, format_float, integer, null_bulk, parse_arg,
+        parse_float, wrong_args,
+    },
+    store::{
+        commands::zset::ZAddFlags,
+        zset::{LexBound, ScoreBound},
+        Database,
+    },
+};
+
+const SYNTAX_ERR: &str = "ERR syntax error";
+const NOT_INTEGER: &str = "ERR value is not an integer or out of range";
+const NOT_FLOAT: &str = "ERR value is not a valid float";
+
+pub fn handle_zset(cmd: &str, parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    match cmd {
+        "ZADD" => zadd(parts, db),
+
+        "ZREM" => {
+            if parts.len() < 3 {
+                return wrong_args("ZREM");
+            }
+            match db.zrem(&parts[1], &parts[2..]) {
+                Ok(removed) => integer(removed as i64),
+                Err(e) => error(e),
+            }
+        }
+
+        "ZSCORE" => {
+            if let (Some(key), Some(member)) = (parts.get(1), parts.get(2)) {
+                match db.zscore(key, member) {
+                    Ok(Some(score)) => bulk_string(format_float(score).as_bytes()),
+                    Ok(None) => null_bulk(),
+                    Err(e) => error(e),
+                }
+            } else {
+                wrong_args("ZSCORE")
+            }
+        }
+
+        "ZINCRBY" => {
+            if let (Some(key), Some(by), Some(member), None) =
+                (parts.get(1), parts.get(2), parts.get(3), parts.get(4))
+            {
+                let Some(by) = parse_float(by) else {
+                    return error(NOT_FLOAT);
+                };
+                match db.zincrby(key, by, member) {
+                    Ok(score) => bulk_string(format_float(score).as_bytes()),
+                    Err(e) => error(e),
+                }
+            } else {
+                wrong_args("ZINCRBY")
+            }
+        }
+
+        "ZCARD" => {
+            if let Some(key) = parts.get(1) {
+                match db.zcard(key) {
+                    Ok(len) => integer(len as i64),
+                    Err(e) => error(e),
+                }
+            } else {
+                wrong_args("ZCARD")
+            }
+        }
+
+        "ZCOUNT" => {
+            if let (Some(key), Some(min), Some(max)) = (parts.get(1), parts.get(2), parts.get(3)) {
+                let (Some(min), Some(max)) = (parse_score_bound(min), parse_score_bound(max)) else {
+                    return error("ERR min or max is not a float");
+                };
+                match db.zcount(key, min, max) {
+                    Ok(count) => integer(count as i64),
+                    Err(e) => error(e),
+                }
+            } else {
+                wrong_args("ZCOUNT")
+            }
+        }
+
+        "ZRANK" | "ZREVRANK" => {
+            let (Some(key), Some(member)) = (parts.get(1), parts.get(2)) else {
+                return wrong_args(cmd);
+            };
+            let with_score = match parts.get(3) {
+                None => false,
+                Some(opt) if opt.eq_ignore_ascii_case(b"WITHSCORE") && parts.len() == 4 => true,
+                Some(_) => return error(SYNTAX_ERR),
+            };
+            match db.zrank(key, member, cmd == "ZREVRANK") {
+                Ok(Some((rank, score))) if with_score => {
+                    let mut resp = b"*2\r\n".to_vec();
+                    resp.extend(integer(rank as i64));
+                    resp.extend(bulk_string(format_float(score).as_bytes()));
+                    resp
+                }
+                Ok(Some((rank, _))) => integer(rank as i64),
+                Ok(None) if with_score => b"*-1\r\n".to_vec(),
+                Ok(None) => null_bulk(),
+                Err(e) => error(e),
+            }
+        }
+
+        "ZRANGE" => zrange(parts, db),
+
+        "ZRANGEBYSCORE" => {
+            if parts.len() < 4 {
+                return wrong_args("ZRANGEBYSCORE");
+            }
+            let mut with_scores = false;
+            let mut limit = None;
+            let mut i = 4;
+            while i < parts.len() {
+                let opt = parts[i].to_ascii_uppercase();
+                match opt.as_slice() {
+                    b"WITHSCORES" => with_scores = true,
+                    b"LIMIT" => match parse_limit(parts.get(i + 1), parts.get(i + 2)) {
+                        Ok(l) => {
+                            limit = Some(l);
+                            i += 2;
+                        }
+                        Err(e) => return e,
+                    },
+                    _ => return error(SYNTAX_ERR),
+                }
+                i += 1;
+            }
+            let (Some(min), Some(max)) = (parse_score_bound(&parts[2]), parse_score_bound(&parts[3]))
+            else {
+                return error("ERR min or max is not a float");
+            };
+            let (offset, count) = limit.unwrap_or((0, None));
+            match db.zrange_by_score(&parts[1], min, max, false, offset, count) {
+                Ok(entries) => format_entries(entries, with_scores),
+                Err(e) => error(e),
+            }
+        }
+
+        "ZREMRANGEBYSCORE" => {
+            if let (Some(key), Some(min), Some(max), None) =
+                (parts.get(1), parts.get(2), parts.get(3), parts.get(4))
+            {
+                let (Some(min), Some(max)) = (parse_score_bound(min), parse_score_bound(max)) else {
+                    return error("ERR min or max is not a float");
+                };
+                match db.zremrangebyscore(key, min, max) {
+                    Ok(removed) => integer(removed as i64),
+                    Err(e) => error(e),
+                }
+            } else {
+                wrong_args("ZREMRANGEBYSCORE")
+            }
+        }
+
+        "ZREMRANGEBYRANK" => {
+            if let (Some(key), Some(start), Some(stop), None) =
+                (parts.get(1), parts.get(2), parts.get(3), parts.get(4))
+            {
+                let (Some(start), Some(stop)) = (parse_arg::<isize>(start), parse_arg::<isize>(stop))
+                else {
+                    return error(NOT_INTEGER);
+                };
+                match db.zremrangebyrank(key, start, stop) {
+                    Ok(removed) => integer(removed as i64),
+                    Err(e) => error(e),
+                }
+            } else {
+                wrong_args("ZREMRANGEBYRANK")
+            }
+        }
+
+        "ZPOPMIN" | "ZPOPMAX" => {
+            let Some(key) = parts.get(1) else {
+                return wrong_args(cmd);
+            };
+            if parts.len() > 3 {
+                return error(SYNTAX_ERR);
+            }
+            let count = match parts.get(2) {
+                None => 1,
+                Some(arg) => match parse_arg::<i64>(arg) {
+                    Some(n) if n >= 0 => n as usize,
+                    Some(_) => return error("ERR value is out of range, must be positive"),
+                    None => return error(NOT_INTEGER),
+                },
+            };
+            match db.zpop(key, count, cmd == "ZPOPMAX") {
+                Ok(entries) => format_entries(entries, true),
+                Err(e) => error(e),
+            }
+        }
+
+        _ => {
+            error(format!("ERR unknown command '{}'", cmd))
+        }
+    }
+}
+
+// ZADD key [NX|XX] [GT|LT] [CH] [INCR] score member [score member ...]
+fn zadd(parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    let Some(key) = parts.get(1) else {
+        return wrong_args("ZADD");
+    };
+
+    let mut flags = ZAddFlags::default();
+    let mut i = 2;
+    while let Some(opt) = parts.get(i) {
+        match opt.to_ascii_uppercase().as_slice() {
+            b"NX" => flags.nx = true,
+            b"XX" => flags.xx = true,
+            b"GT" => flags.gt = true,
+            b"LT" => flags.lt = true,
+            b"CH" => flags.ch = true,
+            b"INCR" => flags.incr = true,
+            _ => break,
+        }
+        i += 1;
+    }
+
+    let rest = &parts[i..];
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        return error(SYNTAX_ERR);
+    }
+    if flags.nx && flags.xx {
+        return error("ERR XX and NX options at the same time are not compatible");
+    }
+    if (flags.gt && flags.lt) || (flags.nx && (flags.gt || flags.lt)) {
+        return error("ERR GT, LT, and/or NX options at the same time are not compatible");
+    }
+    if flags.incr && rest.len() > 2 {
+        return error("ERR INCR option supports a single increment-element pair");
+    }
+
+    let mut pairs = Vec::with_capacity(rest.len() / 2);
+    for pair in rest.chunks(2) {
+        let Some(score) = parse_float(&pair[0]) else {
+            return error(NOT_FLOAT);
+        };
+        pairs.push((score, pair[1].clone()));
+    }
+
+    match db.zadd(key, flags, &pairs) {
+        Ok((_, Some(score))) if flags.incr => bulk_string(format_float(score).as_bytes()),
+        Ok((_, None)) if flags.incr => null_bulk(),
+        Ok((count, _)) => integer(count as i64),
+        Err(e) => error(e),
+    }
+}
+
+// ZRANGE key start stop [BYSCORE|BYLEX] [REV] [LIMIT offset count] [WITHSCORES]
+fn zrange(parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    if parts.len() < 4 {
+        return wrong_args("ZRANGE");
+    }
+
+    let mut by_score = false;
+    let mut by_lex = false;
+    let mut rev = false;
+    let mut with_scores = false;
+    let mut limit = None;
+
+    let mut i = 4;
+    while i < parts.len() {
+        match parts[i].to_ascii_uppercase().as_slice() {
+            b"BYSCORE" => by_score = true,
+            b"BYLEX" => by_lex = true,
+            b"REV" => rev = true,
+            b"WITHSCORES" => with_scores = true,
+            b"LIMIT" => match parse_limit(parts.get(i + 1), parts.get(i + 2)) {
+                Ok(l) => {
+                    limit = Some(l);
+                    i += 2;
+                }
+                Err(e) => return e,
+            },
+            _ => return error(SYNTAX_ERR),
+        }
+        i += 1;
+    }
+
+    if by_score && by_lex {
+        return error(SYNTAX_ERR);
+    }
+    if limit.is_some() && !by_score && !by_lex {
+        return error(
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX",
+        );
+    }
+    if with_scores && by_lex {
+        return error("ERR syntax error, WITHSCORES not supported in combination with BYLEX");
+    }
+
+    // With REV the interval is given as <max> <min>.
+    let (lo, hi) = if rev && (by_score || by_lex) {
+        (&parts[3], &parts[2])
+    } else {
+        (&parts[2], &parts[3])
+    };
+    let (offset, count) = limit.unwrap_or((0, None));
+    let key = &parts[1];
+
+    let result = if by_score {
+        let (Some(min), Some(max)) = (parse_score_bound(lo), parse_score_bound(hi)) else {
+            return error("ERR min or max is not a float");
+        };
+        db.zrange_by_score(key, min, max, rev, offset, count)
+    } else if by_lex {
+        let (Some(min), Some(max)) = (parse_lex_bound(lo), parse_lex_bound(hi)) else {
+            return error("ERR min or max not valid string range item");
+        };
+        db.zrange_by_lex(key, &min, &max, rev, offset, count)
+    } else {
+        let (Some(start), Some(stop)) = (parse_arg::<isize>(lo), parse_arg::<isize>(hi)) else {
+            return error(NOT_INTEGER);
+        };
+        db.zrange_by_rank(key, start, stop, rev)
+    };
+
+    match result {
+        Ok(entries) => format_entries(entries, with_scores),
+        Err(e) => error(e),
+    }
+}
+
+/// Parses `LIMIT offset count`. A negative offset yields nothing and a
+/// negative count means "all remaining".
+fn parse_limit(
+    offset: Option<&Vec<u8>>,
+    count: Option<&Vec<u8>>,
+) -> Result<(usize, Option<usize>), Vec<u8>> {
+    let (Some(offset), Some(count)) = (offset, count) else {
+        return Err(error(SYNTAX_ERR));
+    };
+    let (Some(offset), Some(count)) = (parse_arg::<i64>(offset), parse_arg::<i64>(count)) else {
+        return Err(error(NOT_INTEGER));
+    };
+    if offset < 0 {
+        return Ok((0, Some(0)));
+    }
+    let count = if count < 0 { None } else { Some(count as usize) };
+    Ok((offset as usize, count))
+}
+
+fn parse_score_bound(arg: &[u8]) -> Option<ScoreBound> {
+    let (exclusive, value) = match arg.strip_prefix(b"(") {
+        Some(rest) => (true, rest),
+        None => (false, arg),
+    };
+    Some(ScoreBound {
+        value: parse_float(value)?,
+        exclusive,
+    })
+}
+
+fn parse_lex_bound(arg: &[u8]) -> Option<LexBound> {
+    match arg.split_first() {
+        Some((b'-', [])) => Some(LexBound::Min),
+        Some((b'+', [])) => Some(LexBound::Max),
+        Some((b'[', rest)) => Some(LexBound::Inclusive(rest.to_vec())),
+        Some((b'(', rest)) => Some(LexBound::Exclusive(rest.to_vec())),
+        _ => None,
+    }
+}
+
+fn format_entries(entries: Vec<(Vec<u8>, f64)>, with_scores: bool) -> Vec<u8> {
+    let mut flat = Vec::with_capacity(entries.len() * if with_scores { 2 } else { 1 });
+    for (member, score) in entries {
+        flat.push(member);
+        if with_scores {
+            flat.push(format_float(score).into_bytes());
+        }
+    }
+    format_array(flat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(db: &mut Database, args: &[&str]) -> Vec<u8> {
+        let parts: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        handle_zset(&args[0].to_uppercase(), &parts, db)
+    }
+
+    fn items(values: &[&str]) -> Vec<u8> {
+        format_array(values.iter().map(|value| value.as_bytes().to_vec()).collect())
+    }
+
+    fn score(db: &mut Database, member: &str) -> Vec<u8> {
+        run(db, &["ZSCORE", "z", member])
+    }
+
+    #[test]
+    fn zadd_nx_and_xx_pick_new_or_existing_members() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, &["ZADD", "z", "1", "a"]), integer(1));
+        assert_eq!(run(&mut db, &["ZADD", "z", "NX", "5", "a", "2", "b"]), integer(1));
+        assert_eq!(score(&mut db, "a"), bulk_string(b"1"));
+        assert_eq!(run(&mut db, &["ZADD", "z", "XX", "3", "a", "4", "c"]), integer(0));
+        assert_eq!(score(&mut db, "a"), bulk_string(b"3"));
+        assert_eq!(score(&mut db, "c"), null_bulk());
+        assert_eq!(run(&mut db, &["ZADD", "z", "XX", "CH", "4", "a", "2", "b"]), integer(1));
+
+        // XX on a key that doesn't exist creates nothing.
+        assert_eq!(run(&mut db, &["ZADD", "y", "XX", "1", "a"]), integer(0));
+        assert_eq!(run(&mut db, &["ZCARD", "y"]), integer(0));
+    }
+
+    #[test]
+    fn zadd_gt_and_lt_only_move_scores_one_way() {
+        let mut db = Database::new();
+        run(&mut db, &["ZADD", "z", "5", "a"]);
+        assert_eq!(run(&mut db, &["ZADD", "z", "GT", "CH", "3", "a"]), integer(0));
+        assert_eq!(score(&mut db, "a"), bulk_string(b"5"));
+        assert_eq!(run(&mut db, &["ZADD", "z", "GT", "CH", "7", "a"]), integer(1));
+        assert_eq!(run(&mut db, &["ZADD", "z", "LT", "CH", "9", "a"]), integer(0));
+        assert_eq!(run(&mut db, &["ZADD", "z", "LT", "CH", "6", "a"]), integer(1));
+        assert_eq!(score(&mut db, "a"), bulk_string(b"6"));
+
+        // Neither stops a new member being added.
+        assert_eq!(run(&mut db, &["ZADD", "z", "GT", "1", "b"]), integer(1));
+        assert_eq!(run(&mut db, &["ZADD", "z", "LT", "XX", "1", "c"]), integer(0));
+    }
+
+    #[test]
+    fn zadd_ch_counts_changed_scores_too() {
+        let mut db = Database::new();
+        run(&mut db, &["ZADD", "z", "1", "a", "2", "b"]);
+        assert_eq!(run(&mut db, &["ZADD", "z", "1", "a", "3", "b", "4", "c"]), integer(1));
+        assert_eq!(
+            run(&mut db, &["ZADD", "z", "CH", "1", "a", "5", "b", "6", "d"]),
+            integer(2)
+        );
+    }
+
+    #[test]
+    fn zadd_incr_returns_the_new_score_or_nil() {
+        let mut db = Database::new();
+        assert_eq!(run(&mut db, &["ZADD", "z", "INCR", "2.5", "a"]), bulk_string(b"2.5"));
+        assert_eq!(run(&mut db, &["ZADD", "z", "INCR", "-1", "a"]), bulk_string(b"1.5"));
+        assert_eq!(run(&mut db, &["ZADD", "z", "NX", "INCR", "1", "a"]), null_bulk());
+        assert_eq!(run(&mut db, &["ZADD", "z", "XX", "INCR", "1", "b"]), null_bulk());
+        assert_eq!(run(&mut db, &["ZADD", "z", "GT", "INCR", "-1", "a"]), null_bulk());
+        assert_eq!(score(&mut db, "a"), bulk_string(b"1.5"));
+        assert_eq!(
+            run(&mut db, &["ZADD", "z", "INCR", "1", "a", "1", "b"]),
+            error("ERR INCR option supports a single increment-element pair")
+        );
+    }
+
+    #[test]
+    fn zadd_rejects_conflicting_flags_and_bad_scores() {
+        let mut db = Database::new();
+        assert_eq!(
+            run(&mut db, &["ZADD", "z", "NX", "XX", "1", "a"]),
+            error("ERR XX and NX options at the same time are not compatible")
+        );
+        let incompatible =
+            error("ERR GT, LT, and/or NX options at the same time are not compatible");
+        assert_eq!(run(&mut db, &["ZADD", "z", "GT", "LT", "1", "a"]), incompatible);
+        assert_eq!(run(&mut db, &["ZADD", "z", "NX", "GT", "1", "a"]), incompatible);
+        assert_eq!(run(&mut db, &["ZADD", "z", "1", "a", "2"]), error(SYNTAX_ERR));
+        assert_eq!(run(&mut db, &["ZADD", "z", "nan", "a"]), error(NOT_FLOAT));
+        assert_eq!(run(&mut db, &["ZCARD", "z"]), integer(0));
+    }
+
+    #[test]
+    fn ties_are_ordered_by_member() {
+        let mut db = Database::new();
+        run(&mut db, &["ZADD", "z", "1", "c", "1", "a", "0", "d", "1", "b"]);
+        assert_eq!(run(&mut db, &["ZRANGE", "z", "0", "-1"]), items(&["d", "a", "b", "c"]));
+        assert_eq!(run(&mut db, &["ZRANGE", "z", "0", "1", "REV"]), items(&["c", "b"]));
+        assert_eq!(run(&mut db, &["ZRANK", "z", "b"]), integer(2));
+        assert_eq!(run(&mut db, &["ZREVRANK", "z", "b"]), integer(1));
+
+        // Moving a member re-sorts it among the members it now ties with.
+        run(&mut db, &["ZADD", "z", "1", "d"]);
+        assert_eq!(run(&mut db, &["ZRANK", "z", "d"]), integer(3));
+        assert_eq!(run(&mut db, &["ZRANK", "z", "nope"]), null_bulk());
+    }
+
+    #[test]
+    fn score_ranges() {
+        let mut db = Database::new();
+        run(&mut db, &["ZADD", "z", "1", "a", "2", "b", "2", "c", "3", "d", "-inf", "e"]);
+        let range = |db: &mut Database, args: &[&str]| {
+            let mut command = vec!["ZRANGE", "z"];
+            command.extend_from_slice(args);
+            run(db, &command)
+        };
+
+        assert_eq!(range(&mut db, &["2", "2", "BYSCORE"]), items(&["b", "c"]));
+        assert_eq!(range(&mut db, &["(1", "(3", "BYSCORE"]), items(&["b", "c"]));
+        assert_eq!(range(&mut db, &["-inf", "(2", "BYSCORE"]), items(&["e", "a"]));
+        assert_eq!(range(&mut db, &["(2", "2", "BYSCORE"]), items(&[]));
+        assert_eq!(range(&mut db, &["3", "1", "BYSCORE"]), items(&[]));
+        assert_eq!(range(&mut db, &["+inf", "2", "BYSCORE", "REV"]), items(&["d", "c", "b"]));
+        assert_eq!(
+            range(&mut db, &["-inf", "+inf", "BYSCORE", "LIMIT", "1", "2"]),
+            items(&["a", "b"])
+        );
+        assert_eq!(
+            range(&mut db, &["3", "1", "BYSCORE", "REV", "LIMIT", "1", "-1", "WITHSCORES"]),
+            items(&["c", "2", "b", "2", "a", "1"])
+        );
+        assert_eq!(
+            range(&mut db, &["x", "1", "BYSCORE"]),
+            error("ERR min or max is not a float")
+        );
+        assert_eq!(run(&mut db, &["ZCOUNT", "z", "(1", "+inf"]), integer(3));
+    }
+
+    #[test]
+    fn lex_ranges() {
+        let mut db = Database::new();
+        run(&mut db, &["ZADD", "z", "0", "b", "0", "a", "0", "d", "0", "c", "0", "e"]);
+        let range = |db: &mut Database, args: &[&str]| {
+            let mut command = vec!["ZRANGE", "z"];
+            command.extend_from_slice(args);
+            run(db, &command)
+        };
+
+        assert_eq!(range(&mut db, &["-", "+", "BYLEX"]), items(&["a", "b", "c", "d", "e"]));
+        assert_eq!(range(&mut db, &["[b", "(d", "BYLEX"]), items(&["b", "c"]));
+        assert_eq!(range(&mut db, &["(b", "[d", "BYLEX"]), items(&["c", "d"]));
+        assert_eq!(range(&mut db, &["[bb", "+", "BYLEX"]), items(&["c", "d", "e"]));
+        assert_eq!(range(&mut db, &["+", "-", "BYLEX"]), items(&[]));
+        assert_eq!(range(&mut db, &["[d", "[b", "BYLEX"]), items(&[]));
+        assert_eq!(range(&mut db, &["[d", "-", "BYLEX", "REV"]), items(&["d", "c", "b", "a"]));
+        assert_eq!(
+            range(&mut db, &["+", "-", "BYLEX", "REV", "LIMIT", "1", "2"]),
+            items(&["d", "c"])
+        );
+        assert_eq!(
+            range(&mut db, &["b", "+", "BYLEX"]),
+            error("ERR min or max not valid string range item")
+        );
+    }
+}
@@ -1,62 +1,192 @@
-use crate::pubsub::{cmd::handle_subscribe, PubSub};
+use crate::persistence::{Persister, Saver};
+use crate::pubsub::{
+    cmd::{
+        handle_psubscribe, handle_publish, handle_pubsub, handle_punsubscribe, handle_ssubscribe,
+        handle_subscribe, handle_sunsubscribe, handle_unsubscribe,
+    },
+    PubSubSession,
+};
 use crate::store::db::Database;
 use std::sync::Arc;
-use tokio::net::tcp::OwnedWriteHalf;
-use tokio::sync::Mutex;
 
+use super::utils::{bulk_string, error, integer, simple_string};
 use super::commands::{
     hash_set::handle_hash_set,
     key::handle_key,
     list::handle_list,
     number::handle_number,
+    scripting::handle_scripting,
     set::handle_set,
+    stream::handle_stream,
     string::handle_string,
+    zset::handle_zset,
 };
 
 pub async fn handle_command(
-    parts: &[String],
+    parts: &[Vec<u8>],
     db: &mut Database,
-    pubsub: Arc<PubSub>,
-    writer: Arc<Mutex<OwnedWriteHalf>>,
-) -> String {
+    session: &mut PubSubSession,
+    persister: &(dyn Persister + Send + Sync),
+    saver: &Arc<Saver>,
+) -> Vec<u8> {
     if parts.is_empty() {
-        return "-ERR empty command\r\n".to_string();
+        return error("ERR empty command");
     }
 
-    let cmd = parts[0].to_uppercase();
+    let cmd = String::from_utf8_lossy(&parts[0]).to_uppercase();
 
     match cmd.as_str() {
-        // Regular commands
-        "PING" | "ECHO" | "SET" | "GET" | "DEL" => handle_string(&cmd, parts, db),
-        "INCR" | "INCRBY" | "DECR" | "DECRBY" => handle_number(&cmd, parts, db),
-        "EXISTS" | "KEYS" | "EXPIRE" | "TTL" | "PERSIST" => handle_key(&cmd, parts, db),
-        "LPOP" | "RPOP" | "LPUSH" | "RPUSH" | "LLEN" | "LINDEX" | "LRANGE" | "LSET" => {
-            handle_list(&cmd, parts, db)
+        // These write their own replies so they reach the client before
+        // any message does.
+        "SUBSCRIBE" => {
+            handle_subscribe(parts, session).await;
+            vec![]
         }
-        "SADD" | "SREM" | "SMEMBERS" | "SISMEMBER" | "SCARD" => handle_set(&cmd, parts, db),
-        "HSET" | "HGET" | "HDEL" | "HKEYS" | "HVALS" | "HLEN" | "HGETALL" | "HEXISTS" => {
-            handle_hash_set(&cmd, parts, db)
+
+        "PSUBSCRIBE" => {
+            handle_psubscribe(parts, session).await;
+            vec![]
         }
-        "FLUSHDB" => {
-            db.flushdb();
-            "+OK\r\n".to_string()
+
+        "UNSUBSCRIBE" => {
+            handle_unsubscribe(parts, session).await;
+            vec![]
         }
 
-        "SUBSCRIBE" => {
-            handle_subscribe(parts.iter().map(|s| s.as_str()).collect(), writer, pubsub).await;
-            "".to_string()
+        "PUNSUBSCRIBE" => {
+            handle_punsubscribe(parts, session).await;
+            vec![]
         }
 
-        "PUBLISH" => {
-            if parts.len() < 3 {
-                return "-ERR usage: PUBLISH <channel> <message>\r\n".to_string();
+        "SSUBSCRIBE" => {
+            handle_ssubscribe(parts, session).await;
+            vec![]
+        }
+
+        "SUNSUBSCRIBE" => {
+            handle_sunsubscribe(parts, session).await;
+            vec![]
+        }
+
+        "PUBSUB" => handle_pubsub(parts, session.pubsub()),
+
+        // Subscribers get PING's reply in the shape of a message.
+        "PING" if session.is_subscribed() => {
+            let message = parts.get(1).map(Vec::as_slice).unwrap_or_default();
+            let mut response = b"*2\r\n".to_vec();
+            response.extend(bulk_string(b"pong"));
+            response.extend(bulk_string(message));
+            response
+        }
+
+        "PUBLISH" => handle_publish(parts, session.pubsub()),
+
+        "SPUBLISH" => {
+            if parts.len() != 3 {
+                return error("ERR usage: SPUBLISH <shardchannel> <message>");
             }
-            let channel = parts[1].clone();
-            let message = parts[2..].join(" ");
-            let delivered = pubsub.publish(&channel, message).await;
-            format!(":{}\r\n", delivered)
+            let delivered = session.pubsub().spublish(&parts[1], &parts[2]);
+            integer(delivered as i64)
+        }
+
+        "BGREWRITEAOF" => match persister.rewrite(db) {
+            Ok(()) => simple_string("Background append only file rewriting started"),
+            Err(e) => error(format!("ERR {e}")),
+        },
+
+        "SAVE" => match saver.save(db) {
+            Ok(()) => simple_string("OK"),
+            Err(e) => error(format!("ERR {e}")),
+        },
+
+        "BGSAVE" => match saver.bgsave(db) {
+            Ok(()) => simple_string("Background saving started"),
+            Err(e) => error(format!("ERR {e}")),
+        },
+
+        "LASTSAVE" => integer(saver.last_save() as i64),
+
+        // A script may run for a long time; the worker thread hands its other
+        // connections to another thread meanwhile, so they can be told BUSY.
+        "EVAL" | "EVALSHA" | "SCRIPT" => tokio::task::block_in_place(|| {
+            handle_scripting(&cmd, parts, db, persister, session.pubsub())
+        }),
+
+        _ => execute(&cmd, parts, db),
+    }
+}
+
+/// How many parts, the name included, `cmd` takes: exactly `n` for a
+/// positive arity, at least `-n` for a negative one. `None` if the command
+/// doesn't exist. MULTI checks commands against it before queueing them.
+pub fn arity(cmd: &str) -> Option<i64> {
+    Some(match cmd {
+        "GET" | "GETDEL" | "STRLEN" | "INCR" | "DECR" | "KEYS" | "TTL" | "PTTL" | "PERSIST"
+        | "TYPE" | "LLEN" | "SMEMBERS" | "SCARD" | "HKEYS" | "HVALS" | "HLEN" | "HGETALL"
+        | "ZCARD" | "XLEN" | "ECHO" => 2,
+        "SETNX" | "GETSET" | "APPEND" | "INCRBY" | "DECRBY" | "LINDEX" | "RPOPLPUSH"
+        | "SISMEMBER" | "HGET" | "HEXISTS" | "HSTRLEN" | "ZSCORE" | "SPUBLISH" => 3,
+        "SETEX" | "PSETEX" | "GETRANGE" | "SETRANGE" | "LRANGE" | "LSET" | "LREM" | "LTRIM"
+        | "BRPOPLPUSH" | "SMOVE" | "HSETNX" | "HINCRBY" | "HINCRBYFLOAT" | "ZINCRBY"
+        | "ZCOUNT" | "ZREMRANGEBYSCORE" | "ZREMRANGEBYRANK" => 4,
+        "LINSERT" | "LMOVE" => 5,
+        "BLMOVE" => 6,
+        "PING" | "FLUSHDB" | "UNSUBSCRIBE" | "PUNSUBSCRIBE" | "SUNSUBSCRIBE" | "BGSAVE" => -1,
+        "DEL" | "EXISTS" | "MGET" | "GETEX" | "LPOP" | "RPOP" | "SINTER" | "SUNION" | "SDIFF"
+        | "SPOP" | "SRANDMEMBER" | "HRANDFIELD" | "ZPOPMIN" | "ZPOPMAX" | "XGROUP"
+        | "SUBSCRIBE" | "PSUBSCRIBE" | "SSUBSCRIBE" | "PUBSUB" | "SCRIPT" | "WATCH" => -2,
+        "SET" | "MSET" | "MSETNX" | "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" | "LPUSH"
+        | "RPUSH" | "LPUSHX" | "RPUSHX" | "LPOS" | "BLPOP" | "BRPOP" | "SADD" | "SREM"
+        | "SMISMEMBER"
+        | "SINTERSTORE" | "SUNIONSTORE" | "SDIFFSTORE" | "SINTERCARD" | "HDEL" | "HMGET"
+        | "HSCAN" | "ZREM" | "ZRANK" | "ZREVRANK" | "XDEL" | "XSETID" | "XPENDING"
+        | "PUBLISH" | "EVAL" | "EVALSHA" => -3,
+        "LMPOP" | "HSET" | "HMSET" | "ZADD" | "ZRANGE" | "ZRANGEBYSCORE" | "XRANGE"
+        | "XREVRANGE" | "XTRIM" | "XREAD" | "XACK" => -4,
+        "HTTL" | "HPTTL" | "HPERSIST" | "XADD" => -5,
+        "HEXPIRE" | "HPEXPIRE" | "HEXPIREAT" | "HPEXPIREAT" | "XCLAIM" | "XAUTOCLAIM" => -6,
+        "XREADGROUP" => -7,
+        "BGREWRITEAOF" | "SAVE" | "LASTSAVE" | "MULTI" | "EXEC" | "DISCARD" | "UNWATCH" => 1,
+        _ => return None,
+    })
+}
+
+/// Runs a command that only touches the keyspace. This is also how logged
+/// commands are re-applied at startup, where there is no connection.
+pub fn execute(cmd: &str, parts: &[Vec<u8>], db: &mut Database) -> Vec<u8> {
+    match cmd {
+        // Regular commands
+        "PING" | "ECHO" | "SET" | "GET" | "DEL" | "SETNX" | "SETEX" | "PSETEX" | "MGET" | "MSET"
+        | "MSETNX" | "GETSET" | "GETDEL" | "GETEX" | "APPEND" | "STRLEN" | "GETRANGE"
+        | "SETRANGE" => handle_string(cmd, parts, db),
+        "INCR" | "INCRBY" | "DECR" | "DECRBY" => handle_number(cmd, parts, db),
+        "EXISTS" | "KEYS" | "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" | "TTL" | "PTTL"
+        | "PERSIST" | "TYPE" => {
+            handle_key(cmd, parts, db)
+        }
+        "LPOP" | "RPOP" | "LPUSH" | "RPUSH" | "LPUSHX" | "RPUSHX" | "LLEN" | "LINDEX"
+        | "LRANGE" | "LSET" | "LINSERT" | "LREM" | "LTRIM" | "LPOS" | "LMOVE" | "RPOPLPUSH"
+        | "LMPOP" | "BLPOP" | "BRPOP" | "BLMOVE" | "BRPOPLPUSH" => handle_list(cmd, parts, db),
+        "SADD" | "SREM" | "SMEMBERS" | "SISMEMBER" | "SCARD" | "SMISMEMBER" | "SINTER"
+        | "SUNION" | "SDIFF" | "SINTERSTORE" | "SUNIONSTORE" | "SDIFFSTORE" | "SINTERCARD"
+        | "SMOVE" | "SPOP" | "SRANDMEMBER" => handle_set(cmd, parts, db),
+        "HSET" | "HGET" | "HDEL" | "HKEYS" | "HVALS" | "HLEN" | "HGETALL" | "HEXISTS" | "HMGET"
+        | "HMSET" | "HSETNX" | "HINCRBY" | "HINCRBYFLOAT" | "HSTRLEN" | "HRANDFIELD" | "HSCAN"
+        | "HEXPIRE" | "HPEXPIRE" | "HEXPIREAT" | "HPEXPIREAT" | "HTTL" | "HPTTL" | "HPERSIST" => {
+            handle_hash_set(cmd, parts, db)
+        }
+        "ZADD" | "ZREM" | "ZSCORE" | "ZINCRBY" | "ZCARD" | "ZCOUNT" | "ZRANK" | "ZREVRANK"
+        | "ZRANGE" | "ZRANGEBYSCORE" | "ZREMRANGEBYSCORE" | "ZREMRANGEBYRANK" | "ZPOPMIN"
+        | "ZPOPMAX" => handle_zset(cmd, parts, db),
+        "XADD" | "XLEN" | "XRANGE" | "XREVRANGE" | "XDEL" | "XTRIM" | "XSETID" | "XREAD"
+        | "XREADGROUP" | "XGROUP" | "XACK" | "XPENDING" | "XCLAIM" | "XAUTOCLAIM" => {
+            handle_stream(cmd, parts, db)
+        }
+        "FLUSHDB" => {
+            db.flushdb();
+            simple_string("OK")
         }
 
-        _ => "-ERR unknown command\r\n".to_string(),
+        _ => error("ERR unknown command"),
     }
 }
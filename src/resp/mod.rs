@@ -1,3 +1,6 @@
 pub mod handler;
+pub mod parser;
 pub mod utils;
-pub mod commands;
\ No newline at end of file
+pub mod commands;
+pub mod blocking;
+pub mod transaction;
@@ -0,0 +1,343 @@
+use std::fmt;
+
+// Limits mirror the defaults of the reference implementation.
+const MAX_INLINE_LEN: usize = 64 * 1024;
+const MAX_MULTIBULK_LEN: i64 = 1024 * 1024;
+const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+
+/// A decoded request and the number of bytes it occupied in the buffer.
+pub type Frame = (Vec<Vec<u8>>, usize);
+
+type ParseResult = Result<Option<Frame>, ProtocolError>;
+
+#[derive(Debug)]
+pub struct ProtocolError(String);
+
+impl ProtocolError {
+    fn new(msg: impl Into<String>) -> Self {
+        ProtocolError(msg.into())
+    }
+
+    pub fn to_resp(&self) -> String {
+        format!("-ERR Protocol error: {}\r\n", self.0)
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Protocol error: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// Tries to decode one request from the front of `buf`.
+///
+/// Returns `Ok(None)` when more bytes are needed, otherwise the command
+/// parts together with the number of bytes consumed. Empty requests (a blank
+/// inline line or `*0`) decode to an empty part list.
+pub fn parse_command(buf: &[u8]) -> ParseResult {
+    Decoder::default().decode(buf)
+}
+
+/// A connection's decoder. It remembers how far a multibulk request that
+/// hasn't fully arrived was decoded, so a big request read in many small
+/// chunks is only scanned and copied once.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    partial: Option<PartialMultibulk>,
+}
+
+#[derive(Debug)]
+struct PartialMultibulk {
+    parts: Vec<Vec<u8>>,
+    /// Bulk strings still to come.
+    remaining: usize,
+    /// Where the next bulk string starts, from the start of the request.
+    pos: usize,
+}
+
+impl Decoder {
+    /// Like [`parse_command`]. `buf` has to start with the same request on
+    /// every call until that request is returned, so callers may only drop
+    /// the bytes of requests already decoded.
+    pub fn decode(&mut self, buf: &[u8]) -> ParseResult {
+        let mut partial = match self.partial.take() {
+            Some(partial) => partial,
+            None => match buf.first() {
+                None => return Ok(None),
+                Some(b'*') => match parse_multibulk_header(buf)? {
+                    None => return Ok(None),
+                    Some((0, pos)) => return Ok(Some((vec![], pos))),
+                    Some((count, pos)) => PartialMultibulk {
+                        parts: Vec::with_capacity(count.min(1024)),
+                        remaining: count,
+                        pos,
+                    },
+                },
+                Some(_) => return parse_inline(buf),
+            },
+        };
+
+        while partial.remaining > 0 {
+            let Some((part, next)) = parse_bulk(buf, partial.pos)? else {
+                self.partial = Some(partial);
+                return Ok(None);
+            };
+            partial.parts.push(part);
+            partial.remaining -= 1;
+            partial.pos = next;
+        }
+        Ok(Some((partial.parts, partial.pos)))
+    }
+}
+
+// `*<count>\r\n`: the number of bulk strings, none for a negative count,
+// and where the first one starts.
+fn parse_multibulk_header(buf: &[u8]) -> Result<Option<(usize, usize)>, ProtocolError> {
+    let Some(end) = find_crlf(buf, 0) else {
+        if buf.len() > MAX_INLINE_LEN {
+            return Err(ProtocolError::new("too big mbulk count string"));
+        }
+        return Ok(None);
+    };
+
+    let count = parse_len(&buf[1..end])
+        .filter(|n| *n <= MAX_MULTIBULK_LEN)
+        .ok_or_else(|| ProtocolError::new("invalid multibulk length"))?;
+    Ok(Some((count.max(0) as usize, end + 2)))
+}
+
+// One `$<len>\r\n<data>\r\n` at `pos`, copied only once it is all there.
+fn parse_bulk(buf: &[u8], pos: usize) -> Result<Option<(Vec<u8>, usize)>, ProtocolError> {
+    let Some(&prefix) = buf.get(pos) else {
+        return Ok(None);
+    };
+    if prefix != b'$' {
+        return Err(ProtocolError::new(format!(
+            "expected '$', got '{}'",
+            prefix as char
+        )));
+    }
+
+    let Some(end) = find_crlf(buf, pos) else {
+        if buf.len() - pos > MAX_INLINE_LEN {
+            return Err(ProtocolError::new("too big bulk count string"));
+        }
+        return Ok(None);
+    };
+
+    let len = parse_len(&buf[pos + 1..end])
+        .filter(|n| (0..=MAX_BULK_LEN).contains(n))
+        .ok_or_else(|| ProtocolError::new("invalid bulk length"))? as usize;
+
+    let start = end + 2;
+    let stop = start + len;
+    if buf.len() < stop + 2 {
+        return Ok(None);
+    }
+    if &buf[stop..stop + 2] != b"\r\n" {
+        return Err(ProtocolError::new("bulk string is not terminated by CRLF"));
+    }
+    Ok(Some((buf[start..stop].to_vec(), stop + 2)))
+}
+
+fn parse_inline(buf: &[u8]) -> ParseResult {
+    let Some(newline) = buf.iter().position(|&b| b == b'\n') else {
+        if buf.len() > MAX_INLINE_LEN {
+            return Err(ProtocolError::new("too big inline request"));
+        }
+        return Ok(None);
+    };
+
+    let mut line = &buf[..newline];
+    if let Some(stripped) = line.strip_suffix(b"\r") {
+        line = stripped;
+    }
+
+    let parts = split_inline_args(line)
+        .ok_or_else(|| ProtocolError::new("unbalanced quotes in request"))?;
+    Ok(Some((parts, newline + 1)))
+}
+
+// Splits an inline request on whitespace, honouring "double" quotes (with
+// backslash escapes) and 'single' quotes the same way redis-cli does.
+fn split_inline_args(line: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut args = Vec::new();
+    let mut i = 0;
+
+    loop {
+        while i < line.len() && line[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i >= line.len() {
+            return Some(args);
+        }
+
+        let mut current = Vec::new();
+        let mut in_double = false;
+        let mut in_single = false;
+
+        loop {
+            if in_double {
+                let &c = line.get(i)?;
+                if c == b'\\' && i + 3 < line.len() && line[i + 1] == b'x' {
+                    if let Some(byte) = hex_byte(line[i + 2], line[i + 3]) {
+                        current.push(byte);
+                        i += 4;
+                        continue;
+                    }
+                }
+                if c == b'\\' && i + 1 < line.len() {
+                    current.push(match line[i + 1] {
+                        b'n' => b'\n',
+                        b'r' => b'\r',
+                        b't' => b'\t',
+                        b'b' => 0x08,
+                        b'a' => 0x07,
+                        other => other,
+                    });
+                    i += 2;
+                } else if c == b'"' {
+                    // Closing quote must be followed by whitespace or end.
+                    if line.get(i + 1).is_some_and(|b| !b.is_ascii_whitespace()) {
+                        return None;
+                    }
+                    i += 1;
+                    break;
+                } else {
+                    current.push(c);
+                    i += 1;
+                }
+            } else if in_single {
+                let &c = line.get(i)?;
+                if c == b'\\' && line.get(i + 1) == Some(&b'\'') {
+                    current.push(b'\'');
+                    i += 2;
+                } else if c == b'\'' {
+                    if line.get(i + 1).is_some_and(|b| !b.is_ascii_whitespace()) {
+                        return None;
+                    }
+                    i += 1;
+                    break;
+                } else {
+                    current.push(c);
+                    i += 1;
+                }
+            } else {
+                match line.get(i) {
+                    None => break,
+                    Some(c) if c.is_ascii_whitespace() => break,
+                    Some(b'"') => in_double = true,
+                    Some(b'\'') => in_single = true,
+                    Some(&c) => current.push(c),
+                }
+                i += 1;
+            }
+        }
+
+        args.push(current);
+    }
+}
+
+fn hex_byte(hi: u8, lo: u8) -> Option<u8> {
+    let hi = (hi as char).to_digit(16)?;
+    let lo = (lo as char).to_digit(16)?;
+    Some((hi * 16 + lo) as u8)
+}
+
+fn find_crlf(buf: &[u8], from: usize) -> Option<usize> {
+    buf[from..]
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .map(|i| from + i)
+}
+
+fn parse_len(digits: &[u8]) -> Option<i64> {
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(args: &[&[u8]]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.to_vec()).collect()
+    }
+
+    #[test]
+    fn decodes_multibulk() {
+        let buf = b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n";
+        let (decoded, used) = parse_command(buf).unwrap().unwrap();
+        assert_eq!(decoded, parts(&[b"GET", b"k"]));
+        assert_eq!(used, buf.len());
+    }
+
+    #[test]
+    fn bulk_strings_are_binary_safe() {
+        let buf = b"*2\r\n$3\r\nSET\r\n$4\r\na\r\n\0\r\n";
+        let (decoded, _) = parse_command(buf).unwrap().unwrap();
+        assert_eq!(decoded[1], b"a\r\n\0");
+    }
+
+    #[test]
+    fn incomplete_frames_need_more_bytes() {
+        let buf = b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n";
+        for len in 0..buf.len() {
+            assert!(parse_command(&buf[..len]).unwrap().is_none(), "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn pipelined_frames_decode_one_at_a_time() {
+        let buf = b"*1\r\n$4\r\nPING\r\nPING\r\n*1\r\n$4\r\nPING\r\n";
+        let mut consumed = 0;
+        let mut decoded = Vec::new();
+        while let Some((frame, used)) = parse_command(&buf[consumed..]).unwrap() {
+            decoded.push(frame);
+            consumed += used;
+        }
+        assert_eq!(consumed, buf.len());
+        assert_eq!(decoded, vec![parts(&[b"PING"]); 3]);
+    }
+
+    #[test]
+    fn decoder_resumes_a_request_fed_byte_by_byte() {
+        let buf = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\nvalue\r\n*1\r\n$4\r\nPING\r\n";
+        let first = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\nvalue\r\n".len();
+        let mut decoder = Decoder::default();
+        for len in 0..first {
+            assert!(decoder.decode(&buf[..len]).unwrap().is_none(), "{len} bytes");
+        }
+        let (decoded, used) = decoder.decode(buf).unwrap().unwrap();
+        assert_eq!(decoded, parts(&[b"SET", b"k", b"value"]));
+        assert_eq!(used, first);
+        let (decoded, _) = decoder.decode(&buf[used..]).unwrap().unwrap();
+        assert_eq!(decoded, parts(&[b"PING"]));
+    }
+
+    #[test]
+    fn empty_requests_decode_to_no_parts() {
+        assert_eq!(parse_command(b"*0\r\n").unwrap().unwrap(), (vec![], 4));
+        assert_eq!(parse_command(b"\r\n").unwrap().unwrap(), (vec![], 2));
+    }
+
+    #[test]
+    fn inline_requests_honour_quotes() {
+        let (decoded, used) = parse_command(b"SET \"a b\" 'c\\'d' \"\\x41\\n\"\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded, parts(&[b"SET", b"a b", b"c'd", b"A\n"]));
+        assert_eq!(used, 27);
+        assert!(parse_command(b"SET \"a\r\n").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        assert!(parse_command(b"*x\r\n").is_err());
+        assert!(parse_command(b"*1\r\n+OK\r\n").is_err());
+        assert!(parse_command(b"*1\r\n$-1\r\n").is_err());
+        assert!(parse_command(b"*1\r\n$1\r\nab\r\n").is_err());
+        assert!(parse_command(&vec![b'a'; MAX_INLINE_LEN + 1]).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{persistence::persister::Command, store::Database};
+
+use super::handler::arity;
+
+/// A connection's MULTI queue and WATCHed keys.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    /// `Some` between MULTI and EXEC/DISCARD.
+    queued: Option<Vec<Command>>,
+    /// A command was refused while queueing, so EXEC runs nothing.
+    failed: bool,
+    /// Keys with the version they had when WATCH was called.
+    watched: Vec<(Vec<u8>, u64)>,
+}
+
+/// Commands that act on the transaction itself instead of being queued.
+pub fn is_transaction_command(cmd: &str) -> bool {
+    matches!(cmd, "MULTI" | "EXEC" | "DISCARD" | "WATCH" | "UNWATCH")
+}
+
+// These write their replies straight to the socket, which would land in the
+// middle of EXEC's reply.
+fn is_refused_in_multi(cmd: &str) -> bool {
+    matches!(
+        cmd,
+        "SUBSCRIBE" | "PSUBSCRIBE" | "SSUBSCRIBE" | "UNSUBSCRIBE" | "PUNSUBSCRIBE" | "SUNSUBSCRIBE"
+    )
+}
+
+impl Transaction {
+    pub fn in_multi(&self) -> bool {
+        self.queued.is_some()
+    }
+
+    pub fn multi(&mut self) -> Result<(), &'static str> {
+        if self.in_multi() {
+            return Err("ERR MULTI calls can not be nested");
+        }
+        self.queued = Some(Vec::new());
+        self.failed = false;
+        Ok(())
+    }
+
+    /// Queues `parts` for EXEC once its name and number of arguments check
+    /// out. A command that doesn't makes the whole transaction fail.
+    pub fn queue(&mut self, cmd: &str, parts: Command) -> Result<(), String> {
+        let len = parts.len() as i64;
+        let refused = match arity(cmd) {
+            None => {
+                let args: String = parts[1..]
+                    .iter()
+                    .map(|arg| format!("'{}' ", String::from_utf8_lossy(arg)))
+                    .collect();
+                Some(format!(
+                    "ERR unknown command '{}', with args beginning with: {args}",
+                    String::from_utf8_lossy(&parts[0])
+                ))
+            }
+            Some(n) if (n > 0 && len != n) || (n < 0 && len < -n) => Some(
+                format!("ERR wrong number of arguments for '{}' command", cmd.to_lowercase()),
+            ),
+            Some(_) if is_refused_in_multi(cmd) => {
+                Some("ERR Command not allowed inside a transaction".to_string())
+            }
+            Some(_) => None,
+        };
+        if let Some(e) = refused {
+            self.failed = true;
+            return Err(e);
+        }
+        if let Some(queued) = &mut self.queued {
+            queued.push(parts);
+        }
+        Ok(())
+    }
+
+    pub fn discard(&mut self, db: &mut Database) -> Result<(), &'static str> {
+        if self.queued.take().is_none() {
+            return Err("ERR DISCARD without MULTI");
+        }
+        self.unwatch(db);
+        Ok(())
+    }
+
+    /// Drops any open transaction and every watch (RESET).
+    pub fn reset(&mut self, db: &mut Database) {
+        self.queued = None;
+        self.failed = false;
+        self.unwatch(db);
+    }
+
+    pub fn watch(&mut self, keys: &[Vec<u8>], db: &mut Database) -> Result<(), &'static str> {
+        if self.in_multi() {
+            return Err("ERR WATCH inside MULTI is not allowed");
+        }
+        for key in keys {
+            // Expire it now so a key that lapses after WATCH counts as a change.
+            db.is_expired(key);
+            let version = db.watched_mut().watch(key);
+            self.watched.push((key.clone(), version));
+        }
+        Ok(())
+    }
+
+    pub fn unwatch(&mut self, db: &mut Database) {
+        for (key, _) in self.watched.drain(..) {
+            db.watched_mut().unwatch(&key);
+        }
+    }
+
+    /// Ends the transaction for EXEC and returns the queued commands, or
+    /// `Ok(None)` if a watched key was written since WATCH and nothing
+    /// should run. Either way every key is unwatched.
+    pub fn exec(&mut self, db: &mut Database) -> Result<Option<Vec<Command>>, &'static str> {
+        let queued = self.queued.take().ok_or("ERR EXEC without MULTI")?;
+        if self.failed {
+            self.unwatch(db);
+            return Err("EXECABORT Transaction discarded because of previous errors.");
+        }
+        for (key, _) in &self.watched {
+            db.is_expired(key);
+        }
+        let changed = self
+            .watched
+            .iter()
+            .any(|(key, version)| db.watched_ref().version(key) != *version);
+        self.unwatch(db);
+        Ok(if changed { None } else { Some(queued) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> Command {
+        args.iter().map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn queues_valid_commands() {
+        let mut db = Database::new();
+        let mut tx = Transaction::default();
+        tx.multi().unwrap();
+        tx.queue("SET", command(&["SET", "k", "v"])).unwrap();
+        tx.queue("PING", command(&["PING"])).unwrap();
+        assert_eq!(tx.exec(&mut db).unwrap().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn queueing_errors_abort_exec() {
+        for bad in [&["NOSUCH", "k"][..], &["GET"], &["GET", "a", "b"], &["SUBSCRIBE", "ch"]] {
+            let mut db = Database::new();
+            let mut tx = Transaction::default();
+            tx.multi().unwrap();
+            tx.queue("SET", command(&["SET", "k", "v"])).unwrap();
+            assert!(tx.queue(bad[0], command(bad)).is_err(), "{bad:?}");
+            assert!(tx.exec(&mut db).unwrap_err().starts_with("EXECABORT"));
+            assert!(!tx.in_multi());
+        }
+    }
+
+    #[test]
+    fn a_new_multi_starts_clean() {
+        let mut db = Database::new();
+        let mut tx = Transaction::default();
+        tx.multi().unwrap();
+        assert!(tx.queue("GET", command(&["GET"])).is_err());
+        tx.discard(&mut db).unwrap();
+        tx.multi().unwrap();
+        tx.queue("GET", command(&["GET", "k"])).unwrap();
+        assert_eq!(tx.exec(&mut db).unwrap().unwrap().len(), 1);
+    }
+}
@@ -1,46 +1,56 @@
-pub fn wrong_args(cmd: &str) -> String {
-    format!("-ERR wrong number of arguments for '{}'\r\n", cmd)
+use std::{fmt::Display, str::FromStr};
+
+pub fn wrong_args(cmd: &str) -> Vec<u8> {
+    format!("-ERR wrong number of arguments for '{}'\r\n", cmd).into_bytes()
+}
+
+pub fn simple_string(s: &str) -> Vec<u8> {
+    format!("+{}\r\n", s).into_bytes()
+}
+
+/// An error reply; `msg` must already carry its code (`ERR`, `WRONGTYPE`, ...).
+pub fn error(msg: impl Display) -> Vec<u8> {
+    format!("-{}\r\n", msg).into_bytes()
+}
+
+pub fn integer(n: i64) -> Vec<u8> {
+    format!(":{}\r\n", n).into_bytes()
 }
 
-pub fn bulk_string(s: &str) -> String {
-    format!("${}\r\n{}\r\n", s.len(), s)
+pub fn null_bulk() -> Vec<u8> {
+    b"$-1\r\n".to_vec()
 }
 
-pub fn format_array(values: Vec<String>) -> String {
-    let mut resp = format!("*{}\r\n", values.len());
+pub fn bulk_string(s: &[u8]) -> Vec<u8> {
+    let mut resp = format!("${}\r\n", s.len()).into_bytes();
+    resp.extend_from_slice(s);
+    resp.extend_from_slice(b"\r\n");
+    resp
+}
+
+pub fn format_array(values: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut resp = format!("*{}\r\n", values.len()).into_bytes();
     for v in values {
-        resp.push_str(&bulk_string(&v));
+        resp.extend(bulk_string(&v));
     }
     resp
 }
 
+/// Parses a numeric argument, rejecting anything that is not valid UTF-8.
+pub fn parse_arg<T: FromStr>(arg: &[u8]) -> Option<T> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
 
-pub fn parse_resp(input: &str) -> Vec<String> {
-    let lines: Vec<&str> = input.lines().collect();
-    if lines.is_empty() {
-        return vec![];
-    }
+/// Parses a float argument the way Redis does: `inf`/`-inf` are accepted,
+/// NaN is not.
+pub fn parse_float(arg: &[u8]) -> Option<f64> {
+    parse_arg::<f64>(arg).filter(|f| !f.is_nan())
+}
 
-    // RESP protocol: starts with *N indicating array of N elements
-    if lines[0].starts_with('*') {
-        let mut result = Vec::new();
-        let mut i = 1;
-        while i < lines.len() {
-            if lines[i].starts_with('$') {
-                // Next line is the actual value
-                if i + 1 < lines.len() {
-                    result.push(lines[i + 1].to_string());
-                    i += 2;
-                } else {
-                    break;
-                }
-            } else {
-                i += 1;
-            }
-        }
-        result
+pub fn format_float(f: f64) -> String {
+    if f.is_infinite() {
+        if f > 0.0 { "inf".to_string() } else { "-inf".to_string() }
     } else {
-        // Inline command: split by whitespace
-        input.split_whitespace().map(|s| s.to_string()).collect()
+        f.to_string()
     }
 }
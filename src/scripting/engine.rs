@@ -0,0 +1,353 @@
+use std::{cell::RefCell, collections::HashMap, error::Error, fmt, sync::Arc};
+
+use mlua::{HookTriggers, Lua, LuaOptions, MultiValue, StdLib, Table, Value};
+
+use crate::resp::utils::{bulk_string, error, integer, null_bulk, simple_string};
+
+use super::{
+    monitor::ScriptMonitor,
+    reply::{parse_reply, Reply},
+};
+
+// How often, in Lua VM instructions, a running script checks for SCRIPT KILL.
+const KILL_CHECK_INTERVAL: u32 = 10_000;
+
+/// Scripts seen by EVAL or SCRIPT LOAD, keyed by the SHA1 of their body,
+/// and the monitor of the one running.
+#[derive(Debug, Default)]
+pub struct ScriptCache {
+    scripts: HashMap<String, Vec<u8>>,
+    monitor: Arc<ScriptMonitor>,
+}
+
+impl ScriptCache {
+    pub fn monitor(&self) -> &Arc<ScriptMonitor> {
+        &self.monitor
+    }
+
+    /// Caches `body` and returns its SHA1.
+    pub fn load(&mut self, body: &[u8]) -> String {
+        let sha = sha1_hex(body);
+        self.scripts
+            .entry(sha.clone())
+            .or_insert_with(|| body.to_vec());
+        sha
+    }
+
+    pub fn get(&self, sha: &[u8]) -> Option<&[u8]> {
+        let sha = String::from_utf8_lossy(sha).to_ascii_lowercase();
+        self.scripts.get(&sha).map(Vec::as_slice)
+    }
+
+    pub fn flush(&mut self) {
+        self.scripts.clear();
+    }
+}
+
+pub fn sha1_hex(data: &[u8]) -> String {
+    sha1_smol::Sha1::from(data).digest().to_string()
+}
+
+/// An error reply raised through the script so EVAL returns it unchanged:
+/// one from `redis.call`, or the script being killed.
+#[derive(Debug)]
+struct CallError(String);
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for CallError {}
+
+/// Runs `body` with `KEYS` and `ARGV` set and returns its result as a RESP
+/// reply. `redis.call` and `redis.pcall` hand their arguments to `call`,
+/// which runs the command and returns its reply.
+///
+/// Each run gets a fresh interpreter with only the base, table, string and
+/// math libraries, so scripts can't reach the filesystem or leak globals
+/// into each other.
+///
+/// The database stays locked while a script runs; `monitor` lets other
+/// clients see it is running and stop it with SCRIPT KILL, which only
+/// succeeds while it hasn't written.
+pub fn run_script<F>(
+    body: &[u8],
+    keys: &[Vec<u8>],
+    args: &[Vec<u8>],
+    monitor: &Arc<ScriptMonitor>,
+    call: F,
+) -> Vec<u8>
+where
+    F: FnMut(&[Vec<u8>]) -> Vec<u8>,
+{
+    let lua = match Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    ) {
+        Ok(lua) => lua,
+        Err(e) => return error(format!("ERR Error creating script interpreter: {}", describe(&e))),
+    };
+
+    let watched = Arc::clone(monitor);
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(KILL_CHECK_INTERVAL),
+        move |lua, _| {
+            if !watched.is_killed() {
+                return Ok(());
+            }
+            // From now on every instruction fails, so a script can't catch
+            // the error with pcall and keep going.
+            lua.set_hook(HookTriggers::new().every_nth_instruction(1), |_, _| Err(killed()));
+            Err(killed())
+        },
+    );
+
+    let func = match lua.load(body).set_name("@user_script").into_function() {
+        Ok(func) => func,
+        Err(e) => return error(format!("ERR Error compiling script: {}", describe(&e))),
+    };
+
+    let call = RefCell::new(call);
+    monitor.start();
+    let result = (|| {
+        let redis = redis_table(&lua)?;
+        let globals = lua.globals();
+        globals.set("KEYS", string_table(&lua, keys)?)?;
+        globals.set("ARGV", string_table(&lua, args)?)?;
+        globals.set("redis", redis.clone())?;
+
+        // `redis.call` borrows the caller's state, so it only exists for
+        // the duration of this run.
+        lua.scope(|scope| {
+            let call = &call;
+            redis.set(
+                "call",
+                scope.create_function(move |lua, args| dispatch(lua, args, call, true))?,
+            )?;
+            redis.set(
+                "pcall",
+                scope.create_function(move |lua, args| dispatch(lua, args, call, false))?,
+            )?;
+            let value: Value = func.call(())?;
+            Ok(to_resp(&value))
+        })
+    })();
+    monitor.finish();
+
+    match result {
+        Ok(reply) => reply,
+        Err(e) => match call_error(&e) {
+            Some(msg) => error(msg),
+            None => error(format!("ERR Error running script: {}", describe(&e))),
+        },
+    }
+}
+
+fn killed() -> mlua::Error {
+    mlua::Error::external(CallError("ERR Script killed by user with SCRIPT KILL".to_string()))
+}
+
+fn redis_table(lua: &Lua) -> mlua::Result<Table<'_>> {
+    let redis = lua.create_table()?;
+    redis.set(
+        "error_reply",
+        lua.create_function(|lua, msg: mlua::String| {
+            let reply = lua.create_table()?;
+            reply.set("err", msg)?;
+            Ok(reply)
+        })?,
+    )?;
+    redis.set(
+        "status_reply",
+        lua.create_function(|lua, msg: mlua::String| {
+            let reply = lua.create_table()?;
+            reply.set("ok", msg)?;
+            Ok(reply)
+        })?,
+    )?;
+    redis.set(
+        "sha1hex",
+        lua.create_function(|_, data: mlua::String| Ok(sha1_hex(data.as_bytes())))?,
+    )?;
+    Ok(redis)
+}
+
+fn string_table<'lua>(lua: &'lua Lua, items: &[Vec<u8>]) -> mlua::Result<Table<'lua>> {
+    lua.create_sequence_from(
+        items
+            .iter()
+            .map(|item| lua.create_string(item))
+            .collect::<mlua::Result<Vec<_>>>()?,
+    )
+}
+
+/// `redis.call` (`raise`) and `redis.pcall`: the first turns an error reply
+/// into a script error, the second returns it as an `{err=...}` table.
+fn dispatch<'lua, F>(
+    lua: &'lua Lua,
+    args: MultiValue<'lua>,
+    call: &RefCell<F>,
+    raise: bool,
+) -> mlua::Result<Value<'lua>>
+where
+    F: FnMut(&[Vec<u8>]) -> Vec<u8>,
+{
+    let fail = |msg: &str| mlua::Error::external(CallError(msg.to_string()));
+
+    let parts = args
+        .into_iter()
+        .map(|arg| match arg {
+            Value::String(s) => Ok(s.as_bytes().to_vec()),
+            Value::Integer(n) => Ok(n.to_string().into_bytes()),
+            Value::Number(n) => Ok(format_number(n).into_bytes()),
+            _ => Err(fail("ERR Lua redis lib command arguments must be strings or integers")),
+        })
+        .collect::<mlua::Result<Vec<_>>>()?;
+    if parts.is_empty() {
+        return Err(fail("ERR Please specify at least one argument for this redis lib call"));
+    }
+
+    let reply = (call.borrow_mut())(&parts);
+    match parse_reply(&reply).map(|(reply, _)| reply) {
+        Some(Reply::Error(msg)) if raise => Err(fail(&String::from_utf8_lossy(&msg))),
+        Some(reply) => to_lua(lua, reply),
+        None => Ok(Value::Boolean(false)),
+    }
+}
+
+/// Lua numbers are doubles; whole ones are passed on without a fraction.
+fn format_number(n: f64) -> String {
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        (n as i64).to_string()
+    } else {
+        n.to_string()
+    }
+}
+
+/// Converts a command reply the way Redis does: nil becomes `false`,
+/// status and error replies become `{ok=...}` / `{err=...}` tables.
+fn to_lua(lua: &Lua, reply: Reply) -> mlua::Result<Value<'_>> {
+    Ok(match reply {
+        Reply::Integer(n) => Value::Integer(n as mlua::Integer),
+        Reply::Bulk(Some(data)) => Value::String(lua.create_string(&data)?),
+        Reply::Bulk(None) | Reply::Array(None) => Value::Boolean(false),
+        Reply::Status(msg) => {
+            let table = lua.create_table()?;
+            table.set("ok", lua.create_string(&msg)?)?;
+            Value::Table(table)
+        }
+        Reply::Error(msg) => {
+            let table = lua.create_table()?;
+            table.set("err", lua.create_string(&msg)?)?;
+            Value::Table(table)
+        }
+        Reply::Array(Some(items)) => {
+            let table = lua.create_table_with_capacity(items.len(), 0)?;
+            for (i, item) in items.into_iter().enumerate() {
+                table.raw_set(i + 1, to_lua(lua, item)?)?;
+            }
+            Value::Table(table)
+        }
+    })
+}
+
+/// Converts a script's return value back: numbers are truncated to
+/// integers, `true` is 1, `false` and nil are nil, and an array table
+/// stops at its first nil.
+fn to_resp(value: &Value) -> Vec<u8> {
+    match value {
+        Value::String(s) => bulk_string(s.as_bytes()),
+        Value::Integer(n) => integer(*n),
+        Value::Number(n) => integer(*n as i64),
+        Value::Boolean(true) => integer(1),
+        Value::Table(table) => {
+            if let Ok(Value::String(msg)) = table.raw_get::<_, Value>("err") {
+                return error(String::from_utf8_lossy(msg.as_bytes()));
+            }
+            if let Ok(Value::String(msg)) = table.raw_get::<_, Value>("ok") {
+                return simple_string(&String::from_utf8_lossy(msg.as_bytes()));
+            }
+
+            let mut items = Vec::new();
+            for i in 1.. {
+                match table.raw_get::<_, Value>(i) {
+                    Ok(Value::Nil) | Err(_) => break,
+                    Ok(item) => items.push(to_resp(&item)),
+                }
+            }
+            let mut resp = format!("*{}\r\n", items.len()).into_bytes();
+            items.iter().for_each(|item| resp.extend_from_slice(item));
+            resp
+        }
+        _ => null_bulk(),
+    }
+}
+
+/// The `redis.call` error reply a script failed with, if that's why it failed.
+fn call_error(e: &mlua::Error) -> Option<String> {
+    match e {
+        mlua::Error::CallbackError { cause, .. } => call_error(cause),
+        mlua::Error::ExternalError(err) => err.downcast_ref::<CallError>().map(|e| e.0.clone()),
+        _ => None,
+    }
+}
+
+/// A one-line description of a Lua error, without the traceback.
+fn describe(e: &mlua::Error) -> String {
+    let msg = match e {
+        mlua::Error::CallbackError { cause, .. } => return describe(cause),
+        mlua::Error::RuntimeError(msg) => msg.clone(),
+        mlua::Error::SyntaxError { message, .. } => message.clone(),
+        other => other.to_string(),
+    };
+    let msg = msg.split("stack traceback:").next().unwrap_or_default();
+    msg.trim().replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+
+    fn run(body: &str, monitor: &Arc<ScriptMonitor>) -> Vec<u8> {
+        run_script(body.as_bytes(), &[], &[], monitor, |_| simple_string("OK"))
+    }
+
+    /// Sends SCRIPT KILL once a script has been running for a while.
+    fn kill_soon(monitor: &Arc<ScriptMonitor>) -> thread::JoinHandle<Vec<u8>> {
+        let monitor = Arc::clone(monitor);
+        monitor.set_busy_threshold(Duration::from_millis(20));
+        thread::spawn(move || {
+            while !monitor.is_busy() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            monitor.kill()
+        })
+    }
+
+    #[test]
+    fn killed_scripts_stop_even_inside_pcall() {
+        for body in [
+            "while true do end",
+            "while true do pcall(function() while true do end end) end",
+        ] {
+            let monitor = Arc::default();
+            let killer = kill_soon(&monitor);
+            let reply = run(body, &monitor);
+            assert_eq!(killer.join().unwrap(), simple_string("OK"));
+            assert_eq!(reply, error("ERR Script killed by user with SCRIPT KILL"), "{body}");
+            assert!(!monitor.is_busy());
+        }
+    }
+
+    #[test]
+    fn scripts_left_alone_finish() {
+        let monitor = Arc::default();
+        let reply = run("local n = 0 for i = 1, 100000 do n = n + i end return n", &monitor);
+        assert_eq!(reply, integer(5000050000));
+        assert_eq!(monitor.kill(), error("NOTBUSY No scripts in execution right now."));
+    }
+}
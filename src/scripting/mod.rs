@@ -0,0 +1,6 @@
+pub mod engine;
+pub mod monitor;
+pub mod reply;
+
+pub use engine::{run_script, ScriptCache};
+pub use monitor::{busy_error, ScriptMonitor};
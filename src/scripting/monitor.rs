@@ -0,0 +1,92 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::resp::utils::{error, simple_string};
+
+/// What other connections can see of the script holding the database lock,
+/// without waiting for that lock: how long it has been running, whether it
+/// has written, and whether SCRIPT KILL asked it to stop.
+///
+/// A script is never stopped once it has written, so a script's writes are
+/// applied either all or not at all.
+#[derive(Debug, Default)]
+pub struct ScriptMonitor {
+    /// `busy-reply-threshold` in milliseconds; 0 never reports busy.
+    busy_threshold: AtomicU64,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    started: Option<Instant>,
+    wrote: bool,
+    killed: bool,
+}
+
+impl ScriptMonitor {
+    pub fn set_busy_threshold(&self, threshold: Duration) {
+        self.busy_threshold
+            .store(threshold.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub(super) fn start(&self) {
+        *self.state.lock().unwrap() = State {
+            started: Some(Instant::now()),
+            ..State::default()
+        };
+    }
+
+    pub(super) fn finish(&self) {
+        *self.state.lock().unwrap() = State::default();
+    }
+
+    /// Called before the running script writes. Returns `false` if it has
+    /// been killed, in which case the write must not happen.
+    pub fn note_write(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.wrote = !state.killed;
+        !state.killed
+    }
+
+    pub(super) fn is_killed(&self) -> bool {
+        self.state.lock().unwrap().killed
+    }
+
+    /// A script has been running for longer than the busy threshold, so
+    /// other clients get a BUSY error instead of waiting.
+    pub fn is_busy(&self) -> bool {
+        let threshold = self.busy_threshold.load(Ordering::Relaxed);
+        threshold > 0
+            && self
+                .state
+                .lock()
+                .unwrap()
+                .started
+                .is_some_and(|started| started.elapsed() >= Duration::from_millis(threshold))
+    }
+
+    /// SCRIPT KILL: stops the running script, unless it has already written.
+    pub fn kill(&self) -> Vec<u8> {
+        let mut state = self.state.lock().unwrap();
+        if state.started.is_none() {
+            return error("NOTBUSY No scripts in execution right now.");
+        }
+        if state.wrote {
+            return error(
+                "UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command.",
+            );
+        }
+        state.killed = true;
+        simple_string("OK")
+    }
+}
+
+/// The reply to any command but SCRIPT KILL while a script is busy.
+pub fn busy_error() -> Vec<u8> {
+    error("BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE.")
+}
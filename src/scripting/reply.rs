@@ -0,0 +1,52 @@
+/// A decoded RESP reply, as produced by the command handlers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Status(Vec<u8>),
+    Error(Vec<u8>),
+    Integer(i64),
+    /// `None` is the null bulk string.
+    Bulk(Option<Vec<u8>>),
+    /// `None` is the null array.
+    Array(Option<Vec<Reply>>),
+}
+
+/// Decodes the reply at the front of `buf`, returning it with the number of
+/// bytes it took. Handlers only ever produce complete, well-formed replies,
+/// so anything else is `None`.
+pub fn parse_reply(buf: &[u8]) -> Option<(Reply, usize)> {
+    let end = buf.windows(2).position(|w| w == b"\r\n")?;
+    let line = &buf[1..end];
+    let mut pos = end + 2;
+
+    let reply = match buf.first()? {
+        b'+' => Reply::Status(line.to_vec()),
+        b'-' => Reply::Error(line.to_vec()),
+        b':' => Reply::Integer(std::str::from_utf8(line).ok()?.parse().ok()?),
+        b'$' => {
+            let len: i64 = std::str::from_utf8(line).ok()?.parse().ok()?;
+            if len < 0 {
+                Reply::Bulk(None)
+            } else {
+                let data = buf.get(pos..pos + len as usize)?.to_vec();
+                pos += len as usize + 2;
+                Reply::Bulk(Some(data))
+            }
+        }
+        b'*' => {
+            let count: i64 = std::str::from_utf8(line).ok()?.parse().ok()?;
+            if count < 0 {
+                Reply::Array(None)
+            } else {
+                let mut items = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let (item, used) = parse_reply(&buf[pos..])?;
+                    items.push(item);
+                    pos += used;
+                }
+                Reply::Array(Some(items))
+            }
+        }
+        _ => return None,
+    };
+    Some((reply, pos))
+}
@@ -1,24 +1,73 @@
 use crate::pubsub::PubSub;
-use crate::{client, persistence::Persister, store::db::Database, utils::start_expiry_worker};
-use std::sync::Arc;
+use crate::{
+    client,
+    config::KeyspaceEvents,
+    persistence::{Persister, Saver}, resp::handler::execute,
+    store::{db::Database, notify::KeyspaceNotifier},
+    utils::start_expiry_worker,
+};
+use std::{sync::Arc, time::Duration};
 use tokio::net::TcpListener;
 use tokio::sync::Mutex;
 
-pub async fn run(addr: &str, persister: Arc<dyn Persister + Send + Sync>, pubsub: Arc<PubSub>) {
+pub async fn run(
+    addr: &str,
+    persister: Arc<dyn Persister + Send + Sync>,
+    saver: Arc<Saver>,
+    pubsub: Arc<PubSub>,
+    ignore_corrupt_snapshot: bool,
+    notify_keyspace_events: KeyspaceEvents,
+    busy_reply_threshold: u64,
+) {
     let listener = TcpListener::bind(addr)
         .await
         .expect("Failed to bind to address");
 
-    let db = match persister.load() {
-        Some(db) => {
+    // Starting empty over an unreadable snapshot would overwrite it on the
+    // next save, so that needs an explicit opt-in.
+    let mut db = match persister.load() {
+        Ok(Some(db)) => {
             println!("🔄 Loaded database from file");
             db
         }
-        None => {
+        Ok(None) => {
             println!("🗄️  No database file found, starting with an empty database");
             Database::new()
         }
+        Err(e) if ignore_corrupt_snapshot => {
+            eprintln!("⚠️  {e}; starting with an empty database (--ignore-corrupt-snapshot yes)");
+            Database::new()
+        }
+        Err(e) => {
+            eprintln!("❌ {e}");
+            eprintln!("❌ Refusing to start; pass --ignore-corrupt-snapshot yes to start with an empty database");
+            std::process::exit(1);
+        }
+    };
+
+    // A log that can't be read must not be silently replaced by an empty one.
+    let commands = match persister.replay() {
+        Ok(commands) => commands,
+        Err(e) => {
+            eprintln!("❌ Failed to read the append-only file: {e}");
+            std::process::exit(1);
+        }
     };
+    if !commands.is_empty() {
+        for parts in &commands {
+            let cmd = String::from_utf8_lossy(&parts[0]).to_uppercase();
+            execute(&cmd, parts, &mut db);
+        }
+        // Replayed commands are already in the log.
+        db.take_propagated();
+        println!("🔄 Replayed {} commands from the append-only file", commands.len());
+    }
+    db.index_field_expiring();
+
+    // Loading and replaying aren't changes anyone should be notified of.
+    db.set_notifier(KeyspaceNotifier::new(notify_keyspace_events, Arc::clone(&pubsub)));
+    let scripts = Arc::clone(db.scripts_ref().monitor());
+    scripts.set_busy_threshold(Duration::from_millis(busy_reply_threshold));
 
     let db = Arc::new(Mutex::new(db));
 
@@ -26,21 +75,48 @@ pub async fn run(addr: &str, persister: Arc<dyn Persister + Send + Sync>, pubsub
     let persister_worker = Arc::clone(&persister);
 
     start_expiry_worker(db_worker, persister_worker);
+    Arc::clone(&saver).start(Arc::clone(&db));
 
     loop {
-        match listener.accept().await {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        match accepted {
             Ok((stream, _)) => {
                 println!("🔗 Accepted new connection");
 
                 let db = Arc::clone(&db);
                 let persister = Arc::clone(&persister);
+                let saver = Arc::clone(&saver);
                 let pubsub = Arc::clone(&pubsub);
+                let scripts = Arc::clone(&scripts);
 
                 tokio::spawn(async move {
-                    client::handle_connection(stream, db, persister, pubsub).await;
+                    client::handle_connection(stream, db, persister, saver, pubsub, scripts)
+                        .await;
                 });
             }
             Err(e) => eprintln!("Failed to accept connection: {e}"),
         }
     }
+
+    shutdown(&db, &saver).await;
+}
+
+/// Saves whatever the background saver hasn't written yet before exiting.
+async fn shutdown(db: &Mutex<Database>, saver: &Saver) {
+    println!("🛑 Shutting down");
+    while saver.is_saving() {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let db = db.lock().await;
+    if saver.unsaved_changes(&db) == 0 {
+        return;
+    }
+    match saver.save(&db) {
+        Ok(()) => println!("💾 Database saved on exit"),
+        Err(e) => eprintln!("❌ Failed to save database on exit: {e}"),
+    }
 }
@@ -0,0 +1,108 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use tokio::sync::oneshot;
+
+/// Clients parked on keys until data arrives, kept in arrival order per key.
+///
+/// Registration happens while the `Database` lock is held, and so does
+/// serving: writes mark the keys they touch as ready, and before the writer
+/// lets go of the lock the parked commands on those keys are run again,
+/// longest-waiting first, with their replies handed over here. A command
+/// that grabs the lock next can't take data a parked client was owed, and no
+/// client is woken just to find nothing.
+#[derive(Debug, Default)]
+pub struct BlockedClients {
+    waiters: HashMap<u64, Waiter>,
+    /// Ids of the clients parked on each key, longest-waiting first.
+    queues: HashMap<Vec<u8>, VecDeque<u64>>,
+    /// Keys with parked clients written to since they were last served.
+    ready: VecDeque<Vec<u8>>,
+    ready_set: HashSet<Vec<u8>>,
+    next_id: u64,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    keys: Vec<Vec<u8>>,
+    command: Vec<Vec<u8>>,
+    reply: oneshot::Sender<Vec<u8>>,
+}
+
+impl BlockedClients {
+    /// Parks `command` on `keys`. The reply arrives on the returned receiver
+    /// once a write lets it through; the id is for [`BlockedClients::leave`].
+    pub fn register(
+        &mut self,
+        keys: Vec<Vec<u8>>,
+        command: Vec<Vec<u8>>,
+    ) -> (u64, oneshot::Receiver<Vec<u8>>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        for key in &keys {
+            self.queues.entry(key.clone()).or_default().push_back(id);
+        }
+        let (reply, rx) = oneshot::channel();
+        self.waiters.insert(id, Waiter { keys, command, reply });
+        (id, rx)
+    }
+
+    /// Takes a client out of line because it timed out or went away.
+    /// Returns `false` if it had already been served.
+    pub fn leave(&mut self, id: u64) -> bool {
+        self.remove(id).is_some()
+    }
+
+    /// Notes that `key` was written to, if anyone is parked on it.
+    pub fn signal(&mut self, key: &[u8]) {
+        if self.queues.contains_key(key) && self.ready_set.insert(key.to_vec()) {
+            self.ready.push_back(key.to_vec());
+        }
+    }
+
+    /// The next key written to since its clients were last served.
+    pub fn take_ready(&mut self) -> Option<Vec<u8>> {
+        let key = self.ready.pop_front()?;
+        self.ready_set.remove(&key);
+        Some(key)
+    }
+
+    /// Clients parked on `key` that are still listening, longest-waiting
+    /// first. Those that went away without leaving are dropped.
+    pub fn waiting_on(&mut self, key: &[u8]) -> Vec<u64> {
+        let ids: Vec<u64> = self.queues.get(key).into_iter().flatten().copied().collect();
+        ids.into_iter()
+            .filter(|id| {
+                let gone = self.waiters[id].reply.is_closed();
+                if gone {
+                    self.remove(*id);
+                }
+                !gone
+            })
+            .collect()
+    }
+
+    /// The command a parked client is waiting to run.
+    pub fn command(&self, id: u64) -> Option<&[Vec<u8>]> {
+        self.waiters.get(&id).map(|waiter| waiter.command.as_slice())
+    }
+
+    /// Hands `reply` to a parked client and takes it out of every line.
+    pub fn serve(&mut self, id: u64, reply: Vec<u8>) {
+        if let Some(waiter) = self.remove(id) {
+            let _ = waiter.reply.send(reply);
+        }
+    }
+
+    fn remove(&mut self, id: u64) -> Option<Waiter> {
+        let waiter = self.waiters.remove(&id)?;
+        for key in &waiter.keys {
+            if let Some(queue) = self.queues.get_mut(key) {
+                queue.retain(|waiter| *waiter != id);
+                if queue.is_empty() {
+                    self.queues.remove(key);
+                }
+            }
+        }
+        Some(waiter)
+    }
+}
@@ -1,102 +1,351 @@
-use std::collections::HashMap;
+use crate::{
+    config::KeyspaceEvents,
+    store::{
+        hash::{FieldValue, Hash},
+        Database, StoreError,
+    },
+    types::RedisValue,
+    utils::current_unix_millis,
+};
 
-use crate::{store::Database, types::RedisValue};
+/// Condition HEXPIRE and friends check against a field's current TTL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpireCondition {
+    /// Only fields without a TTL.
+    Nx,
+    /// Only fields that have a TTL.
+    Xx,
+    /// Only if the new expiry is later; no TTL counts as infinite.
+    Gt,
+    /// Only if the new expiry is earlier; no TTL counts as infinite.
+    Lt,
+}
+
+/// HEXPIRE's reply for a field whose TTL was set.
+pub const FIELD_EXPIRE_SET: i64 = 1;
+/// HEXPIRE's reply for a field deleted because its expiry was in the past.
+pub const FIELD_DELETED: i64 = 2;
+/// HEXPIRE's reply for a field whose condition wasn't met.
+pub const FIELD_CONDITION_NOT_MET: i64 = 0;
+/// Reply for a field (or hash) that doesn't exist.
+pub const NO_SUCH_FIELD: i64 = -2;
+/// HTTL / HPERSIST reply for a field without a TTL.
+pub const FIELD_NO_TTL: i64 = -1;
 
 impl Database{
-    
-    pub fn hset(&mut self, key: &str, field: &str, value: &str) -> usize {
+    fn hash_ref(&mut self, key: &[u8]) -> Result<Option<&Hash>, StoreError> {
         self.is_expired(key);
-        let entry = self
-            .store_mut()
-            .entry(key.to_string())
-            .or_insert(RedisValue::Hash(HashMap::new()));
-        if let RedisValue::Hash(hash) = entry {
-            let is_new = !hash.contains_key(field);
-            hash.insert(field.to_string(), value.to_string());
-            if is_new { 1 } else { 0 }
-        } else {
-            0
+        self.expire_fields(key);
+        match self.store_ref().get(key) {
+            Some(RedisValue::Hash(hash)) => Ok(Some(hash)),
+            Some(_) => Err(StoreError::WrongType),
+            None => Ok(None),
         }
     }
 
-    pub fn hget(&mut self, key: &str, field: &str) -> Option<String> {
-        if self.is_expired(key) {
-            return None;
-        }
-        match self.store_ref().get(key) {
-            Some(RedisValue::Hash(hash)) => hash.get(field).cloned(),
-            _ => None,
+    fn hash_mut(&mut self, key: &[u8]) -> Result<Option<&mut Hash>, StoreError> {
+        self.is_expired(key);
+        self.expire_fields(key);
+        match self.store_mut().get_mut(key) {
+            Some(RedisValue::Hash(hash)) => Ok(Some(hash)),
+            Some(_) => Err(StoreError::WrongType),
+            None => Ok(None),
         }
     }
 
-    pub fn hdel(&mut self, key: &str, fields: &[String]) -> usize {
-        if self.is_expired(key) {
-            return 0;
+    // Creates the hash on first write.
+    fn hash_entry(&mut self, key: &[u8]) -> Result<&mut Hash, StoreError> {
+        self.is_expired(key);
+        self.expire_fields(key);
+        let entry = self
+            .store_mut()
+            .entry(key.to_vec())
+            .or_insert_with(|| RedisValue::Hash(Hash::new()));
+        match entry {
+            RedisValue::Hash(hash) => Ok(hash),
+            _ => Err(StoreError::WrongType),
         }
-        match self.store_mut().get_mut(key) {
-            Some(RedisValue::Hash(ref mut hash)) => {
-                let mut removed = 0;
-                for field in fields.iter() {
-                    if hash.remove(field).is_some() {
-                        removed += 1;
-                    }
-                }
-                removed
+    }
+
+    /// Hashes never stay in the keyspace once their last field is gone.
+    fn remove_if_empty_hash(&mut self, key: &[u8]) {
+        if let Some(RedisValue::Hash(hash)) = self.store_ref().get(key) {
+            if hash.is_empty() {
+                self.store_mut().remove(key);
+                self.expiry_mut().remove(key);
+                self.notify(KeyspaceEvents::GENERIC, "del", key);
             }
-            _ => 0,
         }
     }
 
-    pub fn hkeys(&mut self, key: &str) -> Vec<String> {
-        if self.is_expired(key) {
+    /// Removes the fields of the hash at `key` whose own TTL is up, deleting
+    /// the hash if that empties it. Returns the removed fields.
+    pub fn expire_fields(&mut self, key: &[u8]) -> Vec<Vec<u8>> {
+        let Some(RedisValue::Hash(hash)) = self.store_mut().get_mut(key) else {
             return vec![];
+        };
+        let expired = hash.remove_expired(current_unix_millis());
+        if !expired.is_empty() {
+            self.notify(KeyspaceEvents::HASH, "hexpired", key);
+            self.remove_if_empty_hash(key);
         }
-        match self.store_ref().get(key) {
-            Some(RedisValue::Hash(hash)) => hash.keys().cloned().collect(),
-            _ => vec![],
-        }
+        expired
     }
 
-    pub fn hvals(&mut self, key: &str) -> Vec<String> {
-        if self.is_expired(key) {
-            return vec![];
+    pub fn hset(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> Result<usize, StoreError> {
+        let hash = self.hash_entry(key)?;
+        let is_new = hash.insert(field.to_vec(), value.to_vec());
+        self.notify(KeyspaceEvents::HASH, "hset", key);
+        Ok(if is_new { 1 } else { 0 })
+    }
+
+    /// Sets `field` only if it doesn't exist yet.
+    pub fn hsetnx(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> Result<bool, StoreError> {
+        if self.hash_ref(key)?.is_some_and(|hash| hash.contains(field)) {
+            return Ok(false);
         }
-        match self.store_ref().get(key) {
-            Some(RedisValue::Hash(hash)) => hash.values().cloned().collect(),
-            _ => vec![],
+        self.hset(key, field, value)?;
+        Ok(true)
+    }
+
+    pub fn hget(&mut self, key: &[u8], field: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.hash_ref(key)?.and_then(|hash| hash.get(field).cloned()))
+    }
+
+    pub fn hmget(&mut self, key: &[u8], fields: &[Vec<u8>]) -> Result<Vec<Option<Vec<u8>>>, StoreError> {
+        let hash = self.hash_ref(key)?;
+        Ok(fields
+            .iter()
+            .map(|field| hash.and_then(|hash| hash.get(field).cloned()))
+            .collect())
+    }
+
+    pub fn hdel(&mut self, key: &[u8], fields: &[Vec<u8>]) -> Result<usize, StoreError> {
+        let Some(hash) = self.hash_mut(key)? else {
+            return Ok(0);
+        };
+        let removed = fields
+            .iter()
+            .filter(|field| hash.remove(field).is_some())
+            .count();
+        if removed > 0 {
+            self.notify(KeyspaceEvents::HASH, "hdel", key);
+            self.remove_if_empty_hash(key);
         }
+        Ok(removed)
     }
 
-    pub fn hlen(&mut self, key: &str) -> usize {
-        if self.is_expired(key) {
-            return 0;
+    /// Adds `by` to the integer in `field`, which starts at 0. The field
+    /// keeps its TTL.
+    pub fn hincrby(&mut self, key: &[u8], field: &[u8], by: i64) -> Result<i64, StoreError> {
+        let hash = self.hash_entry(key)?;
+        let current: i64 = match hash.get(field) {
+            Some(value) => std::str::from_utf8(value)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or(StoreError::HashNotInteger)?,
+            None => 0,
+        };
+        let new_value = current.checked_add(by).ok_or(StoreError::Overflow)?;
+        set_keeping_ttl(hash, field, new_value.to_string().into_bytes());
+        self.notify(KeyspaceEvents::HASH, "hincrby", key);
+        Ok(new_value)
+    }
+
+    /// Adds `by` to the float in `field`, which starts at 0. The field keeps
+    /// its TTL.
+    pub fn hincrbyfloat(&mut self, key: &[u8], field: &[u8], by: f64) -> Result<f64, StoreError> {
+        if !by.is_finite() {
+            return Err(StoreError::NanOrInfinity);
         }
-        match self.store_ref().get(key) {
-            Some(RedisValue::Hash(hash)) => hash.len(),
-            _ => 0,
+        let hash = self.hash_entry(key)?;
+        let current: f64 = match hash.get(field) {
+            Some(value) => std::str::from_utf8(value)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|f: &f64| !f.is_nan())
+                .ok_or(StoreError::HashNotFloat)?,
+            None => 0.0,
+        };
+        let new_value = current + by;
+        if !new_value.is_finite() {
+            return Err(StoreError::NanOrInfinity);
         }
+        set_keeping_ttl(hash, field, new_value.to_string().into_bytes());
+        self.notify(KeyspaceEvents::HASH, "hincrbyfloat", key);
+        Ok(new_value)
     }
 
-    pub fn hgetall(&mut self, key: &str) -> HashMap<String, String> {
-        if self.is_expired(key) {
-            return HashMap::new();
-        }
-        match self.store_ref().get(key) {
-            Some(RedisValue::Hash(hash)) => hash.clone(),
-            _ => HashMap::new(),
+    pub fn hstrlen(&mut self, key: &[u8], field: &[u8]) -> Result<usize, StoreError> {
+        Ok(self
+            .hash_ref(key)?
+            .and_then(|hash| hash.get(field))
+            .map_or(0, |value| value.len()))
+    }
+
+    pub fn hkeys(&mut self, key: &[u8]) -> Result<Vec<Vec<u8>>, StoreError> {
+        Ok(self
+            .hash_ref(key)?
+            .map(|hash| hash.iter().map(|(field, _)| field.clone()).collect())
+            .unwrap_or_default())
+    }
+
+    pub fn hvals(&mut self, key: &[u8]) -> Result<Vec<Vec<u8>>, StoreError> {
+        Ok(self
+            .hash_ref(key)?
+            .map(|hash| hash.iter().map(|(_, value)| value.clone()).collect())
+            .unwrap_or_default())
+    }
+
+    pub fn hlen(&mut self, key: &[u8]) -> Result<usize, StoreError> {
+        Ok(self.hash_ref(key)?.map_or(0, |hash| hash.len()))
+    }
+
+    pub fn hgetall(&mut self, key: &[u8]) -> Result<Vec<FieldValue>, StoreError> {
+        Ok(self
+            .hash_ref(key)?
+            .map(|hash| hash.iter().map(|(f, v)| (f.clone(), v.clone())).collect())
+            .unwrap_or_default())
+    }
+
+    pub fn hexists(&mut self, key: &[u8], field: &[u8]) -> Result<bool, StoreError> {
+        Ok(self.hash_ref(key)?.is_some_and(|hash| hash.contains(field)))
+    }
+
+    /// Random fields with their values: `count` distinct ones (or the whole
+    /// hash) when positive, `-count` possibly repeated ones when negative
+    /// (at most `MAX_RANDOM_PICKS`).
+    pub fn hrandfield(&mut self, key: &[u8], count: i64) -> Result<Vec<FieldValue>, StoreError> {
+        let Some(hash) = self.hash_ref(key)? else {
+            return Ok(vec![]);
+        };
+        let pick = |(f, v): (&Vec<u8>, &Vec<u8>)| (f.clone(), v.clone());
+        if count >= 0 {
+            return Ok(fastrand::choose_multiple(hash.iter(), count as usize)
+                .into_iter()
+                .map(pick)
+                .collect());
         }
+        let picks = super::random_picks(count)?;
+        let entries: Vec<_> = hash.iter().collect();
+        Ok((0..picks)
+            .map(|_| pick(entries[fastrand::usize(..entries.len())]))
+            .collect())
     }
 
-    pub fn hexists(&mut self, key: &str, field: &str) -> bool {
-        if self.is_expired(key) {
-            return false;
+    /// One HSCAN step from `cursor`; see [`Hash::scan`].
+    pub fn hscan(
+        &mut self,
+        key: &[u8],
+        cursor: u64,
+        count: usize,
+    ) -> Result<(u64, Vec<FieldValue>), StoreError> {
+        Ok(self
+            .hash_ref(key)?
+            .map_or((0, vec![]), |hash| hash.scan(cursor, count)))
+    }
+
+    /// Gives `fields` a TTL ending at `at` (unix ms), subject to `condition`.
+    /// Replies per field with one of the `FIELD_*` / `NO_SUCH_FIELD` codes.
+    pub fn hexpire(
+        &mut self,
+        key: &[u8],
+        at: u64,
+        condition: Option<ExpireCondition>,
+        fields: &[Vec<u8>],
+    ) -> Result<Vec<i64>, StoreError> {
+        let Some(hash) = self.hash_mut(key)? else {
+            return Ok(vec![NO_SUCH_FIELD; fields.len()]);
+        };
+        let now = current_unix_millis();
+        let mut replies = Vec::with_capacity(fields.len());
+        let (mut set, mut deleted) = (false, false);
+        for field in fields {
+            if !hash.contains(field) {
+                replies.push(NO_SUCH_FIELD);
+                continue;
+            }
+            let current = hash.expire_at(field);
+            let allowed = match condition {
+                None => true,
+                Some(ExpireCondition::Nx) => current.is_none(),
+                Some(ExpireCondition::Xx) => current.is_some(),
+                Some(ExpireCondition::Gt) => current.is_some_and(|current| at > current),
+                Some(ExpireCondition::Lt) => current.is_none_or(|current| at < current),
+            };
+            if !allowed {
+                replies.push(FIELD_CONDITION_NOT_MET);
+            } else if at <= now {
+                hash.remove(field);
+                deleted = true;
+                replies.push(FIELD_DELETED);
+            } else {
+                hash.set_expire_at(field, at);
+                set = true;
+                replies.push(FIELD_EXPIRE_SET);
+            }
         }
-        match self.store_ref().get(key) {
-            Some(RedisValue::Hash(hash)) => hash.contains_key(field),
-            _ => false,
+
+        if set {
+            self.field_expiring_mut().insert(key.to_vec());
+            self.notify(KeyspaceEvents::HASH, "hexpire", key);
+        }
+        if deleted {
+            self.notify(KeyspaceEvents::HASH, "hexpired", key);
+            self.remove_if_empty_hash(key);
+        }
+        Ok(replies)
+    }
+
+    /// Remaining TTL of each field in milliseconds, or `FIELD_NO_TTL` /
+    /// `NO_SUCH_FIELD`.
+    pub fn hpttl(&mut self, key: &[u8], fields: &[Vec<u8>]) -> Result<Vec<i64>, StoreError> {
+        let Some(hash) = self.hash_ref(key)? else {
+            return Ok(vec![NO_SUCH_FIELD; fields.len()]);
+        };
+        let now = current_unix_millis();
+        Ok(fields
+            .iter()
+            .map(|field| match hash.expire_at(field) {
+                Some(at) => at.saturating_sub(now) as i64,
+                None if hash.contains(field) => FIELD_NO_TTL,
+                None => NO_SUCH_FIELD,
+            })
+            .collect())
+    }
+
+    /// Removes the TTL of each field: 1 if it had one, otherwise
+    /// `FIELD_NO_TTL` / `NO_SUCH_FIELD`.
+    pub fn hpersist(&mut self, key: &[u8], fields: &[Vec<u8>]) -> Result<Vec<i64>, StoreError> {
+        let Some(hash) = self.hash_mut(key)? else {
+            return Ok(vec![NO_SUCH_FIELD; fields.len()]);
+        };
+        let replies: Vec<i64> = fields
+            .iter()
+            .map(|field| {
+                if hash.persist(field) {
+                    1
+                } else if hash.contains(field) {
+                    FIELD_NO_TTL
+                } else {
+                    NO_SUCH_FIELD
+                }
+            })
+            .collect();
+        if replies.contains(&1) {
+            self.notify(KeyspaceEvents::HASH, "hpersist", key);
         }
+        Ok(replies)
     }
 
-    
+}
 
-}
\ No newline at end of file
+// HINCRBY and HINCRBYFLOAT change a value without touching its TTL.
+fn set_keeping_ttl(hash: &mut Hash, field: &[u8], value: Vec<u8>) {
+    match hash.get_mut(field) {
+        Some(current) => *current = value,
+        None => {
+            hash.insert(field.to_vec(), value);
+        }
+    }
+}
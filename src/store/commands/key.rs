@@ -1,9 +1,9 @@
 
 
-use crate::{store::Database, utils::current_unix_timestamp};
+use crate::{config::KeyspaceEvents, store::Database, utils::current_unix_millis};
 
 impl Database{
-    pub fn exists(&mut self, keys: &[String]) -> usize {
+    pub fn exists(&mut self, keys: &[Vec<u8>]) -> usize {
         keys.iter()
             .filter(|key| {
                 self.is_expired(key);
@@ -12,35 +12,40 @@ impl Database{
             .count()
     }
 
+    pub fn key_type(&mut self, key: &[u8]) -> &'static str {
+        self.is_expired(key);
+        self.store_ref().get(key).map_or("none", |value| value.type_name())
+    }
+
     // Return only non expired keys.
-    pub fn keys(&mut self) -> Vec<String> {
+    pub fn keys(&mut self) -> Vec<Vec<u8>> {
         // Check all keys for expiry first.
-        let current_keys: Vec<String> = self.store_ref().keys().cloned().collect();
+        let current_keys: Vec<Vec<u8>> = self.store_ref().keys().cloned().collect();
         for key in current_keys.iter() {
             self.is_expired(key);
         }
         self.store_ref().keys().cloned().collect()
     }
 
-    pub fn expire(&mut self, key: &str, seconds: u64) -> usize {
+    /// Sets an absolute expiry, in Unix milliseconds.
+    pub fn expire_at(&mut self, key: &[u8], timestamp: u64) -> usize {
         if self.is_expired(key) || !self.store_ref().contains_key(key) {
             0
         } else {
-            let now = current_unix_timestamp();
-            let expire_at = now + seconds;
-            self.expiry_mut().insert(key.to_string(), expire_at);
+            self.expiry_mut().insert(key.to_vec(), timestamp);
+            self.notify(KeyspaceEvents::GENERIC, "expire", key);
             1
         }
     }
-    
 
-    pub fn ttl(&mut self, key: &str) -> isize {
+    /// Milliseconds the key has left to live.
+    pub fn pttl(&mut self, key: &[u8]) -> i64 {
         if self.is_expired(key) || !self.store_ref().contains_key(key) {
             -2  // Key does not exist
         } else if let Some(&expire_at) = self.expiry_ref().get(key) {
-            let now = current_unix_timestamp();
+            let now = current_unix_millis();
             let ttl = expire_at.saturating_sub(now);
-            ttl as isize
+            ttl as i64
         } else {
             -1  // Key exists, no expiry
         }
@@ -48,10 +53,11 @@ impl Database{
     
 
 
-    pub fn persist(&mut self, key: &str) -> usize {
+    pub fn persist(&mut self, key: &[u8]) -> usize {
     if self.is_expired(key) || !self.store_mut().contains_key(key) {
         0
     } else if self.expiry_mut().remove(key).is_some() {
+        self.notify(KeyspaceEvents::GENERIC, "persist", key);
         1
     } else {
         0
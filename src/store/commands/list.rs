@@ -1,129 +1,340 @@
-use crate::{store::Database, types::RedisValue};
+use std::collections::{HashSet, VecDeque};
+
+use crate::{config::KeyspaceEvents, store::{Database, StoreError}, types::RedisValue};
+
+/// Which end of a list an element is taken from or added to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
 
 impl Database{
-    pub fn lpush(&mut self, key: &str, values: &[String]) -> usize {
+    fn list_ref(&mut self, key: &[u8]) -> Result<Option<&VecDeque<Vec<u8>>>, StoreError> {
         self.is_expired(key);
-        let entry = self
-            .store_mut()
-            .entry(key.to_string())
-            .or_insert(RedisValue::List(vec![]));
-        if let RedisValue::List(list) = entry {
-            for value in values.iter().rev() {
-                list.insert(0, value.clone());
-            }
-            list.len()
-        } else {
-            0
+        match self.store_ref().get(key) {
+            Some(RedisValue::List(list)) => Ok(Some(list)),
+            Some(_) => Err(StoreError::WrongType),
+            None => Ok(None),
         }
     }
 
-    pub fn rpush(&mut self, key: &str, values: &[String]) -> usize {
+    fn list_mut(&mut self, key: &[u8]) -> Result<Option<&mut VecDeque<Vec<u8>>>, StoreError> {
+        self.is_expired(key);
+        match self.store_mut().get_mut(key) {
+            Some(RedisValue::List(list)) => Ok(Some(list)),
+            Some(_) => Err(StoreError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    // Creates the list on first push.
+    fn list_entry(&mut self, key: &[u8]) -> Result<&mut VecDeque<Vec<u8>>, StoreError> {
         self.is_expired(key);
         let entry = self
             .store_mut()
-            .entry(key.to_string())
-            .or_insert(RedisValue::List(vec![]));
-        if let RedisValue::List(list) = entry {
-            for value in values.iter() {
-                list.push(value.clone());
-            }
-            list.len()
-        } else {
-            0
+            .entry(key.to_vec())
+            .or_insert(RedisValue::List(VecDeque::new()));
+        match entry {
+            RedisValue::List(list) => Ok(list),
+            _ => Err(StoreError::WrongType),
         }
     }
 
-    // Returns None if the key is expired.
-    pub fn lpop(&mut self, key: &str) -> Option<String> {
-        if self.is_expired(key) {
-            return None;
+    pub fn lpush(&mut self, key: &[u8], values: &[Vec<u8>]) -> Result<usize, StoreError> {
+        let list = self.list_entry(key)?;
+        for value in values.iter() {
+            list.push_front(value.clone());
         }
-        match self.store_mut().get_mut(key) {
-            Some(RedisValue::List(ref mut list)) => {
-                if list.is_empty() {
-                    None
-                } else {
-                    Some(list.remove(0))
-                }
-            }
-            _ => None,
+        let len = list.len();
+        self.notify(KeyspaceEvents::LIST, "lpush", key);
+        self.blocked_mut().signal(key);
+        Ok(len)
+    }
+
+    pub fn rpush(&mut self, key: &[u8], values: &[Vec<u8>]) -> Result<usize, StoreError> {
+        let list = self.list_entry(key)?;
+        for value in values.iter() {
+            list.push_back(value.clone());
         }
+        let len = list.len();
+        self.notify(KeyspaceEvents::LIST, "rpush", key);
+        self.blocked_mut().signal(key);
+        Ok(len)
     }
 
-    // Returns None if the key is expired.
-    pub fn rpop(&mut self, key: &str) -> Option<String> {
-        if self.is_expired(key) {
-            return None;
+    /// Lists never stay in the keyspace once their last element is gone.
+    fn remove_if_empty_list(&mut self, key: &[u8]) {
+        if let Some(RedisValue::List(list)) = self.store_ref().get(key) {
+            if list.is_empty() {
+                self.store_mut().remove(key);
+                self.expiry_mut().remove(key);
+                self.notify(KeyspaceEvents::GENERIC, "del", key);
+            }
         }
-        match self.store_mut().get_mut(key) {
-            Some(RedisValue::List(ref mut list)) => list.pop(),
-            _ => None,
+    }
+
+    /// LPUSHX: pushes only onto a list that already exists.
+    pub fn lpushx(&mut self, key: &[u8], values: &[Vec<u8>]) -> Result<usize, StoreError> {
+        if self.list_ref(key)?.is_none() {
+            return Ok(0);
         }
+        self.lpush(key, values)
     }
 
-    pub fn llen(&mut self, key: &str) -> usize {
-        if self.is_expired(key) {
-            return 0;
+    /// RPUSHX: pushes only onto a list that already exists.
+    pub fn rpushx(&mut self, key: &[u8], values: &[Vec<u8>]) -> Result<usize, StoreError> {
+        if self.list_ref(key)?.is_none() {
+            return Ok(0);
         }
-        match self.store_mut().get(key) {
-            Some(RedisValue::List(list)) => list.len(),
-            _ => 0,
+        self.rpush(key, values)
+    }
+
+    /// Pops up to `count` elements from one end, in the order they come off.
+    /// `None` means there is no list at `key`.
+    pub fn list_pop(
+        &mut self,
+        key: &[u8],
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<Vec<Vec<u8>>>, StoreError> {
+        let Some(list) = self.list_mut(key)? else {
+            return Ok(None);
+        };
+        let n = count.min(list.len());
+        let (popped, event): (Vec<_>, _) = match end {
+            ListEnd::Left => (list.drain(..n).collect(), "lpop"),
+            ListEnd::Right => (list.drain(list.len() - n..).rev().collect(), "rpop"),
+        };
+        if !popped.is_empty() {
+            self.notify(KeyspaceEvents::LIST, event, key);
+            self.remove_if_empty_list(key);
         }
+        Ok(Some(popped))
     }
 
-    pub fn lindex(&mut self, key: &str, index: isize) -> Option<String> {
-        if self.is_expired(key) {
-            return None;
+    // Returns None if the key is expired.
+    pub fn lpop(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self
+            .list_pop(key, ListEnd::Left, 1)?
+            .and_then(|popped| popped.into_iter().next()))
+    }
+
+    // Returns None if the key is expired.
+    pub fn rpop(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self
+            .list_pop(key, ListEnd::Right, 1)?
+            .and_then(|popped| popped.into_iter().next()))
+    }
+
+    /// Pops from `from` of `source` and pushes onto `to` of `destination`,
+    /// which may be the same list. Nothing is popped if `destination` holds
+    /// another type.
+    pub fn lmove(
+        &mut self,
+        source: &[u8],
+        destination: &[u8],
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<Vec<u8>>, StoreError> {
+        if source == destination {
+            return self.lrotate(source, from, to);
         }
-        match self.store_ref().get(key) {
-            Some(RedisValue::List(list)) => {
-                let len = list.len() as isize;
-                let idx = if index < 0 { len + index } else { index };
-                if idx < 0 || idx >= len {
-                    None
-                } else {
-                    list.get(idx as usize).cloned()
-                }
+        self.list_ref(destination)?;
+        let popped = match from {
+            ListEnd::Left => self.lpop(source)?,
+            ListEnd::Right => self.rpop(source)?,
+        };
+        let Some(value) = popped else {
+            return Ok(None);
+        };
+        let pushed = std::slice::from_ref(&value);
+        match to {
+            ListEnd::Left => self.lpush(destination, pushed)?,
+            ListEnd::Right => self.rpush(destination, pushed)?,
+        };
+        Ok(Some(value))
+    }
+
+    // Moving within one list never empties it, so the key keeps its TTL and
+    // is never deleted, not even for a moment.
+    fn lrotate(
+        &mut self,
+        key: &[u8],
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<Vec<u8>>, StoreError> {
+        let Some(list) = self.list_mut(key)? else {
+            return Ok(None);
+        };
+        let (value, popped) = match from {
+            ListEnd::Left => (list.pop_front(), "lpop"),
+            ListEnd::Right => (list.pop_back(), "rpop"),
+        };
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        let pushed = match to {
+            ListEnd::Left => {
+                list.push_front(value.clone());
+                "lpush"
+            }
+            ListEnd::Right => {
+                list.push_back(value.clone());
+                "rpush"
             }
-            _ => None,
+        };
+        self.notify(KeyspaceEvents::LIST, popped, key);
+        self.notify(KeyspaceEvents::LIST, pushed, key);
+        Ok(Some(value))
+    }
+
+    pub fn llen(&mut self, key: &[u8]) -> Result<usize, StoreError> {
+        Ok(self.list_ref(key)?.map_or(0, |list| list.len()))
+    }
+
+    pub fn lindex(&mut self, key: &[u8], index: isize) -> Result<Option<Vec<u8>>, StoreError> {
+        let Some(list) = self.list_ref(key)? else {
+            return Ok(None);
+        };
+        let len = list.len() as isize;
+        let idx = if index < 0 { len + index } else { index };
+        if idx < 0 || idx >= len {
+            Ok(None)
+        } else {
+            Ok(list.get(idx as usize).cloned())
         }
     }
 
-    pub fn lset(&mut self, key: &str, index: isize, value: String) -> Result<(), &'static str> {
-        if self.is_expired(key) {
-            return Err("Key does not exist");
+    pub fn lset(&mut self, key: &[u8], index: isize, value: Vec<u8>) -> Result<(), StoreError> {
+        let list = self.list_mut(key)?.ok_or(StoreError::NoSuchKey)?;
+        let len = list.len() as isize;
+        let idx = if index < 0 { len + index } else { index };
+        if idx < 0 || idx >= len {
+            Err(StoreError::IndexOutOfRange)
+        } else {
+            list[idx as usize] = value;
+            self.notify(KeyspaceEvents::LIST, "lset", key);
+            Ok(())
         }
-        match self.store_mut().get_mut(key) {
-            Some(RedisValue::List(ref mut list)) => {
-                let len = list.len() as isize;
-                let idx = if index < 0 { len + index } else { index };
-                if idx < 0 || idx >= len {
-                    Err("Index out of range")
-                } else {
-                    list[idx as usize] = value;
-                    Ok(())
-                }
-            }
-            _ => Err("Key does not exist or is not a list"),
+    }
+
+    /// Inserts `value` before or after the first `pivot`, returning the new
+    /// length, -1 if there is no `pivot` or 0 if there is no list.
+    pub fn linsert(
+        &mut self,
+        key: &[u8],
+        before: bool,
+        pivot: &[u8],
+        value: &[u8],
+    ) -> Result<i64, StoreError> {
+        let Some(list) = self.list_mut(key)? else {
+            return Ok(0);
+        };
+        let Some(at) = list.iter().position(|element| element == pivot) else {
+            return Ok(-1);
+        };
+        list.insert(if before { at } else { at + 1 }, value.to_vec());
+        let len = list.len() as i64;
+        self.notify(KeyspaceEvents::LIST, "linsert", key);
+        Ok(len)
+    }
+
+    /// Removes up to `count` occurrences of `value`: from the head when
+    /// `count` is positive, from the tail when negative, all of them for 0.
+    pub fn lrem(&mut self, key: &[u8], count: i64, value: &[u8]) -> Result<usize, StoreError> {
+        let Some(list) = self.list_mut(key)? else {
+            return Ok(0);
+        };
+        let limit = if count == 0 { usize::MAX } else { count.unsigned_abs() as usize };
+        let matches = |i: &usize| list[*i] == value;
+        let doomed: HashSet<usize> = if count < 0 {
+            (0..list.len()).rev().filter(matches).take(limit).collect()
+        } else {
+            (0..list.len()).filter(matches).take(limit).collect()
+        };
+
+        let mut i = 0;
+        list.retain(|_| {
+            let keep = !doomed.contains(&i);
+            i += 1;
+            keep
+        });
+        if !doomed.is_empty() {
+            self.notify(KeyspaceEvents::LIST, "lrem", key);
+            self.remove_if_empty_list(key);
         }
+        Ok(doomed.len())
     }
 
-    pub fn lrange(&mut self, key: &str, start: isize, end: isize) -> Vec<String> {
-        if self.is_expired(key) {
-            return vec![];
+    /// Keeps only the elements from `start` to `end` (inclusive, negative
+    /// indexes count from the tail).
+    pub fn ltrim(&mut self, key: &[u8], start: isize, end: isize) -> Result<(), StoreError> {
+        let Some(list) = self.list_mut(key)? else {
+            return Ok(());
+        };
+        let len = list.len() as isize;
+        let start = if start < 0 { (len + start).max(0) } else { start };
+        let end = if end < 0 { len + end } else { end.min(len - 1) };
+        if start >= len || end < 0 || start > end {
+            list.clear();
+        } else {
+            list.truncate(end as usize + 1);
+            list.drain(..start as usize);
         }
-        match self.store_ref().get(key) {
-            Some(RedisValue::List(list)) => {
-                let len = list.len() as isize;
-                let start = if start < 0 { (len + start).max(0) } else { start };
-                let end = if end < 0 { len + end } else { end.min(len - 1) };
-                if start >= len || end < 0 || start > end {
-                    vec![]
-                } else {
-                    list[start as usize..=end as usize].to_vec()
-                }
-            }
-            _ => vec![],
+        self.notify(KeyspaceEvents::LIST, "ltrim", key);
+        self.remove_if_empty_list(key);
+        Ok(())
+    }
+
+    /// Positions of `value` (LPOS). Matching starts at the `rank`th match,
+    /// scanning from the tail when `rank` is negative; at most `count`
+    /// positions are returned (0 for all) and at most `maxlen` elements are
+    /// compared (0 for no limit).
+    pub fn lpos(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        rank: i64,
+        count: usize,
+        maxlen: usize,
+    ) -> Result<Vec<usize>, StoreError> {
+        let Some(list) = self.list_ref(key)? else {
+            return Ok(vec![]);
+        };
+        let scanned = if maxlen == 0 { list.len() } else { maxlen.min(list.len()) };
+        let skip = rank.unsigned_abs() as usize - 1;
+        let wanted = if count == 0 { usize::MAX } else { count };
+        let matches = |i: &usize| list[*i] == value;
+        Ok(if rank < 0 {
+            (0..list.len())
+                .rev()
+                .take(scanned)
+                .filter(matches)
+                .skip(skip)
+                .take(wanted)
+                .collect()
+        } else {
+            (0..list.len())
+                .take(scanned)
+                .filter(matches)
+                .skip(skip)
+                .take(wanted)
+                .collect()
+        })
+    }
+
+    pub fn lrange(&mut self, key: &[u8], start: isize, end: isize) -> Result<Vec<Vec<u8>>, StoreError> {
+        let Some(list) = self.list_ref(key)? else {
+            return Ok(vec![]);
+        };
+        let len = list.len() as isize;
+        let start = if start < 0 { (len + start).max(0) } else { start };
+        let end = if end < 0 { len + end } else { end.min(len - 1) };
+        if start >= len || end < 0 || start > end {
+            Ok(vec![])
+        } else {
+            Ok(list.range(start as usize..=end as usize).cloned().collect())
         }
     }
-}
\ No newline at end of file
+}
@@ -3,4 +3,21 @@ pub mod set;
 pub mod hash_set;
 pub mod key;
 pub mod number;
-pub mod list;
\ No newline at end of file
+pub mod list;
+pub mod zset;
+pub mod stream;
+
+use crate::store::StoreError;
+
+/// Most members a negative `SRANDMEMBER`/`HRANDFIELD` count may ask for. The
+/// reply is built in memory, so an unbounded count could abort the process
+/// on allocation.
+const MAX_RANDOM_PICKS: u64 = 1024 * 1024;
+
+/// How many possibly repeated members a negative random count asks for.
+fn random_picks(count: i64) -> Result<usize, StoreError> {
+    match count.checked_neg() {
+        Some(picks) if picks as u64 <= MAX_RANDOM_PICKS => Ok(picks as usize),
+        _ => Err(StoreError::OutOfRange),
+    }
+}
@@ -1,23 +1,27 @@
-use crate::{store::Database, types::RedisValue};
+use crate::{config::KeyspaceEvents, store::{Database, StoreError}, types::RedisValue};
 
 impl Database{
-    pub fn incr(&mut self, key: &str) -> Result<i64, &'static str> {
+    pub fn incr(&mut self, key: &[u8]) -> Result<i64, StoreError> {
         self.incr_by(key, 1)
     }
-    pub fn incr_by(&mut self, key: &str, by: i64) -> Result<i64, &'static str> {
+    pub fn incr_by(&mut self, key: &[u8], by: i64) -> Result<i64, StoreError> {
         // Even for incr, check expiry first.
         self.is_expired(key);
-        let val = self.store_mut().entry(key.to_string()).or_insert(RedisValue::String("0".to_string()));
+        let val = self.store_mut().entry(key.to_vec()).or_insert(RedisValue::String(b"0".to_vec()));
         match val {
             RedisValue::String(ref mut s) => {
-                let current_value: i64 = s.parse().map_err(|_| "Value is not an integer")?;
-                let new_value = current_value + by;
-                *s = new_value.to_string();
+                let current_value: i64 = std::str::from_utf8(s)
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or(StoreError::NotInteger)?;
+                let new_value = current_value.checked_add(by).ok_or(StoreError::Overflow)?;
+                *s = new_value.to_string().into_bytes();
+                self.notify(KeyspaceEvents::STRING, "incrby", key);
                 Ok(new_value)
             }
-            _ => Err("Value is not an integer"),
+            _ => Err(StoreError::WrongType),
         }
     }
 
-    
-}
\ No newline at end of file
+
+}
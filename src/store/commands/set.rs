@@ -1,15 +1,31 @@
 use std::collections::HashSet;
 
-use crate::{store::Database, types::RedisValue};
+use crate::{config::KeyspaceEvents, store::{Database, StoreError}, types::RedisValue};
 
 impl Database{
-    
+    fn set_ref(&mut self, key: &[u8]) -> Result<Option<&HashSet<Vec<u8>>>, StoreError> {
+        self.is_expired(key);
+        match self.store_ref().get(key) {
+            Some(RedisValue::Set(set)) => Ok(Some(set)),
+            Some(_) => Err(StoreError::WrongType),
+            None => Ok(None),
+        }
+    }
 
-    pub fn sadd(&mut self, key: &str, values: &[String]) -> usize {
+    fn set_mut(&mut self, key: &[u8]) -> Result<Option<&mut HashSet<Vec<u8>>>, StoreError> {
+        self.is_expired(key);
+        match self.store_mut().get_mut(key) {
+            Some(RedisValue::Set(set)) => Ok(Some(set)),
+            Some(_) => Err(StoreError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    pub fn sadd(&mut self, key: &[u8], values: &[Vec<u8>]) -> Result<usize, StoreError> {
         self.is_expired(key);
         let entry = self
             .store_mut()
-            .entry(key.to_string())
+            .entry(key.to_vec())
             .or_insert(RedisValue::Set(HashSet::new()));
         if let RedisValue::Set(set) = entry {
             let mut added = 0;
@@ -18,58 +34,215 @@ impl Database{
                     added += 1;
                 }
             }
-            added
+            if added > 0 {
+                self.notify(KeyspaceEvents::SET, "sadd", key);
+            }
+            Ok(added)
         } else {
-            0
+            Err(StoreError::WrongType)
         }
     }
 
-    pub fn srem(&mut self, key: &str, values: &[String]) -> usize {
-        if self.is_expired(key) {
-            return 0;
+    pub fn srem(&mut self, key: &[u8], values: &[Vec<u8>]) -> Result<usize, StoreError> {
+        let Some(set) = self.set_mut(key)? else {
+            return Ok(0);
+        };
+        let removed = values.iter().filter(|value| set.remove(*value)).count();
+        if removed > 0 {
+            self.notify(KeyspaceEvents::SET, "srem", key);
+            self.remove_if_empty_set(key);
         }
-        match self.store_mut().get_mut(key) {
-            Some(RedisValue::Set(ref mut set)) => {
-                let mut removed = 0;
-                for value in values.iter() {
-                    if set.remove(value) {
-                        removed += 1;
-                    }
-                }
-                removed
+        Ok(removed)
+    }
+
+    /// Sets never stay in the keyspace once their last member is gone.
+    fn remove_if_empty_set(&mut self, key: &[u8]) {
+        if let Some(RedisValue::Set(set)) = self.store_ref().get(key) {
+            if set.is_empty() {
+                self.store_mut().remove(key);
+                self.expiry_mut().remove(key);
+                self.notify(KeyspaceEvents::GENERIC, "del", key);
             }
-            _ => 0,
         }
     }
 
-    pub fn smembers(&mut self, key: &str) -> Vec<String> {
-        if self.is_expired(key) {
-            return vec![];
+    /// The sets at `keys`, `None` where there is none. Every key is checked
+    /// for type before any set is read.
+    fn sets_ref(&mut self, keys: &[Vec<u8>]) -> Result<Vec<Option<&HashSet<Vec<u8>>>>, StoreError> {
+        for key in keys {
+            self.is_expired(key);
         }
-        match self.store_ref().get(key) {
-            Some(RedisValue::Set(set)) => set.iter().cloned().collect(),
-            _ => vec![],
+        keys.iter()
+            .map(|key| match self.store_ref().get(key) {
+                Some(RedisValue::Set(set)) => Ok(Some(set)),
+                Some(_) => Err(StoreError::WrongType),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    pub fn sinter(&mut self, keys: &[Vec<u8>]) -> Result<HashSet<Vec<u8>>, StoreError> {
+        let sets = self.sets_ref(keys)?;
+        Ok(intersection(&sets, usize::MAX).into_iter().cloned().collect())
+    }
+
+    /// Size of the intersection, counting no further than `limit` (0 for no
+    /// limit).
+    pub fn sintercard(&mut self, keys: &[Vec<u8>], limit: usize) -> Result<usize, StoreError> {
+        let sets = self.sets_ref(keys)?;
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        Ok(intersection(&sets, limit).len())
+    }
+
+    pub fn sunion(&mut self, keys: &[Vec<u8>]) -> Result<HashSet<Vec<u8>>, StoreError> {
+        let sets = self.sets_ref(keys)?;
+        Ok(sets.into_iter().flatten().flatten().cloned().collect())
+    }
+
+    /// Members of the first set that are in none of the others.
+    pub fn sdiff(&mut self, keys: &[Vec<u8>]) -> Result<HashSet<Vec<u8>>, StoreError> {
+        let sets = self.sets_ref(keys)?;
+        let Some((Some(first), others)) = sets.split_first() else {
+            return Ok(HashSet::new());
+        };
+        Ok(first
+            .iter()
+            .filter(|member| !others.iter().flatten().any(|set| set.contains(*member)))
+            .cloned()
+            .collect())
+    }
+
+    /// Replaces whatever is at `destination` with `members`, for the STORE
+    /// variants. An empty result deletes `destination`. Returns the new size.
+    pub fn store_set(&mut self, destination: &[u8], members: HashSet<Vec<u8>>, event: &str) -> usize {
+        self.is_expired(destination);
+        let len = members.len();
+        let old = if members.is_empty() {
+            self.store_mut().remove(destination)
+        } else {
+            self.store_mut()
+                .insert(destination.to_vec(), RedisValue::Set(members))
+        };
+        self.expiry_mut().remove(destination);
+        // Clients blocked on a list or stream here find out it's gone.
+        if old.as_ref().is_some_and(|old| !matches!(old, RedisValue::Set(_))) {
+            self.blocked_mut().signal(destination);
+        }
+
+        if len > 0 {
+            self.notify(KeyspaceEvents::SET, event, destination);
+        } else if old.is_some() {
+            self.notify(KeyspaceEvents::GENERIC, "del", destination);
         }
+        len
     }
 
-    pub fn sismember(&mut self, key: &str, value: &str) -> bool {
-        if self.is_expired(key) {
-            return false;
+    /// Moves `member` from `source` to `destination`. Returns false if it
+    /// wasn't in `source`.
+    pub fn smove(&mut self, source: &[u8], destination: &[u8], member: &[u8]) -> Result<bool, StoreError> {
+        self.set_ref(destination)?;
+        if source == destination {
+            return self.sismember(source, member);
         }
-        match self.store_ref().get(key) {
-            Some(RedisValue::Set(set)) => set.contains(value),
-            _ => false,
+        let Some(set) = self.set_mut(source)? else {
+            return Ok(false);
+        };
+        if !set.remove(member) {
+            return Ok(false);
         }
+        self.notify(KeyspaceEvents::SET, "srem", source);
+        self.remove_if_empty_set(source);
+        self.sadd(destination, &[member.to_vec()])?;
+        Ok(true)
     }
 
-    pub fn scard(&mut self, key: &str) -> usize {
-        if self.is_expired(key) {
-            return 0;
+    /// Removes and returns up to `count` random members. `None` means there
+    /// is no set at `key`.
+    pub fn spop(&mut self, key: &[u8], count: usize) -> Result<Option<Vec<Vec<u8>>>, StoreError> {
+        let Some(set) = self.set_mut(key)? else {
+            return Ok(None);
+        };
+        let popped: Vec<Vec<u8>> = if count >= set.len() {
+            set.drain().collect()
+        } else {
+            let chosen: Vec<Vec<u8>> = fastrand::choose_multiple(set.iter(), count)
+                .into_iter()
+                .cloned()
+                .collect();
+            for member in &chosen {
+                set.remove(member);
+            }
+            chosen
+        };
+        if !popped.is_empty() {
+            // Replaying the log must remove the same members.
+            let mut srem = vec![b"SREM".to_vec(), key.to_vec()];
+            srem.extend(popped.iter().cloned());
+            self.propagate_as(srem);
+            self.notify(KeyspaceEvents::SET, "spop", key);
+            self.remove_if_empty_set(key);
         }
-        match self.store_ref().get(key) {
-            Some(RedisValue::Set(set)) => set.len(),
-            _ => 0,
+        Ok(Some(popped))
+    }
+
+    /// Random members without removing them: `count` distinct ones (or the
+    /// whole set) when positive, `-count` possibly repeated ones when
+    /// negative (at most `MAX_RANDOM_PICKS`).
+    pub fn srandmember(&mut self, key: &[u8], count: i64) -> Result<Vec<Vec<u8>>, StoreError> {
+        let Some(set) = self.set_ref(key)? else {
+            return Ok(vec![]);
+        };
+        if count >= 0 {
+            return Ok(fastrand::choose_multiple(set.iter(), count as usize)
+                .into_iter()
+                .cloned()
+                .collect());
         }
+        let picks = super::random_picks(count)?;
+        let members: Vec<&Vec<u8>> = set.iter().collect();
+        Ok((0..picks)
+            .map(|_| members[fastrand::usize(..members.len())].clone())
+            .collect())
+    }
+
+    pub fn smismember(&mut self, key: &[u8], members: &[Vec<u8>]) -> Result<Vec<bool>, StoreError> {
+        let set = self.set_ref(key)?;
+        Ok(members
+            .iter()
+            .map(|member| set.is_some_and(|set| set.contains(member)))
+            .collect())
+    }
+
+    pub fn smembers(&mut self, key: &[u8]) -> Result<Vec<Vec<u8>>, StoreError> {
+        Ok(self
+            .set_ref(key)?
+            .map(|set| set.iter().cloned().collect())
+            .unwrap_or_default())
     }
 
-}
\ No newline at end of file
+    pub fn sismember(&mut self, key: &[u8], value: &[u8]) -> Result<bool, StoreError> {
+        Ok(self.set_ref(key)?.is_some_and(|set| set.contains(value)))
+    }
+
+    pub fn scard(&mut self, key: &[u8]) -> Result<usize, StoreError> {
+        Ok(self.set_ref(key)?.map_or(0, |set| set.len()))
+    }
+
+}
+
+/// Members in every one of `sets`, at most `limit` of them. A missing set
+/// empties the intersection; the smallest set is the one scanned.
+fn intersection<'a>(sets: &[Option<&'a HashSet<Vec<u8>>>], limit: usize) -> Vec<&'a Vec<u8>> {
+    let Some(mut sets) = sets.iter().copied().collect::<Option<Vec<_>>>() else {
+        return vec![];
+    };
+    sets.sort_by_key(|set| set.len());
+    let Some((smallest, others)) = sets.split_first() else {
+        return vec![];
+    };
+    smallest
+        .iter()
+        .filter(|member| others.iter().all(|set| set.contains(*member)))
+        .take(limit)
+        .collect()
+}
@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    store::{
+        stream::{ClaimOptions, Delivered, Entry, IdSpec, PendingEntry, Stream, StreamId, TrimSpec},
+        Database, StoreError,
+    },
+    types::RedisValue,
+    utils::current_unix_millis,
+};
+
+impl Database {
+    fn stream_ref(&mut self, key: &[u8]) -> Result<Option<&Stream>, StoreError> {
+        self.is_expired(key);
+        match self.store_ref().get(key) {
+            Some(RedisValue::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(StoreError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    fn stream_mut(&mut self, key: &[u8]) -> Result<Option<&mut Stream>, StoreError> {
+        self.is_expired(key);
+        match self.store_mut().get_mut(key) {
+            Some(RedisValue::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(StoreError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Appends an entry and wakes any XREAD / XREADGROUP blocked on `key`.
+    /// Returns `None` when NOMKSTREAM was given and the stream is missing.
+    pub fn xadd(
+        &mut self,
+        key: &[u8],
+        spec: IdSpec,
+        fields: Vec<Vec<u8>>,
+        nomkstream: bool,
+        trim: Option<TrimSpec>,
+    ) -> Result<Option<StreamId>, StoreError> {
+        let exists = self.stream_ref(key)?.is_some();
+        if !exists && nomkstream {
+            return Ok(None);
+        }
+
+        let entry = self
+            .store_mut()
+            .entry(key.to_vec())
+            .or_insert_with(|| RedisValue::Stream(Stream::new()));
+        let RedisValue::Stream(stream) = entry else {
+            return Err(StoreError::WrongType);
+        };
+
+        let id = match stream.add(spec, fields, current_unix_millis()) {
+            Ok(id) => id,
+            Err(e) => {
+                // Don't leave an empty stream behind for a rejected ID.
+                if !exists {
+                    self.store_mut().remove(key);
+                }
+                return Err(e);
+            }
+        };
+        if let Some(trim) = trim {
+            stream.trim(trim);
+        }
+
+        self.blocked_mut().signal(key);
+        Ok(Some(id))
+    }
+
+    pub fn xlen(&mut self, key: &[u8]) -> Result<usize, StoreError> {
+        Ok(self.stream_ref(key)?.map_or(0, |s| s.len()))
+    }
+
+    pub fn xrange(
+        &mut self,
+        key: &[u8],
+        start: StreamId,
+        end: StreamId,
+        rev: bool,
+        count: Option<usize>,
+    ) -> Result<Vec<Entry>, StoreError> {
+        Ok(self
+            .stream_ref(key)?
+            .map(|s| s.range(start, end, rev, count))
+            .unwrap_or_default())
+    }
+
+    pub fn xdel(&mut self, key: &[u8], ids: &[StreamId]) -> Result<usize, StoreError> {
+        Ok(self.stream_mut(key)?.map_or(0, |s| s.delete(ids)))
+    }
+
+    pub fn xtrim(&mut self, key: &[u8], spec: TrimSpec) -> Result<usize, StoreError> {
+        Ok(self.stream_mut(key)?.map_or(0, |s| s.trim(spec)))
+    }
+
+    pub fn xsetid(&mut self, key: &[u8], id: StreamId) -> Result<(), StoreError> {
+        self.stream_mut(key)?
+            .ok_or(StoreError::NoSuchKey)?
+            .set_last_id(id)
+    }
+
+    /// The ID `$` refers to: the last ID ever added, or `0-0`.
+    pub fn stream_last_id(&mut self, key: &[u8]) -> Result<StreamId, StoreError> {
+        Ok(self.stream_ref(key)?.map_or(StreamId::MIN, |s| s.last_id()))
+    }
+
+    /// Entries strictly after `after`, for XREAD.
+    pub fn xread(
+        &mut self,
+        key: &[u8],
+        after: StreamId,
+        count: Option<usize>,
+    ) -> Result<Vec<Entry>, StoreError> {
+        Ok(self
+            .stream_ref(key)?
+            .map(|s| s.read_after(after, count))
+            .unwrap_or_default())
+    }
+
+    /// XGROUP CREATE. `start == None` means `$`.
+    pub fn xgroup_create(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        start: Option<StreamId>,
+        mkstream: bool,
+    ) -> Result<(), StoreError> {
+        if self.stream_ref(key)?.is_none() {
+            if !mkstream {
+                return Err(StoreError::GroupKeyMissing);
+            }
+            self.store_mut()
+                .insert(key.to_vec(), RedisValue::Stream(Stream::new()));
+        }
+
+        let stream = self.stream_mut(key)?.expect("created above");
+        if stream.create_group(group, start) {
+            Ok(())
+        } else {
+            Err(StoreError::BusyGroup)
+        }
+    }
+
+    pub fn xgroup_setid(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        id: Option<StreamId>,
+    ) -> Result<(), StoreError> {
+        let stream = self.stream_mut(key)?.ok_or(StoreError::NoGroup)?;
+        let last = stream.last_id();
+        let group = stream.group_mut(group).ok_or(StoreError::NoGroup)?;
+        group.last_delivered = id.unwrap_or(last);
+        Ok(())
+    }
+
+    pub fn xgroup_destroy(&mut self, key: &[u8], group: &[u8]) -> Result<bool, StoreError> {
+        let stream = self.stream_mut(key)?.ok_or(StoreError::NoGroup)?;
+        Ok(stream.destroy_group(group))
+    }
+
+    pub fn xgroup_createconsumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &[u8],
+    ) -> Result<bool, StoreError> {
+        let stream = self.stream_mut(key)?.ok_or(StoreError::NoGroup)?;
+        let group = stream.group_mut(group).ok_or(StoreError::NoGroup)?;
+        Ok(group.touch_consumer(consumer, current_unix_millis()))
+    }
+
+    pub fn xgroup_delconsumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &[u8],
+    ) -> Result<usize, StoreError> {
+        let stream = self.stream_mut(key)?.ok_or(StoreError::NoGroup)?;
+        let group = stream.group_mut(group).ok_or(StoreError::NoGroup)?;
+        Ok(group.remove_consumer(consumer).unwrap_or(0))
+    }
+
+    /// XREADGROUP for a single stream. `start == None` means `>`.
+    pub fn xreadgroup(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &[u8],
+        start: Option<StreamId>,
+        count: Option<usize>,
+        noack: bool,
+    ) -> Result<Delivered, StoreError> {
+        let stream = self.stream_mut(key)?.ok_or(StoreError::NoGroup)?;
+        stream
+            .read_group(group, consumer, start, count, noack, current_unix_millis())
+            .ok_or(StoreError::NoGroup)
+    }
+
+    /// Whether XREADGROUP has a group to read from on `key`.
+    pub fn has_group(&mut self, key: &[u8], group: &[u8]) -> Result<bool, StoreError> {
+        Ok(self
+            .stream_ref(key)?
+            .is_some_and(|s| s.group(group).is_some()))
+    }
+
+    pub fn xack(&mut self, key: &[u8], group: &[u8], ids: &[StreamId]) -> Result<usize, StoreError> {
+        Ok(self
+            .stream_mut(key)?
+            .and_then(|s| s.group_mut(group))
+            .map_or(0, |g| g.ack(ids)))
+    }
+
+    /// The group's whole pending entries list, oldest first.
+    pub fn xpending(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+    ) -> Result<Vec<(StreamId, PendingEntry)>, StoreError> {
+        let stream = self.stream_ref(key)?.ok_or(StoreError::NoGroup)?;
+        let group = stream.group(group).ok_or(StoreError::NoGroup)?;
+        Ok(group
+            .pending()
+            .iter()
+            .map(|(id, p)| (*id, p.clone()))
+            .collect())
+    }
+
+    pub fn xclaim(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &[u8],
+        ids: &[StreamId],
+        opts: ClaimOptions,
+    ) -> Result<Delivered, StoreError> {
+        let stream = self.stream_mut(key)?.ok_or(StoreError::NoGroup)?;
+        stream
+            .claim(group, consumer, ids, opts, current_unix_millis())
+            .ok_or(StoreError::NoGroup)
+    }
+
+    pub fn xautoclaim(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &[u8],
+        start: StreamId,
+        count: usize,
+        opts: ClaimOptions,
+    ) -> Result<(StreamId, Delivered, Vec<StreamId>), StoreError> {
+        let now = current_unix_millis();
+        let stream = self.stream_mut(key)?.ok_or(StoreError::NoGroup)?;
+        let (next, claimed, deleted) = stream
+            .auto_claim(group, consumer, start, count, opts, now)
+            .ok_or(StoreError::NoGroup)?;
+
+        // Replayed later, XAUTOCLAIM would judge idleness by a different
+        // clock, so log what it did: an XCLAIM of the IDs it claimed, with
+        // the delivery time and count each now has, and one of the IDs it
+        // dropped, which replay drops from the PEL the same way.
+        let pending = stream.group(group).expect("claimed from it").pending();
+        let mut by_count: BTreeMap<u64, Vec<StreamId>> = BTreeMap::new();
+        for (id, _) in &claimed {
+            by_count.entry(pending[id].delivery_count).or_default().push(*id);
+        }
+        let xclaim = |ids: &[StreamId], options: Vec<Vec<u8>>| {
+            let mut command = vec![
+                b"XCLAIM".to_vec(), key.to_vec(), group.to_vec(), consumer.to_vec(), b"0".to_vec(),
+            ];
+            command.extend(ids.iter().map(|id| id.to_string().into_bytes()));
+            command.extend(options);
+            command
+        };
+        let mut commands: Vec<_> = by_count
+            .iter()
+            .map(|(count, ids)| {
+                xclaim(ids, vec![
+                    b"TIME".to_vec(), now.to_string().into_bytes(),
+                    b"RETRYCOUNT".to_vec(), count.to_string().into_bytes(),
+                    b"JUSTID".to_vec(),
+                ])
+            })
+            .collect();
+        if !deleted.is_empty() {
+            commands.push(xclaim(&deleted, vec![b"JUSTID".to_vec()]));
+        }
+        if commands.is_empty() {
+            // It still created the consumer.
+            commands.push(vec![
+                b"XGROUP".to_vec(), b"CREATECONSUMER".to_vec(), key.to_vec(), group.to_vec(),
+                consumer.to_vec(),
+            ]);
+        }
+        for command in commands {
+            self.propagate_as(command);
+        }
+        Ok((next, claimed, deleted))
+    }
+}
@@ -1,41 +1,242 @@
+use crate::{
+    config::KeyspaceEvents,
+    store::{Database, StoreError},
+    types::RedisValue,
+};
 
+/// Largest string SETRANGE and APPEND may build, like Redis'
+/// `proto-max-bulk-len`.
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
 
-use crate::{store::Database, types::RedisValue, utils::current_unix_timestamp};
+/// What a write does to the key's TTL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyExpiry {
+    /// Drop any TTL, as a plain SET does.
+    Clear,
+    /// Leave the TTL as it is.
+    Keep,
+    /// Expire at this Unix time, in milliseconds.
+    At(u64),
+}
+
+/// SET's NX and XX.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetCondition {
+    /// Only set a key that doesn't exist.
+    Nx,
+    /// Only set a key that exists.
+    Xx,
+}
 
 impl Database{
-    pub fn set(&mut self, key: &str, value: String, ttl: Option<u64>) {
-        self.store_mut()
-            .insert(key.to_string(), RedisValue::String(value));
-    
-        if let Some(seconds) = ttl {
-            let expire_at = current_unix_timestamp() + seconds;
-            self.expiry_mut().insert(key.to_string(), expire_at);
-        } else {
-            self.expiry_mut().remove(key);
+    fn string_ref(&mut self, key: &[u8]) -> Result<Option<&Vec<u8>>, StoreError> {
+        self.is_expired(key);
+        match self.store_ref().get(key) {
+            Some(RedisValue::String(value)) => Ok(Some(value)),
+            Some(_) => Err(StoreError::WrongType),
+            None => Ok(None),
         }
     }
-    
+
+    // Creates an empty string on first write.
+    fn string_entry(&mut self, key: &[u8]) -> Result<&mut Vec<u8>, StoreError> {
+        self.is_expired(key);
+        let entry = self
+            .store_mut()
+            .entry(key.to_vec())
+            .or_insert_with(|| RedisValue::String(Vec::new()));
+        match entry {
+            RedisValue::String(value) => Ok(value),
+            _ => Err(StoreError::WrongType),
+        }
+    }
+
+    pub fn set(&mut self, key: &[u8], value: Vec<u8>, expiry: KeyExpiry) {
+        self.is_expired(key);
+        let old = self
+            .store_mut()
+            .insert(key.to_vec(), RedisValue::String(value));
+        // Clients blocked on a list or stream here find out it's gone.
+        if old.is_some_and(|old| !matches!(old, RedisValue::String(_))) {
+            self.blocked_mut().signal(key);
+        }
+
+        self.notify(KeyspaceEvents::STRING, "set", key);
+
+        match expiry {
+            KeyExpiry::Clear => {
+                self.expiry_mut().remove(key);
+            }
+            KeyExpiry::Keep => {}
+            KeyExpiry::At(at) => {
+                self.expiry_mut().insert(key.to_vec(), at);
+                self.notify(KeyspaceEvents::GENERIC, "expire", key);
+            }
+        }
+    }
+
+    /// SET with its options: writes only if `condition` holds and, for
+    /// `get`, reads the string that was there first (a key of another type
+    /// is then left alone). Returns whether it wrote and the old string.
+    pub fn set_with_options(
+        &mut self,
+        key: &[u8],
+        value: Vec<u8>,
+        expiry: KeyExpiry,
+        condition: Option<SetCondition>,
+        get: bool,
+    ) -> Result<(bool, Option<Vec<u8>>), StoreError> {
+        let old = if get { self.get(key)? } else { None };
+        self.is_expired(key);
+        let exists = self.store_ref().contains_key(key);
+        let allowed = match condition {
+            None => true,
+            Some(SetCondition::Nx) => !exists,
+            Some(SetCondition::Xx) => exists,
+        };
+        if allowed {
+            self.set(key, value, expiry);
+        }
+        Ok((allowed, old))
+    }
+
+    /// SETNX: sets `key` only if it doesn't exist.
+    pub fn setnx(&mut self, key: &[u8], value: Vec<u8>) -> bool {
+        self.is_expired(key);
+        if self.store_ref().contains_key(key) {
+            return false;
+        }
+        self.set(key, value, KeyExpiry::Clear);
+        true
+    }
 
     // Now returns None if key is expired.
-    pub fn get(&mut self, key: &str) -> Option<String> {
-        if self.is_expired(key) {
-            return None;
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.string_ref(key)?.cloned())
+    }
+
+    /// MGET: anything that isn't a string reads as nil.
+    pub fn mget(&mut self, keys: &[Vec<u8>]) -> Vec<Option<Vec<u8>>> {
+        keys.iter()
+            .map(|key| self.get(key).ok().flatten())
+            .collect()
+    }
+
+    pub fn mset(&mut self, pairs: &[(Vec<u8>, Vec<u8>)]) {
+        for (key, value) in pairs {
+            self.set(key, value.clone(), KeyExpiry::Clear);
         }
-        match self.store_ref().get(key) {
-            Some(RedisValue::String(value)) => Some(value.clone()),
-            _ => None,
+    }
+
+    /// MSETNX: sets every pair, or none if any of the keys exists.
+    pub fn msetnx(&mut self, pairs: &[(Vec<u8>, Vec<u8>)]) -> bool {
+        if self.exists(&pairs.iter().map(|(key, _)| key.clone()).collect::<Vec<_>>()) > 0 {
+            return false;
+        }
+        self.mset(pairs);
+        true
+    }
+
+    /// GETSET: sets `key` and returns the string it held.
+    pub fn getset(&mut self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>, StoreError> {
+        let old = self.get(key)?;
+        self.set(key, value, KeyExpiry::Clear);
+        Ok(old)
+    }
+
+    /// GETDEL: deletes `key` and returns the string it held.
+    pub fn getdel(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        let old = self.get(key)?;
+        if old.is_some() {
+            self.delete(&[key.to_vec()]);
+        }
+        Ok(old)
+    }
+
+    /// GETEX: returns the string at `key` and changes its TTL.
+    pub fn getex(&mut self, key: &[u8], expiry: KeyExpiry) -> Result<Option<Vec<u8>>, StoreError> {
+        let Some(value) = self.get(key)? else {
+            return Ok(None);
+        };
+        match expiry {
+            KeyExpiry::Keep => {}
+            KeyExpiry::Clear => {
+                if self.expiry_mut().remove(key).is_some() {
+                    self.notify(KeyspaceEvents::GENERIC, "persist", key);
+                }
+            }
+            KeyExpiry::At(at) => {
+                self.expiry_mut().insert(key.to_vec(), at);
+                self.notify(KeyspaceEvents::GENERIC, "expire", key);
+            }
+        }
+        Ok(Some(value))
+    }
+
+    /// APPEND: returns the new length.
+    pub fn append(&mut self, key: &[u8], suffix: &[u8]) -> Result<usize, StoreError> {
+        if self.string_ref(key)?.map_or(0, |value| value.len()) + suffix.len() > MAX_STRING_LEN {
+            return Err(StoreError::StringTooLong);
+        }
+        let value = self.string_entry(key)?;
+        value.extend_from_slice(suffix);
+        let len = value.len();
+        self.notify(KeyspaceEvents::STRING, "append", key);
+        Ok(len)
+    }
+
+    pub fn strlen(&mut self, key: &[u8]) -> Result<usize, StoreError> {
+        Ok(self.string_ref(key)?.map_or(0, |value| value.len()))
+    }
+
+    /// GETRANGE: the bytes from `start` to `end` inclusive, negative offsets
+    /// counting from the end.
+    pub fn getrange(&mut self, key: &[u8], start: i64, end: i64) -> Result<Vec<u8>, StoreError> {
+        let Some(value) = self.string_ref(key)? else {
+            return Ok(vec![]);
+        };
+        let len = value.len() as i64;
+        let start = if start < 0 { (len + start).max(0) } else { start };
+        let end = if end < 0 { len + end } else { end.min(len - 1) };
+        if start >= len || end < 0 || start > end {
+            Ok(vec![])
+        } else {
+            Ok(value[start as usize..=end as usize].to_vec())
+        }
+    }
+
+    /// SETRANGE: overwrites from `offset`, padding with zero bytes if the
+    /// string is shorter. Returns the new length.
+    pub fn setrange(&mut self, key: &[u8], offset: usize, data: &[u8]) -> Result<usize, StoreError> {
+        let current = self.string_ref(key)?.map_or(0, |value| value.len());
+        // Nothing to write leaves a missing key missing.
+        if data.is_empty() {
+            return Ok(current);
+        }
+        if offset + data.len() > MAX_STRING_LEN {
+            return Err(StoreError::StringTooLong);
+        }
+
+        let value = self.string_entry(key)?;
+        if value.len() < offset + data.len() {
+            value.resize(offset + data.len(), 0);
         }
+        value[offset..offset + data.len()].copy_from_slice(data);
+        let len = value.len();
+        self.notify(KeyspaceEvents::STRING, "setrange", key);
+        Ok(len)
     }
 
-    pub fn delete(&mut self, keys: &[String]) -> usize {
+    pub fn delete(&mut self, keys: &[Vec<u8>]) -> usize {
         let mut removed = 0;
         for key in keys {
             self.is_expired(key);
             if self.store_mut().remove(key).is_some() {
                 self.expiry_mut().remove(key);
+                self.notify(KeyspaceEvents::GENERIC, "del", key);
                 removed += 1;
             }
         }
         removed
     }
-}
\ No newline at end of file
+}
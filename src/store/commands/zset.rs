@@ -0,0 +1,267 @@
+use crate::{
+    store::{
+        zset::{LexBound, ScoreBound, ZSet},
+        Database, StoreError,
+    },
+    types::RedisValue,
+};
+
+/// Modifiers accepted by ZADD.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ZAddFlags {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+    pub ch: bool,
+    pub incr: bool,
+}
+
+impl Database {
+    fn zset_ref(&mut self, key: &[u8]) -> Result<Option<&ZSet>, StoreError> {
+        self.is_expired(key);
+        match self.store_ref().get(key) {
+            Some(RedisValue::ZSet(zset)) => Ok(Some(zset)),
+            Some(_) => Err(StoreError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    fn zset_mut(&mut self, key: &[u8]) -> Result<Option<&mut ZSet>, StoreError> {
+        self.is_expired(key);
+        match self.store_mut().get_mut(key) {
+            Some(RedisValue::ZSet(zset)) => Ok(Some(zset)),
+            Some(_) => Err(StoreError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    // Sorted sets never stay in the keyspace once their last member is gone.
+    fn remove_if_empty_zset(&mut self, key: &[u8]) {
+        if let Some(RedisValue::ZSet(zset)) = self.store_ref().get(key) {
+            if zset.is_empty() {
+                self.store_mut().remove(key);
+                self.expiry_mut().remove(key);
+            }
+        }
+    }
+
+    /// Applies ZADD semantics for each `(score, member)` pair.
+    ///
+    /// Returns the number of added (or, with CH, added + updated) members and
+    /// the final score of the last member touched, which is what ZADD INCR
+    /// replies with. The score is `None` when a condition blocked the update.
+    pub fn zadd(
+        &mut self,
+        key: &[u8],
+        flags: ZAddFlags,
+        pairs: &[(f64, Vec<u8>)],
+    ) -> Result<(usize, Option<f64>), StoreError> {
+        let exists = self.zset_ref(key)?.is_some();
+        if !exists && flags.xx {
+            return Ok((0, None));
+        }
+
+        let entry = self
+            .store_mut()
+            .entry(key.to_vec())
+            .or_insert_with(|| RedisValue::ZSet(ZSet::new()));
+        let RedisValue::ZSet(zset) = entry else {
+            return Err(StoreError::WrongType);
+        };
+
+        let mut added = 0;
+        let mut changed = 0;
+        let mut last_score = None;
+        let mut result = Ok(());
+
+        for (score, member) in pairs {
+            last_score = None;
+            match zset.score(member) {
+                Some(current) => {
+                    if flags.nx {
+                        continue;
+                    }
+                    let new_score = if flags.incr { current + score } else { *score };
+                    if new_score.is_nan() {
+                        result = Err(StoreError::NanScore);
+                        break;
+                    }
+                    if (flags.lt && new_score >= current) || (flags.gt && new_score <= current) {
+                        continue;
+                    }
+                    if new_score != current {
+                        zset.insert(member.clone(), new_score);
+                        changed += 1;
+                    }
+                    last_score = Some(new_score);
+                }
+                None => {
+                    if flags.xx {
+                        continue;
+                    }
+                    zset.insert(member.clone(), *score);
+                    added += 1;
+                    last_score = Some(*score);
+                }
+            }
+        }
+
+        self.remove_if_empty_zset(key);
+        result?;
+
+        let count = if flags.ch { added + changed } else { added };
+        Ok((count, last_score))
+    }
+
+    pub fn zincrby(&mut self, key: &[u8], by: f64, member: &[u8]) -> Result<f64, StoreError> {
+        let flags = ZAddFlags {
+            incr: true,
+            ..ZAddFlags::default()
+        };
+        let (_, score) = self.zadd(key, flags, &[(by, member.to_vec())])?;
+        Ok(score.unwrap_or(by))
+    }
+
+    pub fn zrem(&mut self, key: &[u8], members: &[Vec<u8>]) -> Result<usize, StoreError> {
+        let Some(zset) = self.zset_mut(key)? else {
+            return Ok(0);
+        };
+        let removed = members
+            .iter()
+            .filter(|member| zset.remove(member).is_some())
+            .count();
+        self.remove_if_empty_zset(key);
+        Ok(removed)
+    }
+
+    pub fn zscore(&mut self, key: &[u8], member: &[u8]) -> Result<Option<f64>, StoreError> {
+        Ok(self.zset_ref(key)?.and_then(|zset| zset.score(member)))
+    }
+
+    pub fn zcard(&mut self, key: &[u8]) -> Result<usize, StoreError> {
+        Ok(self.zset_ref(key)?.map_or(0, |zset| zset.len()))
+    }
+
+    pub fn zcount(
+        &mut self,
+        key: &[u8],
+        min: ScoreBound,
+        max: ScoreBound,
+    ) -> Result<usize, StoreError> {
+        Ok(self
+            .zset_ref(key)?
+            .map_or(0, |zset| zset.range_by_score(min, max, false, 0, None).len()))
+    }
+
+    pub fn zrank(
+        &mut self,
+        key: &[u8],
+        member: &[u8],
+        rev: bool,
+    ) -> Result<Option<(usize, f64)>, StoreError> {
+        Ok(self.zset_ref(key)?.and_then(|zset| {
+            let rank = zset.rank(member, rev)?;
+            Some((rank, zset.score(member)?))
+        }))
+    }
+
+    pub fn zrange_by_rank(
+        &mut self,
+        key: &[u8],
+        start: isize,
+        stop: isize,
+        rev: bool,
+    ) -> Result<Vec<(Vec<u8>, f64)>, StoreError> {
+        Ok(self
+            .zset_ref(key)?
+            .map(|zset| zset.range_by_rank(start, stop, rev))
+            .unwrap_or_default())
+    }
+
+    pub fn zrange_by_score(
+        &mut self,
+        key: &[u8],
+        min: ScoreBound,
+        max: ScoreBound,
+        rev: bool,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<(Vec<u8>, f64)>, StoreError> {
+        Ok(self
+            .zset_ref(key)?
+            .map(|zset| zset.range_by_score(min, max, rev, offset, limit))
+            .unwrap_or_default())
+    }
+
+    pub fn zrange_by_lex(
+        &mut self,
+        key: &[u8],
+        min: &LexBound,
+        max: &LexBound,
+        rev: bool,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<(Vec<u8>, f64)>, StoreError> {
+        Ok(self
+            .zset_ref(key)?
+            .map(|zset| zset.range_by_lex(min, max, rev, offset, limit))
+            .unwrap_or_default())
+    }
+
+    pub fn zremrangebyscore(
+        &mut self,
+        key: &[u8],
+        min: ScoreBound,
+        max: ScoreBound,
+    ) -> Result<usize, StoreError> {
+        let Some(zset) = self.zset_mut(key)? else {
+            return Ok(0);
+        };
+        let doomed = zset.range_by_score(min, max, false, 0, None);
+        for (member, _) in &doomed {
+            zset.remove(member);
+        }
+        self.remove_if_empty_zset(key);
+        Ok(doomed.len())
+    }
+
+    pub fn zremrangebyrank(
+        &mut self,
+        key: &[u8],
+        start: isize,
+        stop: isize,
+    ) -> Result<usize, StoreError> {
+        let Some(zset) = self.zset_mut(key)? else {
+            return Ok(0);
+        };
+        let doomed = zset.range_by_rank(start, stop, false);
+        for (member, _) in &doomed {
+            zset.remove(member);
+        }
+        self.remove_if_empty_zset(key);
+        Ok(doomed.len())
+    }
+
+    /// Pops up to `count` members from the low end, or the high end if `max`.
+    pub fn zpop(
+        &mut self,
+        key: &[u8],
+        count: usize,
+        max: bool,
+    ) -> Result<Vec<(Vec<u8>, f64)>, StoreError> {
+        let Some(zset) = self.zset_mut(key)? else {
+            return Ok(vec![]);
+        };
+        let mut popped = Vec::with_capacity(count.min(zset.len()));
+        while popped.len() < count {
+            let next = if max { zset.pop_max() } else { zset.pop_min() };
+            match next {
+                Some(entry) => popped.push(entry),
+                None => break,
+            }
+        }
+        self.remove_if_empty_zset(key);
+        Ok(popped)
+    }
+}
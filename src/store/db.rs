@@ -1,15 +1,38 @@
-use std:: collections::HashMap;
+use std:: collections::{HashMap, HashSet};
 
 
 use serde::{Deserialize, Serialize};
 
-use crate::types::RedisValue;
+use crate::{
+    config::KeyspaceEvents,
+    persistence::{encoding::keyed_map, persister::Command},
+    scripting::ScriptCache,
+    types::RedisValue,
+};
+
+use super::{blocking::BlockedClients, notify::KeyspaceNotifier, watch::WatchedKeys};
 
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Database {
-    store: HashMap<String, RedisValue>, // key: value
-    expiry: HashMap<String, u64>,     // key: expiry time
+    #[serde(with = "keyed_map")]
+    store: HashMap<Vec<u8>, RedisValue>, // key: value
+    #[serde(rename = "expiry_ms", with = "keyed_map")]
+    expiry: HashMap<Vec<u8>, u64>,     // key: expiry time, unix ms
+    #[serde(skip)]
+    blocked: BlockedClients,          // clients waiting on keys
+    #[serde(skip)]
+    watched: WatchedKeys,             // versions of WATCHed keys
+    #[serde(skip)]
+    scripts: ScriptCache,             // EVAL / SCRIPT LOAD bodies by SHA1
+    #[serde(skip)]
+    dirty: u64,                       // writes since startup
+    #[serde(skip)]
+    notifier: KeyspaceNotifier,       // notify-keyspace-events publisher
+    #[serde(skip)]
+    propagated: Vec<Command>,         // what to log instead of the running command
+    #[serde(skip)]
+    field_expiring: HashSet<Vec<u8>>, // hashes that may have fields with a TTL
 }
 
 impl Database {
@@ -17,29 +40,124 @@ impl Database {
         Database {
             store: HashMap::new(),
             expiry: HashMap::new(),
+            blocked: BlockedClients::default(),
+            watched: WatchedKeys::default(),
+            scripts: ScriptCache::default(),
+            dirty: 0,
+            notifier: KeyspaceNotifier::default(),
+            propagated: Vec::new(),
+            field_expiring: HashSet::new(),
         }
     }
 
-    pub fn store_ref(&self) -> &HashMap<String, RedisValue> {
+    pub fn store_ref(&self) -> &HashMap<Vec<u8>, RedisValue> {
         &self.store
     }
 
-    pub fn expiry_ref(&self) -> &HashMap<String, u64> {
+    pub fn expiry_ref(&self) -> &HashMap<Vec<u8>, u64> {
         &self.expiry
     }
 
-    pub fn store_mut(&mut self) -> &mut HashMap<String, RedisValue> {
+    pub fn store_mut(&mut self) -> &mut HashMap<Vec<u8>, RedisValue> {
         &mut self.store
     }
 
-    pub fn expiry_mut(&mut self) -> &mut HashMap<String, u64> {
+    pub fn expiry_mut(&mut self) -> &mut HashMap<Vec<u8>, u64> {
         &mut self.expiry
     }
 
+    pub fn blocked_ref(&self) -> &BlockedClients {
+        &self.blocked
+    }
+
+    pub fn blocked_mut(&mut self) -> &mut BlockedClients {
+        &mut self.blocked
+    }
+
+
+    /// Number of writes since startup. It only ever grows; savers remember
+    /// the value they last saved at.
+    pub fn dirty(&self) -> u64 {
+        self.dirty
+    }
+
+    pub fn add_dirty(&mut self, changes: u64) {
+        self.dirty += changes;
+    }
+
+    pub fn field_expiring_mut(&mut self) -> &mut HashSet<Vec<u8>> {
+        &mut self.field_expiring
+    }
+
+    /// Finds the hashes with field TTLs after loading, for the expiry worker.
+    pub fn index_field_expiring(&mut self) {
+        self.field_expiring = self
+            .store
+            .iter()
+            .filter(|(_, value)| matches!(value, RedisValue::Hash(hash) if hash.has_expiries()))
+            .map(|(key, _)| key.clone())
+            .collect();
+    }
+
+    pub fn watched_ref(&self) -> &WatchedKeys {
+        &self.watched
+    }
+
+    pub fn watched_mut(&mut self) -> &mut WatchedKeys {
+        &mut self.watched
+    }
+
+    pub fn scripts_ref(&self) -> &ScriptCache {
+        &self.scripts
+    }
+
+    pub fn scripts_mut(&mut self) -> &mut ScriptCache {
+        &mut self.scripts
+    }
+
+    pub fn set_notifier(&mut self, notifier: KeyspaceNotifier) {
+        self.notifier = notifier;
+    }
+
+    /// Publishes a keyspace notification for `event` on `key`, if its class
+    /// is enabled.
+    pub fn notify(&self, class: KeyspaceEvents, event: &str, key: &[u8]) {
+        self.notifier.notify(class, event, key);
+    }
+
+    /// Logs `parts` in place of the command being run, for commands whose
+    /// effect replay couldn't reproduce (SPOP picks members at random).
+    /// Called more than once, the commands are logged as one atomic block.
+    pub fn propagate_as(&mut self, parts: Command) {
+        self.propagated.push(parts);
+    }
+
+    pub fn take_propagated(&mut self) -> Vec<Command> {
+        std::mem::take(&mut self.propagated)
+    }
+
+    /// A copy of the keyspace for background persistence. Blocked and
+    /// watching clients, cached scripts and the notifier belong to the live
+    /// database and are not carried over.
+    pub fn snapshot(&self) -> Database {
+        Database {
+            store: self.store.clone(),
+            expiry: self.expiry.clone(),
+            blocked: BlockedClients::default(),
+            watched: WatchedKeys::default(),
+            scripts: ScriptCache::default(),
+            dirty: self.dirty,
+            notifier: KeyspaceNotifier::default(),
+            propagated: Vec::new(),
+            field_expiring: HashSet::new(),
+        }
+    }
 
     pub fn flushdb(&mut self) {
         self.store.clear();
         self.expiry.clear();
+        self.field_expiring.clear();
+        self.watched.touch_all();
     }
 
     
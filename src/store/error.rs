@@ -0,0 +1,68 @@
+use std::fmt;
+
+/// Errors raised by `Database` commands.
+///
+/// `Display` renders the exact message Redis replies with (error code
+/// included, without the leading `-`), so the RESP layer can forward it as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreError {
+    /// The key holds a value of a different type than the command expects.
+    WrongType,
+    NotInteger,
+    Overflow,
+    NoSuchKey,
+    IndexOutOfRange,
+    OutOfRange,
+    NanScore,
+    HashNotInteger,
+    HashNotFloat,
+    NanOrInfinity,
+    StringTooLong,
+    NoGroup,
+    BusyGroup,
+    GroupKeyMissing,
+    StreamIdZero,
+    StreamIdTooSmall,
+    StreamExhausted,
+    StreamIdBelowTop,
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            StoreError::WrongType => {
+                "WRONGTYPE Operation against a key holding the wrong kind of value"
+            }
+            StoreError::NotInteger => "ERR value is not an integer or out of range",
+            StoreError::Overflow => "ERR increment or decrement would overflow",
+            StoreError::NoSuchKey => "ERR no such key",
+            StoreError::IndexOutOfRange => "ERR index out of range",
+            StoreError::OutOfRange => "ERR value is out of range",
+            StoreError::NanScore => "ERR resulting score is not a number (NaN)",
+            StoreError::HashNotInteger => "ERR hash value is not an integer",
+            StoreError::HashNotFloat => "ERR hash value is not a float",
+            StoreError::NanOrInfinity => "ERR increment would produce NaN or Infinity",
+            StoreError::StringTooLong => {
+                "ERR string exceeds maximum allowed size (proto-max-bulk-len)"
+            }
+            StoreError::NoGroup => "NOGROUP No such key or consumer group",
+            StoreError::BusyGroup => "BUSYGROUP Consumer Group name already exists",
+            StoreError::GroupKeyMissing => {
+                "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically."
+            }
+            StoreError::StreamIdZero => "ERR The ID specified in XADD must be greater than 0-0",
+            StoreError::StreamIdTooSmall => {
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+            }
+            StoreError::StreamExhausted => {
+                "ERR The stream has exhausted the last possible ID, unable to add more items"
+            }
+            StoreError::StreamIdBelowTop => {
+                "ERR The ID specified in XSETID is smaller than the target stream top item"
+            }
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for StoreError {}
@@ -1,17 +1,22 @@
 
 
-use crate::{persistence::Persister, utils::current_unix_timestamp};
+use crate::{
+    config::KeyspaceEvents, persistence::Persister, types::RedisValue,
+    utils::current_unix_millis,
+};
 
 use super::db::Database;
 
 impl Database{
     
 // Checks expiration; if expired, removes the key from both store and expiry.
-pub fn is_expired(&mut self, key: &str) -> bool {
+pub fn is_expired(&mut self, key: &[u8]) -> bool {
     if let Some(&expire_time) = self.expiry_mut().get(key) {
-        if current_unix_timestamp() >= expire_time {
+        if current_unix_millis() >= expire_time {
            self.store_mut().remove(key);
             self.expiry_mut().remove(key);
+            self.watched_mut().touch(key);
+            self.notify(KeyspaceEvents::EXPIRED, "expired", key);
             return true;
         }
     }
@@ -19,9 +24,9 @@ pub fn is_expired(&mut self, key: &str) -> bool {
 }
 
 pub fn remove_expired_keys(&mut self, persister : &dyn Persister){
-    let now = current_unix_timestamp();
+    let now = current_unix_millis();
 
-    let expired_keys : Vec<String> = self.expiry_ref()
+    let expired_keys : Vec<Vec<u8>> = self.expiry_ref()
     .iter()
     .filter(|(_, &exp)| exp <= now)
     .map(|(key, _)| key.clone())
@@ -34,11 +39,40 @@ pub fn remove_expired_keys(&mut self, persister : &dyn Persister){
     for key in &expired_keys {
         self.store_mut().remove(key);
         self.expiry_mut().remove(key);
-        println!("Key expired, thus removed: {}", key);
+        self.watched_mut().touch(key);
+        self.notify(KeyspaceEvents::EXPIRED, "expired", key);
+        println!("Key expired, thus removed: {}", String::from_utf8_lossy(key));
     }
+    self.add_dirty(expired_keys.len() as u64);
 
-    if let Err(e) = persister.save(self) {
+    // Logged as a DEL so an append-only log replays the same removals.
+    let mut del = vec![b"DEL".to_vec()];
+    del.extend(expired_keys);
+    if let Err(e) = persister.record(&del, self) {
         eprintln!("Failed to persist DB after expiry cleanup: {e}");
     }
 }
-}
\ No newline at end of file
+
+/// Reclaims hash fields whose own TTL is up, in the hashes that have any.
+pub fn remove_expired_fields(&mut self, persister: &dyn Persister) {
+    let keys: Vec<Vec<u8>> = self.field_expiring_mut().iter().cloned().collect();
+    for key in keys {
+        let fields = self.expire_fields(&key);
+        if !matches!(self.store_ref().get(&key), Some(RedisValue::Hash(hash)) if hash.has_expiries()) {
+            self.field_expiring_mut().remove(&key);
+        }
+        if fields.is_empty() {
+            continue;
+        }
+
+        self.watched_mut().touch(&key);
+        self.add_dirty(1);
+        // Logged as an HDEL so an append-only log replays the same removals.
+        let mut hdel = vec![b"HDEL".to_vec(), key];
+        hdel.extend(fields);
+        if let Err(e) = persister.record(&hdel, self) {
+            eprintln!("Failed to persist DB after field expiry cleanup: {e}");
+        }
+    }
+}
+}
@@ -0,0 +1,198 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, BuildHasherDefault, DefaultHasher},
+};
+
+use serde::{ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::persistence::encoding::{bytes_map, keyed_map, BytesRef};
+
+/// A field and its value.
+pub type FieldValue = (Vec<u8>, Vec<u8>);
+
+/// Hash: field -> value, plus the fields given their own TTL by HEXPIRE and
+/// friends, as unix milliseconds.
+#[derive(Debug, Clone, Default)]
+pub struct Hash {
+    fields: HashMap<Vec<u8>, Vec<u8>>,
+    expiry: HashMap<Vec<u8>, u64>,
+}
+
+impl Hash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&Vec<u8>> {
+        self.fields.get(field)
+    }
+
+    /// The value of `field`, to be changed in place; its TTL is kept.
+    pub fn get_mut(&mut self, field: &[u8]) -> Option<&mut Vec<u8>> {
+        self.fields.get_mut(field)
+    }
+
+    pub fn contains(&self, field: &[u8]) -> bool {
+        self.fields.contains_key(field)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &Vec<u8>)> {
+        self.fields.iter()
+    }
+
+    /// Sets `field`, dropping any TTL it had. Returns `true` if the field
+    /// is new.
+    pub fn insert(&mut self, field: Vec<u8>, value: Vec<u8>) -> bool {
+        self.expiry.remove(&field);
+        self.fields.insert(field, value).is_none()
+    }
+
+    pub fn remove(&mut self, field: &[u8]) -> Option<Vec<u8>> {
+        self.expiry.remove(field);
+        self.fields.remove(field)
+    }
+
+    pub fn expire_at(&self, field: &[u8]) -> Option<u64> {
+        self.expiry.get(field).copied()
+    }
+
+    /// Gives an existing `field` a TTL, expiring at `at` (unix ms).
+    pub fn set_expire_at(&mut self, field: &[u8], at: u64) {
+        if self.fields.contains_key(field) {
+            self.expiry.insert(field.to_vec(), at);
+        }
+    }
+
+    /// Removes the TTL of `field`. Returns `false` if it had none.
+    pub fn persist(&mut self, field: &[u8]) -> bool {
+        self.expiry.remove(field).is_some()
+    }
+
+    /// Fields with a TTL and when they expire.
+    pub fn expiries(&self) -> impl Iterator<Item = (&Vec<u8>, u64)> {
+        self.expiry.iter().map(|(field, at)| (field, *at))
+    }
+
+    pub fn has_expiries(&self) -> bool {
+        !self.expiry.is_empty()
+    }
+
+    /// Removes the fields whose TTL is up at `now` (unix ms) and returns them.
+    pub fn remove_expired(&mut self, now: u64) -> Vec<Vec<u8>> {
+        if self.expiry.is_empty() {
+            return vec![];
+        }
+        let expired: Vec<Vec<u8>> = self
+            .expiry
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(field, _)| field.clone())
+            .collect();
+        for field in &expired {
+            self.remove(field);
+        }
+        expired
+    }
+
+    /// One HSCAN step: up to about `count` fields from `cursor` on, and the
+    /// cursor to continue from (0 once done).
+    ///
+    /// Fields are visited in the order of a fixed hash of their name and the
+    /// cursor is the next hash value to visit, so a field that is there for
+    /// the whole scan is returned exactly once however the hash changes in
+    /// between.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<FieldValue>) {
+        let mut pending: Vec<_> = self
+            .fields
+            .iter()
+            .map(|(field, value)| (scan_order(field), field, value))
+            .filter(|(order, _, _)| *order >= cursor)
+            .collect();
+        pending.sort_unstable_by_key(|(order, _, _)| *order);
+
+        let count = count.max(1);
+        if pending.len() <= count {
+            return (0, pending.into_iter().map(|(_, f, v)| (f.clone(), v.clone())).collect());
+        }
+        // Fields that share a hash value go out together, so the cursor can
+        // move past all of them.
+        let last = pending[count - 1].0;
+        let batch: Vec<_> = pending
+            .iter()
+            .take_while(|(order, _, _)| *order <= last)
+            .map(|(_, f, v)| (f.to_vec(), v.to_vec()))
+            .collect();
+        let next = if batch.len() == pending.len() { 0 } else { last + 1 };
+        (next, batch)
+    }
+}
+
+fn scan_order(field: &[u8]) -> u64 {
+    BuildHasherDefault::<DefaultHasher>::default().hash_one(field)
+}
+
+impl FromIterator<(Vec<u8>, Vec<u8>)> for Hash {
+    fn from_iter<I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>>(iter: I) -> Self {
+        Hash {
+            fields: iter.into_iter().collect(),
+            expiry: HashMap::new(),
+        }
+    }
+}
+
+// Persisted as `[field, value]` pairs; snapshots from before fields were
+// binary hold a JSON object instead. A hash with field TTLs is written as
+// `{"fields": [...], "expiry": [[field, ms], ...]}`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredHash {
+    // Tried first: an old-style hash written as a JSON object could have
+    // fields called "fields" and "expiry", but not with these values.
+    WithExpiry {
+        #[serde(with = "bytes_map")]
+        fields: HashMap<Vec<u8>, Vec<u8>>,
+        #[serde(with = "keyed_map")]
+        expiry: HashMap<Vec<u8>, u64>,
+    },
+    Plain(#[serde(with = "bytes_map")] HashMap<Vec<u8>, Vec<u8>>),
+}
+
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.expiry.is_empty() {
+            return bytes_map::serialize(&self.fields, serializer);
+        }
+        let fields: Vec<_> = self
+            .fields
+            .iter()
+            .map(|(field, value)| (BytesRef(field), BytesRef(value)))
+            .collect();
+        let mut stored = serializer.serialize_struct("Hash", 2)?;
+        stored.serialize_field("fields", &fields)?;
+        stored.serialize_field(
+            "expiry",
+            &self.expiry.iter().map(|(field, at)| (BytesRef(field), at)).collect::<Vec<_>>(),
+        )?;
+        stored.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match StoredHash::deserialize(deserializer)? {
+            StoredHash::Plain(fields) => Hash {
+                fields,
+                expiry: HashMap::new(),
+            },
+            StoredHash::WithExpiry { fields, expiry } => Hash { fields, expiry },
+        })
+    }
+}
@@ -1,5 +1,13 @@
+pub mod blocking;
 pub mod db;
+pub mod error;
 pub mod expiry;
+pub mod hash;
+pub mod notify;
 pub mod commands;
+pub mod stream;
+pub mod watch;
+pub mod zset;
 
-pub use db::Database;
\ No newline at end of file
+pub use db::Database;
+pub use error::StoreError;
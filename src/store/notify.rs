@@ -0,0 +1,202 @@
+use std::sync::Arc;
+
+use crate::{config::KeyspaceEvents, pubsub::PubSub};
+
+/// Publishes keyspace notifications for the enabled event classes.
+///
+/// Publishing never waits, so notifications go out while the command that
+/// caused them still holds the database lock: none is dropped, and
+/// subscribers see them in the order the writes happened, in line with
+/// PUBLISH. A subscriber that can't keep up is disconnected by its output
+/// buffer limit rather than slowing writers down.
+#[derive(Debug, Default)]
+pub struct KeyspaceNotifier {
+    events: KeyspaceEvents,
+    pubsub: Option<Arc<PubSub>>,
+}
+
+impl KeyspaceNotifier {
+    pub fn new(events: KeyspaceEvents, pubsub: Arc<PubSub>) -> Self {
+        if !events.is_enabled() {
+            return KeyspaceNotifier::default();
+        }
+        KeyspaceNotifier {
+            events,
+            pubsub: Some(pubsub),
+        }
+    }
+
+    pub fn notify(&self, class: KeyspaceEvents, event: &str, key: &[u8]) {
+        let Some(pubsub) = &self.pubsub else {
+            return;
+        };
+        if !self.events.intersects(class) {
+            return;
+        }
+
+        if self.events.intersects(KeyspaceEvents::KEYSPACE) {
+            let mut channel = b"__keyspace@0__:".to_vec();
+            channel.extend_from_slice(key);
+            pubsub.publish(&channel, event.as_bytes());
+        }
+        if self.events.intersects(KeyspaceEvents::KEYEVENT) {
+            let channel = format!("__keyevent@0__:{event}").into_bytes();
+            pubsub.publish(&channel, key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use super::*;
+    use crate::{config::OutputBufferLimit, resp::handler::execute, store::Database};
+
+    /// A database publishing every event class, and a connection subscribed
+    /// to all of its keyspace channels.
+    fn watched() -> (Database, UnboundedReceiver<Vec<u8>>, Arc<PubSub>) {
+        let pubsub = PubSub::new(OutputBufferLimit::default());
+        let (subscriber, rx) = pubsub.subscriber();
+        pubsub.psubscribe(&subscriber, b"__keyspace@0__:*");
+        let mut db = Database::new();
+        let events = "KA".parse().unwrap();
+        db.set_notifier(KeyspaceNotifier::new(events, Arc::clone(&pubsub)));
+        (db, rx, pubsub)
+    }
+
+    /// Runs a command and returns the `(key, event)` pairs it published.
+    fn events(
+        db: &mut Database,
+        rx: &mut UnboundedReceiver<Vec<u8>>,
+        args: &[&str],
+    ) -> Vec<(String, String)> {
+        let parts: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        execute(&args[0].to_uppercase(), &parts, db);
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|frame| {
+                // *4 pmessage <pattern> <channel> <event>: the bulk strings'
+                // contents are every other line after the header.
+                let frame = String::from_utf8(frame).unwrap();
+                let lines: Vec<&str> = frame.split("\r\n").collect();
+                let key = lines[6].trim_start_matches("__keyspace@0__:");
+                (key.to_string(), lines[8].to_string())
+            })
+            .collect()
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected
+            .iter()
+            .map(|(key, event)| (key.to_string(), event.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn string_and_generic_commands() {
+        let (mut db, mut rx, _pubsub) = watched();
+        assert_eq!(events(&mut db, &mut rx, &["SET", "k", "1"]), pairs(&[("k", "set")]));
+        assert_eq!(
+            events(&mut db, &mut rx, &["SET", "k", "1", "EX", "100"]),
+            pairs(&[("k", "set"), ("k", "expire")])
+        );
+        assert_eq!(events(&mut db, &mut rx, &["INCR", "k"]), pairs(&[("k", "incrby")]));
+        assert_eq!(events(&mut db, &mut rx, &["APPEND", "k", "0"]), pairs(&[("k", "append")]));
+        assert_eq!(
+            events(&mut db, &mut rx, &["SETRANGE", "k", "0", "x"]),
+            pairs(&[("k", "setrange")])
+        );
+        assert_eq!(events(&mut db, &mut rx, &["PERSIST", "k"]), pairs(&[("k", "persist")]));
+        assert!(events(&mut db, &mut rx, &["PERSIST", "k"]).is_empty());
+        assert_eq!(events(&mut db, &mut rx, &["EXPIRE", "k", "100"]), pairs(&[("k", "expire")]));
+        assert_eq!(events(&mut db, &mut rx, &["DEL", "k", "missing"]), pairs(&[("k", "del")]));
+        assert!(events(&mut db, &mut rx, &["GET", "k"]).is_empty());
+    }
+
+    #[test]
+    fn reading_an_expired_key_reports_it_expired() {
+        let (mut db, mut rx, _pubsub) = watched();
+        events(&mut db, &mut rx, &["SET", "k", "1", "PX", "1"]);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(events(&mut db, &mut rx, &["GET", "k"]), pairs(&[("k", "expired")]));
+    }
+
+    #[test]
+    fn list_commands() {
+        let (mut db, mut rx, _pubsub) = watched();
+        assert_eq!(events(&mut db, &mut rx, &["RPUSH", "l", "a", "b"]), pairs(&[("l", "rpush")]));
+        assert_eq!(events(&mut db, &mut rx, &["LPUSH", "l", "c"]), pairs(&[("l", "lpush")]));
+        assert_eq!(events(&mut db, &mut rx, &["LSET", "l", "0", "z"]), pairs(&[("l", "lset")]));
+        assert_eq!(
+            events(&mut db, &mut rx, &["LINSERT", "l", "BEFORE", "a", "y"]),
+            pairs(&[("l", "linsert")])
+        );
+        assert_eq!(events(&mut db, &mut rx, &["LREM", "l", "0", "y"]), pairs(&[("l", "lrem")]));
+        assert!(events(&mut db, &mut rx, &["LREM", "l", "0", "nope"]).is_empty());
+        assert_eq!(events(&mut db, &mut rx, &["LTRIM", "l", "0", "1"]), pairs(&[("l", "ltrim")]));
+        assert_eq!(events(&mut db, &mut rx, &["LPOP", "l"]), pairs(&[("l", "lpop")]));
+        // Popping the last element deletes the key as well.
+        assert_eq!(
+            events(&mut db, &mut rx, &["RPOP", "l"]),
+            pairs(&[("l", "rpop"), ("l", "del")])
+        );
+    }
+
+    #[test]
+    fn set_commands() {
+        let (mut db, mut rx, _pubsub) = watched();
+        assert_eq!(events(&mut db, &mut rx, &["SADD", "s", "a", "b"]), pairs(&[("s", "sadd")]));
+        assert!(events(&mut db, &mut rx, &["SADD", "s", "a"]).is_empty());
+        assert_eq!(events(&mut db, &mut rx, &["SREM", "s", "a"]), pairs(&[("s", "srem")]));
+        assert_eq!(
+            events(&mut db, &mut rx, &["SINTERSTORE", "d", "s", "s"]),
+            pairs(&[("d", "sinterstore")])
+        );
+        assert_eq!(
+            events(&mut db, &mut rx, &["SINTERSTORE", "d", "s", "missing"]),
+            pairs(&[("d", "del")])
+        );
+        assert_eq!(
+            events(&mut db, &mut rx, &["SPOP", "s"]),
+            pairs(&[("s", "spop"), ("s", "del")])
+        );
+    }
+
+    #[test]
+    fn hash_commands() {
+        let (mut db, mut rx, _pubsub) = watched();
+        assert_eq!(events(&mut db, &mut rx, &["HSET", "h", "f", "1"]), pairs(&[("h", "hset")]));
+        assert_eq!(
+            events(&mut db, &mut rx, &["HINCRBY", "h", "f", "1"]),
+            pairs(&[("h", "hincrby")])
+        );
+        assert_eq!(
+            events(&mut db, &mut rx, &["HINCRBYFLOAT", "h", "f", "0.5"]),
+            pairs(&[("h", "hincrbyfloat")])
+        );
+        assert_eq!(
+            events(&mut db, &mut rx, &["HEXPIRE", "h", "100", "FIELDS", "1", "f"]),
+            pairs(&[("h", "hexpire")])
+        );
+        assert_eq!(
+            events(&mut db, &mut rx, &["HPERSIST", "h", "FIELDS", "1", "f"]),
+            pairs(&[("h", "hpersist")])
+        );
+        assert_eq!(
+            events(&mut db, &mut rx, &["HDEL", "h", "f"]),
+            pairs(&[("h", "hdel"), ("h", "del")])
+        );
+    }
+
+    #[test]
+    fn disabled_classes_publish_nothing() {
+        let pubsub = PubSub::new(OutputBufferLimit::default());
+        let (subscriber, mut rx) = pubsub.subscriber();
+        pubsub.psubscribe(&subscriber, b"*");
+        let mut db = Database::new();
+        db.set_notifier(KeyspaceNotifier::new("Kl".parse().unwrap(), Arc::clone(&pubsub)));
+
+        assert!(events(&mut db, &mut rx, &["SET", "k", "1"]).is_empty());
+        assert_eq!(events(&mut db, &mut rx, &["RPUSH", "l", "a"]), pairs(&[("l", "rpush")]));
+    }
+}
@@ -0,0 +1,563 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    ops::Bound,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    persistence::encoding::{bytes, bytes_seq, keyed_map},
+    store::StoreError,
+};
+
+/// A stream entry ID, `<milliseconds>-<sequence>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        StreamId { ms, seq }
+    }
+
+    /// Parses `ms-seq`, or a bare `ms` whose sequence becomes `missing_seq`.
+    pub fn parse(s: &[u8], missing_seq: u64) -> Option<StreamId> {
+        let s = std::str::from_utf8(s).ok()?;
+        match s.split_once('-') {
+            Some((ms, seq)) => Some(StreamId::new(ms.parse().ok()?, seq.parse().ok()?)),
+            None => Some(StreamId::new(s.parse().ok()?, missing_seq)),
+        }
+    }
+
+    /// The smallest ID strictly greater than this one.
+    pub fn next(self) -> Option<StreamId> {
+        if self.seq < u64::MAX {
+            Some(StreamId::new(self.ms, self.seq + 1))
+        } else if self.ms < u64::MAX {
+            Some(StreamId::new(self.ms + 1, 0))
+        } else {
+            None
+        }
+    }
+
+    /// The largest ID strictly smaller than this one.
+    pub fn prev(self) -> Option<StreamId> {
+        if self.seq > 0 {
+            Some(StreamId::new(self.ms, self.seq - 1))
+        } else if self.ms > 0 {
+            Some(StreamId::new(self.ms - 1, u64::MAX))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+// IDs are persisted in their textual form so they can key JSON objects.
+impl Serialize for StreamId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for StreamId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        StreamId::parse(s.as_bytes(), 0)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid stream ID {s:?}")))
+    }
+}
+
+/// How XADD should pick the ID of a new entry.
+#[derive(Debug, Clone, Copy)]
+pub enum IdSpec {
+    /// `*`
+    Auto,
+    /// `<ms>-*`
+    AutoSeq(u64),
+    /// `<ms>-<seq>`
+    Explicit(StreamId),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TrimStrategy {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+/// A MAXLEN / MINID clause. `limit` caps how many entries one call evicts.
+#[derive(Debug, Clone, Copy)]
+pub struct TrimSpec {
+    pub strategy: TrimStrategy,
+    pub limit: Option<usize>,
+}
+
+/// The field/value pairs of one entry, stored flat: `[f1, v1, f2, v2, ...]`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamEntry(#[serde(with = "bytes_seq")] pub Vec<Vec<u8>>);
+
+pub type Entry = (StreamId, Vec<Vec<u8>>);
+
+/// Entries handed to a consumer; `None` marks an entry deleted since delivery.
+pub type Delivered = Vec<(StreamId, Option<Vec<Vec<u8>>>)>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingEntry {
+    #[serde(with = "bytes")]
+    pub consumer: Vec<u8>,
+    pub delivered_at: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Consumer {
+    pub seen_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConsumerGroup {
+    pub last_delivered: StreamId,
+    pending: BTreeMap<StreamId, PendingEntry>,
+    #[serde(with = "keyed_map")]
+    consumers: HashMap<Vec<u8>, Consumer>,
+}
+
+impl ConsumerGroup {
+    fn new(last_delivered: StreamId) -> Self {
+        ConsumerGroup {
+            last_delivered,
+            pending: BTreeMap::new(),
+            consumers: HashMap::new(),
+        }
+    }
+
+    /// Reassembles a group read back from a snapshot.
+    pub fn from_parts(
+        last_delivered: StreamId,
+        pending: BTreeMap<StreamId, PendingEntry>,
+        consumers: HashMap<Vec<u8>, Consumer>,
+    ) -> Self {
+        ConsumerGroup {
+            last_delivered,
+            pending,
+            consumers,
+        }
+    }
+
+    /// Registers `name` if needed. Returns `true` if it was created.
+    pub fn touch_consumer(&mut self, name: &[u8], now_ms: u64) -> bool {
+        match self.consumers.get_mut(name) {
+            Some(consumer) => {
+                consumer.seen_at = now_ms;
+                false
+            }
+            None => {
+                self.consumers
+                    .insert(name.to_vec(), Consumer { seen_at: now_ms });
+                true
+            }
+        }
+    }
+
+    /// Removes a consumer and its pending entries, returning how many were
+    /// pending, or `None` if the consumer did not exist.
+    pub fn remove_consumer(&mut self, name: &[u8]) -> Option<usize> {
+        self.consumers.remove(name)?;
+        let before = self.pending.len();
+        self.pending.retain(|_, p| p.consumer != name);
+        Some(before - self.pending.len())
+    }
+
+    pub fn ack(&mut self, ids: &[StreamId]) -> usize {
+        ids.iter()
+            .filter(|id| self.pending.remove(id).is_some())
+            .count()
+    }
+
+    pub fn pending(&self) -> &BTreeMap<StreamId, PendingEntry> {
+        &self.pending
+    }
+
+    pub fn consumers(&self) -> &HashMap<Vec<u8>, Consumer> {
+        &self.consumers
+    }
+}
+
+/// Options shared by XCLAIM and XAUTOCLAIM.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClaimOptions {
+    pub min_idle: u64,
+    /// Delivery time to record instead of "now" (from IDLE / TIME).
+    pub delivered_at: Option<u64>,
+    pub retry_count: Option<u64>,
+    pub force: bool,
+    pub just_id: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, StreamEntry>,
+    last_id: StreamId,
+    #[serde(with = "keyed_map")]
+    groups: HashMap<Vec<u8>, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reassembles a stream read back from a snapshot.
+    pub fn from_parts(
+        entries: BTreeMap<StreamId, StreamEntry>,
+        last_id: StreamId,
+        groups: HashMap<Vec<u8>, ConsumerGroup>,
+    ) -> Self {
+        Stream {
+            entries,
+            last_id,
+            groups,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// XSETID: moves the last ID forward (or back, down to the top entry).
+    pub fn set_last_id(&mut self, id: StreamId) -> Result<(), StoreError> {
+        if self.entries.last_key_value().is_some_and(|(top, _)| id < *top) {
+            return Err(StoreError::StreamIdBelowTop);
+        }
+        self.last_id = id;
+        Ok(())
+    }
+
+    /// All entries, oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = (&StreamId, &StreamEntry)> {
+        self.entries.iter()
+    }
+
+    /// Appends an entry, enforcing strictly increasing IDs.
+    pub fn add(
+        &mut self,
+        spec: IdSpec,
+        fields: Vec<Vec<u8>>,
+        now_ms: u64,
+    ) -> Result<StreamId, StoreError> {
+        let last = self.last_id;
+        let id = match spec {
+            IdSpec::Auto => {
+                if now_ms > last.ms {
+                    StreamId::new(now_ms, 0)
+                } else {
+                    last.next().ok_or(StoreError::StreamExhausted)?
+                }
+            }
+            IdSpec::AutoSeq(ms) => {
+                if ms > last.ms {
+                    StreamId::new(ms, 0)
+                } else if ms == last.ms && last.seq < u64::MAX {
+                    StreamId::new(ms, last.seq + 1)
+                } else {
+                    return Err(StoreError::StreamIdTooSmall);
+                }
+            }
+            IdSpec::Explicit(id) => id,
+        };
+
+        if id == StreamId::MIN {
+            return Err(StoreError::StreamIdZero);
+        }
+        if id <= last {
+            return Err(StoreError::StreamIdTooSmall);
+        }
+
+        self.entries.insert(id, StreamEntry(fields));
+        self.last_id = id;
+        Ok(id)
+    }
+
+    /// Entries with IDs in `[start, end]`, newest first when `rev`.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+        rev: bool,
+        count: Option<usize>,
+    ) -> Vec<Entry> {
+        if start > end {
+            return vec![];
+        }
+        let count = count.unwrap_or(usize::MAX);
+        let range = self
+            .entries
+            .range(start..=end)
+            .map(|(id, entry)| (*id, entry.0.clone()));
+        if rev {
+            range.rev().take(count).collect()
+        } else {
+            range.take(count).collect()
+        }
+    }
+
+    /// Entries strictly after `id`.
+    pub fn read_after(&self, id: StreamId, count: Option<usize>) -> Vec<Entry> {
+        self.entries
+            .range((Bound::Excluded(id), Bound::Unbounded))
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, entry)| (*id, entry.0.clone()))
+            .collect()
+    }
+
+    pub fn delete(&mut self, ids: &[StreamId]) -> usize {
+        ids.iter()
+            .filter(|id| self.entries.remove(id).is_some())
+            .count()
+    }
+
+    /// Evicts the oldest entries according to `spec`. Returns how many were
+    /// removed.
+    pub fn trim(&mut self, spec: TrimSpec) -> usize {
+        let limit = spec.limit.unwrap_or(usize::MAX);
+        let mut removed = 0;
+        while removed < limit {
+            let Some((&oldest, _)) = self.entries.first_key_value() else {
+                break;
+            };
+            let evict = match spec.strategy {
+                TrimStrategy::MaxLen(max) => self.entries.len() > max,
+                TrimStrategy::MinId(min) => oldest < min,
+            };
+            if !evict {
+                break;
+            }
+            self.entries.remove(&oldest);
+            removed += 1;
+        }
+        removed
+    }
+
+    pub fn groups(&self) -> &HashMap<Vec<u8>, ConsumerGroup> {
+        &self.groups
+    }
+
+    pub fn group(&self, name: &[u8]) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    pub fn group_mut(&mut self, name: &[u8]) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    /// Creates a group; `None` starts it at the current last ID (`$`).
+    pub fn create_group(&mut self, name: &[u8], start: Option<StreamId>) -> bool {
+        if self.groups.contains_key(name) {
+            return false;
+        }
+        let start = start.unwrap_or(self.last_id);
+        self.groups.insert(name.to_vec(), ConsumerGroup::new(start));
+        true
+    }
+
+    pub fn destroy_group(&mut self, name: &[u8]) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// XREADGROUP for one stream. `start == None` means `>`: deliver new
+    /// entries and add them to the PEL. Otherwise the consumer's own pending
+    /// history after `start` is replayed; deleted entries come back as `None`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn read_group(
+        &mut self,
+        group: &[u8],
+        consumer: &[u8],
+        start: Option<StreamId>,
+        count: Option<usize>,
+        noack: bool,
+        now_ms: u64,
+    ) -> Option<Delivered> {
+        let entries = &self.entries;
+        let group = self.groups.get_mut(group)?;
+        group.touch_consumer(consumer, now_ms);
+        let count = count.unwrap_or(usize::MAX);
+
+        let Some(start) = start else {
+            let delivered: Vec<Entry> = entries
+                .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+                .take(count)
+                .map(|(id, entry)| (*id, entry.0.clone()))
+                .collect();
+
+            for (id, _) in &delivered {
+                group.last_delivered = *id;
+                if !noack {
+                    group.pending.insert(
+                        *id,
+                        PendingEntry {
+                            consumer: consumer.to_vec(),
+                            delivered_at: now_ms,
+                            delivery_count: 1,
+                        },
+                    );
+                }
+            }
+            return Some(
+                delivered
+                    .into_iter()
+                    .map(|(id, fields)| (id, Some(fields)))
+                    .collect(),
+            );
+        };
+
+        let history = group
+            .pending
+            .range((Bound::Excluded(start), Bound::Unbounded))
+            .filter(|(_, p)| p.consumer == consumer)
+            .take(count)
+            .map(|(id, _)| (*id, entries.get(id).map(|e| e.0.clone())))
+            .collect();
+        Some(history)
+    }
+
+    /// XCLAIM: transfers ownership of the given pending IDs to `consumer`.
+    pub fn claim(
+        &mut self,
+        group: &[u8],
+        consumer: &[u8],
+        ids: &[StreamId],
+        opts: ClaimOptions,
+        now_ms: u64,
+    ) -> Option<Delivered> {
+        let entries = &self.entries;
+        let group = self.groups.get_mut(group)?;
+        group.touch_consumer(consumer, now_ms);
+
+        let mut claimed = Vec::new();
+        for id in ids {
+            let Some(fields) = entries.get(id) else {
+                // The entry is gone from the stream; drop it from the PEL.
+                group.pending.remove(id);
+                continue;
+            };
+
+            if !group.pending.contains_key(id) {
+                if !opts.force {
+                    continue;
+                }
+                group.pending.insert(
+                    *id,
+                    PendingEntry {
+                        consumer: consumer.to_vec(),
+                        delivered_at: now_ms,
+                        delivery_count: 0,
+                    },
+                );
+            }
+
+            let pending = group.pending.get_mut(id).expect("checked above");
+            if opts.min_idle > 0 && now_ms.saturating_sub(pending.delivered_at) < opts.min_idle {
+                continue;
+            }
+
+            pending.consumer = consumer.to_vec();
+            pending.delivered_at = opts.delivered_at.unwrap_or(now_ms);
+            if let Some(retry_count) = opts.retry_count {
+                pending.delivery_count = retry_count;
+            } else if !opts.just_id {
+                pending.delivery_count += 1;
+            }
+
+            let fields = if opts.just_id { None } else { Some(fields.0.clone()) };
+            claimed.push((*id, fields));
+        }
+        Some(claimed)
+    }
+
+    /// XAUTOCLAIM: scans the PEL from `start`, claiming up to `count` idle
+    /// entries. Returns the next cursor, the claimed entries and the IDs that
+    /// were dropped because the entry no longer exists.
+    pub fn auto_claim(
+        &mut self,
+        group: &[u8],
+        consumer: &[u8],
+        start: StreamId,
+        count: usize,
+        opts: ClaimOptions,
+        now_ms: u64,
+    ) -> Option<(StreamId, Delivered, Vec<StreamId>)> {
+        let entries = &self.entries;
+        let group = self.groups.get_mut(group)?;
+        group.touch_consumer(consumer, now_ms);
+
+        let mut claimed = Vec::new();
+        let mut deleted = Vec::new();
+        let mut next = None;
+        let mut last_scanned = None;
+
+        // Redis bounds the scan to ten times COUNT PEL entries per call.
+        let candidates: Vec<StreamId> = group
+            .pending
+            .range(start..)
+            .map(|(id, _)| *id)
+            .take(count.saturating_mul(10))
+            .collect();
+
+        for id in candidates {
+            if claimed.len() >= count {
+                next = Some(id);
+                break;
+            }
+            last_scanned = Some(id);
+
+            let Some(fields) = entries.get(&id) else {
+                group.pending.remove(&id);
+                deleted.push(id);
+                continue;
+            };
+            let pending = group.pending.get_mut(&id).expect("collected from the PEL");
+            if now_ms.saturating_sub(pending.delivered_at) < opts.min_idle {
+                continue;
+            }
+            pending.consumer = consumer.to_vec();
+            pending.delivered_at = now_ms;
+            if !opts.just_id {
+                pending.delivery_count += 1;
+            }
+            let fields = if opts.just_id { None } else { Some(fields.0.clone()) };
+            claimed.push((id, fields));
+        }
+
+        // The cursor is 0-0 once the whole PEL has been scanned.
+        let next = next
+            .or_else(|| {
+                let last = last_scanned?;
+                group
+                    .pending
+                    .range((Bound::Excluded(last), Bound::Unbounded))
+                    .next()
+                    .map(|(id, _)| *id)
+            })
+            .unwrap_or(StreamId::MIN);
+
+        Some((next, claimed, deleted))
+    }
+}
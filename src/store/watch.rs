@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+
+/// Versions of the keys some connection is WATCHing.
+///
+/// Only watched keys are tracked: each entry counts its watchers and is
+/// dropped with the last one, so keys nobody watches cost nothing. A write
+/// to a watched key bumps its version, and EXEC compares against the
+/// version seen at WATCH time.
+#[derive(Debug, Default)]
+pub struct WatchedKeys {
+    keys: HashMap<Vec<u8>, (usize, u64)>,
+}
+
+impl WatchedKeys {
+    /// Starts watching `key` and returns its current version.
+    pub fn watch(&mut self, key: &[u8]) -> u64 {
+        let (watchers, version) = self.keys.entry(key.to_vec()).or_insert((0, 0));
+        *watchers += 1;
+        *version
+    }
+
+    pub fn unwatch(&mut self, key: &[u8]) {
+        if let Some((watchers, _)) = self.keys.get_mut(key) {
+            *watchers -= 1;
+            if *watchers == 0 {
+                self.keys.remove(key);
+            }
+        }
+    }
+
+    pub fn version(&self, key: &[u8]) -> u64 {
+        self.keys.get(key).map_or(0, |(_, version)| *version)
+    }
+
+    /// Records a write to `key`.
+    pub fn touch(&mut self, key: &[u8]) {
+        if let Some((_, version)) = self.keys.get_mut(key) {
+            *version += 1;
+        }
+    }
+
+    /// Records a write to every key, e.g. FLUSHDB.
+    pub fn touch_all(&mut self) {
+        for (_, version) in self.keys.values_mut() {
+            *version += 1;
+        }
+    }
+}
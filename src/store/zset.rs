@@ -0,0 +1,631 @@
+use std::{cmp::Ordering, collections::HashMap, fmt, iter};
+
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::persistence::encoding::{keyed_map, BytesRef};
+
+/// Tallest a skiplist node can be; with a 1 in 4 chance of each extra level
+/// that covers far more members than fit in memory.
+const MAX_LEVEL: usize = 32;
+
+/// Arena slot of the head node, which holds no member and starts every level.
+const HEAD: usize = 0;
+
+/// One end of a `ZRANGEBYSCORE`-style interval, e.g. `5` or `(5`.
+#[derive(Debug, Clone, Copy)]
+pub struct ScoreBound {
+    pub value: f64,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    fn above_min(&self, score: f64) -> bool {
+        if self.exclusive {
+            score > self.value
+        } else {
+            score >= self.value
+        }
+    }
+
+    fn below_max(&self, score: f64) -> bool {
+        if self.exclusive {
+            score < self.value
+        } else {
+            score <= self.value
+        }
+    }
+}
+
+/// One end of a `ZRANGEBYLEX`-style interval: `-`, `+`, `[a` or `(a`.
+#[derive(Debug, Clone)]
+pub enum LexBound {
+    Min,
+    Max,
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+}
+
+impl LexBound {
+    fn above_min(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Inclusive(v) => member >= v.as_slice(),
+            LexBound::Exclusive(v) => member > v.as_slice(),
+        }
+    }
+
+    fn below_max(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(v) => member <= v.as_slice(),
+            LexBound::Exclusive(v) => member < v.as_slice(),
+        }
+    }
+}
+
+/// Sorted set: a member -> score index plus a skiplist of the members
+/// ordered by `(score, member)`.
+///
+/// As in Redis, each skiplist link records how many members it steps over,
+/// so finding a member's rank, or the member at a rank, takes O(log n).
+/// Nodes live in one arena and point at each other by slot.
+///
+/// NaN never reaches a sorted set: every code path that produces a score
+/// rejects it first, and `-0.0` is normalised to `0.0` on insert.
+#[derive(Debug, Clone)]
+pub struct ZSet {
+    scores: HashMap<Vec<u8>, f64>,
+    nodes: Vec<Node>,
+    /// Arena slots left by removed members, reused before the arena grows.
+    free: Vec<usize>,
+    tail: Option<usize>,
+    /// Levels in use, i.e. the height of the tallest node.
+    level: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Vec<u8>,
+    score: f64,
+    backward: Option<usize>,
+    /// One link per level the node reaches, lowest first.
+    links: Vec<Link>,
+}
+
+/// The next node on a level, and how many places further on it is.
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    next: Option<usize>,
+    span: usize,
+}
+
+impl Default for ZSet {
+    fn default() -> Self {
+        let head = Node {
+            member: Vec::new(),
+            score: 0.0,
+            backward: None,
+            links: vec![Link { next: None, span: 0 }; MAX_LEVEL],
+        };
+        ZSet {
+            scores: HashMap::new(),
+            nodes: vec![head],
+            free: Vec::new(),
+            tail: None,
+            level: 1,
+        }
+    }
+}
+
+impl ZSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Inserts or updates `member`. Returns `true` if the member is new.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> bool {
+        let score = if score == 0.0 { 0.0 } else { score };
+        match self.scores.insert(member.clone(), score) {
+            Some(old) if old == score => false,
+            Some(old) => {
+                self.unlink(&member, old);
+                self.link(member, score);
+                false
+            }
+            None => {
+                self.link(member, score);
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.unlink(member, score);
+        Some(score)
+    }
+
+    /// Members with their scores, lowest score first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Vec<u8>, f64)> {
+        Iter {
+            zset: self,
+            front: self.nodes[HEAD].links[0].next,
+            back: self.tail,
+            left: self.len(),
+        }
+    }
+
+    /// Zero-based position of `member`, counted from the highest score when
+    /// `rev` is set.
+    pub fn rank(&self, member: &[u8], rev: bool) -> Option<usize> {
+        let score = self.score(member)?;
+        let (_, position) = self.seek(|node| self.compare(node, score, member).is_le());
+        let rank = position - 1;
+        Some(if rev { self.len() - 1 - rank } else { rank })
+    }
+
+    /// Members between two ranks, with Redis' negative index semantics.
+    pub fn range_by_rank(&self, start: isize, stop: isize, rev: bool) -> Vec<(Vec<u8>, f64)> {
+        let len = self.len() as isize;
+        let start = if start < 0 { (len + start).max(0) } else { start };
+        let stop = if stop < 0 { len + stop } else { stop.min(len - 1) };
+        if start >= len || stop < 0 || start > stop {
+            return vec![];
+        }
+
+        let take = (stop - start + 1) as usize;
+        let first = if rev { len - 1 - start } else { start };
+        self.walk(self.node_at(first as usize), rev)
+            .take(take)
+            .map(|node| (node.member.clone(), node.score))
+            .collect()
+    }
+
+    pub fn range_by_score(
+        &self,
+        min: ScoreBound,
+        max: ScoreBound,
+        rev: bool,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Vec<(Vec<u8>, f64)> {
+        if rev {
+            let (last, _) = self.seek(|node| max.below_max(node.score));
+            let start = (last != HEAD).then_some(last);
+            self.collect(start, rev, |node| min.above_min(node.score), offset, limit)
+        } else {
+            let (before, _) = self.seek(|node| !min.above_min(node.score));
+            let start = self.nodes[before].links[0].next;
+            self.collect(start, rev, |node| max.below_max(node.score), offset, limit)
+        }
+    }
+
+    /// Lexicographic range; only meaningful when all members share a score.
+    pub fn range_by_lex(
+        &self,
+        min: &LexBound,
+        max: &LexBound,
+        rev: bool,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Vec<(Vec<u8>, f64)> {
+        if rev {
+            let (last, _) = self.seek(|node| max.below_max(&node.member));
+            let start = (last != HEAD).then_some(last);
+            self.collect(start, rev, |node| min.above_min(&node.member), offset, limit)
+        } else {
+            let (before, _) = self.seek(|node| !min.above_min(&node.member));
+            let start = self.nodes[before].links[0].next;
+            self.collect(start, rev, |node| max.below_max(&node.member), offset, limit)
+        }
+    }
+
+    pub fn pop_min(&mut self) -> Option<(Vec<u8>, f64)> {
+        self.pop(self.nodes[HEAD].links[0].next?)
+    }
+
+    pub fn pop_max(&mut self) -> Option<(Vec<u8>, f64)> {
+        self.pop(self.tail?)
+    }
+
+    fn pop(&mut self, id: usize) -> Option<(Vec<u8>, f64)> {
+        let member = self.nodes[id].member.clone();
+        let score = self.remove(&member)?;
+        Some((member, score))
+    }
+
+    /// How `node` sorts against the position `(score, member)`.
+    fn compare(&self, node: &Node, score: f64, member: &[u8]) -> Ordering {
+        node.score
+            .total_cmp(&score)
+            .then_with(|| node.member.as_slice().cmp(member))
+    }
+
+    /// Follows the skiplist from the head past every node for which `before`
+    /// holds, which must be a leading run of the list. Returns the last node
+    /// passed (the head if none) and its one-based position.
+    fn seek(&self, before: impl Fn(&Node) -> bool) -> (usize, usize) {
+        let (mut x, mut position) = (HEAD, 0);
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].links[i].next {
+                if !before(&self.nodes[next]) {
+                    break;
+                }
+                position += self.nodes[x].links[i].span;
+                x = next;
+            }
+        }
+        (x, position)
+    }
+
+    /// The node at zero-based `rank`.
+    fn node_at(&self, rank: usize) -> Option<usize> {
+        let target = rank + 1;
+        let (mut x, mut position) = (HEAD, 0);
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].links[i].next {
+                if position + self.nodes[x].links[i].span > target {
+                    break;
+                }
+                position += self.nodes[x].links[i].span;
+                x = next;
+            }
+            if position == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    /// Nodes from `start` on, towards the lowest score when `rev` is set.
+    fn walk(&self, start: Option<usize>, rev: bool) -> impl Iterator<Item = &Node> {
+        iter::successors(start, move |&id| {
+            let node = &self.nodes[id];
+            if rev {
+                node.backward
+            } else {
+                node.links[0].next
+            }
+        })
+        .map(move |id| &self.nodes[id])
+    }
+
+    fn collect(
+        &self,
+        start: Option<usize>,
+        rev: bool,
+        in_range: impl Fn(&Node) -> bool,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Vec<(Vec<u8>, f64)> {
+        self.walk(start, rev)
+            .take_while(|node| in_range(node))
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|node| (node.member.clone(), node.score))
+            .collect()
+    }
+
+    /// For each level, the last node sorting before `(score, member)` and
+    /// that node's position.
+    fn path(&self, score: f64, member: &[u8]) -> ([usize; MAX_LEVEL], [usize; MAX_LEVEL]) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut positions = [0; MAX_LEVEL];
+        let (mut x, mut position) = (HEAD, 0);
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].links[i].next {
+                if self.compare(&self.nodes[next], score, member).is_ge() {
+                    break;
+                }
+                position += self.nodes[x].links[i].span;
+                x = next;
+            }
+            update[i] = x;
+            positions[i] = position;
+        }
+        (update, positions)
+    }
+
+    fn link(&mut self, member: Vec<u8>, score: f64) {
+        let (update, positions) = self.path(score, &member);
+        let level = random_level();
+        if level > self.level {
+            // New levels start at the head and, for now, span the whole list.
+            let linked = self.nodes.len() - 1 - self.free.len();
+            for i in self.level..level {
+                self.nodes[HEAD].links[i] = Link { next: None, span: linked };
+            }
+            self.level = level;
+        }
+
+        let node = Node {
+            member,
+            score,
+            backward: (update[0] != HEAD).then_some(update[0]),
+            links: Vec::with_capacity(level),
+        };
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.nodes[id] = node;
+                id
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for (i, &prev) in update.iter().enumerate().take(level) {
+            let skipped = positions[0] - positions[i];
+            let old = self.nodes[prev].links[i];
+            self.nodes[id].links.push(Link {
+                next: old.next,
+                span: old.span - skipped,
+            });
+            self.nodes[prev].links[i] = Link {
+                next: Some(id),
+                span: skipped + 1,
+            };
+        }
+        for (i, &prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[prev].links[i].span += 1;
+        }
+        match self.nodes[id].links[0].next {
+            Some(next) => self.nodes[next].backward = Some(id),
+            None => self.tail = Some(id),
+        }
+    }
+
+    fn unlink(&mut self, member: &[u8], score: f64) {
+        let (update, _) = self.path(score, member);
+        let Some(id) = self.nodes[update[0]].links[0].next else {
+            return;
+        };
+        for (i, &prev) in update.iter().enumerate().take(self.level) {
+            if self.nodes[prev].links[i].next == Some(id) {
+                let removed = self.nodes[id].links[i];
+                let link = &mut self.nodes[prev].links[i];
+                link.next = removed.next;
+                link.span = link.span + removed.span - 1;
+            } else {
+                self.nodes[prev].links[i].span -= 1;
+            }
+        }
+        let backward = self.nodes[id].backward;
+        match self.nodes[id].links[0].next {
+            Some(next) => self.nodes[next].backward = backward,
+            None => self.tail = backward,
+        }
+        while self.level > 1 && self.nodes[HEAD].links[self.level - 1].next.is_none() {
+            self.level -= 1;
+        }
+
+        let node = &mut self.nodes[id];
+        node.member = Vec::new();
+        node.links = Vec::new();
+        self.free.push(id);
+    }
+}
+
+fn random_level() -> usize {
+    let mut level = 1;
+    while level < MAX_LEVEL && fastrand::u8(..4) == 0 {
+        level += 1;
+    }
+    level
+}
+
+/// Both ends of the skiplist, meeting in the middle.
+struct Iter<'a> {
+    zset: &'a ZSet,
+    front: Option<usize>,
+    back: Option<usize>,
+    left: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Vec<u8>, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.left == 0 {
+            return None;
+        }
+        let node = &self.zset.nodes[self.front?];
+        self.front = node.links[0].next;
+        self.left -= 1;
+        Some((&node.member, node.score))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.left, Some(self.left))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.left == 0 {
+            return None;
+        }
+        let node = &self.zset.nodes[self.back?];
+        self.back = node.backward;
+        self.left -= 1;
+        Some((&node.member, node.score))
+    }
+}
+
+impl FromIterator<(Vec<u8>, f64)> for ZSet {
+    fn from_iter<I: IntoIterator<Item = (Vec<u8>, f64)>>(iter: I) -> Self {
+        let mut zset = ZSet::new();
+        for (member, score) in iter {
+            zset.insert(member, score);
+        }
+        zset
+    }
+}
+
+// Persisted as `[member, score]` pairs. JSON has no infinities, so those
+// scores are written as the strings "inf" / "-inf".
+#[derive(Serialize, Deserialize)]
+struct StoredScore(#[serde(with = "stored_score")] f64);
+
+mod stored_score {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(score: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        if score.is_finite() {
+            serializer.serialize_f64(*score)
+        } else if *score > 0.0 {
+            serializer.serialize_str("inf")
+        } else {
+            serializer.serialize_str("-inf")
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        struct ScoreVisitor;
+
+        impl Visitor<'_> for ScoreVisitor {
+            type Value = f64;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a number, \"inf\" or \"-inf\"")
+            }
+
+            fn visit_f64<E>(self, v: f64) -> Result<f64, E> {
+                Ok(v)
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<f64, E> {
+                Ok(v as f64)
+            }
+
+            fn visit_u64<E>(self, v: u64) -> Result<f64, E> {
+                Ok(v as f64)
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<f64, E> {
+                match v {
+                    "inf" => Ok(f64::INFINITY),
+                    "-inf" => Ok(f64::NEG_INFINITY),
+                    _ => Err(E::custom(format!("invalid score {v:?}"))),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(ScoreVisitor)
+    }
+}
+
+impl Serialize for ZSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            self.scores
+                .iter()
+                .map(|(member, score)| (BytesRef(member), StoredScore(*score))),
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for ZSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pairs: Vec<(Vec<u8>, StoredScore)> = keyed_map::deserialize(deserializer)?;
+        Ok(pairs.into_iter().map(|(m, s)| (m, s.0)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members(entries: Vec<(Vec<u8>, f64)>) -> Vec<Vec<u8>> {
+        entries.into_iter().map(|(member, _)| member).collect()
+    }
+
+    #[test]
+    fn ranks_match_a_sorted_model_through_inserts_updates_and_removals() {
+        let mut zset = ZSet::new();
+        let mut model: Vec<(f64, Vec<u8>)> = Vec::new();
+        for step in 0..4000 {
+            let member = format!("m{}", fastrand::u16(..300)).into_bytes();
+            if step % 3 == 2 {
+                let removed = zset.remove(&member);
+                let at = model.iter().position(|(_, m)| *m == member);
+                assert_eq!(removed, at.map(|at| model.remove(at).0));
+            } else {
+                // Few distinct scores, so ties are ordered by member.
+                let score = fastrand::u8(..20) as f64 - 10.0;
+                model.retain(|(_, m)| *m != member);
+                model.push((score, member.clone()));
+                zset.insert(member, score);
+            }
+            model.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+
+            if step % 97 == 96 {
+                for (rank, (_, member)) in model.iter().enumerate() {
+                    assert_eq!(zset.rank(member, false), Some(rank));
+                    assert_eq!(zset.rank(member, true), Some(model.len() - 1 - rank));
+                }
+                let iterated: Vec<Vec<u8>> = zset.iter().map(|(m, _)| m.clone()).collect();
+                let expected: Vec<Vec<u8>> = model.iter().map(|(_, m)| m.clone()).collect();
+                assert_eq!(iterated, expected);
+                let reversed: Vec<Vec<u8>> = zset.iter().rev().map(|(m, _)| m.clone()).collect();
+                assert_eq!(reversed, expected.iter().rev().cloned().collect::<Vec<_>>());
+                assert_eq!(members(zset.range_by_rank(5, 9, false)), expected[5..10]);
+                let tail = members(zset.range_by_rank(-3, -1, true));
+                assert_eq!(tail, expected[..3].iter().rev().cloned().collect::<Vec<_>>());
+            }
+        }
+        assert_eq!(zset.len(), model.len());
+    }
+
+    #[test]
+    fn pops_take_the_ends_and_reuse_their_slots() {
+        let mut zset: ZSet = (0..10).map(|i| (vec![b'a' + i], i as f64)).collect();
+        assert_eq!(zset.pop_min(), Some((b"a".to_vec(), 0.0)));
+        assert_eq!(zset.pop_max(), Some((b"j".to_vec(), 9.0)));
+        zset.insert(b"z".to_vec(), -1.0);
+        assert_eq!(zset.nodes.len(), 11);
+        assert_eq!(zset.rank(b"z", false), Some(0));
+        assert_eq!(zset.rank(b"i", true), Some(0));
+        while zset.pop_min().is_some() {}
+        assert!(zset.is_empty());
+        assert_eq!(zset.iter().count(), 0);
+    }
+
+    #[test]
+    fn score_ranges_respect_exclusive_ends() {
+        let zset: ZSet = [("a", 1.0), ("b", 2.0), ("c", 2.0), ("d", 3.0)]
+            .into_iter()
+            .map(|(m, s)| (m.as_bytes().to_vec(), s))
+            .collect();
+        let bound = |value, exclusive| ScoreBound { value, exclusive };
+        let range = |min, max, rev| members(zset.range_by_score(min, max, rev, 0, None));
+
+        assert_eq!(range(bound(2.0, false), bound(2.0, false), false), [b"b", b"c"]);
+        assert_eq!(range(bound(2.0, false), bound(2.0, false), true), [b"c", b"b"]);
+        assert_eq!(range(bound(1.0, true), bound(3.0, true), false), [b"b", b"c"]);
+        assert!(range(bound(2.0, true), bound(2.0, false), false).is_empty());
+        assert!(range(bound(3.0, false), bound(1.0, false), true).is_empty());
+        let everything = range(bound(f64::NEG_INFINITY, false), bound(f64::INFINITY, false), true);
+        assert_eq!(everything, [b"d", b"c", b"b", b"a"]);
+        let limited = zset.range_by_score(bound(1.0, false), bound(3.0, false), false, 1, Some(2));
+        assert_eq!(members(limited), [b"b", b"c"]);
+    }
+}
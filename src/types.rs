@@ -1,11 +1,32 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashSet, VecDeque};
 
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    persistence::encoding::{bytes, bytes_seq},
+    store::{hash::Hash, stream::Stream, zset::ZSet},
+};
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum RedisValue {
-    String(String),
-    List(Vec<String>),
-    Set(HashSet<String>),
-    Hash(HashMap<String, String>),
-}
\ No newline at end of file
+    String(#[serde(with = "bytes")] Vec<u8>),
+    List(#[serde(with = "bytes_seq")] VecDeque<Vec<u8>>),
+    Set(#[serde(with = "bytes_seq")] HashSet<Vec<u8>>),
+    Hash(Hash),
+    ZSet(ZSet),
+    Stream(Stream),
+}
+
+impl RedisValue {
+    /// The name TYPE reports for this value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RedisValue::String(_) => "string",
+            RedisValue::List(_) => "list",
+            RedisValue::Set(_) => "set",
+            RedisValue::Hash(_) => "hash",
+            RedisValue::ZSet(_) => "zset",
+            RedisValue::Stream(_) => "stream",
+        }
+    }
+}
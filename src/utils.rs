@@ -6,29 +6,84 @@ use tokio::sync::Mutex;
 use crate::{persistence::Persister, store::Database};
 
 pub fn is_mutating_command(input: &str) -> bool {
-    let cmd = input.trim().split_whitespace().next();
+    let cmd = input.split_whitespace().next();
 
     match cmd {
         Some(cmd) => {
             matches!(
                 cmd.to_uppercase().as_str(),
                 "SET"
+                    | "SETNX"
+                    | "SETEX"
+                    | "PSETEX"
+                    | "MSET"
+                    | "MSETNX"
+                    | "GETSET"
+                    | "GETDEL"
+                    | "GETEX"
+                    | "APPEND"
+                    | "SETRANGE"
                     | "DEL"
                     | "INCR"
                     | "INCRBY"
                     | "DECR"
                     | "DECRBY"
                     | "EXPIRE"
+                    | "PEXPIRE"
+                    | "EXPIREAT"
+                    | "PEXPIREAT"
                     | "PERSIST"
                     | "LPOP"
                     | "RPOP"
                     | "LPUSH"
                     | "RPUSH"
                     | "LSET"
+                    | "LPUSHX"
+                    | "RPUSHX"
+                    | "LINSERT"
+                    | "LREM"
+                    | "LTRIM"
+                    | "LMOVE"
+                    | "RPOPLPUSH"
+                    | "LMPOP"
+                    | "BLPOP"
+                    | "BRPOP"
+                    | "BLMOVE"
+                    | "BRPOPLPUSH"
                     | "SADD"
                     | "SREM"
+                    | "SMOVE"
+                    | "SPOP"
+                    | "SINTERSTORE"
+                    | "SUNIONSTORE"
+                    | "SDIFFSTORE"
                     | "HSET"
                     | "HDEL"
+                    | "HMSET"
+                    | "HSETNX"
+                    | "HINCRBY"
+                    | "HINCRBYFLOAT"
+                    | "HEXPIRE"
+                    | "HPEXPIRE"
+                    | "HEXPIREAT"
+                    | "HPEXPIREAT"
+                    | "HPERSIST"
+                    | "ZADD"
+                    | "ZREM"
+                    | "ZINCRBY"
+                    | "ZREMRANGEBYSCORE"
+                    | "ZREMRANGEBYRANK"
+                    | "ZPOPMIN"
+                    | "ZPOPMAX"
+                    | "XADD"
+                    | "XDEL"
+                    | "XTRIM"
+                    | "XSETID"
+                    | "XGROUP"
+                    | "XREADGROUP"
+                    | "XACK"
+                    | "XCLAIM"
+                    | "XAUTOCLAIM"
                     | "FLUSHDB"
             )
         }
@@ -36,6 +91,66 @@ pub fn is_mutating_command(input: &str) -> bool {
     }
 }
 
+/// The keys a mutating command may have written, so WATCH notices the change.
+/// FLUSHDB touches every watched key itself.
+pub fn written_keys<'a>(cmd: &str, parts: &'a [Vec<u8>]) -> Vec<&'a [u8]> {
+    if cmd == "MSET" || cmd == "MSETNX" {
+        return parts.iter().skip(1).step_by(2).map(Vec::as_slice).collect();
+    }
+    let keys: &[Vec<u8>] = match cmd {
+        "DEL" => parts.get(1..).unwrap_or_default(),
+        "BLPOP" | "BRPOP" => parts.get(1..parts.len().saturating_sub(1)).unwrap_or_default(),
+        "BLMOVE" | "BRPOPLPUSH" | "LMOVE" | "RPOPLPUSH" => parts.get(1..3).unwrap_or_default(),
+        "LMPOP" => {
+            let numkeys = parts
+                .get(1)
+                .and_then(|n| std::str::from_utf8(n).ok()?.parse::<usize>().ok())
+                .unwrap_or(0);
+            parts.get(2..2 + numkeys).unwrap_or_default()
+        }
+        "SMOVE" => parts.get(1..3).unwrap_or_default(),
+        "XGROUP" => parts.get(2..3).unwrap_or_default(),
+        "XREADGROUP" => {
+            let streams = parts
+                .iter()
+                .position(|p| p.eq_ignore_ascii_case(b"STREAMS"))
+                .map_or(&[][..], |i| &parts[i + 1..]);
+            &streams[..streams.len() / 2]
+        }
+        "FLUSHDB" => &[],
+        _ => parts.get(1..2).unwrap_or_default(),
+    };
+    keys.iter().map(Vec::as_slice).collect()
+}
+
+// Count the write for the saver, bump the versions of any WATCHed keys it
+// wrote and hand it to the persister if mutating
+pub fn persist_if_mutating(
+    command_name: &str,
+    parts: &[Vec<u8>],
+    db: &mut Database,
+    persister: &(dyn Persister + Send + Sync),
+) {
+    if is_mutating_command(command_name) {
+        db.add_dirty(1);
+        for key in written_keys(command_name, parts) {
+            db.watched_mut().touch(key);
+        }
+        let result = match db.take_propagated().as_slice() {
+            [] => persister.record(parts, db),
+            [command] => persister.record(command, db),
+            commands => {
+                persister.begin_atomic();
+                let recorded = commands.iter().try_for_each(|c| persister.record(c, db));
+                persister.end_atomic(db).and(recorded)
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("❌ Failed to persist command: {e}");
+        }
+    }
+}
+
 pub fn current_unix_timestamp() -> u64 {
     use std::time::{SystemTime, UNIX_EPOCH};
     SystemTime::now()
@@ -43,14 +158,203 @@ pub fn current_unix_timestamp() -> u64 {
         .unwrap()
         .as_secs()
 }
+
+pub fn current_unix_millis() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
 pub fn start_expiry_worker(db: Arc<Mutex<Database>>, persister: Arc<dyn Persister + Send + Sync>) {
     tokio::spawn(async move {
         loop {
             {
                 let mut db = db.lock().await;
                 db.remove_expired_keys(&*persister);
+                db.remove_expired_fields(&*persister);
             }
             tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         }
     });
 }
+
+/// Redis glob matching: `*` matches any run of bytes, `?` any single byte,
+/// `[abc]`, `[^abc]` and `[a-z]` a byte class, and `\` escapes the next byte.
+///
+/// Only the last `*` is ever backtracked to: a later star can cover anything
+/// an earlier one would have, so matching takes at most
+/// `pattern.len() * string.len()` steps however many stars there are.
+pub fn glob_match(mut pattern: &[u8], string: &[u8]) -> bool {
+    let mut s = 0;
+    // The pattern after the last `*` and where in `string` that star
+    // currently stops.
+    let mut star: Option<(&[u8], usize)> = None;
+    while s < string.len() {
+        if let [b'*', rest @ ..] = pattern {
+            pattern = rest;
+            star = Some((rest, s));
+            continue;
+        }
+        if let Some(rest) = match_one(pattern, string[s]) {
+            pattern = rest;
+            s += 1;
+            continue;
+        }
+        // Let the last star take one more byte and retry from there.
+        let Some((after_star, stop)) = star else {
+            return false;
+        };
+        pattern = after_star;
+        s = stop + 1;
+        star = Some((after_star, s));
+    }
+    pattern.iter().all(|&b| b == b'*')
+}
+
+/// Matches `c` against the single-byte token `pattern` starts with,
+/// returning the pattern after the token if it matched.
+fn match_one(pattern: &[u8], c: u8) -> Option<&[u8]> {
+    match pattern {
+        [] => None,
+        [b'?', rest @ ..] => Some(rest),
+        [b'[', class @ ..] => {
+            let (matched, rest) = match_class(class, c);
+            matched.then_some(rest)
+        }
+        [b'\\', escaped, rest @ ..] => (*escaped == c).then_some(rest),
+        [byte, rest @ ..] => (*byte == c).then_some(rest),
+    }
+}
+
+/// Matches `c` against the class that starts right after a `[`, returning
+/// whether it matched and the pattern after the closing `]`. An unclosed
+/// class runs to the end of the pattern.
+fn match_class(mut pattern: &[u8], c: u8) -> (bool, &[u8]) {
+    let negate = pattern.first() == Some(&b'^');
+    if negate {
+        pattern = &pattern[1..];
+    }
+
+    let mut matched = false;
+    loop {
+        match pattern {
+            [] => break,
+            [b']', rest @ ..] => {
+                pattern = rest;
+                break;
+            }
+            [b'\\', escaped, rest @ ..] => {
+                matched |= *escaped == c;
+                pattern = rest;
+            }
+            [start, b'-', end, rest @ ..] if *end != b']' => {
+                let (lo, hi) = if start <= end { (*start, *end) } else { (*end, *start) };
+                matched |= (lo..=hi).contains(&c);
+                pattern = rest;
+            }
+            [byte, rest @ ..] => {
+                matched |= *byte == c;
+                pattern = rest;
+            }
+        }
+    }
+    (matched != negate, pattern)
+}
+
+/// Number of hash slots keys (and shard channels) are spread over, as in
+/// Redis Cluster.
+pub const HASH_SLOTS: u16 = 16384;
+
+/// The hash slot that owns `key`: CRC16 of the key modulo 16384, or of just
+/// the part inside the first non-empty `{...}` hash tag so related keys can
+/// be kept together.
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let tagged = key.iter().position(|&b| b == b'{').and_then(|open| {
+        let rest = &key[open + 1..];
+        rest.iter()
+            .position(|&b| b == b'}')
+            .filter(|&close| close > 0)
+            .map(|close| &rest[..close])
+    });
+    crc16(tagged.unwrap_or(key)) % HASH_SLOTS
+}
+
+/// CRC16-CCITT (XMODEM), the checksum Redis Cluster hashes keys with.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, &byte| {
+        (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn matches(pattern: &str, string: &str) -> bool {
+        glob_match(pattern.as_bytes(), string.as_bytes())
+    }
+
+    #[test]
+    fn stars_match_any_run() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("news.*", "news.sport"));
+        assert!(matches("news.*", "news."));
+        assert!(!matches("news.*", "news"));
+        assert!(matches("*.log", "a.b.log"));
+        assert!(matches("a*b*c", "axxbyybzc"));
+        assert!(!matches("a*b*c", "axxbyybz"));
+        assert!(matches("**a**", "bab"));
+    }
+
+    #[test]
+    fn question_marks_match_one_byte() {
+        assert!(matches("h?llo", "hello"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(!matches("h?llo", "heello"));
+        assert!(matches("?*", "x"));
+        assert!(!matches("?*", ""));
+    }
+
+    #[test]
+    fn classes_ranges_and_negation() {
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(matches("[c-a]x", "bx"));
+        assert!(!matches("[a-c]x", "dx"));
+        assert!(matches("[a\\]]", "]"));
+        assert!(matches("[a-]", "-"));
+        // An unclosed class runs to the end of the pattern.
+        assert!(matches("[ab", "b"));
+    }
+
+    #[test]
+    fn backslash_escapes_the_next_byte() {
+        assert!(matches("a\\*b", "a*b"));
+        assert!(!matches("a\\*b", "axb"));
+        assert!(matches("\\?", "?"));
+        assert!(!matches("\\?", "x"));
+        assert!(matches("end\\", "end\\"));
+    }
+
+    #[test]
+    fn many_stars_stay_fast() {
+        let pattern = "*a".repeat(30) + "*b";
+        let string = "a".repeat(5000);
+        let started = Instant::now();
+        assert!(!matches(&pattern, &string));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}